@@ -12,6 +12,7 @@ import {
   showFirewallAlert,
   AlertType
 } from "../../lib/utils/popup-alerts";
+import { errorMessage } from "../../lib/utils/errors";
 
 export default function AdminPage() {
   const [systemInfo, setSystemInfo] = useState({
@@ -100,7 +101,7 @@ export default function AdminPage() {
       setTimeout(() => setTestResult(null), 5000);
     } catch (error) {
       console.error("Failed to trigger test notification:", error);
-      setTestResult("❌ Failed to trigger test notification: " + error);
+      setTestResult("❌ Failed to trigger test notification: " + errorMessage(error));
       setTimeout(() => setTestResult(null), 5000);
     }
   };  const testDomainBlock = async () => {
@@ -2,6 +2,7 @@
 
 import { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
+import { errorMessage } from "../../lib/utils/errors";
 
 export default function DomainBlockerPage() {
   const [blockedDomains, setBlockedDomains] = useState<string[]>([]);
@@ -23,7 +24,7 @@ export default function DomainBlockerPage() {
       setError(null);
     } catch (err) {
       console.error("Failed to fetch blocked domains:", err);
-      setError(`Failed to fetch blocked domains: ${err}`);
+      setError(`Failed to fetch blocked domains: ${errorMessage(err)}`);
     } finally {
       setLoading(false);
     }
@@ -45,7 +46,7 @@ export default function DomainBlockerPage() {
       fetchBlockedDomains();
     } catch (err) {
       console.error("Failed to block domain:", err);
-      setError(`Failed to block domain: ${err}`);
+      setError(`Failed to block domain: ${errorMessage(err)}`);
     }
   };
 
@@ -56,7 +57,7 @@ export default function DomainBlockerPage() {
       fetchBlockedDomains();
     } catch (err) {
       console.error(`Failed to unblock domain ${domain}:`, err);
-      setError(`Failed to unblock domain ${domain}: ${err}`);
+      setError(`Failed to unblock domain ${domain}: ${errorMessage(err)}`);
     }
   };
 
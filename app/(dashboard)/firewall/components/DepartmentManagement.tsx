@@ -1,11 +1,14 @@
 "use client";
 
-import React, { useState } from "react";
+import React, { useEffect, useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
 import DepartmentSetup from "./DepartmentSetup";
 import InterDepartment from "./InterDepartment";
+import { errorMessage } from "../../../lib/utils/errors";
 
 interface Device {
   name: string;
+  address?: string | null;
 }
 
 interface Department {
@@ -26,6 +29,19 @@ interface ConnectionRule {
   action: "Allow" | "Block";
 }
 
+interface PolicyConflict {
+  first: number;
+  second: number;
+  message: string;
+}
+
+interface PolicyPlan {
+  rules: { name: string }[];
+  conflicts: PolicyConflict[];
+  removed: string[];
+  applied: boolean;
+}
+
 interface Props {
   deptTab: "setup" | "connections";
   setDeptTab: (tab: "setup" | "connections") => void;
@@ -41,36 +57,20 @@ export default function DepartmentManagement({ deptTab, setDeptTab }: Props) {
     description: "",
   });
 
-  // Departments state
-  const [departments, setDepartments] = useState<Department[]>(
-    [
-      {
-        id: "1",
-        name: "IT Department",
-        subnet: "192.168.1.0/24",
-        protocol: "TCP",
-        action: "Allow",
-        description: "Information Technology Department",
-        devices: [
-          { name: "Server-01" },
-          { name: "Workstation-01" },
-          { name: "Printer-IT" },
-        ],
-      },
-      {
-        id: "2",
-        name: "BA Department",
-        subnet: "192.168.2.0/24",
-        protocol: "TCP",
-        description: "Business Analyst Department",
-        action: "Allow",
-        devices: [
-          { name: "Server-02" },
-          { name: "Desktop-01" },
-          { name: "Desktop-02" },],
-      },
-    ]
-  );
+  // Departments state, persisted by the backend
+  const [departments, setDepartments] = useState<Department[]>([]);
+  const [error, setError] = useState<string | null>(null);
+
+  useEffect(() => {
+    invoke<Department[]>("get_departments")
+      .then(setDepartments)
+      .catch((err) => setError(`Failed to load departments: ${errorMessage(err)}`));
+  }, []);
+
+  // Replaces one department with the backend's updated copy
+  const replaceDepartment = (updated: Department) => {
+    setDepartments((prev) => prev.map((d) => (d.id === updated.id ? updated : d)));
+  };
 
   // Device input state (per department)
   const [deviceInputs, setDeviceInputs] = useState<{ [deptId: string]: string }>(
@@ -87,71 +87,96 @@ export default function DepartmentManagement({ deptTab, setDeptTab }: Props) {
   });
   const [connectionRules, setConnectionRules] = useState<ConnectionRule[]>([]);
 
+  useEffect(() => {
+    invoke<ConnectionRule[]>("get_connection_rules")
+      .then(setConnectionRules)
+      .catch((err) => setError(`Failed to load connection rules: ${errorMessage(err)}`));
+  }, []);
+
+  // Compiles and applies the whole rule list; the list only changes if the backend applied it
+  const applyConnectionRules = async (rules: ConnectionRule[]) => {
+    try {
+      const plan = await invoke<PolicyPlan>("apply_department_policy", { connections: rules });
+      if (!plan.applied) {
+        setError(`Connection rules conflict: ${plan.conflicts.map((c) => c.message).join("; ")}`);
+        return false;
+      }
+      setConnectionRules(rules);
+      setError(null);
+      return true;
+    } catch (err) {
+      setError(`Failed to apply connection rules: ${errorMessage(err)}`);
+      return false;
+    }
+  };
+
   // Add department handler
-  const addDepartment = () => {
+  const addDepartment = async () => {
     if (!form.name || !form.subnet) return;
-    setDepartments([
-      ...departments,
-      {
-        id: Date.now().toString(),
-        name: form.name,
-        subnet: form.subnet,
-        protocol: form.protocol,
-        action: form.action,
-        description: form.description,
-        devices: [],
-      },
-    ]);
-    setForm({
-      name: "",
-      subnet: "",
-      protocol: "ALL",
-      action: "Allow",
-      description: "",
-    });
+    try {
+      const created = await invoke<Department>("create_department", { department: form });
+      setDepartments([...departments, created]);
+      setForm({
+        name: "",
+        subnet: "",
+        protocol: "ALL",
+        action: "Allow",
+        description: "",
+      });
+      setError(null);
+    } catch (err) {
+      setError(`Failed to add department: ${errorMessage(err)}`);
+    }
   };
 
   // Delete department handler
-  const deleteDepartment = (id: string) => {
-    setDepartments(departments.filter((d) => d.id !== id));
+  const deleteDepartment = async (id: string) => {
+    try {
+      await invoke("delete_department", { id });
+      setDepartments(departments.filter((d) => d.id !== id));
+    } catch (err) {
+      setError(`Failed to delete department: ${errorMessage(err)}`);
+    }
   };
 
   // Add device handler
-  const addDevice = (deptId: string) => {
+  const addDevice = async (deptId: string) => {
     const deviceName = deviceInputs[deptId]?.trim();
     if (!deviceName) return;
-    setDepartments((prev) =>
-      prev.map((dept) =>
-        dept.id === deptId
-          ? { ...dept, devices: [...dept.devices, { name: deviceName }] }
-          : dept
-      )
-    );
-    setDeviceInputs({ ...deviceInputs, [deptId]: "" });
+    try {
+      const updated = await invoke<Department>("add_department_device", {
+        departmentId: deptId,
+        device: { name: deviceName },
+      });
+      replaceDepartment(updated);
+      setDeviceInputs({ ...deviceInputs, [deptId]: "" });
+      setError(null);
+    } catch (err) {
+      setError(`Failed to add device: ${errorMessage(err)}`);
+    }
   };
 
   // Delete device handler
-  const deleteDevice = (deptId: string, deviceName: string) => {
-    setDepartments((prev) =>
-      prev.map((dept) =>
-        dept.id === deptId
-          ? { ...dept, devices: dept.devices.filter((d) => d.name !== deviceName) }
-          : dept
-      )
-    );
+  const deleteDevice = async (deptId: string, deviceName: string) => {
+    try {
+      const updated = await invoke<Department>("remove_department_device", {
+        departmentId: deptId,
+        deviceName,
+      });
+      replaceDepartment(updated);
+    } catch (err) {
+      setError(`Failed to remove device: ${errorMessage(err)}`);
+    }
   };
 
   // Add connection rule handler
-  const addConnectionRule = () => {
-    if (
-      !connectionForm.from ||
-      !connectionForm.to ||
-      !connectionForm.port ||
-      isNaN(Number(connectionForm.port))
-    ) {
-      return; // <-- Add this!
+  const addConnectionRule = async () => {
+    if (!connectionForm.from || !connectionForm.to) {
+      return;
+    }
+    if (!(await applyConnectionRules([...connectionRules, connectionForm]))) {
+      return;
     }
-    setConnectionRules([...connectionRules, connectionForm]);
     setConnectionForm({
       from: "",
       to: "",
@@ -162,8 +187,8 @@ export default function DepartmentManagement({ deptTab, setDeptTab }: Props) {
   };
 
   // Remove connection rule handler
-  const removeConnectionRule = (index: number) => {
-    setConnectionRules(connectionRules.filter((_, i) => i !== index));
+  const removeConnectionRule = async (index: number) => {
+    await applyConnectionRules(connectionRules.filter((_, i) => i !== index));
   };
 
   return (
@@ -197,6 +222,18 @@ export default function DepartmentManagement({ deptTab, setDeptTab }: Props) {
         </button>
       </div>
 
+      {error && (
+        <div className="bg-red-100 border border-red-400 text-red-700 px-4 py-3 rounded mb-4">
+          <p>{error}</p>
+          <button
+            className="underline ml-2"
+            onClick={() => setError(null)}
+          >
+            Dismiss
+          </button>
+        </div>
+      )}
+
       {/* Tab Content */}
       <div className="space-y-6">
         {deptTab === "setup" && (
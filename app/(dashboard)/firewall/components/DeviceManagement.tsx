@@ -1,6 +1,8 @@
 "use client";
-import { useState, useEffect } from "react";
+import { useState, useEffect, useCallback } from "react";
 import { invoke } from "@tauri-apps/api/core";
+import { errorMessage } from "../../../lib/utils/errors";
+import PendingChangeBanner, { PendingChange } from "./PendingChangeBanner";
 
 interface FirewallRule {
   name: string;
@@ -27,13 +29,11 @@ export default function DeviceManagement() {
     action: "Block",
     enabled: true,
   });
+  // Seconds before an unconfirmed change is reverted; empty applies changes straight away.
+  const [confirmTimeout, setConfirmTimeout] = useState<number | null>(null);
+  const [pending, setPending] = useState<PendingChange | null>(null);
 
-  // Fetch firewall rules on component mount
-  useEffect(() => {
-    fetchRules();
-  }, []);
-
-  const fetchRules = async () => {
+  const fetchRules = useCallback(async () => {
     setLoading(true);
     try {
       const result = await invoke<FirewallRule[]>("get_firewall_rules");
@@ -41,15 +41,23 @@ export default function DeviceManagement() {
       setError(null);
     } catch (err) {
       console.error("Failed to fetch firewall rules:", err);
-      setError(`Failed to fetch firewall rules: ${err}`);
+      setError(`Failed to fetch firewall rules: ${errorMessage(err)}`);
     } finally {
       setLoading(false);
     }
-  };
+  }, []);
+
+  // Fetch firewall rules on component mount
+  useEffect(() => {
+    fetchRules();
+  }, [fetchRules]);
 
   const addRule = async () => {
     try {
-      await invoke("add_firewall_rule", { ruleInfo: newRule });
+      setPending(await invoke<PendingChange | null>("add_firewall_rule", {
+        ruleInfo: newRule,
+        confirmTimeoutSeconds: confirmTimeout,
+      }));
       // Reset the form
       setNewRule({
         name: "",
@@ -65,29 +73,36 @@ export default function DeviceManagement() {
       fetchRules();
     } catch (err) {
       console.error("Failed to add firewall rule:", err);
-      setError(`Failed to add firewall rule: ${err}`);
+      setError(`Failed to add firewall rule: ${errorMessage(err)}`);
     }
   };
 
   const removeRule = async (ruleName: string) => {
     try {
-      await invoke("remove_firewall_rule", { ruleName });
+      setPending(await invoke<PendingChange | null>("remove_firewall_rule", {
+        ruleName,
+        confirmTimeoutSeconds: confirmTimeout,
+      }));
       // Refresh the rules list
       fetchRules();
     } catch (err) {
       console.error(`Failed to remove rule ${ruleName}:`, err);
-      setError(`Failed to remove rule ${ruleName}: ${err}`);
+      setError(`Failed to remove rule ${ruleName}: ${errorMessage(err)}`);
     }
   };
   
   const toggleRuleStatus = async (ruleName: string, enable: boolean) => {
     try {
-      await invoke("enable_disable_rule", { ruleName, enable });
+      setPending(await invoke<PendingChange | null>("enable_disable_rule", {
+        ruleName,
+        enable,
+        confirmTimeoutSeconds: confirmTimeout,
+      }));
       // Refresh the rules list
       fetchRules();
     } catch (err) {
       console.error(`Failed to ${enable ? 'enable' : 'disable'} rule ${ruleName}:`, err);
-      setError(`Failed to ${enable ? 'enable' : 'disable'} rule ${ruleName}: ${err}`);
+      setError(`Failed to ${enable ? 'enable' : 'disable'} rule ${ruleName}: ${errorMessage(err)}`);
     }
   };
 
@@ -107,6 +122,8 @@ export default function DeviceManagement() {
       */}
 
       <div className="container mx-auto p-4 relative z-10 rounded-6xl">
+
+      <PendingChangeBanner pending={pending} onSettled={fetchRules} />
       
       {error && (
         <div className="bg-red-100 border border-red-400 text-red-700 px-4 py-3 rounded mb-4">
@@ -220,6 +237,21 @@ export default function DeviceManagement() {
             </select>
           </div>
           
+          <div>
+            <label className="block text-gray-700 text-sm font-bold mb-2">
+              Revert unless confirmed within (seconds, optional)
+            </label>
+            <input
+              type="number"
+              min={10}
+              max={3600}
+              className="shadow appearance-none border rounded w-full py-2 px-3 text-gray-700 leading-tight focus:outline-none focus:shadow-outline"
+              value={confirmTimeout ?? ""}
+              onChange={(e) => setConfirmTimeout(e.target.value ? parseInt(e.target.value) : null)}
+              placeholder="Apply without confirmation"
+            />
+          </div>
+
           <div className="md:col-span-2">
             <label className="flex items-center">
               <input
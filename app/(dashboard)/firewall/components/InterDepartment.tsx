@@ -17,8 +17,8 @@ interface InterDepartmentProps {
   connectionForm: ConnectionRule;
   setConnectionForm: any;
   connectionRules: ConnectionRule[];
-  addConnectionRule: () => void;
-  removeConnectionRule: (index: number) => void;
+  addConnectionRule: () => void | Promise<void>;
+  removeConnectionRule: (index: number) => void | Promise<void>;
 }
 
 const InterDepartment: React.FC<InterDepartmentProps> = ({
@@ -88,14 +88,13 @@ const InterDepartment: React.FC<InterDepartmentProps> = ({
             <div className="space-y-2">
               <label className="font-bold">Port</label>
               <input
-                type="number"
+                type="text"
                 className="w-full border border-gray-300 rounded px-3 py-2 focus:border-purple-500"
                 value={connectionForm.port}
                 onChange={(e) =>
                   setConnectionForm({ ...connectionForm, port: e.target.value })
                 }
-                placeholder="Port"
-                min={1}
+                placeholder="Any, or e.g. 80,443,8000-8080"
               />
             </div>
             <div className="space-y-2">
@@ -156,12 +155,13 @@ const InterDepartment: React.FC<InterDepartmentProps> = ({
                 <th className="px-4 py-2 text-left font-semibold">PORT</th>
                 <th className="px-4 py-2 text-left font-semibold">DIRECTION</th>
                 <th className="px-4 py-2 text-left font-semibold">ACTION</th>
+                <th className="px-4 py-2"></th>
               </tr>
             </thead>
             <tbody>
               {connectionRules.length === 0 ? (
                 <tr>
-                  <td colSpan={5} className="text-center py-4 text-gray-400">
+                  <td colSpan={6} className="text-center py-4 text-gray-400">
                     No inter-department connection rules configured
                   </td>
                 </tr>
@@ -170,7 +170,7 @@ const InterDepartment: React.FC<InterDepartmentProps> = ({
                   <tr key={idx} className="border-t">
                     <td className="px-4 py-2">{rule.from}</td>
                     <td className="px-4 py-2">{rule.to}</td>
-                    <td className="px-4 py-2">{rule.port}</td>
+                    <td className="px-4 py-2">{rule.port || "Any"}</td>
                     <td className="px-4 py-2">{rule.direction}</td>
                     <td className="px-4 py-2">
                       <span
@@ -183,6 +183,14 @@ const InterDepartment: React.FC<InterDepartmentProps> = ({
                         {rule.action}
                       </span>
                     </td>
+                    <td className="px-4 py-2 text-right">
+                      <button
+                        className="text-red-600 hover:text-red-800"
+                        onClick={() => removeConnectionRule(idx)}
+                      >
+                        Remove
+                      </button>
+                    </td>
                   </tr>
                 ))
               )}
@@ -0,0 +1,129 @@
+"use client";
+import { useState, useEffect } from "react";
+import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+import { errorMessage } from "../../../lib/utils/errors";
+
+export interface PendingChange {
+  snapshot_id: string;
+  description: string;
+  source: string;
+  created_at: string;
+  deadline: string;
+}
+
+interface RevertedChange {
+  change: PendingChange;
+  report: { snapshot_id: string; removed: string[]; added: string[] };
+}
+
+interface PendingChangeBannerProps {
+  // The change just made, so the banner shows it without waiting for the next poll.
+  pending: PendingChange | null;
+  onSettled: () => void;
+}
+
+// Counts down a tentative change and lets the user keep it or roll it back before it reverts.
+export default function PendingChangeBanner({ pending: latest, onSettled }: PendingChangeBannerProps) {
+  const [pending, setPending] = useState<PendingChange | null>(null);
+  const [now, setNow] = useState(Date.now());
+  const [notice, setNotice] = useState<string | null>(null);
+  const [error, setError] = useState<string | null>(null);
+
+  useEffect(() => {
+    invoke<PendingChange | null>("get_pending_change")
+      .then(setPending)
+      .catch((err) => console.error("Failed to load pending change:", err));
+
+    const unlistenReverted = listen<RevertedChange>("tentative-change-reverted", (event) => {
+      setPending(null);
+      setNotice(`"${event.payload.change.description}" was not confirmed in time and has been reverted.`);
+      onSettled();
+    });
+    const timer = setInterval(() => setNow(Date.now()), 1000);
+
+    return () => {
+      unlistenReverted.then((unlisten) => unlisten());
+      clearInterval(timer);
+    };
+  }, [onSettled]);
+
+  useEffect(() => {
+    if (latest) {
+      setPending(latest);
+      setNotice(null);
+    }
+  }, [latest]);
+
+  const confirm = async () => {
+    try {
+      await invoke("confirm_pending_change");
+      setPending(null);
+      setError(null);
+    } catch (err) {
+      setError(`Failed to confirm change: ${errorMessage(err)}`);
+    }
+  };
+
+  const revert = async () => {
+    try {
+      const reverted = await invoke<RevertedChange>("revert_pending_change");
+      setPending(null);
+      setError(null);
+      setNotice(`Reverted "${reverted.change.description}".`);
+      onSettled();
+    } catch (err) {
+      setError(`Failed to revert change: ${errorMessage(err)}`);
+    }
+  };
+
+  if (!pending && !notice && !error) {
+    return null;
+  }
+
+  const secondsLeft = pending
+    ? Math.max(0, Math.round((new Date(pending.deadline).getTime() - now) / 1000))
+    : 0;
+
+  return (
+    <div className="mb-4">
+      {pending && (
+        <div className="bg-yellow-100 border border-yellow-400 text-yellow-800 px-4 py-3 rounded flex items-center justify-between">
+          <p>
+            &quot;{pending.description}&quot; reverts in {secondsLeft}s unless you keep it.
+          </p>
+          <div>
+            <button
+              className="bg-green-500 hover:bg-green-700 text-white font-bold py-1 px-3 rounded mr-2"
+              onClick={confirm}
+            >
+              Keep
+            </button>
+            <button
+              className="bg-red-500 hover:bg-red-700 text-white font-bold py-1 px-3 rounded"
+              onClick={revert}
+            >
+              Revert now
+            </button>
+          </div>
+        </div>
+      )}
+      {notice && (
+        <div className="bg-blue-100 border border-blue-400 text-blue-800 px-4 py-3 rounded mt-2">
+          <p>{notice}</p>
+          <button className="underline ml-2" onClick={() => setNotice(null)}>
+            Dismiss
+          </button>
+        </div>
+      )}
+      {error && (
+        <div className="bg-red-100 border border-red-400 text-red-700 px-4 py-3 rounded mt-2">
+          <p>{error}</p>
+          <button className="underline ml-2" onClick={() => setError(null)}>
+            Dismiss
+          </button>
+        </div>
+      )}
+    </div>
+  );
+}
@@ -2,7 +2,9 @@
 
 import { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import { useRouter } from "next/navigation";
+import { errorMessage } from "../../lib/utils/errors";
 
 type AlertEvent = {
   timestamp: string;
@@ -15,7 +17,14 @@ type AlertEvent = {
   severity?: number;
 };
 
+type AlertBatch = {
+  alerts: AlertEvent[];
+  skipped: number;
+};
+
 const PAGE_SIZE = 1000;
+// As many as read_alert_events returns.
+const MAX_ALERTS = 500;
 
 export default function NetworkTrafficAnalysisPage() {
   const [isActive, setIsActive] = useState<boolean | null>(null);
@@ -33,14 +42,11 @@ export default function NetworkTrafficAnalysisPage() {
       const active = await invoke<boolean>("is_suricata_active");
       setIsActive(active);
 
-      if (active) {
-        await invoke("extract_and_handle_events");
-      }
       const alertList = await invoke<AlertEvent[]>("read_alert_events");
       setAlerts(alertList);
       setError(null);
     } catch (err) {
-      setError(`Failed to fetch status or alerts: ${err}`);
+      setError(`Failed to fetch status or alerts: ${errorMessage(err)}`);
     } finally {
       setLoading(false);
     }
@@ -48,6 +54,19 @@ export default function NetworkTrafficAnalysisPage() {
 
   useEffect(() => {
     refreshStatusAndAlerts();
+
+    // New alerts are pushed by the backend as they are stored.
+    const unlistenAlerts = listen<AlertBatch>("suricata-alerts", (event) => {
+      setAlerts((prev) => [...prev, ...event.payload.alerts].slice(-MAX_ALERTS));
+    });
+    invoke("subscribe_live_events", { filter: {} }).catch((err) =>
+      setError(`Failed to subscribe to alerts: ${errorMessage(err)}`)
+    );
+
+    return () => {
+      unlistenAlerts.then((unlisten) => unlisten());
+      invoke("unsubscribe_live_events");
+    };
   }, []);
 
   const handleRun = async () => {
@@ -56,7 +75,7 @@ export default function NetworkTrafficAnalysisPage() {
       await invoke("run_suricata");
       setTimeout(refreshStatusAndAlerts, 1000);
     } catch (err) {
-      setError(`Failed to start Suricata: ${err}`);
+      setError(`Failed to start Suricata: ${errorMessage(err)}`);
     } finally {
       setLoading(false);
     }
@@ -68,7 +87,7 @@ export default function NetworkTrafficAnalysisPage() {
       await invoke("kill_suricata");
       setTimeout(refreshStatusAndAlerts, 1000);
     } catch (err) {
-      setError(`Failed to kill Suricata: ${err}`);
+      setError(`Failed to kill Suricata: ${errorMessage(err)}`);
     } finally {
       setLoading(false);
     }
@@ -3,6 +3,7 @@
 import { useEffect, useRef, useState } from "react";
 import { invoke } from "@tauri-apps/api/core";
 import ReactMarkdown from "react-markdown";
+import { errorMessage } from "../../lib/utils/errors";
 
 export default function ChatAssistant({
     isOpen,
@@ -168,7 +169,7 @@ export default function ChatAssistant({
             console.error("AI Error:", err);
             setMessages((prev) => [
                 ...prev,
-                { role: "error", text: `Failed to get response: ${err}` },
+                { role: "error", text: `Failed to get response: ${errorMessage(err)}` },
             ]);
         }
     }; 
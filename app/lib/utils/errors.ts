@@ -0,0 +1,34 @@
+// Errors returned by Tauri commands
+export type AppErrorKind =
+  | 'admin_required'
+  | 'backend_missing'
+  | 'command_failed'
+  | 'parse_error'
+  | 'invalid_input'
+  | 'not_found'
+  | 'conflict'
+  | 'resolution_failed'
+  | 'suricata_not_running'
+  | 'io'
+  | 'other';
+
+export interface AppError {
+  kind: AppErrorKind;
+  message: string;
+  details?: string | null;
+  retryable: boolean;
+}
+
+export function isAppError(err: unknown): err is AppError {
+  return typeof err === 'object' && err !== null && 'kind' in err && 'message' in err;
+}
+
+/**
+ * Human-readable text for a rejected invoke()
+ */
+export function errorMessage(err: unknown): string {
+  if (isAppError(err)) {
+    return err.message;
+  }
+  return err instanceof Error ? err.message : String(err);
+}
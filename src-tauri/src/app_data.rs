@@ -0,0 +1,114 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager};
+use tracing::{debug, warn};
+
+// Must match `identifier` in tauri.conf.json, so the CLI finds the desktop app's files.
+pub const APP_IDENTIFIER: &str = "com.lengzailenglui.sm";
+const DATA_DIR_ENV_VAR: &str = "SECURITY_SMILE_DATA_DIR";
+// Created by the systemd unit (StateDirectory=security-smile).
+const SERVICE_STATE_DIR: &str = "/var/lib/security-smile";
+
+fn data_dir_override() -> Option<PathBuf> {
+    std::env::var(DATA_DIR_ENV_VAR)
+        .ok()
+        .filter(|dir| !dir.trim().is_empty())
+        .map(PathBuf::from)
+}
+
+// Where the desktop app keeps its JSON files.
+pub fn app_data_dir(app: &AppHandle) -> PathBuf {
+    if let Some(dir) = data_dir_override() {
+        return dir;
+    }
+    match app.path().app_data_dir() {
+        Ok(dir) => dir,
+        Err(_) => {
+            warn!("Failed to get app data directory, using current directory");
+            std::env::current_dir().unwrap_or_default()
+        }
+    }
+}
+
+// The same directory without a running app, resolved the way Tauri does it.
+pub fn default_data_dir() -> PathBuf {
+    if let Some(dir) = data_dir_override() {
+        return dir;
+    }
+    match dirs::data_dir() {
+        Some(dir) => dir.join(APP_IDENTIFIER),
+        None => {
+            warn!("Failed to get app data directory, using current directory");
+            std::env::current_dir().unwrap_or_default()
+        }
+    }
+}
+
+// Where the event database and the eve.json read position are kept. The desktop app, the CLI
+// and the service all use them, so once the service is installed they live in its state
+// directory, where members of the security-smile group can write.
+pub fn events_dir() -> PathBuf {
+    let service_dir = Path::new(SERVICE_STATE_DIR);
+    if service_dir.is_dir() {
+        return events_dir_in(service_dir);
+    }
+    events_dir_in(&default_data_dir())
+}
+
+pub fn events_dir_in(data_dir: &Path) -> PathBuf {
+    data_dir.join("events")
+}
+
+// Creates `dir` so that the group can write to it as well, and files created in it keep the
+// directory's group.
+pub fn create_shared_dir(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    share_with_group(dir, 0o2770);
+    Ok(())
+}
+
+// Lets the group read and write a file this process owns, so the root service and a desktop
+// user in the group can both use it. Files owned by someone else are left to their owner.
+#[cfg(unix)]
+pub fn share_with_group(path: &Path, mode: u32) {
+    use std::os::unix::fs::PermissionsExt;
+    let Ok(metadata) = fs::metadata(path) else { return };
+    if metadata.permissions().mode() & 0o7777 == mode {
+        return;
+    }
+    if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(mode)) {
+        debug!("Failed to set permissions on {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn share_with_group(_path: &Path, _mode: u32) {}
+
+// An OS lock on a file, released when it's dropped or the holder dies, so a crash never leaves
+// it held. Processes that share the file wait for each other.
+pub struct FileLock {
+    _file: fs::File,
+}
+
+impl FileLock {
+    pub fn acquire(path: &Path) -> std::io::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        share_with_group(path, 0o660);
+        file.lock()?;
+        Ok(FileLock { _file: file })
+    }
+}
+
+pub fn data_file_path(data_dir: &Path, file_name: &str) -> PathBuf {
+    if !data_dir.exists() {
+        if let Err(e) = fs::create_dir_all(data_dir) {
+            warn!("Failed to create app data directory: {}", e);
+        }
+    }
+
+    data_dir.join(file_name)
+}
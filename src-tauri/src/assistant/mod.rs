@@ -1,6 +1,7 @@
 
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use crate::error::AppError;
 
 #[derive(Deserialize)]
 pub struct AskPrompt {
@@ -49,7 +50,8 @@ struct GeminiContent {
 
 
 #[tauri::command]
-pub async fn ask_ai(prompt: String) -> Result<String, String> {
+#[tracing::instrument(skip_all, err)]
+pub async fn ask_ai(prompt: String) -> Result<String, AppError> {
     let api_key = std::env::var("GEMINI_API_KEY")
         .map_err(|_| "GEMINI_API_KEY not set".to_string())?;
 
@@ -78,7 +80,7 @@ pub async fn ask_ai(prompt: String) -> Result<String, String> {
 
     if !status.is_success() {
         let error_text = res.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("API returned error {}: {}", status, error_text));
+        return Err(format!("API returned error {}: {}", status, error_text).into());
     }
 
     let json: GeminiResponse = res.json().await.map_err(|e| format!("JSON parsing error: {}", e))?;
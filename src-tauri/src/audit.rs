@@ -0,0 +1,377 @@
+use std::fs::{self, OpenOptions};
+use std::fmt;
+use std::future::Future;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tauri::AppHandle;
+use tracing::warn;
+use crate::app_data::{app_data_dir, data_file_path, FileLock};
+use crate::error::AppError;
+use crate::firewall::snapshots::{current_user, ChangeSource};
+
+const AUDIT_FILE: &str = "audit.jsonl";
+// Held while appending so the desktop app, the CLI and the daemon don't fork the chain.
+const LOCK_FILE: &str = "audit.lock";
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const DEFAULT_QUERY_LIMIT: usize = 100;
+
+// One line of audit.jsonl. `hash` covers every other field, including the previous entry's
+// hash, so editing or removing a line breaks the chain from that point on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub source: ChangeSource,
+    pub user: Option<String>,
+    pub action: String,
+    pub params: Value,
+    pub ok: bool,
+    pub result: Value,
+    pub error: Option<String>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> String {
+        let mut unsigned = self.clone();
+        unsigned.hash = String::new();
+        let bytes = serde_json::to_vec(&unsigned).unwrap_or_default();
+        format!("{:x}", Sha256::digest(&bytes))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuditQuery {
+    // Matches actions containing this text.
+    #[serde(default)]
+    pub action: Option<String>,
+    #[serde(default)]
+    pub source: Option<ChangeSource>,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub failed_only: bool,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.action.as_ref().is_none_or(|action| entry.action.contains(action.as_str()))
+            && self.source.is_none_or(|source| entry.source == source)
+            && self.user.as_ref().is_none_or(|user| entry.user.as_ref() == Some(user))
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp <= until)
+            && (!self.failed_only || !entry.ok)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditVerification {
+    pub valid: bool,
+    pub entries: usize,
+    pub head_hash: Option<String>,
+    // Line number (from 1) of the first entry that doesn't check out.
+    pub broken_at_line: Option<usize>,
+    pub error: Option<String>,
+}
+
+fn audit_path(data_dir: &Path) -> PathBuf {
+    data_file_path(data_dir, AUDIT_FILE)
+}
+
+// Reads backwards from the end of the file until it has a whole last line.
+fn read_last_entry(path: &Path) -> Result<Option<AuditEntry>, String> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to open audit log: {}", e)),
+    };
+    let len = file.metadata().map_err(|e| format!("Failed to read audit log: {}", e))?.len();
+
+    let mut chunk = 8192u64;
+    loop {
+        let start = len.saturating_sub(chunk);
+        file.seek(SeekFrom::Start(start)).map_err(|e| format!("Failed to read audit log: {}", e))?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer).map_err(|e| format!("Failed to read audit log: {}", e))?;
+        let text = String::from_utf8_lossy(&buffer);
+        let trimmed = text.trim_end();
+        if trimmed.is_empty() {
+            return Ok(None);
+        }
+
+        match trimmed.rfind('\n') {
+            Some(newline) => return parse_entry(&trimmed[newline + 1..]).map(Some),
+            None if start == 0 => return parse_entry(trimmed).map(Some),
+            None => chunk *= 4,
+        }
+    }
+}
+
+fn parse_entry(line: &str) -> Result<AuditEntry, String> {
+    serde_json::from_str(line).map_err(|e| format!("Failed to parse audit entry: {}", e))
+}
+
+pub fn append_entry(
+    data_dir: &Path,
+    source: ChangeSource,
+    action: &str,
+    params: Value,
+    outcome: Result<Value, String>
+) -> Result<AuditEntry, String> {
+    // Held only for one append, so waiting for it doesn't hold up a caller for long.
+    let _lock = FileLock::acquire(&data_file_path(data_dir, LOCK_FILE))
+        .map_err(|e| format!("Failed to lock the audit log: {}", e))?;
+    let path = audit_path(data_dir);
+    let (seq, prev_hash) = match read_last_entry(&path)? {
+        Some(last) => (last.seq + 1, last.hash),
+        None => (1, GENESIS_HASH.to_string()),
+    };
+
+    let (ok, result, error) = match outcome {
+        Ok(result) => (true, result, None),
+        Err(e) => (false, Value::Null, Some(e)),
+    };
+    let mut entry = AuditEntry {
+        seq,
+        timestamp: Utc::now(),
+        source,
+        user: current_user(),
+        action: action.to_string(),
+        params,
+        ok,
+        result,
+        error,
+        prev_hash,
+        hash: String::new(),
+    };
+    entry.hash = entry.compute_hash();
+
+    let mut line = serde_json::to_string(&entry)
+        .map_err(|e| format!("Failed to serialize audit entry: {}", e))?;
+    line.push('\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("Failed to open audit log: {}", e))?;
+    file.write_all(line.as_bytes())
+        .and_then(|_| file.sync_data())
+        .map_err(|e| format!("Failed to write audit entry: {}", e))?;
+
+    Ok(entry)
+}
+
+// Writes the entry for a change that has already been made; a failure to write it is logged
+// rather than turned into an error for a change that did happen.
+pub fn record<T: Serialize, E: fmt::Display>(
+    data_dir: &Path,
+    source: ChangeSource,
+    action: &str,
+    params: Value,
+    result: &Result<T, E>
+) {
+    let outcome = match result {
+        Ok(value) => Ok(serde_json::to_value(value).unwrap_or(Value::Null)),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = append_entry(data_dir, source, action, params, outcome) {
+        warn!("Failed to write audit entry for {}: {}", action, e);
+    }
+}
+
+pub async fn audited<T, F>(
+    data_dir: &Path,
+    source: ChangeSource,
+    action: &str,
+    params: Value,
+    change: F
+) -> Result<T, AppError>
+where
+    T: Serialize,
+    F: Future<Output = Result<T, AppError>>,
+{
+    let result = change.await;
+    record(data_dir, source, action, params, &result);
+    result
+}
+
+fn read_lines(data_dir: &Path) -> Result<Vec<String>, String> {
+    match fs::read_to_string(audit_path(data_dir)) {
+        Ok(text) => Ok(text.lines().filter(|l| !l.trim().is_empty()).map(str::to_string).collect()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("Failed to read audit log: {}", e)),
+    }
+}
+
+// Newest first.
+pub fn query_audit_log(data_dir: &Path, query: &AuditQuery) -> Result<Vec<AuditEntry>, String> {
+    let limit = query.limit.unwrap_or(DEFAULT_QUERY_LIMIT);
+    let mut entries = Vec::new();
+    for line in read_lines(data_dir)?.iter().rev() {
+        if entries.len() >= limit {
+            break;
+        }
+        let entry = parse_entry(line)?;
+        if query.matches(&entry) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+pub fn verify_audit_chain(data_dir: &Path) -> Result<AuditVerification, String> {
+    let lines = read_lines(data_dir)?;
+    let mut verification = AuditVerification {
+        valid: true,
+        entries: lines.len(),
+        head_hash: None,
+        broken_at_line: None,
+        error: None,
+    };
+
+    let mut expected_seq = 1;
+    let mut prev_hash = GENESIS_HASH.to_string();
+    for (index, line) in lines.iter().enumerate() {
+        let problem = match parse_entry(line) {
+            Err(e) => Some(e),
+            Ok(entry) if entry.seq != expected_seq => {
+                Some(format!("expected sequence number {}, found {}", expected_seq, entry.seq))
+            },
+            Ok(entry) if entry.prev_hash != prev_hash => {
+                Some(format!("entry {} does not follow the previous entry's hash", entry.seq))
+            },
+            Ok(entry) if entry.compute_hash() != entry.hash => {
+                Some(format!("entry {} has been modified", entry.seq))
+            },
+            Ok(entry) => {
+                expected_seq += 1;
+                prev_hash = entry.hash;
+                None
+            },
+        };
+        if let Some(problem) = problem {
+            verification.valid = false;
+            verification.broken_at_line = Some(index + 1);
+            verification.error = Some(problem);
+            return Ok(verification);
+        }
+    }
+
+    verification.head_hash = (!lines.is_empty()).then_some(prev_hash);
+    Ok(verification)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_audit_log(app: AppHandle, query: Option<AuditQuery>) -> Result<Vec<AuditEntry>, AppError> {
+    Ok(query_audit_log(&app_data_dir(&app), &query.unwrap_or_default())?)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn verify_audit_log(app: AppHandle) -> Result<AuditVerification, AppError> {
+    Ok(verify_audit_chain(&app_data_dir(&app))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("security-smile-audit-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn entries_are_chained_and_queryable() {
+        let dir = temp_dir("chain");
+        let first = append_entry(&dir, ChangeSource::Cli, "block_domain", json!({ "domain": "ads.example.com" }), Ok(Value::Null)).unwrap();
+        let second = append_entry(&dir, ChangeSource::Desktop, "remove_firewall_rule", json!({ "rule_name": "Web" }), Err("denied".to_string())).unwrap();
+
+        assert_eq!(first.seq, 1);
+        assert_eq!(first.prev_hash, GENESIS_HASH);
+        assert_eq!(second.prev_hash, first.hash);
+
+        let verification = verify_audit_chain(&dir).unwrap();
+        assert!(verification.valid);
+        assert_eq!(verification.entries, 2);
+        assert_eq!(verification.head_hash, Some(second.hash.clone()));
+
+        let all = query_audit_log(&dir, &AuditQuery::default()).unwrap();
+        assert_eq!(all, vec![second.clone(), first.clone()]);
+        let failed = query_audit_log(&dir, &AuditQuery { failed_only: true, ..Default::default() }).unwrap();
+        assert_eq!(failed, vec![second]);
+        let from_cli = query_audit_log(&dir, &AuditQuery { source: Some(ChangeSource::Cli), ..Default::default() }).unwrap();
+        assert_eq!(from_cli, vec![first]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn edited_and_removed_entries_break_the_chain() {
+        let dir = temp_dir("tamper");
+        for domain in ["a.example.com", "b.example.com", "c.example.com"] {
+            append_entry(&dir, ChangeSource::Daemon, "block_domains", json!({ "domains": [domain] }), Ok(Value::Null)).unwrap();
+        }
+        let path = audit_path(&dir);
+        let original = fs::read_to_string(&path).unwrap();
+
+        fs::write(&path, original.replace("b.example.com", "x.example.com")).unwrap();
+        let verification = verify_audit_chain(&dir).unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.broken_at_line, Some(2));
+
+        let without_second: Vec<&str> = original.lines().enumerate().filter(|(i, _)| *i != 1).map(|(_, l)| l).collect();
+        fs::write(&path, without_second.join("\n")).unwrap();
+        let verification = verify_audit_chain(&dir).unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.broken_at_line, Some(2));
+
+        // Appending continues from whatever the last line is.
+        fs::write(&path, &original).unwrap();
+        let next = append_entry(&dir, ChangeSource::Daemon, "refresh_domains", Value::Null, Ok(Value::Null)).unwrap();
+        assert_eq!(next.seq, 4);
+        assert!(verify_audit_chain(&dir).unwrap().valid);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn concurrent_appends_keep_one_chain() {
+        let dir = temp_dir("concurrent");
+        // A lock file left behind by an earlier process doesn't hold anyone up.
+        fs::write(data_file_path(&dir, LOCK_FILE), "").unwrap();
+
+        let writers: Vec<_> = (0..4).map(|writer| {
+            let dir = dir.clone();
+            std::thread::spawn(move || {
+                for n in 0..10 {
+                    append_entry(&dir, ChangeSource::Cli, "block_domain", json!({ "writer": writer, "n": n }), Ok(Value::Null)).unwrap();
+                }
+            })
+        }).collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let verification = verify_audit_chain(&dir).unwrap();
+        assert!(verification.valid, "{:?}", verification);
+        assert_eq!(verification.entries, 40);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,3 @@
+fn main() -> std::process::ExitCode {
+    security_smile_lib::cli::main()
+}
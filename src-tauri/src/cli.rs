@@ -0,0 +1,1122 @@
+use std::path::{Path, PathBuf};
+use std::process::{ExitCode, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use tracing::{debug, warn, Level};
+use crate::app_data::{default_data_dir, events_dir};
+use crate::audit::{query_audit_log, record, verify_audit_chain, AuditQuery};
+use crate::logging::{init_logging, read_recent_logs};
+use crate::daemon::{daemon_running, run_daemon, DaemonStatus};
+use crate::daemon::ipc::{default_socket_path, send_request, DaemonRequest};
+use crate::firewall::backend::{select_backend, FirewallBackend};
+use crate::firewall::command_runner::ProcessCommandRunner;
+use crate::firewall::common::{BlockedDomains, FirewallRuleInfo};
+use crate::firewall::domain_blocking::batch::{block_and_record, unblock_and_record, DomainBatchResult};
+use crate::firewall::domain_blocking::load_blocked_domains;
+use crate::firewall::domain_blocking::resolver::DnsResolverState;
+use crate::firewall::ownership::{initialize_rule_manifest, RuleManifestState};
+use crate::firewall::rule_model::{format_list, parse_list};
+use crate::firewall::snapshots::{
+    capture_snapshot,
+    delete_snapshot,
+    list_snapshots,
+    restore_snapshot,
+    snapshot_before_change,
+    ChangeSource,
+    SnapshotSummary
+};
+use crate::firewall::tentative::{
+    begin_pending_change,
+    confirm_pending,
+    load_pending_change,
+    revert_if_expired,
+    revert_pending,
+    validate_confirm_timeout,
+    WATCH_INTERVAL_SECONDS
+};
+use crate::network_traffic_analysis::report::{read_flow_report, write_flow_report};
+use crate::network_traffic_analysis::supervisor::{SuricataState, SuricataStatus, SuricataSupervisor};
+use crate::network_traffic_analysis::suricata::{
+    launch_suricata,
+    suricata_log_dir,
+    terminate_suricata,
+    AlertEvent
+};
+use crate::network_traffic_analysis::store::{AlertQuery, AlertSort, EventStore};
+use crate::network_traffic_analysis::tailer::EveTailer;
+use crate::error::AppError;
+
+const ALERT_POLL_SECONDS: u64 = 2;
+const CONFIRM_WITHIN_HELP: &str = "Revert the change after this many seconds unless `pending confirm` is run";
+
+// Headless access to the same firewall and IDS code the desktop app uses. Changes are saved
+// to the app's data files; a running desktop app picks them up on its next start.
+#[derive(Debug, Parser)]
+#[command(name = "security-smile-cli", version, about = "Manage Security Smile from the command line")]
+pub struct Cli {
+    #[arg(long, global = true, help = "Data directory (defaults to the desktop app's, or SECURITY_SMILE_DATA_DIR)")]
+    data_dir: Option<PathBuf>,
+
+    #[arg(long, global = true, help = "Print results as JSON")]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    #[command(subcommand, about = "List, add or remove firewall rules")]
+    Rules(RulesCommand),
+    #[command(subcommand, about = "Block or unblock domains")]
+    Domain(DomainCommand),
+    #[command(subcommand, about = "Start, stop or check Suricata")]
+    Suricata(SuricataCommand),
+    #[command(subcommand, about = "Show IDS alerts")]
+    Alerts(AlertsCommand),
+    #[command(subcommand, about = "Build the flow report from captured traffic")]
+    Report(ReportCommand),
+    #[command(subcommand, about = "Run or query the background service")]
+    Daemon(DaemonCommand),
+    #[command(subcommand, about = "List, take or restore firewall snapshots")]
+    Snapshot(SnapshotCommand),
+    #[command(subcommand, about = "Confirm or revert a tentative change")]
+    Pending(PendingCommand),
+    #[command(subcommand, about = "Search or verify the audit log")]
+    Audit(AuditCommand),
+    #[command(about = "Show recent log lines from the app, the CLI and the daemon")]
+    Logs {
+        #[arg(short = 'n', long, default_value_t = 50)]
+        lines: usize,
+        #[arg(long, help = "Only this level and above: error, warn, info, debug or trace")]
+        level: Option<Level>,
+    },
+}
+
+// Changes made from the command line are audited under the same names the desktop app uses.
+fn audit_action(command: &Command) -> Option<&'static str> {
+    match command {
+        Command::Rules(RulesCommand::Add(_)) => Some("add_firewall_rule"),
+        Command::Rules(RulesCommand::Remove { .. }) => Some("remove_firewall_rule"),
+        Command::Domain(DomainCommand::Block { .. }) => Some("block_domains"),
+        Command::Domain(DomainCommand::Unblock { .. }) => Some("unblock_domains"),
+        Command::Suricata(SuricataCommand::Start) => Some("run_suricata"),
+        Command::Suricata(SuricataCommand::Stop) => Some("kill_suricata"),
+        Command::Snapshot(SnapshotCommand::Create { .. }) => Some("create_firewall_snapshot"),
+        Command::Snapshot(SnapshotCommand::Restore { .. }) => Some("restore_firewall_snapshot"),
+        Command::Snapshot(SnapshotCommand::Delete { .. }) => Some("delete_firewall_snapshot"),
+        Command::Pending(PendingCommand::Confirm) => Some("confirm_pending_change"),
+        Command::Pending(PendingCommand::Revert) => Some("revert_pending_change"),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum RulesCommand {
+    List,
+    Add(RuleArgs),
+    Remove {
+        name: String,
+        #[arg(long, value_name = "SECONDS", help = CONFIRM_WITHIN_HELP)]
+        confirm_within: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Action {
+    Allow,
+    Block,
+}
+
+#[derive(Debug, Args)]
+struct RuleArgs {
+    #[arg(long)]
+    name: String,
+    #[arg(long, value_enum)]
+    direction: Direction,
+    #[arg(long, value_enum)]
+    action: Action,
+    #[arg(long, default_value = "Any")]
+    protocol: String,
+    #[arg(long, default_value = "", help = "Comma-separated ports or ranges, e.g. 80,443,8000-8080")]
+    local_ports: String,
+    #[arg(long, default_value = "")]
+    remote_ports: String,
+    #[arg(long, default_value = "", help = "Comma-separated addresses, networks or ranges")]
+    local_addresses: String,
+    #[arg(long, default_value = "")]
+    remote_addresses: String,
+    #[arg(long)]
+    program: Option<String>,
+    #[arg(long, default_value = "")]
+    description: String,
+    #[arg(long)]
+    disabled: bool,
+    #[arg(long, value_name = "SECONDS", help = CONFIRM_WITHIN_HELP)]
+    confirm_within: Option<u64>,
+}
+
+#[derive(Debug, Subcommand)]
+enum DomainCommand {
+    Block {
+        #[arg(required = true)]
+        domains: Vec<String>,
+        #[arg(long)]
+        include_subdomains: bool,
+    },
+    Unblock {
+        #[arg(required = true)]
+        domains: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum SuricataCommand {
+    Start,
+    Stop,
+    Status,
+}
+
+#[derive(Debug, Subcommand)]
+enum AlertsCommand {
+    Tail {
+        #[arg(short = 'n', long, default_value_t = 20)]
+        lines: usize,
+        #[arg(short, long, help = "Keep printing new alerts as they arrive")]
+        follow: bool,
+    },
+    Query {
+        #[arg(long, value_parser = parse_timestamp, help = "RFC 3339 time, e.g. 2025-01-31T09:00:00Z")]
+        since: Option<DateTime<Utc>>,
+        #[arg(long, value_parser = parse_timestamp)]
+        until: Option<DateTime<Utc>>,
+        #[arg(long, help = "Only alerts at least this severe (1 is the most severe)")]
+        min_severity: Option<u8>,
+        #[arg(long, help = "Address or CIDR block at either end of the connection")]
+        ip: Option<String>,
+        #[arg(long, help = "Port at either end of the connection")]
+        port: Option<u16>,
+        #[arg(long, help = "Only signatures containing this text")]
+        signature: Option<String>,
+        #[arg(long)]
+        category: Option<String>,
+        #[arg(long, value_enum, default_value_t = SortArg::Newest)]
+        sort: SortArg,
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+        #[arg(short = 'n', long, default_value_t = 50)]
+        limit: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SortArg {
+    Newest,
+    Oldest,
+    Severity,
+}
+
+impl From<SortArg> for AlertSort {
+    fn from(sort: SortArg) -> Self {
+        match sort {
+            SortArg::Newest => AlertSort::Newest,
+            SortArg::Oldest => AlertSort::Oldest,
+            SortArg::Severity => AlertSort::Severity,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum ReportCommand {
+    Generate,
+}
+
+#[derive(Debug, Subcommand)]
+enum AuditCommand {
+    Query {
+        #[arg(long, help = "Only actions containing this text")]
+        action: Option<String>,
+        #[arg(long, value_enum)]
+        source: Option<SourceArg>,
+        #[arg(long)]
+        user: Option<String>,
+        #[arg(long, value_parser = parse_timestamp, help = "RFC 3339 time, e.g. 2025-01-31T09:00:00Z")]
+        since: Option<DateTime<Utc>>,
+        #[arg(long, value_parser = parse_timestamp)]
+        until: Option<DateTime<Utc>>,
+        #[arg(long, help = "Only failed actions")]
+        failed: bool,
+        #[arg(short = 'n', long, default_value_t = 50)]
+        limit: usize,
+    },
+    Verify,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SourceArg {
+    Desktop,
+    Cli,
+    Daemon,
+}
+
+impl From<SourceArg> for ChangeSource {
+    fn from(source: SourceArg) -> Self {
+        match source {
+            SourceArg::Desktop => ChangeSource::Desktop,
+            SourceArg::Cli => ChangeSource::Cli,
+            SourceArg::Daemon => ChangeSource::Daemon,
+        }
+    }
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|e| format!("Invalid time {}: {}", value, e))
+}
+
+#[derive(Debug, Subcommand)]
+enum PendingCommand {
+    Show,
+    Confirm,
+    Revert,
+    // Started in the background by a tentative change; reverts it once the deadline passes.
+    #[command(hide = true)]
+    Watch,
+}
+
+#[derive(Debug, Subcommand)]
+enum SnapshotCommand {
+    List,
+    Create {
+        #[arg(long)]
+        label: Option<String>,
+        #[arg(long, help = "Also export the whole firewall configuration")]
+        full: bool,
+    },
+    Restore {
+        id: String,
+        #[arg(long, help = "Import the snapshot's full firewall export instead of only the app's rules")]
+        full: bool,
+    },
+    Delete {
+        id: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum DaemonCommand {
+    Run {
+        #[arg(long, help = "Keep Suricata running and restart it if it exits")]
+        suricata: bool,
+        #[arg(long, help = "IPC socket path (defaults to /run/security-smile/daemon.sock or the data directory)")]
+        socket: Option<PathBuf>,
+    },
+    Status {
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+}
+
+impl RuleArgs {
+    fn to_rule_info(&self) -> Result<FirewallRuleInfo, String> {
+        let mut rule = FirewallRuleInfo {
+            name: self.name.trim().to_string(),
+            description: self.description.clone(),
+            application_path: self.program.clone(),
+            protocol: self.protocol.clone(),
+            direction: match self.direction {
+                Direction::Inbound => "Inbound".to_string(),
+                Direction::Outbound => "Outbound".to_string(),
+            },
+            action: match self.action {
+                Action::Allow => "Allow".to_string(),
+                Action::Block => "Block".to_string(),
+            },
+            enabled: !self.disabled,
+            remote_ports: parse_list(&self.remote_ports)?,
+            local_addresses: parse_list(&self.local_addresses)?,
+            remote_addresses: parse_list(&self.remote_addresses)?,
+            ..Default::default()
+        };
+        if rule.name.is_empty() {
+            return Err("A rule name is required".to_string());
+        }
+        rule.set_local_ports(parse_list(&self.local_ports)?);
+        Ok(rule)
+    }
+}
+
+// The app state the CLI works on, loaded from the data directory instead of Tauri's managed state.
+struct CliContext {
+    data_dir: PathBuf,
+    backend: Arc<dyn FirewallBackend>,
+    manifest: RuleManifestState,
+    blocked: BlockedDomains,
+    resolver: DnsResolverState,
+}
+
+impl CliContext {
+    async fn load(data_dir: PathBuf) -> Result<Self, String> {
+        let runner = Arc::new(ProcessCommandRunner::new(data_dir.clone()));
+        let context = CliContext {
+            backend: select_backend(runner),
+            manifest: RuleManifestState::default(),
+            blocked: BlockedDomains::default(),
+            resolver: DnsResolverState::default(),
+            data_dir,
+        };
+        load_blocked_domains(&context.data_dir, &context.blocked).await?;
+        initialize_rule_manifest(&context.data_dir, &context.manifest)?;
+        Ok(context)
+    }
+
+    async fn snapshot(&self, trigger: &str) -> Result<SnapshotSummary, String> {
+        Ok(snapshot_before_change(
+            &self.data_dir,
+            self.backend.as_ref(),
+            &self.manifest,
+            &self.blocked,
+            ChangeSource::Cli,
+            trigger
+        ).await?)
+    }
+
+    // Records the change as pending and leaves a watcher behind that outlives this process
+    // (and the terminal it runs in), so the change is reverted even if the session drops.
+    // Without a watcher nothing would revert it, so it's undone straight away.
+    async fn begin_tentative(&self, snapshot: &SnapshotSummary, trigger: &str, seconds: u64, json: bool) -> Result<(), String> {
+        let pending = begin_pending_change(&self.data_dir, snapshot, trigger, seconds)?;
+        if let Err(e) = spawn_pending_watcher(&self.data_dir) {
+            let reverted = revert_pending(
+                &self.data_dir,
+                self.backend.as_ref(),
+                &self.manifest,
+                &self.blocked,
+                ChangeSource::Cli
+            ).await;
+            return Err(match reverted {
+                Ok(_) => format!("{}; the change was reverted", e),
+                Err(revert_error) => format!("{}; reverting the change failed too: {}", e, String::from(revert_error)),
+            });
+        }
+        if json {
+            return print_json(&pending);
+        }
+        println!(
+            "Run `security-smile-cli pending confirm` within {} seconds to keep this change, or it will be reverted.",
+            seconds
+        );
+        Ok(())
+    }
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(value)
+        .map_err(|e| format!("Failed to serialize output: {}", e))?;
+    println!("{}", json);
+    Ok(())
+}
+
+fn format_rule(rule: &FirewallRuleInfo) -> String {
+    let ports = match format_list(&rule.effective_local_ports()) {
+        ports if ports.is_empty() => "Any".to_string(),
+        ports => ports,
+    };
+    format!(
+        "{:<40} {:<8} {:<9} {:<6} {:<8} {}",
+        rule.name,
+        if rule.enabled { "enabled" } else { "disabled" },
+        rule.direction,
+        rule.action,
+        rule.protocol,
+        ports
+    )
+}
+
+fn format_alert(alert: &AlertEvent) -> String {
+    let endpoint = |ip: &Option<String>, port: Option<u16>| match (ip, port) {
+        (Some(ip), Some(port)) => format!("{}:{}", ip, port),
+        (Some(ip), None) => ip.clone(),
+        _ => "?".to_string(),
+    };
+    format!(
+        "{} [{}] {} {} -> {}",
+        alert.timestamp,
+        alert.severity.map(|s| s.to_string()).unwrap_or_else(|| "-".to_string()),
+        alert.signature.as_deref().unwrap_or("(no signature)"),
+        endpoint(&alert.src_ip, alert.src_port),
+        endpoint(&alert.dest_ip, alert.dest_port)
+    )
+}
+
+// Prints per-domain results; fails if any domain failed.
+fn report_domain_results(results: &[DomainBatchResult], verb: &str, json: bool) -> Result<(), String> {
+    if json {
+        print_json(&results)?;
+    } else {
+        for result in results {
+            match &result.error {
+                None => println!("{} {}", verb, result.domain),
+                Some(error) => eprintln!("failed {}: {}", result.domain, error),
+            }
+        }
+    }
+
+    let failed = results.iter().filter(|r| !r.success).count();
+    if failed > 0 {
+        return Err(format!("{} of {} domains failed", failed, results.len()));
+    }
+    Ok(())
+}
+
+// eve.json only exists once Suricata has written something, so a missing file isn't an error.
+fn collect_events(store: &Arc<EventStore>) {
+    if let Err(e) = EveTailer::with_event_store(&suricata_log_dir(), &events_dir(), store.clone()).poll() {
+        debug!("No new Suricata events collected: {}", e);
+    }
+}
+
+async fn run_rules(context: &CliContext, command: RulesCommand, json: bool) -> Result<(), String> {
+    match command {
+        RulesCommand::List => {
+            let rules = context.backend.list_rules().await
+                .map_err(|e| AppError::from(e).context("Failed to list firewall rules"))?;
+            if json {
+                return print_json(&rules);
+            }
+            for rule in &rules {
+                println!("{}", format_rule(rule));
+            }
+            Ok(())
+        },
+        RulesCommand::Add(args) => {
+            let rule = args.to_rule_info()?;
+            let confirm_within = args.confirm_within.map(validate_confirm_timeout).transpose()?;
+            let trigger = format!("rules add {}", rule.name);
+            let snapshot = context.snapshot(&trigger).await?;
+            context.backend.add_rule(&rule).await
+                .map_err(|e| AppError::from(e).context(&format!("Failed to add rule {}", rule.name)))?;
+            context.manifest.record_manual_rule(&rule.name);
+            context.manifest.save(&context.data_dir)?;
+            // With --json the pending change, or else the rule, is the only output.
+            if !json {
+                println!("added {}", rule.name);
+            }
+            match confirm_within {
+                Some(seconds) => context.begin_tentative(&snapshot, &trigger, seconds, json).await,
+                None if json => print_json(&rule),
+                None => Ok(()),
+            }
+        },
+        RulesCommand::Remove { name, confirm_within } => {
+            let confirm_within = confirm_within.map(validate_confirm_timeout).transpose()?;
+            let trigger = format!("rules remove {}", name);
+            let snapshot = context.snapshot(&trigger).await?;
+            context.backend.remove_rule(&name).await
+                .map_err(|e| AppError::from(e).context(&format!("Failed to remove rule {}", name)))?;
+            context.manifest.forget_manual_rule(&name);
+            context.manifest.save(&context.data_dir)?;
+            if !json {
+                println!("removed {}", name);
+            }
+            match confirm_within {
+                Some(seconds) => context.begin_tentative(&snapshot, &trigger, seconds, json).await,
+                None if json => print_json(&serde_json::json!({ "removed": name })),
+                None => Ok(()),
+            }
+        },
+    }
+}
+
+async fn run_domain(context: &CliContext, command: DomainCommand, json: bool) -> Result<(), String> {
+    match command {
+        DomainCommand::Block { domains, include_subdomains } => {
+            context.snapshot(&format!("domain block {}", domains.join(", "))).await?;
+            let results = block_and_record(
+                &context.data_dir,
+                &context.resolver.resolver(),
+                context.backend.as_ref(),
+                &context.blocked,
+                &context.manifest,
+                &domains,
+                include_subdomains
+            ).await;
+            report_domain_results(&results, "blocked", json)
+        },
+        DomainCommand::Unblock { domains } => {
+            context.snapshot(&format!("domain unblock {}", domains.join(", "))).await?;
+            let results = unblock_and_record(
+                &context.data_dir,
+                context.backend.as_ref(),
+                &context.blocked,
+                &context.manifest,
+                &domains
+            ).await;
+            report_domain_results(&results, "unblocked", json)
+        },
+    }
+}
+
+// Through the background service when it is running, so Suricata is supervised. Otherwise
+// Suricata is started on its own and nothing restarts it if it exits.
+async fn run_suricata_command(command: SuricataCommand, json: bool) -> Result<(), String> {
+    let socket = default_socket_path();
+    let via_daemon = daemon_running().await;
+    match command {
+        SuricataCommand::Start => {
+            if via_daemon {
+                let status: SuricataStatus = from_daemon(send_request(&socket, &DaemonRequest::StartSuricata).await?)?;
+                return print_suricata_status(&status, json);
+            }
+            launch_suricata()?;
+            println!("suricata started (not supervised; `daemon run --suricata` restarts it if it exits)");
+        },
+        SuricataCommand::Stop => {
+            if via_daemon {
+                send_request(&socket, &DaemonRequest::StopSuricata).await?;
+            } else {
+                terminate_suricata();
+            }
+            println!("suricata stopped");
+        },
+        SuricataCommand::Status => {
+            let status = if via_daemon {
+                let status: DaemonStatus = from_daemon(send_request(&socket, &DaemonRequest::Status).await?)?;
+                status.suricata
+            } else {
+                SuricataSupervisor::default().status()
+            };
+            print_suricata_status(&status, json)?;
+        },
+    }
+    Ok(())
+}
+
+fn from_daemon<T: serde::de::DeserializeOwned>(value: serde_json::Value) -> Result<T, String> {
+    serde_json::from_value(value).map_err(|e| format!("Failed to parse daemon response: {}", e))
+}
+
+fn print_suricata_status(status: &SuricataStatus, json: bool) -> Result<(), String> {
+    if json {
+        return print_json(status);
+    }
+    println!("suricata {}", format_suricata_status(status));
+    if let Some(error) = &status.last_error {
+        println!("last error: {}", error);
+    }
+    Ok(())
+}
+
+fn format_suricata_status(status: &SuricataStatus) -> String {
+    let mut text = match status.state {
+        SuricataState::Running => format!(
+            "running, pid {}, up {}s",
+            status.pid.unwrap_or_default(),
+            status.uptime_seconds.unwrap_or_default()
+        ),
+        SuricataState::Restarting => match status.restart_in_seconds {
+            Some(seconds) => format!("restarting in {}s", seconds),
+            None => "restarting".to_string(),
+        },
+        SuricataState::Stopped => "stopped".to_string(),
+    };
+    if status.restart_count > 0 {
+        text.push_str(&format!(", {} restarts", status.restart_count));
+    }
+    if !status.other_pids.is_empty() {
+        let pids: Vec<String> = status.other_pids.iter().map(|p| p.to_string()).collect();
+        text.push_str(&format!(" (unmanaged suricata running: pid {})", pids.join(", ")));
+    }
+    text
+}
+
+async fn run_alerts(command: AlertsCommand, json: bool) -> Result<(), String> {
+    let store = Arc::new(EventStore::in_dir(&events_dir()));
+    let print = |alerts: &[AlertEvent]| -> Result<(), String> {
+        for alert in alerts {
+            if json {
+                println!("{}", serde_json::to_string(alert).map_err(|e| e.to_string())?);
+            } else {
+                println!("{}", format_alert(alert));
+            }
+        }
+        Ok(())
+    };
+
+    collect_events(&store);
+    let (lines, follow) = match command {
+        AlertsCommand::Tail { lines, follow } => (lines, follow),
+        AlertsCommand::Query { since, until, min_severity, ip, port, signature, category, sort, offset, limit } => {
+            let query = AlertQuery {
+                since,
+                until,
+                min_severity,
+                ip,
+                port,
+                signature,
+                category,
+                after_id: None,
+                sort: sort.into(),
+                offset,
+                limit: Some(limit),
+            };
+            let page = store.query_alerts(&query)?;
+            if json {
+                return print_json(&page);
+            }
+            print(&page.alerts)?;
+            println!("{} of {} matching alerts", page.alerts.len(), page.total);
+            return Ok(());
+        },
+    };
+
+    print(&store.recent_alerts(lines)?)?;
+    if !follow {
+        return Ok(());
+    }
+
+    let mut last_id = store.last_alert_id()?;
+    loop {
+        tokio::time::sleep(Duration::from_secs(ALERT_POLL_SECONDS)).await;
+        collect_events(&store);
+        let page = store.query_alerts(&AlertQuery {
+            after_id: last_id,
+            sort: AlertSort::Oldest,
+            limit: Some(1000),
+            ..Default::default()
+        })?;
+        print(&page.alerts)?;
+        last_id = page.alerts.last().and_then(|alert| alert.id).or(last_id);
+    }
+}
+
+fn run_report(command: ReportCommand) -> Result<(), String> {
+    let ReportCommand::Generate = command;
+    let store = Arc::new(EventStore::in_dir(&events_dir()));
+    collect_events(&store);
+    write_flow_report(&store)?;
+    print_json(&read_flow_report()?)
+}
+
+fn run_logs(data_dir: &Path, lines: usize, level: Option<Level>, json: bool) -> Result<(), String> {
+    let lines = read_recent_logs(data_dir, lines, level)?;
+    if json {
+        return print_json(&lines);
+    }
+    for line in &lines {
+        println!(
+            "{} {:<5} {:<7} {}{}",
+            line.timestamp,
+            line.level,
+            line.process,
+            line.span.as_deref().map(|s| format!("{}: ", s)).unwrap_or_default(),
+            line.message
+        );
+    }
+    Ok(())
+}
+
+fn run_audit(data_dir: &Path, command: AuditCommand, json: bool) -> Result<(), String> {
+    match command {
+        AuditCommand::Query { action, source, user, since, until, failed, limit } => {
+            let query = AuditQuery {
+                action,
+                source: source.map(ChangeSource::from),
+                user,
+                since,
+                until,
+                failed_only: failed,
+                limit: Some(limit),
+            };
+            let entries = query_audit_log(data_dir, &query)?;
+            if json {
+                return print_json(&entries);
+            }
+            for entry in entries.iter().rev() {
+                println!(
+                    "{:>6} {} {:<8} {:<12} {:<28} {}",
+                    entry.seq,
+                    entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
+                    format!("{:?}", entry.source).to_lowercase(),
+                    entry.user.as_deref().unwrap_or("-"),
+                    entry.action,
+                    entry.error.as_deref().map(|e| format!("failed: {}", e)).unwrap_or_else(|| "ok".to_string())
+                );
+            }
+            Ok(())
+        },
+        AuditCommand::Verify => {
+            let verification = verify_audit_chain(data_dir)?;
+            if json {
+                print_json(&verification)?;
+            } else if verification.valid {
+                println!(
+                    "audit log intact: {} entries, head {}",
+                    verification.entries,
+                    verification.head_hash.as_deref().unwrap_or("-")
+                );
+            }
+            match (verification.valid, verification.broken_at_line, verification.error) {
+                (true, _, _) => Ok(()),
+                (false, line, error) => Err(format!(
+                    "audit log broken at line {}: {}",
+                    line.unwrap_or_default(),
+                    error.unwrap_or_default()
+                )),
+            }
+        },
+    }
+}
+
+fn spawn_pending_watcher(data_dir: &Path) -> Result<(), String> {
+    let exe = std::env::current_exe()
+        .map_err(|e| format!("Failed to locate the CLI executable: {}", e))?;
+    let mut command = std::process::Command::new(exe);
+    command
+        .arg("--data-dir")
+        .arg(data_dir)
+        .args(["pending", "watch"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // Its own process group, so closing the terminal doesn't take it down too.
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    // Without a console, and outside the console's Ctrl+C group.
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x0000_0008;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+    }
+    command.spawn()
+        .map_err(|e| format!("Failed to start the revert watcher: {}", e))?;
+    Ok(())
+}
+
+async fn run_pending(context: &CliContext, command: PendingCommand, json: bool) -> Result<(), String> {
+    match command {
+        PendingCommand::Show => {
+            let pending = load_pending_change(&context.data_dir)?;
+            if json {
+                return print_json(&pending);
+            }
+            match pending {
+                Some(pending) => println!(
+                    "{} (snapshot {}), reverts in {}s",
+                    pending.description,
+                    pending.snapshot_id,
+                    pending.seconds_left(Utc::now())
+                ),
+                None => println!("no change is waiting for confirmation"),
+            }
+            Ok(())
+        },
+        PendingCommand::Confirm => {
+            let pending = confirm_pending(&context.data_dir)?;
+            if json {
+                return print_json(&pending);
+            }
+            println!("confirmed {}", pending.description);
+            Ok(())
+        },
+        PendingCommand::Revert => {
+            let (pending, report) = revert_pending(
+                &context.data_dir,
+                context.backend.as_ref(),
+                &context.manifest,
+                &context.blocked,
+                ChangeSource::Cli
+            ).await?;
+            if json {
+                return print_json(&report);
+            }
+            println!("reverted {} to snapshot {}", pending.description, report.snapshot_id);
+            Ok(())
+        },
+        // A failed revert leaves the change pending, so keep trying until it is settled.
+        PendingCommand::Watch => {
+            let mut interval = tokio::time::interval(Duration::from_secs(WATCH_INTERVAL_SECONDS));
+            loop {
+                interval.tick().await;
+                match load_pending_change(&context.data_dir) {
+                    Ok(None) => return Ok(()),
+                    Ok(Some(_)) => {},
+                    Err(e) => {
+                        warn!("Failed to read the pending change: {}", e);
+                        continue;
+                    },
+                }
+                if let Err(e) = revert_if_expired(
+                    &context.data_dir,
+                    context.backend.as_ref(),
+                    &context.manifest,
+                    &context.blocked,
+                    ChangeSource::Cli
+                ).await {
+                    warn!("Failed to revert tentative change: {}", e);
+                }
+            }
+        },
+    }
+}
+
+async fn run_snapshot(context: &CliContext, command: SnapshotCommand, json: bool) -> Result<(), String> {
+    match command {
+        SnapshotCommand::List => {
+            let snapshots = list_snapshots(&context.data_dir)?;
+            if json {
+                return print_json(&snapshots);
+            }
+            for snapshot in &snapshots {
+                println!(
+                    "{:<24} {:<32} {:<8} {:>4} rules{}  {}",
+                    snapshot.id,
+                    snapshot.created_at,
+                    format!("{:?}", snapshot.source).to_lowercase(),
+                    snapshot.rule_count,
+                    if snapshot.has_export { " +export" } else { "" },
+                    snapshot.trigger
+                );
+            }
+            Ok(())
+        },
+        SnapshotCommand::Create { label, full } => {
+            let trigger = label.map(|l| format!("manual: {}", l)).unwrap_or_else(|| "manual".to_string());
+            let summary = capture_snapshot(
+                &context.data_dir,
+                context.backend.as_ref(),
+                &context.manifest,
+                &context.blocked,
+                ChangeSource::Cli,
+                &trigger,
+                full
+            ).await?;
+            if json {
+                return print_json(&summary);
+            }
+            println!("created {}", summary.id);
+            Ok(())
+        },
+        SnapshotCommand::Restore { id, full } => {
+            let report = restore_snapshot(
+                &context.data_dir,
+                context.backend.as_ref(),
+                &context.manifest,
+                &context.blocked,
+                ChangeSource::Cli,
+                &id,
+                full
+            ).await?;
+            if json {
+                return print_json(&report);
+            }
+            println!(
+                "restored {} (removed {}, added {}); undo with: snapshot restore {}",
+                report.snapshot_id,
+                report.removed.len(),
+                report.added.len(),
+                report.backup_id
+            );
+            Ok(())
+        },
+        SnapshotCommand::Delete { id } => {
+            delete_snapshot(&context.data_dir, &id)?;
+            println!("deleted {}", id);
+            Ok(())
+        },
+    }
+}
+
+async fn run_daemon_command(command: DaemonCommand, data_dir: Option<PathBuf>, json: bool) -> Result<(), String> {
+    match command {
+        DaemonCommand::Run { suricata, socket } => {
+            let socket = socket.unwrap_or_else(default_socket_path);
+            run_daemon(data_dir.unwrap_or_else(default_data_dir), &socket, suricata).await
+        },
+        DaemonCommand::Status { socket } => {
+            let socket = socket.unwrap_or_else(default_socket_path);
+            let status = send_request(&socket, &DaemonRequest::Status).await?;
+            if json {
+                return print_json(&status);
+            }
+            let status: DaemonStatus = from_daemon(status)?;
+            println!("pid {} using {} ({} blocked domains)", status.pid, status.firewall_backend, status.blocked_domains);
+            println!("suricata {}", format_suricata_status(&status.suricata));
+            Ok(())
+        },
+    }
+}
+
+async fn run_command(cli: Cli) -> Result<(), String> {
+    let Some(action) = audit_action(&cli.command) else {
+        return dispatch_command(cli).await;
+    };
+    let data_dir = cli.data_dir.clone().unwrap_or_else(default_data_dir);
+    let params = serde_json::json!({ "args": std::env::args().skip(1).collect::<Vec<_>>() });
+    let result = dispatch_command(cli).await;
+    record(&data_dir, ChangeSource::Cli, action, params, &result);
+    result
+}
+
+async fn dispatch_command(cli: Cli) -> Result<(), String> {
+    let json = cli.json;
+    match cli.command {
+        Command::Rules(command) => {
+            let context = CliContext::load(cli.data_dir.unwrap_or_else(default_data_dir)).await?;
+            run_rules(&context, command, json).await
+        },
+        Command::Domain(command) => {
+            let context = CliContext::load(cli.data_dir.unwrap_or_else(default_data_dir)).await?;
+            run_domain(&context, command, json).await
+        },
+        Command::Suricata(command) => run_suricata_command(command, json).await,
+        Command::Alerts(command) => run_alerts(command, json).await,
+        Command::Report(command) => run_report(command),
+        Command::Daemon(command) => run_daemon_command(command, cli.data_dir, json).await,
+        Command::Snapshot(command) => {
+            let context = CliContext::load(cli.data_dir.unwrap_or_else(default_data_dir)).await?;
+            run_snapshot(&context, command, json).await
+        },
+        Command::Pending(command) => {
+            let context = CliContext::load(cli.data_dir.unwrap_or_else(default_data_dir)).await?;
+            run_pending(&context, command, json).await
+        },
+        Command::Audit(command) => run_audit(&cli.data_dir.unwrap_or_else(default_data_dir), command, json),
+        Command::Logs { lines, level } => run_logs(&cli.data_dir.unwrap_or_else(default_data_dir), lines, level, json),
+    }
+}
+
+pub fn main() -> ExitCode {
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
+
+    // The daemon's stderr goes to the journal; other commands keep theirs for their own output.
+    let (process, to_stderr) = match &cli.command {
+        Command::Daemon(DaemonCommand::Run { .. }) => ("daemon", true),
+        _ => ("cli", false),
+    };
+    let data_dir = cli.data_dir.clone().unwrap_or_else(default_data_dir);
+    if let Err(e) = init_logging(&data_dir, process, to_stderr) {
+        eprintln!("warning: {}", e);
+    }
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("error: Failed to create Tokio runtime: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match runtime.block_on(run_command(cli)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use crate::firewall::rule_model::PortSpec;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("security-smile-cli").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn command_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn parses_rule_add_into_a_rule() {
+        let cli = parse(&[
+            "rules", "add", "--name", "Web", "--direction", "inbound", "--action", "allow",
+            "--protocol", "TCP", "--local-ports", "80,443", "--remote-addresses", "10.0.0.0/8", "--json",
+        ]).unwrap();
+        assert!(cli.json);
+
+        let Command::Rules(RulesCommand::Add(args)) = cli.command else {
+            panic!("expected rules add");
+        };
+        let rule = args.to_rule_info().unwrap();
+        assert_eq!(rule.direction, "Inbound");
+        assert_eq!(rule.action, "Allow");
+        assert_eq!(rule.local_ports, vec![PortSpec::single(80), PortSpec::single(443)]);
+        assert_eq!(format_list(&rule.remote_addresses), "10.0.0.0/8");
+        assert!(rule.enabled);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse(&["rules", "add", "--name", "Web", "--direction", "sideways", "--action", "allow"]).is_err());
+        assert!(parse(&["domain", "block"]).is_err());
+
+        let Command::Rules(RulesCommand::Add(args)) = parse(&[
+            "rules", "add", "--name", "Web", "--direction", "inbound", "--action", "allow", "--local-ports", "80-",
+        ]).unwrap().command else {
+            panic!("expected rules add");
+        };
+        assert!(args.to_rule_info().unwrap_err().contains("Invalid port"));
+    }
+
+    #[test]
+    fn parses_domain_and_alert_commands() {
+        let cli = parse(&["domain", "block", "a.example.com", "b.example.com", "--include-subdomains"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Domain(DomainCommand::Block { ref domains, include_subdomains: true }) if domains.len() == 2
+        ));
+
+        let cli = parse(&["alerts", "tail", "-n", "5", "--follow", "--data-dir", "/tmp/smile"]).unwrap();
+        assert!(matches!(cli.command, Command::Alerts(AlertsCommand::Tail { lines: 5, follow: true })));
+        assert_eq!(cli.data_dir, Some(PathBuf::from("/tmp/smile")));
+
+        let cli = parse(&["alerts", "query", "--ip", "10.0.0.0/8", "--min-severity", "2", "--sort", "severity"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Alerts(AlertsCommand::Query { ref ip, min_severity: Some(2), sort: SortArg::Severity, limit: 50, .. })
+                if ip.as_deref() == Some("10.0.0.0/8")
+        ));
+    }
+
+    #[test]
+    fn formats_alerts_on_one_line() {
+        let alert = AlertEvent {
+            id: None,
+            timestamp: "2025-01-01T10:00:00.000000+0000".to_string(),
+            src_ip: Some("10.0.0.5".to_string()),
+            dest_ip: Some("93.184.216.34".to_string()),
+            src_port: Some(51000),
+            dest_port: None,
+            signature: Some("ET POLICY Suspicious".to_string()),
+            category: None,
+            severity: Some(2),
+            flow_id: None,
+            community_id: None,
+        };
+
+        assert_eq!(
+            format_alert(&alert),
+            "2025-01-01T10:00:00.000000+0000 [2] ET POLICY Suspicious 10.0.0.5:51000 -> 93.184.216.34"
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::app_data::default_data_dir;
+use crate::error::AppError;
+
+const SOCKET_ENV_VAR: &str = "SECURITY_SMILE_SOCKET";
+// Created by the systemd unit (RuntimeDirectory=security-smile).
+const SERVICE_RUNTIME_DIR: &str = "/run/security-smile";
+const SOCKET_FILE_NAME: &str = "daemon.sock";
+// Blocking a batch of domains resolves each of them first, so replies can take a while.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn default_socket_path() -> PathBuf {
+    if let Some(path) = std::env::var(SOCKET_ENV_VAR).ok().filter(|p| !p.trim().is_empty()) {
+        return PathBuf::from(path);
+    }
+    let runtime_dir = Path::new(SERVICE_RUNTIME_DIR);
+    if runtime_dir.is_dir() {
+        return runtime_dir.join(SOCKET_FILE_NAME);
+    }
+    default_data_dir().join(SOCKET_FILE_NAME)
+}
+
+// One JSON object per line in each direction: a request, then its response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum DaemonRequest {
+    Status,
+    ListRules,
+    BlockedDomains,
+    BlockDomains {
+        domains: Vec<String>,
+        #[serde(default)]
+        include_subdomains: bool,
+    },
+    UnblockDomains {
+        domains: Vec<String>,
+    },
+    RefreshDomains,
+    StartSuricata,
+    StopSuricata,
+    RecentAlerts {
+        #[serde(default = "default_alert_limit")]
+        limit: usize,
+    },
+    BlockedAccess,
+}
+
+impl DaemonRequest {
+    // Requests that change something, named as they appear in the audit log.
+    pub fn audit_action(&self) -> Option<&'static str> {
+        match self {
+            DaemonRequest::BlockDomains { .. } => Some("block_domains"),
+            DaemonRequest::UnblockDomains { .. } => Some("unblock_domains"),
+            DaemonRequest::RefreshDomains => Some("refresh_blocked_domains"),
+            DaemonRequest::StartSuricata => Some("run_suricata"),
+            DaemonRequest::StopSuricata => Some("kill_suricata"),
+            _ => None,
+        }
+    }
+}
+
+fn default_alert_limit() -> usize {
+    100
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonResponse {
+    pub ok: bool,
+    #[serde(default)]
+    pub result: Value,
+    // Sent whole so the caller gets the same kind the daemon saw, not just the message.
+    #[serde(default)]
+    pub error: Option<AppError>,
+}
+
+impl From<Result<Value, AppError>> for DaemonResponse {
+    fn from(result: Result<Value, AppError>) -> Self {
+        match result {
+            Ok(result) => DaemonResponse { ok: true, result, error: None },
+            Err(e) => DaemonResponse { ok: false, result: Value::Null, error: Some(e) },
+        }
+    }
+}
+
+impl DaemonResponse {
+    pub fn into_result(self) -> Result<Value, AppError> {
+        if self.ok {
+            Ok(self.result)
+        } else {
+            Err(self.error.unwrap_or_else(|| AppError::from("The daemon reported an unknown error")))
+        }
+    }
+}
+
+#[cfg(unix)]
+mod transport {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{UnixListener, UnixStream};
+    use tracing::warn;
+    use super::super::Daemon;
+    use crate::error::AppError;
+    use super::{DaemonRequest, DaemonResponse, CONNECT_TIMEOUT, REQUEST_TIMEOUT};
+
+    // Owner and group only; the systemd unit runs with Group=security-smile so its members can connect.
+    const SOCKET_MODE: u32 = 0o660;
+
+    pub async fn connect(socket: &Path) -> Result<UnixStream, String> {
+        tokio::time::timeout(CONNECT_TIMEOUT, UnixStream::connect(socket))
+            .await
+            .map_err(|_| format!("Timed out connecting to the daemon at {}", socket.display()))?
+            .map_err(|e| format!("Failed to connect to the daemon at {}: {}", socket.display(), e))
+    }
+
+    pub async fn send_request(socket: &Path, request: &DaemonRequest) -> Result<DaemonResponse, String> {
+        let stream = connect(socket).await?;
+        let (reader, mut writer) = stream.into_split();
+
+        let mut line = serde_json::to_string(request)
+            .map_err(|e| format!("Failed to serialize daemon request: {}", e))?;
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await
+            .map_err(|e| format!("Failed to send daemon request: {}", e))?;
+
+        let mut response = String::new();
+        tokio::time::timeout(REQUEST_TIMEOUT, BufReader::new(reader).read_line(&mut response))
+            .await
+            .map_err(|_| "Timed out waiting for the daemon".to_string())?
+            .map_err(|e| format!("Failed to read daemon response: {}", e))?;
+
+        serde_json::from_str(&response)
+            .map_err(|e| format!("Failed to parse daemon response: {}", e))
+    }
+
+    pub async fn bind(socket: &Path) -> Result<UnixListener, String> {
+        if socket.exists() {
+            if connect(socket).await.is_ok() {
+                return Err(format!("A daemon is already listening on {}", socket.display()));
+            }
+            // Left behind by a daemon that didn't shut down cleanly.
+            std::fs::remove_file(socket)
+                .map_err(|e| format!("Failed to remove stale socket {}: {}", socket.display(), e))?;
+        }
+        if let Some(parent) = socket.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create socket directory: {}", e))?;
+        }
+
+        let listener = UnixListener::bind(socket)
+            .map_err(|e| format!("Failed to listen on {}: {}", socket.display(), e))?;
+        std::fs::set_permissions(socket, std::fs::Permissions::from_mode(SOCKET_MODE))
+            .map_err(|e| format!("Failed to set socket permissions: {}", e))?;
+        Ok(listener)
+    }
+
+    pub async fn serve(listener: UnixListener, daemon: Arc<Daemon>) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("Failed to accept daemon connection: {}", e);
+                    continue;
+                }
+            };
+
+            let daemon = daemon.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, &daemon).await {
+                    warn!("Daemon connection failed: {}", e);
+                }
+            });
+        }
+    }
+
+    async fn handle_connection(stream: UnixStream, daemon: &Daemon) -> Result<(), String> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        while let Some(line) = lines.next_line().await.map_err(|e| e.to_string())? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str::<DaemonRequest>(&line) {
+                Ok(request) => DaemonResponse::from(daemon.handle(request).await),
+                Err(e) => DaemonResponse::from(Err(AppError::invalid_input(format!("Invalid daemon request: {}", e)))),
+            };
+
+            let mut reply = serde_json::to_string(&response).map_err(|e| e.to_string())?;
+            reply.push('\n');
+            writer.write_all(reply.as_bytes()).await.map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+mod transport {
+    use std::path::Path;
+    use std::sync::Arc;
+    use super::super::Daemon;
+    use super::{DaemonRequest, DaemonResponse};
+
+    const UNSUPPORTED: &str = "The background service is only supported on Linux and other Unix systems";
+
+    pub struct Listener;
+
+    pub async fn connect(_socket: &Path) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    pub async fn send_request(_socket: &Path, _request: &DaemonRequest) -> Result<DaemonResponse, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    pub async fn bind(_socket: &Path) -> Result<Listener, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    pub async fn serve(_listener: Listener, _daemon: Arc<Daemon>) {}
+}
+
+pub(super) use transport::{bind, serve};
+
+pub async fn send_request(socket: &Path, request: &DaemonRequest) -> Result<Value, AppError> {
+    transport::send_request(socket, request).await?.into_result()
+}
+
+pub async fn is_daemon_running(socket: &Path) -> bool {
+    transport::connect(socket).await.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn requests_use_a_command_tag() {
+        let request: DaemonRequest = serde_json::from_value(json!({
+            "command": "block_domains",
+            "domains": ["ads.example.com"]
+        })).unwrap();
+        assert_eq!(request, DaemonRequest::BlockDomains {
+            domains: vec!["ads.example.com".to_string()],
+            include_subdomains: false,
+        });
+
+        let request: DaemonRequest = serde_json::from_value(json!({ "command": "recent_alerts" })).unwrap();
+        assert_eq!(request, DaemonRequest::RecentAlerts { limit: 100 });
+        assert_eq!(serde_json::to_value(DaemonRequest::Status).unwrap(), json!({ "command": "status" }));
+    }
+
+    #[test]
+    fn error_responses_become_errors() {
+        let response = DaemonResponse::from(Err(AppError::not_found("Domain ads.example.com is not blocked")));
+        assert!(!response.ok);
+
+        // The kind survives the trip over the socket.
+        let response: DaemonResponse = serde_json::from_str(&serde_json::to_string(&response).unwrap()).unwrap();
+        let error = response.into_result().unwrap_err();
+        assert_eq!(error.kind, ErrorKind::NotFound);
+        assert_eq!(error.message, "Domain ads.example.com is not blocked");
+
+        let response: DaemonResponse = serde_json::from_str(r#"{"ok":true,"result":[1,2]}"#).unwrap();
+        assert_eq!(response.into_result().unwrap(), json!([1, 2]));
+    }
+}
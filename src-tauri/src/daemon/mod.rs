@@ -0,0 +1,428 @@
+pub mod ipc;
+
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::de::DeserializeOwned;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use tracing::{info, warn};
+use crate::app_data::events_dir_in;
+use crate::firewall::backend::{select_backend, FirewallBackend};
+use crate::firewall::command_runner::ProcessCommandRunner;
+use crate::firewall::common::{load_notification_settings, BlockedDomains, NotificationSettings};
+use crate::firewall::domain_blocking::batch::{block_and_record, unblock_and_record};
+use crate::firewall::domain_blocking::load_blocked_domains;
+use crate::firewall::domain_blocking::monitor::{
+    check_for_blocked_domain_access,
+    cleanup_old_attempts,
+    BlockedAccess,
+    CLEANUP_INTERVAL_SECONDS,
+    MONITOR_INTERVAL_SECONDS
+};
+use crate::firewall::domain_blocking::refresher::{
+    persist_summary,
+    refresh_blocked_domains_once,
+    RefreshSummary,
+    TICK_SECONDS
+};
+use crate::firewall::domain_blocking::resolver::DnsResolverState;
+use crate::firewall::ownership::{initialize_rule_manifest, RuleManifestState};
+use crate::audit::record;
+use crate::error::{AppError, ErrorKind};
+use crate::firewall::snapshots::{snapshot_before_change, ChangeSource};
+use crate::firewall::tentative::{revert_if_expired, WATCH_INTERVAL_SECONDS};
+use crate::network_traffic_analysis::supervisor::{SuricataStatus, SuricataSupervisor, CHECK_INTERVAL_SECONDS};
+use crate::network_traffic_analysis::store::EventStore;
+use crate::network_traffic_analysis::tailer::EveTailerState;
+use ipc::{default_socket_path, is_daemon_running, send_request, DaemonRequest};
+
+const MAX_BLOCKED_ACCESS: usize = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub started_at: u64,
+    pub data_dir: String,
+    pub firewall_backend: String,
+    pub blocked_domains: usize,
+    pub suricata: SuricataStatus,
+    pub last_ingest_at: Option<u64>,
+    pub last_refresh_at: Option<u64>,
+}
+
+// The long-running service: owns the firewall state, keeps Suricata running, moves events
+// out of eve.json, refreshes blocked domains and watches for access to them.
+pub struct Daemon {
+    data_dir: PathBuf,
+    backend: Arc<dyn FirewallBackend>,
+    blocked: BlockedDomains,
+    manifest: RuleManifestState,
+    resolver: DnsResolverState,
+    notifications: NotificationSettings,
+    store: Arc<EventStore>,
+    events: EveTailerState,
+    started_at: u64,
+    suricata: SuricataSupervisor,
+    last_ingest_at: Mutex<Option<u64>>,
+    last_refresh_at: Mutex<Option<u64>>,
+    blocked_access: Mutex<VecDeque<BlockedAccess>>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl Daemon {
+    pub async fn load(data_dir: PathBuf, supervise_suricata: bool) -> Result<Self, String> {
+        let runner = Arc::new(ProcessCommandRunner::new(data_dir.clone()));
+        let events_dir = events_dir_in(&data_dir);
+        let store = Arc::new(EventStore::in_dir(&events_dir));
+        let daemon = Daemon {
+            backend: select_backend(runner),
+            blocked: BlockedDomains::default(),
+            manifest: RuleManifestState::default(),
+            resolver: DnsResolverState::default(),
+            notifications: load_notification_settings(&data_dir)?,
+            events: EveTailerState::new(&events_dir, store.clone()),
+            store,
+            started_at: now_secs(),
+            suricata: SuricataSupervisor::default(),
+            last_ingest_at: Mutex::new(None),
+            last_refresh_at: Mutex::new(None),
+            blocked_access: Mutex::new(VecDeque::new()),
+            data_dir,
+        };
+        load_blocked_domains(&daemon.data_dir, &daemon.blocked).await?;
+        initialize_rule_manifest(&daemon.data_dir, &daemon.manifest)?;
+        if supervise_suricata {
+            // Retried with backoff when it fails, e.g. before the network is up.
+            if let Err(e) = daemon.suricata.start() {
+                warn!("Failed to start Suricata: {}", e);
+            }
+        }
+        Ok(daemon)
+    }
+
+    fn status(&self) -> DaemonStatus {
+        DaemonStatus {
+            pid: std::process::id(),
+            started_at: self.started_at,
+            data_dir: self.data_dir.display().to_string(),
+            firewall_backend: self.backend.name().to_string(),
+            blocked_domains: self.blocked.domains.lock().unwrap().len(),
+            suricata: self.suricata.status(),
+            last_ingest_at: *self.last_ingest_at.lock().unwrap(),
+            last_refresh_at: *self.last_refresh_at.lock().unwrap(),
+        }
+    }
+
+    pub async fn handle(&self, request: DaemonRequest) -> Result<Value, AppError> {
+        let Some(action) = request.audit_action() else {
+            return self.dispatch(request).await;
+        };
+        let params = serde_json::to_value(&request).unwrap_or(Value::Null);
+        let result = self.dispatch(request).await;
+        record(&self.data_dir, ChangeSource::Daemon, action, params, &result);
+        result
+    }
+
+    async fn dispatch(&self, request: DaemonRequest) -> Result<Value, AppError> {
+        match request {
+            DaemonRequest::Status => to_json(&self.status()),
+            DaemonRequest::ListRules => {
+                let rules = self.backend.list_rules().await
+                    .map_err(|e| AppError::from(e).context("Failed to list firewall rules"))?;
+                to_json(&rules)
+            },
+            DaemonRequest::BlockedDomains => to_json(&*self.blocked.domains.lock().unwrap()),
+            DaemonRequest::BlockDomains { domains, include_subdomains } => {
+                self.snapshot(&format!("block_domains {}", domains.join(", "))).await?;
+                let results = block_and_record(
+                    &self.data_dir,
+                    &self.resolver.resolver(),
+                    self.backend.as_ref(),
+                    &self.blocked,
+                    &self.manifest,
+                    &domains,
+                    include_subdomains
+                ).await;
+                to_json(&results)
+            },
+            DaemonRequest::UnblockDomains { domains } => {
+                self.snapshot(&format!("unblock_domains {}", domains.join(", "))).await?;
+                let results = unblock_and_record(
+                    &self.data_dir,
+                    self.backend.as_ref(),
+                    &self.blocked,
+                    &self.manifest,
+                    &domains
+                ).await;
+                to_json(&results)
+            },
+            DaemonRequest::RefreshDomains => to_json(&self.refresh_domains(true, &mut HashMap::new()).await),
+            DaemonRequest::StartSuricata => to_json(&self.suricata.start()?),
+            DaemonRequest::StopSuricata => {
+                // Waits for Suricata to exit.
+                tokio::task::block_in_place(|| self.suricata.stop())?;
+                to_json(&self.suricata.status())
+            },
+            DaemonRequest::RecentAlerts { limit } => {
+                to_json(&self.store.recent_alerts(limit)?)
+            },
+            DaemonRequest::BlockedAccess => to_json(&*self.blocked_access.lock().unwrap()),
+        }
+    }
+
+    async fn revert_expired_change(&self) -> Result<(), String> {
+        if let Some((change, report)) = revert_if_expired(
+            &self.data_dir,
+            self.backend.as_ref(),
+            &self.manifest,
+            &self.blocked,
+            ChangeSource::Daemon
+        ).await? {
+            info!("Reverted unconfirmed change \"{}\" to snapshot {}", change.description, report.snapshot_id);
+        }
+        Ok(())
+    }
+
+    async fn snapshot(&self, trigger: &str) -> Result<(), AppError> {
+        snapshot_before_change(
+            &self.data_dir,
+            self.backend.as_ref(),
+            &self.manifest,
+            &self.blocked,
+            ChangeSource::Daemon,
+            trigger
+        ).await?;
+        Ok(())
+    }
+
+    async fn refresh_domains(
+        &self,
+        force: bool,
+        retry_at: &mut HashMap<String, u64>
+    ) -> RefreshSummary {
+        let summary = refresh_blocked_domains_once(
+            &self.resolver.resolver(),
+            self.backend.as_ref(),
+            &self.blocked,
+            &self.manifest,
+            retry_at,
+            force
+        ).await;
+        persist_summary(&self.data_dir, &self.blocked, &self.manifest, &summary);
+        *self.last_refresh_at.lock().unwrap() = Some(now_secs());
+        summary
+    }
+
+    fn ingest_events(&self) {
+        // eve.json only exists once Suricata has written something.
+        if self.events.tailer.lock().unwrap().poll().is_ok() {
+            *self.last_ingest_at.lock().unwrap() = Some(now_secs());
+        }
+
+        let found = match check_for_blocked_domain_access(&self.blocked, &self.store, self.notifications.cooldown_seconds) {
+            Ok(found) => found,
+            Err(e) => {
+                warn!("Error checking for blocked domain access: {}", e);
+                return;
+            }
+        };
+
+        let mut recent = self.blocked_access.lock().unwrap();
+        for access in found {
+            warn!("Blocked domain {} accessed at {}", access.domain, access.ip);
+            recent.push_back(access);
+        }
+        while recent.len() > MAX_BLOCKED_ACCESS {
+            recent.pop_front();
+        }
+    }
+}
+
+fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<Value, AppError> {
+    serde_json::to_value(value).map_err(|e| AppError::from(format!("Failed to serialize daemon response: {}", e)))
+}
+
+fn spawn_background_tasks(daemon: &Arc<Daemon>) {
+    let refresher = daemon.clone();
+    tokio::spawn(async move {
+        let mut retry_at = HashMap::new();
+        let mut interval = tokio::time::interval(Duration::from_secs(TICK_SECONDS));
+        loop {
+            interval.tick().await;
+            refresher.refresh_domains(false, &mut retry_at).await;
+        }
+    });
+
+    let ingest = daemon.clone();
+    tokio::spawn(async move {
+        let mut last_cleanup = now_secs();
+        let mut interval = tokio::time::interval(Duration::from_secs(MONITOR_INTERVAL_SECONDS));
+        loop {
+            interval.tick().await;
+            let daemon = ingest.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || daemon.ingest_events()).await {
+                warn!("Event ingestion failed: {}", e);
+            }
+            if now_secs().saturating_sub(last_cleanup) > CLEANUP_INTERVAL_SECONDS {
+                cleanup_old_attempts();
+                last_cleanup = now_secs();
+            }
+        }
+    });
+
+    let reverter = daemon.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(WATCH_INTERVAL_SECONDS));
+        loop {
+            interval.tick().await;
+            if let Err(e) = reverter.revert_expired_change().await {
+                warn!("Failed to revert tentative change: {}", e);
+            }
+        }
+    });
+
+    let supervisor = daemon.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(CHECK_INTERVAL_SECONDS));
+        loop {
+            interval.tick().await;
+            let daemon = supervisor.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || daemon.suricata.check()).await {
+                warn!("Suricata supervision failed: {}", e);
+            }
+        }
+    });
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = terminate.recv() => {},
+                }
+            },
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+// Runs the service until it receives SIGTERM or Ctrl-C.
+pub async fn run_daemon(data_dir: PathBuf, socket: &Path, supervise_suricata: bool) -> Result<(), String> {
+    let listener = ipc::bind(socket).await?;
+    let daemon = Arc::new(Daemon::load(data_dir, supervise_suricata).await?);
+    info!("Daemon listening on {}", socket.display());
+
+    spawn_background_tasks(&daemon);
+    tokio::select! {
+        _ = ipc::serve(listener, daemon.clone()) => {},
+        _ = shutdown_signal() => {},
+    }
+
+    let _ = daemon.suricata.stop();
+    let _ = std::fs::remove_file(socket);
+    Ok(())
+}
+
+// Lets the desktop app leave background work to the service when one is running.
+pub async fn daemon_running() -> bool {
+    is_daemon_running(&default_socket_path()).await
+}
+
+// The service's socket while it runs. The desktop app sends firewall and domain changes there
+// then, so the blocked domains and their rules are only kept in the service's data directory.
+pub async fn running_daemon() -> Option<PathBuf> {
+    let socket = default_socket_path();
+    is_daemon_running(&socket).await.then_some(socket)
+}
+
+// Changes the service can't make for the desktop app are refused while it runs: they would act
+// on the desktop's own copy of the blocked domains and rule manifest, which the service doesn't see.
+pub async fn refuse_while_daemon_runs(action: &str) -> Result<(), AppError> {
+    if running_daemon().await.is_some() {
+        return Err(AppError::new(
+            ErrorKind::Conflict,
+            format!("Can't {} while the Security Smile service is running; it manages the firewall. Stop the service first.", action)
+        ));
+    }
+    Ok(())
+}
+
+pub async fn forward<T: DeserializeOwned>(socket: &Path, request: &DaemonRequest) -> Result<T, AppError> {
+    let value = send_request(socket, request).await?;
+    serde_json::from_value(value)
+        .map_err(|e| AppError::new(ErrorKind::ParseError, format!("Failed to parse daemon response: {}", e)))
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_daemon_status() -> Result<Option<DaemonStatus>, AppError> {
+    let socket = default_socket_path();
+    if !is_daemon_running(&socket).await {
+        return Ok(None);
+    }
+
+    let status = send_request(&socket, &DaemonRequest::Status).await?;
+    let status = serde_json::from_value(status)
+        .map_err(|e| AppError::new(ErrorKind::ParseError, format!("Failed to parse daemon status: {}", e)))?;
+    Ok(Some(status))
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn send_daemon_request(request: DaemonRequest) -> Result<Value, AppError> {
+    send_request(&default_socket_path(), &request).await
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::network_traffic_analysis::supervisor::SuricataState;
+
+    #[tokio::test]
+    async fn serves_requests_over_the_socket() {
+        let dir = std::env::temp_dir().join(format!("security-smile-daemon-{}", std::process::id()));
+        let socket = dir.join("daemon.sock");
+        std::fs::create_dir_all(&dir).unwrap();
+        let settings = NotificationSettings { cooldown_seconds: 5, ..NotificationSettings::default() };
+        crate::firewall::common::save_notification_settings(&dir, &settings).unwrap();
+
+        let daemon = Arc::new(Daemon::load(dir.clone(), false).await.unwrap());
+        daemon.blocked.add_domain("ads.example.com");
+        assert_eq!(daemon.notifications, settings);
+        assert!(daemon.store.recent_alerts(1).unwrap().is_empty());
+        assert!(dir.join("events").is_dir());
+
+        let listener = ipc::bind(&socket).await.unwrap();
+        tokio::spawn(ipc::serve(listener, daemon));
+
+        let status: DaemonStatus = serde_json::from_value(
+            send_request(&socket, &DaemonRequest::Status).await.unwrap()
+        ).unwrap();
+        assert_eq!(status.blocked_domains, 1);
+        assert_eq!(status.suricata.state, SuricataState::Stopped);
+
+        let domains = send_request(&socket, &DaemonRequest::BlockedDomains).await.unwrap();
+        assert_eq!(domains, serde_json::json!(["ads.example.com"]));
+
+        assert!(ipc::bind(&socket).await.unwrap_err().contains("already listening"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
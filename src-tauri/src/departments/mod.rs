@@ -0,0 +1,480 @@
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use ipnet::IpNet;
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use tauri::{AppHandle, State};
+use crate::app_data::{app_data_dir, data_file_path};
+use crate::audit::{audited, record};
+use crate::daemon::refuse_while_daemon_runs;
+use crate::firewall::backend::FirewallBackendState;
+use crate::firewall::common::BlockedDomains;
+use crate::firewall::ownership::RuleManifestState;
+use crate::firewall::rule_model::check_script_text;
+use crate::firewall::snapshots::ChangeSource;
+use crate::error::AppError;
+use policy::ConnectionRule;
+
+pub mod policy;
+
+const DEPARTMENTS_FILE: &str = "departments.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum DepartmentProtocol {
+    All,
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DepartmentAction {
+    Allow,
+    Block,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Device {
+    pub name: String,
+    // When known, the address has to fall inside the department's subnet.
+    #[serde(default)]
+    pub address: Option<IpAddr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Department {
+    pub id: String,
+    pub name: String,
+    pub subnet: IpNet,
+    pub protocol: DepartmentProtocol,
+    pub action: DepartmentAction,
+    pub description: String,
+    pub devices: Vec<Device>,
+}
+
+// What the UI sends to create or edit a department; devices are managed separately.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepartmentInput {
+    pub name: String,
+    pub subnet: String,
+    pub protocol: DepartmentProtocol,
+    pub action: DepartmentAction,
+    #[serde(default)]
+    pub description: String,
+}
+
+pub fn parse_subnet(subnet: &str) -> Result<IpNet, String> {
+    let subnet = subnet.trim();
+    let net = subnet
+        .parse::<IpNet>()
+        .or_else(|_| subnet.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("Invalid subnet: {}", subnet))?;
+
+    if net.trunc() != net {
+        return Err(format!("{} has host bits set; did you mean {}?", subnet, net.trunc()));
+    }
+    Ok(net)
+}
+
+pub(crate) fn overlaps(a: &IpNet, b: &IpNet) -> bool {
+    a.contains(&b.network()) || b.contains(&a.network())
+}
+
+fn new_department_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DepartmentStore {
+    pub departments: Vec<Department>,
+    // The last applied inter-department policy.
+    #[serde(default)]
+    pub connections: Vec<ConnectionRule>,
+}
+
+impl DepartmentStore {
+    fn get_mut(&mut self, id: &str) -> Result<&mut Department, AppError> {
+        self.departments
+            .iter_mut()
+            .find(|d| d.id == id)
+            .ok_or_else(|| AppError::not_found(format!("Department not found: {}", id)))
+    }
+
+    // Whether an edit changes the rules generated for the connections that use the department.
+    fn changes_policy(&self, before: &Department, after: &Department) -> bool {
+        let used = self.connections.iter().any(|c| {
+            c.from.eq_ignore_ascii_case(&after.name) || c.to.eq_ignore_ascii_case(&after.name)
+        });
+        used && (before.name != after.name || before.subnet != after.subnet || before.protocol != after.protocol)
+    }
+
+    // The department a device belongs to; device names are unique across departments.
+    pub fn device_department(&self, device_name: &str) -> Option<&Department> {
+        self.departments
+            .iter()
+            .find(|d| d.devices.iter().any(|device| device.name.eq_ignore_ascii_case(device_name)))
+    }
+
+    // Checks a department's name and subnet against every other department.
+    fn validate(&self, id: Option<&str>, input: &DepartmentInput) -> Result<(String, IpNet), AppError> {
+        let name = input.name.trim();
+        if name.is_empty() {
+            return Err(AppError::invalid_input("A department name is required"));
+        }
+        // Policy rule names are built from department names.
+        check_script_text("A department name", name).map_err(AppError::invalid_input)?;
+        let subnet = parse_subnet(&input.subnet).map_err(AppError::invalid_input)?;
+
+        for other in self.departments.iter().filter(|d| Some(d.id.as_str()) != id) {
+            if other.name.eq_ignore_ascii_case(name) {
+                return Err(AppError::invalid_input(format!("A department named {} already exists", other.name)));
+            }
+            if overlaps(&other.subnet, &subnet) {
+                return Err(AppError::invalid_input(format!("Subnet {} overlaps {} ({})", subnet, other.subnet, other.name)));
+            }
+        }
+
+        Ok((name.to_string(), subnet))
+    }
+
+    pub fn create(&mut self, input: &DepartmentInput) -> Result<Department, AppError> {
+        let (name, subnet) = self.validate(None, input)?;
+        let department = Department {
+            id: new_department_id(),
+            name,
+            subnet,
+            protocol: input.protocol,
+            action: input.action,
+            description: input.description.trim().to_string(),
+            devices: Vec::new(),
+        };
+        self.departments.push(department.clone());
+        Ok(department)
+    }
+
+    pub fn update(&mut self, id: &str, input: &DepartmentInput) -> Result<Department, AppError> {
+        let (name, subnet) = self.validate(Some(id), input)?;
+        let department = self.get_mut(id)?;
+
+        if let Some(device) = department.devices.iter().find(|d| d.address.is_some_and(|a| !subnet.contains(&a))) {
+            return Err(AppError::invalid_input(format!("Device {} would fall outside {}", device.name, subnet)));
+        }
+
+        let old_name = std::mem::replace(&mut department.name, name.clone());
+        department.subnet = subnet;
+        department.protocol = input.protocol;
+        department.action = input.action;
+        department.description = input.description.trim().to_string();
+        let department = department.clone();
+
+        for connection in &mut self.connections {
+            for end in [&mut connection.from, &mut connection.to] {
+                if end.eq_ignore_ascii_case(&old_name) {
+                    *end = name.clone();
+                }
+            }
+        }
+        Ok(department)
+    }
+
+    pub fn delete(&mut self, id: &str) -> Result<Department, AppError> {
+        let index = self.departments
+            .iter()
+            .position(|d| d.id == id)
+            .ok_or_else(|| AppError::not_found(format!("Department not found: {}", id)))?;
+
+        let name = &self.departments[index].name;
+        if self.connections.iter().any(|c| c.from.eq_ignore_ascii_case(name) || c.to.eq_ignore_ascii_case(name)) {
+            return Err(AppError::invalid_input(format!("{} is still used by connection rules", name)));
+        }
+        Ok(self.departments.remove(index))
+    }
+
+    pub fn add_device(&mut self, department_id: &str, device: Device) -> Result<Department, AppError> {
+        let name = device.name.trim().to_string();
+        if name.is_empty() {
+            return Err(AppError::invalid_input("A device name is required"));
+        }
+        if let Some(owner) = self.device_department(&name) {
+            return Err(AppError::invalid_input(format!("Device {} already belongs to {}", name, owner.name)));
+        }
+
+        let department = self.get_mut(department_id)?;
+        if let Some(address) = device.address {
+            if !department.subnet.contains(&address) {
+                return Err(AppError::invalid_input(format!("{} is not in {}'s subnet {}", address, department.name, department.subnet)));
+            }
+        }
+
+        department.devices.push(Device { name, address: device.address });
+        Ok(department.clone())
+    }
+
+    pub fn remove_device(&mut self, department_id: &str, device_name: &str) -> Result<Department, AppError> {
+        let department = self.get_mut(department_id)?;
+        let before = department.devices.len();
+        department.devices.retain(|d| !d.name.eq_ignore_ascii_case(device_name));
+        if department.devices.len() == before {
+            return Err(AppError::not_found(format!("Device {} is not in {}", device_name, department.name)));
+        }
+        Ok(department.clone())
+    }
+}
+
+#[derive(Default)]
+pub struct DepartmentState {
+    pub store: Arc<Mutex<DepartmentStore>>,
+}
+
+impl DepartmentState {
+    // Applies `change` and persists the result; nothing is kept if the change is rejected.
+    pub(crate) fn modify<T>(&self, data_dir: &Path, change: impl FnOnce(&mut DepartmentStore) -> Result<T, AppError>) -> Result<T, AppError> {
+        let (result, store) = {
+            let mut store = self.store.lock().unwrap();
+            let result = change(&mut store)?;
+            (result, store.clone())
+        };
+        save_departments_to_file(data_dir, &store)?;
+        Ok(result)
+    }
+}
+
+fn save_departments_to_file(data_dir: &Path, store: &DepartmentStore) -> Result<(), String> {
+    let file_path = data_file_path(data_dir, DEPARTMENTS_FILE);
+    let json = serde_json::to_string_pretty(store)
+        .map_err(|e| format!("Failed to serialize departments: {}", e))?;
+
+    fs::write(&file_path, json)
+        .map_err(|e| format!("Failed to write to file: {}", e))
+}
+
+fn load_departments_from_file(data_dir: &Path) -> Result<DepartmentStore, String> {
+    let file_path = data_file_path(data_dir, DEPARTMENTS_FILE);
+
+    if !file_path.exists() {
+        return Ok(DepartmentStore::default());
+    }
+
+    let json = fs::read_to_string(&file_path)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+
+    serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to deserialize departments: {}", e))
+}
+
+pub fn initialize_departments(data_dir: &Path, state: &DepartmentState) -> Result<(), String> {
+    let store = load_departments_from_file(data_dir)?;
+    *state.store.lock().unwrap() = store;
+    Ok(())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_departments(state: State<'_, DepartmentState>) -> Result<Vec<Department>, AppError> {
+    Ok(state.store.lock().unwrap().departments.clone())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn create_department(
+    app: AppHandle,
+    department: DepartmentInput,
+    state: State<'_, DepartmentState>
+) -> Result<Department, AppError> {
+    let data_dir = app_data_dir(&app);
+    let result = state.modify(&data_dir, |store| store.create(&department));
+    record(&data_dir, ChangeSource::Desktop, "create_department", json!({ "department": department }), &result);
+    result
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn update_department(
+    app: AppHandle,
+    id: String,
+    department: DepartmentInput,
+    state: State<'_, DepartmentState>,
+    backend: State<'_, FirewallBackendState>,
+    manifest: State<'_, RuleManifestState>,
+    blocked: State<'_, BlockedDomains>
+) -> Result<Department, AppError> {
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "id": id, "department": department });
+    audited(&data_dir, ChangeSource::Desktop, "update_department", params, async {
+        // Renaming or moving a department that connection rules use changes their firewall rules,
+        // so those are replaced before the edit is saved.
+        let (edited, changes_policy) = {
+            let mut edited = state.store.lock().unwrap().clone();
+            let before = edited.get_mut(&id)?.clone();
+            let after = edited.update(&id, &department)?;
+            let changes_policy = edited.changes_policy(&before, &after);
+            (edited, changes_policy)
+        };
+        if changes_policy {
+            refuse_while_daemon_runs("change departments that connection rules use").await?;
+            policy::reapply_department_policy(&data_dir, &edited, &backend, &manifest, &blocked).await?;
+        }
+        state.modify(&data_dir, |store| store.update(&id, &department))
+    }).await
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn delete_department(
+    app: AppHandle,
+    id: String,
+    state: State<'_, DepartmentState>
+) -> Result<Department, AppError> {
+    let data_dir = app_data_dir(&app);
+    let result = state.modify(&data_dir, |store| store.delete(&id));
+    record(&data_dir, ChangeSource::Desktop, "delete_department", json!({ "id": id }), &result);
+    result
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn add_department_device(
+    app: AppHandle,
+    department_id: String,
+    device: Device,
+    state: State<'_, DepartmentState>
+) -> Result<Department, AppError> {
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "department_id": department_id, "device": device });
+    let result = state.modify(&data_dir, |store| store.add_device(&department_id, device));
+    record(&data_dir, ChangeSource::Desktop, "add_department_device", params, &result);
+    result
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn remove_department_device(
+    app: AppHandle,
+    department_id: String,
+    device_name: String,
+    state: State<'_, DepartmentState>
+) -> Result<Department, AppError> {
+    let data_dir = app_data_dir(&app);
+    let result = state.modify(&data_dir, |store| store.remove_device(&department_id, &device_name));
+    record(&data_dir, ChangeSource::Desktop, "remove_department_device", json!({ "department_id": department_id, "device_name": device_name }), &result);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    fn input(name: &str, subnet: &str) -> DepartmentInput {
+        DepartmentInput {
+            name: name.to_string(),
+            subnet: subnet.to_string(),
+            protocol: DepartmentProtocol::Tcp,
+            action: DepartmentAction::Allow,
+            description: String::new(),
+        }
+    }
+
+    fn device(name: &str, address: Option<&str>) -> Device {
+        Device { name: name.to_string(), address: address.map(|a| a.parse().unwrap()) }
+    }
+
+    #[test]
+    fn parses_subnets() {
+        assert_eq!(parse_subnet(" 192.168.1.0/24 ").unwrap().to_string(), "192.168.1.0/24");
+        assert_eq!(parse_subnet("10.0.0.7").unwrap().to_string(), "10.0.0.7/32");
+        assert_eq!(parse_subnet("2001:db8::/48").unwrap().to_string(), "2001:db8::/48");
+        assert!(parse_subnet("192.168.1.0/33").is_err());
+        assert!(parse_subnet("it-department").is_err());
+        assert!(parse_subnet("192.168.1.10/24").unwrap_err().contains("did you mean 192.168.1.0/24"));
+    }
+
+    #[test]
+    fn rejects_overlapping_subnets_and_duplicate_names() {
+        let mut store = DepartmentStore::default();
+        let it = store.create(&input("IT", "192.168.1.0/24")).unwrap();
+
+        assert!(store.create(&input("Lab", "192.168.0.0/16")).unwrap_err().message.contains("overlaps 192.168.1.0/24 (IT)"));
+        assert!(store.create(&input("Printers", "192.168.1.128/25")).unwrap_err().message.contains("overlaps"));
+        assert!(store.create(&input("it", "192.168.2.0/24")).unwrap_err().message.contains("already exists"));
+        assert!(store.create(&input("IT$(whoami)", "192.168.3.0/24")).unwrap_err().message.contains("may not contain '$'"));
+        assert!(store.create(&input("BA", "192.168.2.0/24")).is_ok());
+        // A department doesn't conflict with itself when edited.
+        assert!(store.update(&it.id, &input("IT", "192.168.1.0/25")).is_ok());
+        assert_eq!(store.departments.len(), 2);
+    }
+
+    #[test]
+    fn devices_belong_to_one_department_inside_its_subnet() {
+        let mut store = DepartmentStore::default();
+        let it = store.create(&input("IT", "192.168.1.0/24")).unwrap();
+        let ba = store.create(&input("BA", "192.168.2.0/24")).unwrap();
+
+        store.add_device(&it.id, device("Server-01", Some("192.168.1.10"))).unwrap();
+        store.add_device(&it.id, device("Printer-IT", None)).unwrap();
+
+        assert!(store.add_device(&ba.id, device("server-01", None)).unwrap_err().message.contains("already belongs to IT"));
+        assert!(store.add_device(&ba.id, device("Desktop-01", Some("192.168.1.20"))).unwrap_err().message.contains("not in BA's subnet"));
+        assert_eq!(store.device_department("Printer-IT").unwrap().name, "IT");
+
+        assert!(store.update(&it.id, &input("IT", "192.168.1.0/29")).unwrap_err().message.contains("Server-01"));
+
+        let it = store.remove_device(&it.id, "SERVER-01").unwrap();
+        assert_eq!(it.devices, vec![device("Printer-IT", None)]);
+        assert!(store.remove_device(&it.id, "Server-01").is_err());
+    }
+
+    #[test]
+    fn delete_removes_the_department() {
+        let mut store = DepartmentStore::default();
+        let it = store.create(&input("IT", "192.168.1.0/24")).unwrap();
+
+        assert_eq!(store.delete(&it.id).unwrap().name, "IT");
+        assert_eq!(store.delete(&it.id).unwrap_err().kind, ErrorKind::NotFound);
+        assert!(store.create(&input("Lab", "192.168.0.0/16")).is_ok());
+    }
+
+    #[test]
+    fn connection_rules_follow_renames_and_block_deletes() {
+        let mut store = DepartmentStore::default();
+        let it = store.create(&input("IT", "192.168.1.0/24")).unwrap();
+        store.create(&input("BA", "192.168.2.0/24")).unwrap();
+        store.connections.push(ConnectionRule {
+            from: "BA".to_string(),
+            to: "IT".to_string(),
+            port: "443".to_string(),
+            direction: policy::ConnectionDirection::Inbound,
+            action: DepartmentAction::Allow,
+        });
+
+        let before = it.clone();
+        let renamed = store.update(&it.id, &input("Engineering", "192.168.1.0/24")).unwrap();
+        assert_eq!(store.connections[0].to, "Engineering");
+        assert!(store.changes_policy(&before, &renamed));
+        let described = store.update(&it.id, &DepartmentInput { description: "Servers".to_string(), ..input("Engineering", "192.168.1.0/24") }).unwrap();
+        assert!(!store.changes_policy(&renamed, &described));
+        let moved = store.update(&it.id, &input("Engineering", "192.168.4.0/24")).unwrap();
+        assert!(store.changes_policy(&described, &moved));
+        assert!(store.delete(&it.id).unwrap_err().message.contains("still used by connection rules"));
+    }
+
+    #[test]
+    fn persists_in_the_ui_shape() {
+        let mut store = DepartmentStore::default();
+        let it = store.create(&input("IT", "192.168.1.0/24")).unwrap();
+        store.add_device(&it.id, device("Server-01", Some("192.168.1.10"))).unwrap();
+
+        let json = serde_json::to_value(&store).unwrap();
+        let department = &json["departments"][0];
+        assert_eq!(department["subnet"], "192.168.1.0/24");
+        assert_eq!(department["protocol"], "TCP");
+        assert_eq!(department["action"], "Allow");
+        assert_eq!(department["devices"][0]["address"], "192.168.1.10");
+
+        let restored: DepartmentStore = serde_json::from_value(json).unwrap();
+        assert_eq!(restored, store);
+    }
+}
@@ -0,0 +1,470 @@
+use std::path::Path;
+use ipnet::IpNet;
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use tauri::{AppHandle, State};
+use crate::app_data::app_data_dir;
+use crate::audit::audited;
+use crate::daemon::refuse_while_daemon_runs;
+use crate::firewall::backend::FirewallBackendState;
+use crate::firewall::common::{BlockedDomains, FirewallRuleInfo};
+use crate::firewall::ownership::{RuleManifestState, POLICY_RULE_PREFIX};
+use crate::firewall::rule_model::{parse_list, AddressSpec, PortSpec};
+use crate::firewall::snapshots::{snapshot_before_change, ChangeSource};
+use super::{overlaps, Department, DepartmentAction, DepartmentProtocol, DepartmentState, DepartmentStore};
+use crate::error::AppError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnectionDirection {
+    Both,
+    Inbound,
+    Outbound,
+}
+
+// Traffic from the `from` department to the `to` department, on `port` at the `to` end
+// ("" for any port). Departments are referenced by name, as in the UI.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionRule {
+    pub from: String,
+    pub to: String,
+    #[serde(default)]
+    pub port: String,
+    pub direction: ConnectionDirection,
+    pub action: DepartmentAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyConflict {
+    // Indexes into the connection rule list.
+    pub first: usize,
+    pub second: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PolicyPlan {
+    // In the order they should be evaluated: blocks before allows.
+    pub rules: Vec<FirewallRuleInfo>,
+    pub conflicts: Vec<PolicyConflict>,
+    // Previously generated rules the plan replaces.
+    pub removed: Vec<String>,
+    pub applied: bool,
+}
+
+// One direction of one connection rule, before rules that differ only in port are merged.
+#[derive(Debug, Clone)]
+struct PolicyEntry {
+    source: usize,
+    from: String,
+    to: String,
+    inbound: bool,
+    local: IpNet,
+    remote: IpNet,
+    protocol: String,
+    ports: Vec<PortSpec>,
+    action: DepartmentAction,
+}
+
+fn find_department<'a>(store: &'a DepartmentStore, name: &str) -> Result<&'a Department, String> {
+    store.departments
+        .iter()
+        .find(|d| d.name.eq_ignore_ascii_case(name.trim()))
+        .ok_or_else(|| format!("Unknown department: {}", name))
+}
+
+// The firewall protocols a connection to `to` covers. Ports only exist for TCP and UDP,
+// so a port on an "ALL" department means both.
+fn protocols(to: &Department, has_ports: bool) -> Result<Vec<String>, String> {
+    let protocols = match (to.protocol, has_ports) {
+        (DepartmentProtocol::Tcp, _) => vec!["TCP"],
+        (DepartmentProtocol::Udp, _) => vec!["UDP"],
+        (DepartmentProtocol::All, false) => vec!["Any"],
+        (DepartmentProtocol::All, true) => vec!["TCP", "UDP"],
+        (DepartmentProtocol::Icmp, false) if to.subnet.addr().is_ipv4() => vec!["ICMPv4"],
+        (DepartmentProtocol::Icmp, false) => vec!["ICMPv6"],
+        (DepartmentProtocol::Icmp, true) => {
+            return Err(format!("{} only allows ICMP, which has no ports", to.name));
+        },
+    };
+    Ok(protocols.into_iter().map(String::from).collect())
+}
+
+fn expand(store: &DepartmentStore, connections: &[ConnectionRule]) -> Result<Vec<PolicyEntry>, String> {
+    let mut entries = Vec::new();
+
+    for (index, connection) in connections.iter().enumerate() {
+        let from = find_department(store, &connection.from)?;
+        let to = find_department(store, &connection.to)?;
+        if from.id == to.id {
+            return Err(format!("Rule {} connects {} to itself", index + 1, from.name));
+        }
+        if from.subnet.addr().is_ipv4() != to.subnet.addr().is_ipv4() {
+            return Err(format!("{} and {} use different address families", from.name, to.name));
+        }
+        let ports: Vec<PortSpec> = parse_list(&connection.port)
+            .map_err(|e| format!("Rule {}: {}", index + 1, e))?;
+        // Windows port keywords only mean something to netsh; departments take plain numbers.
+        for port in &ports {
+            match port {
+                PortSpec::Keyword(keyword) => {
+                    return Err(format!("Rule {}: {} is not a port number or range", index + 1, keyword));
+                },
+                PortSpec::Range { start: 0, .. } => return Err(format!("Rule {}: Invalid port: 0", index + 1)),
+                PortSpec::Range { .. } => {},
+            }
+        }
+
+        let directions: &[bool] = match connection.direction {
+            ConnectionDirection::Both => &[true, false],
+            ConnectionDirection::Inbound => &[true],
+            ConnectionDirection::Outbound => &[false],
+        };
+        for protocol in protocols(to, !ports.is_empty())? {
+            for &inbound in directions {
+                // Inbound: this host is in `to`, receiving from `from`. Outbound: this host
+                // is in `from`, sending to `to`.
+                let (local, remote) = if inbound { (to.subnet, from.subnet) } else { (from.subnet, to.subnet) };
+                entries.push(PolicyEntry {
+                    source: index,
+                    from: from.name.clone(),
+                    to: to.name.clone(),
+                    inbound,
+                    local,
+                    remote,
+                    protocol: protocol.clone(),
+                    ports: ports.clone(),
+                    action: connection.action,
+                });
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+fn ports_overlap(a: &[PortSpec], b: &[PortSpec]) -> bool {
+    if a.is_empty() || b.is_empty() {
+        return true;
+    }
+    a.iter().any(|x| b.iter().any(|y| match (x, y) {
+        (PortSpec::Range { start: s1, end: e1 }, PortSpec::Range { start: s2, end: e2 }) => s1 <= e2 && s2 <= e1,
+        (PortSpec::Keyword(k1), PortSpec::Keyword(k2)) => k1.eq_ignore_ascii_case(k2),
+        _ => false,
+    }))
+}
+
+fn protocols_overlap(a: &str, b: &str) -> bool {
+    a == "Any" || b == "Any" || a == b
+}
+
+fn find_conflicts(entries: &[PolicyEntry]) -> Vec<PolicyConflict> {
+    let mut conflicts: Vec<PolicyConflict> = Vec::new();
+
+    for (i, a) in entries.iter().enumerate() {
+        for b in &entries[i + 1..] {
+            let overlapping = a.source != b.source
+                && a.action != b.action
+                && a.inbound == b.inbound
+                && overlaps(&a.local, &b.local)
+                && overlaps(&a.remote, &b.remote)
+                && protocols_overlap(&a.protocol, &b.protocol)
+                && ports_overlap(&a.ports, &b.ports);
+            let reported = conflicts.iter().any(|c| c.first == a.source && c.second == b.source);
+            if overlapping && !reported {
+                conflicts.push(PolicyConflict {
+                    first: a.source,
+                    second: b.source,
+                    message: format!(
+                        "Rule {} ({:?} {} -> {}) and rule {} ({:?} {} -> {}) overlap with different actions",
+                        a.source + 1, a.action, a.from, a.to,
+                        b.source + 1, b.action, b.from, b.to
+                    ),
+                });
+            }
+        }
+    }
+
+    conflicts
+}
+
+fn rule_name(entry: &PolicyEntry) -> String {
+    format!(
+        "{}{}-{}-{:?}-{}-{}",
+        POLICY_RULE_PREFIX,
+        entry.from,
+        entry.to,
+        entry.action,
+        if entry.inbound { "In" } else { "Out" },
+        entry.protocol
+    )
+}
+
+// Whether `a` already matches all of `b`'s traffic with the same action.
+fn subsumes(a: &PolicyEntry, b: &PolicyEntry) -> bool {
+    a.inbound == b.inbound
+        && a.action == b.action
+        && a.local.contains(&b.local)
+        && a.remote.contains(&b.remote)
+        && a.protocol == "Any"
+        && b.protocol != "Any"
+        && a.ports.is_empty()
+}
+
+// Entries that share everything but the port become one rule with a port list, and a rule
+// for any port or protocol swallows the more specific ones.
+fn merge(entries: Vec<PolicyEntry>) -> Vec<PolicyEntry> {
+    let mut merged: Vec<PolicyEntry> = Vec::new();
+
+    for entry in entries {
+        match merged.iter_mut().find(|m| rule_name(m) == rule_name(&entry)) {
+            Some(existing) if existing.ports.is_empty() => {},
+            Some(existing) if entry.ports.is_empty() => existing.ports.clear(),
+            Some(existing) => {
+                for port in entry.ports {
+                    if !existing.ports.contains(&port) {
+                        existing.ports.push(port);
+                    }
+                }
+            },
+            None => merged.push(entry),
+        }
+    }
+
+    let kept: Vec<bool> = merged.iter().map(|b| !merged.iter().any(|a| subsumes(a, b))).collect();
+    merged.into_iter().zip(kept).filter_map(|(entry, keep)| keep.then_some(entry)).collect()
+}
+
+fn to_rule_info(entry: &PolicyEntry) -> FirewallRuleInfo {
+    FirewallRuleInfo {
+        name: rule_name(entry),
+        description: format!("Department policy: {} -> {}", entry.from, entry.to),
+        protocol: entry.protocol.clone(),
+        direction: if entry.inbound { "Inbound".to_string() } else { "Outbound".to_string() },
+        action: format!("{:?}", entry.action),
+        enabled: true,
+        local_addresses: vec![AddressSpec::Network(entry.local)],
+        remote_addresses: vec![AddressSpec::Network(entry.remote)],
+        local_ports: if entry.inbound { entry.ports.clone() } else { Vec::new() },
+        remote_ports: if entry.inbound { Vec::new() } else { entry.ports.clone() },
+        ..Default::default()
+    }
+}
+
+// Turns connection rules into concrete firewall rules. Unknown departments or bad ports are
+// errors; overlapping rules with different actions are reported as conflicts in the plan.
+pub fn compile_policy(store: &DepartmentStore, connections: &[ConnectionRule]) -> Result<PolicyPlan, String> {
+    let entries = expand(store, connections)?;
+    let conflicts = find_conflicts(&entries);
+
+    let mut merged = merge(entries);
+    merged.sort_by_key(|e| (e.action == DepartmentAction::Allow, rule_name(e)));
+
+    Ok(PolicyPlan {
+        rules: merged.iter().map(to_rule_info).collect(),
+        conflicts,
+        ..Default::default()
+    })
+}
+
+// Swaps the previously generated rules for the plan's in one elevated run, after taking a snapshot.
+async fn replace_policy_rules(
+    data_dir: &Path,
+    backend: &FirewallBackendState,
+    manifest: &RuleManifestState,
+    blocked: &BlockedDomains,
+    plan: &mut PolicyPlan,
+    action: &str
+) -> Result<(), AppError> {
+    snapshot_before_change(data_dir, backend.backend.as_ref(), manifest, blocked, ChangeSource::Desktop, action).await?;
+    backend.backend.apply_rule_changes(&plan.removed, &plan.rules).await
+        .map_err(|e| AppError::from(e).context("Failed to apply department policy"))?;
+    plan.applied = true;
+
+    manifest.set_policy_rules(plan.rules.iter().map(|r| r.name.clone()).collect());
+    manifest.save(data_dir)?;
+    Ok(())
+}
+
+// Regenerates the rules of the applied connections from `store`, which holds a department edit
+// that hasn't been saved yet. Nobody reviews this plan, so conflicts are errors.
+pub(crate) async fn reapply_department_policy(
+    data_dir: &Path,
+    store: &DepartmentStore,
+    backend: &FirewallBackendState,
+    manifest: &RuleManifestState,
+    blocked: &BlockedDomains
+) -> Result<PolicyPlan, AppError> {
+    let mut plan = compile_policy(store, &store.connections)
+        .map_err(|e| AppError::invalid_input(format!("The connection rules would no longer apply: {}", e)))?;
+    if let Some(conflict) = plan.conflicts.first() {
+        return Err(AppError::invalid_input(format!("The connection rules would conflict: {}", conflict.message)));
+    }
+    plan.removed = manifest.policy_rules();
+
+    replace_policy_rules(data_dir, backend, manifest, blocked, &mut plan, "update_department").await?;
+    Ok(plan)
+}
+
+// Compiles `connections` and, unless `dry_run` is set, replaces the previously generated
+// rules with the new ones in one elevated run and saves the connections. A plan with
+// conflicts is never applied.
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn apply_department_policy(
+    app: AppHandle,
+    connections: Vec<ConnectionRule>,
+    dry_run: Option<bool>,
+    state: State<'_, DepartmentState>,
+    backend: State<'_, FirewallBackendState>,
+    manifest: State<'_, RuleManifestState>,
+    blocked: State<'_, BlockedDomains>
+) -> Result<PolicyPlan, AppError> {
+    let mut plan = {
+        let store = state.store.lock().unwrap();
+        compile_policy(&store, &connections).map_err(AppError::invalid_input)?
+    };
+    plan.removed = manifest.policy_rules();
+
+    if dry_run.unwrap_or(false) || !plan.conflicts.is_empty() {
+        return Ok(plan);
+    }
+
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "connections": connections });
+    audited(&data_dir, ChangeSource::Desktop, "apply_department_policy", params, async {
+        refuse_while_daemon_runs("apply department policies").await?;
+        replace_policy_rules(&data_dir, &backend, &manifest, &blocked, &mut plan, "apply_department_policy").await?;
+        state.modify(&data_dir, |store| {
+            store.connections = connections;
+            Ok(())
+        })?;
+
+        Ok(plan)
+    }).await
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_connection_rules(state: State<'_, DepartmentState>) -> Result<Vec<ConnectionRule>, AppError> {
+    Ok(state.store.lock().unwrap().connections.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::DepartmentInput;
+    use crate::firewall::rule_model::format_list;
+
+    fn store() -> DepartmentStore {
+        let mut store = DepartmentStore::default();
+        for (name, subnet, protocol) in [
+            ("IT", "192.168.1.0/24", DepartmentProtocol::Tcp),
+            ("BA", "192.168.2.0/24", DepartmentProtocol::All),
+            ("Lab", "192.168.3.0/24", DepartmentProtocol::Icmp),
+        ] {
+            store.create(&DepartmentInput {
+                name: name.to_string(),
+                subnet: subnet.to_string(),
+                protocol,
+                action: DepartmentAction::Allow,
+                description: String::new(),
+            }).unwrap();
+        }
+        store
+    }
+
+    fn connection(from: &str, to: &str, port: &str, direction: ConnectionDirection, action: DepartmentAction) -> ConnectionRule {
+        ConnectionRule {
+            from: from.to_string(),
+            to: to.to_string(),
+            port: port.to_string(),
+            direction,
+            action,
+        }
+    }
+
+    fn names(plan: &PolicyPlan) -> Vec<&str> {
+        plan.rules.iter().map(|r| r.name.as_str()).collect()
+    }
+
+    #[test]
+    fn compiles_both_directions_with_addresses_and_ports() {
+        let plan = compile_policy(&store(), &[
+            connection("BA", "IT", "443", ConnectionDirection::Both, DepartmentAction::Allow),
+        ]).unwrap();
+
+        assert_eq!(names(&plan), vec!["Dept-Policy-BA-IT-Allow-In-TCP", "Dept-Policy-BA-IT-Allow-Out-TCP"]);
+        let inbound = &plan.rules[0];
+        assert_eq!(inbound.direction, "Inbound");
+        assert_eq!(format_list(&inbound.local_addresses), "192.168.1.0/24");
+        assert_eq!(format_list(&inbound.remote_addresses), "192.168.2.0/24");
+        assert_eq!(format_list(&inbound.local_ports), "443");
+        let outbound = &plan.rules[1];
+        assert_eq!(format_list(&outbound.local_addresses), "192.168.2.0/24");
+        assert_eq!(format_list(&outbound.remote_ports), "443");
+        assert!(outbound.local_ports.is_empty());
+        assert!(plan.conflicts.is_empty());
+    }
+
+    #[test]
+    fn merges_ports_and_orders_blocks_first() {
+        let plan = compile_policy(&store(), &[
+            connection("BA", "IT", "80", ConnectionDirection::Inbound, DepartmentAction::Allow),
+            connection("BA", "IT", "443,8000-8080", ConnectionDirection::Inbound, DepartmentAction::Allow),
+            connection("IT", "BA", "", ConnectionDirection::Outbound, DepartmentAction::Block),
+            connection("IT", "BA", "22", ConnectionDirection::Outbound, DepartmentAction::Block),
+        ]).unwrap();
+
+        assert_eq!(names(&plan), vec!["Dept-Policy-IT-BA-Block-Out-Any", "Dept-Policy-BA-IT-Allow-In-TCP"]);
+        assert!(plan.rules[0].remote_ports.is_empty());
+        assert_eq!(format_list(&plan.rules[1].local_ports), "80,443,8000-8080");
+    }
+
+    #[test]
+    fn ports_on_an_all_department_cover_tcp_and_udp() {
+        let plan = compile_policy(&store(), &[
+            connection("IT", "BA", "53", ConnectionDirection::Outbound, DepartmentAction::Allow),
+            connection("IT", "Lab", "", ConnectionDirection::Outbound, DepartmentAction::Allow),
+        ]).unwrap();
+
+        assert_eq!(names(&plan), vec![
+            "Dept-Policy-IT-BA-Allow-Out-TCP",
+            "Dept-Policy-IT-BA-Allow-Out-UDP",
+            "Dept-Policy-IT-Lab-Allow-Out-ICMPv4",
+        ]);
+    }
+
+    #[test]
+    fn reports_overlapping_rules_with_different_actions() {
+        let plan = compile_policy(&store(), &[
+            connection("BA", "IT", "1000-2000", ConnectionDirection::Both, DepartmentAction::Allow),
+            connection("BA", "IT", "1500", ConnectionDirection::Inbound, DepartmentAction::Block),
+            connection("BA", "IT", "3000", ConnectionDirection::Inbound, DepartmentAction::Block),
+            connection("IT", "BA", "", ConnectionDirection::Inbound, DepartmentAction::Block),
+        ]).unwrap();
+
+        assert_eq!(plan.conflicts.len(), 1);
+        assert_eq!((plan.conflicts[0].first, plan.conflicts[0].second), (0, 1));
+        assert!(plan.conflicts[0].message.contains("Rule 1 (Allow BA -> IT) and rule 2 (Block BA -> IT)"));
+    }
+
+    #[test]
+    fn rejects_invalid_connections() {
+        let store = store();
+
+        assert!(compile_policy(&store, &[connection("HR", "IT", "", ConnectionDirection::Both, DepartmentAction::Allow)])
+            .unwrap_err().contains("Unknown department: HR"));
+        assert!(compile_policy(&store, &[connection("IT", "it", "", ConnectionDirection::Both, DepartmentAction::Allow)])
+            .unwrap_err().contains("to itself"));
+        assert!(compile_policy(&store, &[connection("IT", "BA", "http", ConnectionDirection::Both, DepartmentAction::Allow)])
+            .unwrap_err().contains("Rule 1: Invalid port: http"));
+        assert!(compile_policy(&store, &[connection("IT", "BA", "RPC", ConnectionDirection::Both, DepartmentAction::Allow)])
+            .unwrap_err().contains("not a port number"));
+        assert!(compile_policy(&store, &[connection("IT", "BA", "0-80", ConnectionDirection::Both, DepartmentAction::Allow)])
+            .unwrap_err().contains("Invalid port: 0"));
+        assert!(compile_policy(&store, &[connection("IT", "BA", "80-", ConnectionDirection::Both, DepartmentAction::Allow)])
+            .unwrap_err().contains("Rule 1: Invalid port"));
+        assert!(compile_policy(&store, &[connection("IT", "Lab", "80", ConnectionDirection::Both, DepartmentAction::Allow)])
+            .unwrap_err().contains("no ports"));
+    }
+}
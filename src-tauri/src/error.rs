@@ -0,0 +1,174 @@
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use crate::firewall::common::FirewallError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    // The operation needs root / an elevated session, or the elevation prompt was dismissed.
+    AdminRequired,
+    // A program the operation relies on (netsh, nft, pkexec, suricata) isn't installed.
+    BackendMissing,
+    // A host command ran but reported a failure.
+    CommandFailed,
+    ParseError,
+    InvalidInput,
+    NotFound,
+    // Something else has to be settled first, e.g. a change waiting for confirmation.
+    Conflict,
+    ResolutionFailed,
+    SuricataNotRunning,
+    Io,
+    Other,
+}
+
+impl ErrorKind {
+    // Transient failures worth retrying as-is; the rest need something changed first.
+    pub fn is_retryable(self) -> bool {
+        matches!(self, ErrorKind::ResolutionFailed | ErrorKind::Io)
+    }
+}
+
+// What every Tauri command returns on failure, so the frontend can switch on `kind` instead of
+// matching message text.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppError {
+    pub kind: ErrorKind,
+    pub message: String,
+    // Raw output from the failing command, when there is any.
+    pub details: Option<String>,
+    pub retryable: bool,
+}
+
+impl AppError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        AppError {
+            kind,
+            message: message.into(),
+            details: None,
+            retryable: kind.is_retryable(),
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        AppError::new(ErrorKind::NotFound, message)
+    }
+
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        AppError::new(ErrorKind::InvalidInput, message)
+    }
+
+    pub fn with_details(mut self, details: impl Into<String>) -> Self {
+        self.details = Some(details.into());
+        self
+    }
+
+    // Prefixes the message while keeping the kind, like `format!("{}: {}", context, e)` would.
+    pub fn context(mut self, context: &str) -> Self {
+        self.message = format!("{}: {}", context, self.message);
+        self
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for AppError {}
+
+// Plain string errors from code that doesn't classify its failures yet.
+impl From<String> for AppError {
+    fn from(message: String) -> Self {
+        AppError::new(ErrorKind::Other, message)
+    }
+}
+
+impl From<&str> for AppError {
+    fn from(message: &str) -> Self {
+        AppError::new(ErrorKind::Other, message)
+    }
+}
+
+// Lets the CLI and daemon, which still report errors as text, use `?` on these. The details
+// are kept, since the text is all their callers get.
+impl From<AppError> for String {
+    fn from(error: AppError) -> Self {
+        match error.details {
+            Some(details) => format!("{} ({})", error.message, details.trim()),
+            None => error.message,
+        }
+    }
+}
+
+impl From<FirewallError> for AppError {
+    fn from(error: FirewallError) -> Self {
+        match error {
+            FirewallError::CommandError(msg) => AppError::new(ErrorKind::CommandFailed, msg),
+            FirewallError::ParseError(msg) => AppError::new(ErrorKind::ParseError, msg),
+            FirewallError::RuleNotFound(msg) => AppError::new(ErrorKind::NotFound, msg),
+            FirewallError::AdminRequired(msg) => {
+                AppError::new(ErrorKind::AdminRequired, "Administrator privileges are required to manage the firewall")
+                    .with_details(msg)
+            },
+            FirewallError::BackendMissing(msg) => AppError::new(ErrorKind::BackendMissing, msg),
+        }
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(error: std::io::Error) -> Self {
+        let kind = match error.kind() {
+            std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+            std::io::ErrorKind::PermissionDenied => ErrorKind::AdminRequired,
+            _ => ErrorKind::Io,
+        };
+        AppError::new(kind, error.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(error: rusqlite::Error) -> Self {
+        // Another process holding the event database's write lock is worth waiting out.
+        let kind = match error.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked) => ErrorKind::Io,
+            _ => ErrorKind::Other,
+        };
+        AppError::new(kind, format!("Database error: {}", error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_kind_details_and_retryable() {
+        let error = AppError::from(FirewallError::AdminRequired("Error: Operation not permitted".to_string()));
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(json["kind"], "admin_required");
+        assert_eq!(json["details"], "Error: Operation not permitted");
+        assert_eq!(json["retryable"], false);
+
+        let error = AppError::new(ErrorKind::ResolutionFailed, "Timed out waiting for 1.1.1.1:53");
+        assert!(error.retryable);
+        assert_eq!(serde_json::to_value(&error).unwrap()["kind"], "resolution_failed");
+    }
+
+    #[test]
+    fn context_keeps_the_kind() {
+        let error = AppError::from(FirewallError::BackendMissing("nft is not installed".to_string()))
+            .context("Failed to remove firewall rules for domain ads.example.com");
+        assert_eq!(error.kind, ErrorKind::BackendMissing);
+        assert_eq!(error.message, "Failed to remove firewall rules for domain ads.example.com: nft is not installed");
+
+        let text: String = AppError::not_found("Snapshot abc not found").into();
+        assert_eq!(text, "Snapshot abc not found");
+
+        let text: String = AppError::from(FirewallError::AdminRequired("Error: Operation not permitted\n".to_string()))
+            .context("Failed to list firewall rules")
+            .into();
+        assert_eq!(text, "Failed to list firewall rules: Administrator privileges are required to manage the firewall (Error: Operation not permitted)");
+    }
+}
@@ -0,0 +1,110 @@
+pub mod netsh;
+pub mod nftables;
+
+use std::path::Path;
+use std::sync::Arc;
+use async_trait::async_trait;
+use tauri::State;
+use crate::firewall::command_runner::CommandRunner;
+use crate::firewall::common::{FirewallError, FirewallRuleInfo};
+use netsh::NetshBackend;
+use nftables::NftablesBackend;
+
+const BACKEND_ENV_VAR: &str = "SECURITY_SMILE_FIREWALL_BACKEND";
+
+// A set of addresses to block. `existing_rules` are the rules the ownership manifest recorded
+// for the set the last time it was blocked; they are replaced.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IpSetBlock {
+    pub name: String,
+    pub ips: Vec<String>,
+    pub existing_rules: Vec<String>,
+}
+
+#[async_trait]
+pub trait FirewallBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn list_rules(&self) -> Result<Vec<FirewallRuleInfo>, FirewallError>;
+
+    async fn add_rule(&self, rule: &FirewallRuleInfo) -> Result<(), FirewallError>;
+
+    async fn remove_rule(&self, rule_name: &str) -> Result<(), FirewallError>;
+
+    async fn set_rule_enabled(&self, rule_name: &str, enable: bool) -> Result<(), FirewallError>;
+
+    // Blocks inbound and outbound traffic for every address in the set. Calling it again
+    // for the same set name replaces the previous addresses and `existing_rules`.
+    async fn block_ip_set(&self, set_name: &str, ips: &[String], existing_rules: &[String]) -> Result<(), FirewallError> {
+        self.block_ip_sets(&[IpSetBlock {
+            name: set_name.to_string(),
+            ips: ips.to_vec(),
+            existing_rules: existing_rules.to_vec(),
+        }]).await
+    }
+
+    // Moves an already blocked set to `current`. Backends that can't patch a set in place
+    // simply block the full list again.
+    async fn update_ip_set(
+        &self,
+        set_name: &str,
+        current: &[String],
+        _added: &[String],
+        _removed: &[String],
+        existing_rules: &[String]
+    ) -> Result<(), FirewallError> {
+        self.block_ip_set(set_name, current, existing_rules).await
+    }
+
+    // Batch form of block_ip_set that applies every change in a single elevated run, so a
+    // batch costs one prompt. nftables applies it atomically; netsh stops at the first failure.
+    async fn block_ip_sets(&self, sets: &[IpSetBlock]) -> Result<(), FirewallError>;
+
+    // The rule names block_ip_set creates for these addresses, recorded in the ownership manifest.
+    fn ip_set_rule_names(&self, set_name: &str, ips: &[String]) -> Vec<String>;
+
+    // Every name a set's rules may have had, for sets blocked before the manifest existed.
+    fn legacy_ip_set_rule_names(&self, set_name: &str) -> Vec<String>;
+
+    // Deletes exactly the named rules (plus any address sets left unused) in one elevated run.
+    async fn remove_rules(&self, rule_names: &[String]) -> Result<(), FirewallError>;
+
+    // Deletes the named rules and adds `add` in a single elevated run, for swapping out a
+    // whole generated policy at once.
+    async fn apply_rule_changes(&self, remove: &[String], add: &[FirewallRuleInfo]) -> Result<(), FirewallError>;
+
+    // Writes the entire firewall configuration, rules we don't own included, to `path`.
+    async fn export_config(&self, path: &Path) -> Result<(), FirewallError>;
+
+    // Replaces the entire firewall configuration with one written by export_config.
+    async fn import_config(&self, path: &Path) -> Result<(), FirewallError>;
+}
+
+pub struct FirewallBackendState {
+    pub backend: Arc<dyn FirewallBackend>,
+}
+
+impl FirewallBackendState {
+    pub fn new(runner: Arc<dyn CommandRunner>) -> Self {
+        FirewallBackendState {
+            backend: select_backend(runner),
+        }
+    }
+}
+
+pub fn select_backend(runner: Arc<dyn CommandRunner>) -> Arc<dyn FirewallBackend> {
+    let requested = std::env::var(BACKEND_ENV_VAR).unwrap_or_default();
+
+    match requested.trim().to_lowercase().as_str() {
+        "netsh" => Arc::new(NetshBackend::new(runner)),
+        "nft" | "nftables" => Arc::new(NftablesBackend::new(runner)),
+        _ if cfg!(target_os = "windows") => Arc::new(NetshBackend::new(runner)),
+        _ => Arc::new(NftablesBackend::new(runner)),
+    }
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all)]
+pub fn get_firewall_backend(state: State<'_, FirewallBackendState>) -> String {
+    state.backend.name().to_string()
+}
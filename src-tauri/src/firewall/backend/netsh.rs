@@ -0,0 +1,865 @@
+use async_trait::async_trait;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::warn;
+use crate::firewall::common::{
+    FirewallError,
+    FirewallRuleInfo,
+    run_netsh_command,
+    run_elevated_netsh_command,
+    run_elevated_powershell
+};
+use crate::firewall::command_runner::CommandRunner;
+use crate::firewall::rule_model::{format_list, parse_list};
+use super::{FirewallBackend, IpSetBlock};
+
+pub struct NetshBackend {
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl NetshBackend {
+    pub fn new(runner: Arc<dyn CommandRunner>) -> Self {
+        NetshBackend { runner }
+    }
+}
+
+// netsh `show rule` fields that are kept as raw text when they can't be parsed, with the
+// `add rule` argument that takes them back.
+const RAW_FIELD_ARGS: &[(&str, &str)] = &[
+    ("LocalPort", "localport"),
+    ("RemotePort", "remoteport"),
+    ("LocalIP", "localip"),
+    ("RemoteIP", "remoteip"),
+    ("Profiles", "profile"),
+    ("InterfaceTypes", "interfacetype"),
+    ("Edge traversal", "edge"),
+];
+
+// One rule with a value we don't understand shouldn't hide the whole listing, so the field
+// is kept as text on the rule instead.
+fn parse_field<T: std::str::FromStr<Err = String>>(rule: &mut FirewallRuleInfo, key: &str, value: &str) -> Option<Vec<T>> {
+    match parse_list(value) {
+        Ok(items) => Some(items),
+        Err(e) => {
+            keep_unparsed(rule, key, value, &e);
+            None
+        },
+    }
+}
+
+fn keep_unparsed(rule: &mut FirewallRuleInfo, key: &str, value: &str, error: &str) {
+    warn!("Rule {}: keeping {} as text: {}", rule.name, key, error);
+    rule.unparsed_fields.insert(key.to_string(), value.to_string());
+}
+
+fn parse_firewall_rules(output: &str) -> Vec<FirewallRuleInfo> {
+    let mut rules = Vec::new();
+    let mut current_rule: Option<FirewallRuleInfo> = None;
+
+    for line in output.lines() {
+        let line = line.trim();
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+
+        if key == "Rule Name" {
+            if let Some(rule) = current_rule.take() {
+                rules.push(rule);
+            }
+
+            current_rule = Some(FirewallRuleInfo {
+                name: value.to_string(),
+                protocol: "Any".to_string(),
+                direction: "Inbound".to_string(),
+                action: "Block".to_string(),
+                ..Default::default()
+            });
+        } else if let Some(ref mut rule) = current_rule {
+            match key {
+                "Description" => rule.description = value.to_string(),
+                "Enabled" => rule.enabled = value.eq_ignore_ascii_case("Yes"),
+                "Direction" => rule.direction = value.to_string(),
+                "Action" => rule.action = value.to_string(),
+                "Protocol" => rule.protocol = value.to_string(),
+                "Program" if value != "Any" => rule.application_path = Some(value.to_string()),
+                "Grouping" if !value.is_empty() => rule.group = Some(value.to_string()),
+                "LocalPort" => {
+                    if let Some(ports) = parse_field(rule, key, value) {
+                        rule.set_local_ports(ports);
+                    }
+                },
+                "RemotePort" => {
+                    if let Some(ports) = parse_field(rule, key, value) {
+                        rule.remote_ports = ports;
+                    }
+                },
+                "LocalIP" => {
+                    if let Some(addresses) = parse_field(rule, key, value) {
+                        rule.local_addresses = addresses;
+                    }
+                },
+                "RemoteIP" => {
+                    if let Some(addresses) = parse_field(rule, key, value) {
+                        rule.remote_addresses = addresses;
+                    }
+                },
+                "Profiles" => {
+                    if let Some(profiles) = parse_field(rule, key, value) {
+                        rule.profiles = profiles;
+                    }
+                },
+                "InterfaceTypes" => {
+                    if let Some(types) = parse_field(rule, key, value) {
+                        rule.interface_types = types;
+                    }
+                },
+                "Edge traversal" => match value.parse() {
+                    Ok(edge) => rule.edge_traversal = Some(edge),
+                    Err(e) => keep_unparsed(rule, key, value, &e),
+                },
+                _ => {}
+            }
+        }
+    }
+
+    if let Some(rule) = current_rule {
+        rules.push(rule);
+    }
+
+    rules
+}
+
+// Every netsh command runs inside a PowerShell script, where a double-quoted string would
+// expand `$(...)` in a rule name. Single-quoted literals are taken as they are.
+fn ps_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn is_outbound(direction: &str) -> bool {
+    matches!(direction.to_lowercase().as_str(), "outbound" | "out")
+}
+
+fn build_add_rule_args(rule_info: &FirewallRuleInfo) -> Vec<String> {
+    let direction_lower = match rule_info.direction.to_lowercase().as_str() {
+        "inbound" => "in".to_string(),
+        "outbound" => "out".to_string(),
+        other => other.to_string(),
+    };
+    let action_lower = rule_info.action.to_lowercase();
+    let protocol_lower = if rule_info.protocol != "Any" {
+        Some(rule_info.protocol.to_lowercase())
+    } else {
+        None
+    };
+    let local_ports = rule_info.effective_local_ports();
+
+    let mut args = vec![
+        "advfirewall".to_string(),
+        "firewall".to_string(),
+        "add".to_string(),
+        "rule".to_string(),
+        format!("name={}", ps_quote(&rule_info.name)),
+        format!("dir={}", direction_lower),
+        format!("action={}", action_lower),
+    ];
+
+    if !rule_info.description.is_empty() {
+        args.push(format!("description={}", ps_quote(&rule_info.description)));
+    }
+
+    if let Some(path) = &rule_info.application_path {
+        if !path.is_empty() {
+            args.push(format!("program={}", ps_quote(path)));
+        }
+    } else {
+        if let Some(ref proto) = protocol_lower {
+            args.push(format!("protocol={}", proto));
+        }
+        if !local_ports.is_empty() {
+            args.push(format!("localport={}", format_list(&local_ports)));
+        }
+        if !rule_info.remote_ports.is_empty() {
+            args.push(format!("remoteport={}", format_list(&rule_info.remote_ports)));
+        }
+    }
+
+    if !rule_info.local_addresses.is_empty() {
+        args.push(format!("localip={}", format_list(&rule_info.local_addresses)));
+    }
+    if !rule_info.remote_addresses.is_empty() {
+        args.push(format!("remoteip={}", format_list(&rule_info.remote_addresses)));
+    }
+    if !rule_info.profiles.is_empty() {
+        let profiles: Vec<&str> = rule_info.profiles.iter().map(|p| p.netsh_name()).collect();
+        args.push(format!("profile={}", profiles.join(",")));
+    }
+    if let [interface_type] = rule_info.interface_types.as_slice() {
+        args.push(format!("interfacetype={}", interface_type.netsh_name()));
+    }
+    // netsh only accepts edge traversal on inbound rules.
+    if let Some(edge) = rule_info.edge_traversal {
+        if !is_outbound(&rule_info.direction) {
+            args.push(format!("edge={}", edge.netsh_name()));
+        }
+    }
+
+    for (field, arg) in RAW_FIELD_ARGS {
+        if let Some(raw) = rule_info.unparsed_fields.get(*field) {
+            args.push(format!("{}={}", arg, ps_quote(raw)));
+        }
+    }
+
+    args.push(format!("enable={}", if rule_info.enabled { "yes" } else { "no" }));
+    args
+}
+
+// netsh can't set a rule group or more than one interface type; those are applied to the
+// new rule with the NetSecurity cmdlets in the same elevated run.
+fn build_add_rule_followup(rule_info: &FirewallRuleInfo) -> Vec<String> {
+    let mut lines = Vec::new();
+    let name = ps_quote(&rule_info.name);
+
+    if let Some(group) = rule_info.group.as_deref().filter(|g| !g.is_empty()) {
+        lines.push(format!(
+            "Get-NetFirewallRule -DisplayName {} | ForEach-Object {{ $_.Group = {}; $_ | Set-NetFirewallRule }}",
+            name,
+            ps_quote(group)
+        ));
+    }
+    if rule_info.interface_types.len() > 1 {
+        let types: Vec<&str> = rule_info.interface_types.iter().map(|t| t.powershell_name()).collect();
+        lines.push(format!("Set-NetFirewallRule -DisplayName {} -InterfaceType {}", name, types.join(",")));
+    }
+
+    lines
+}
+
+// Missing rules are fine to skip when deleting; a failed add stops the script.
+fn build_rule_changes_script(remove: &[String], add: &[FirewallRuleInfo]) -> String {
+    let mut ps_script = String::new();
+    ps_script.push_str("$ErrorActionPreference = 'Continue'\n$removed_count = 0\n\n");
+    push_delete_commands(&mut ps_script, remove);
+
+    for rule in add {
+        ps_script.push_str(&format!("netsh {}\n", build_add_rule_args(rule).join(" ")));
+        ps_script.push_str(&format!(
+            "if ($LASTEXITCODE -ne 0) {{ Write-Host {}; exit 1 }}\n",
+            ps_quote(&format!("Error creating firewall rule {}", rule.name))
+        ));
+        for line in build_add_rule_followup(rule) {
+            ps_script.push_str(&line);
+            ps_script.push('\n');
+        }
+    }
+
+    ps_script.push_str(&format!("\nWrite-Host \"Removed $removed_count and added {} firewall rules\"\n", add.len()));
+    ps_script
+}
+
+// Rule names for blocking `ips`, as (outbound, inbound) pairs. A single address gets the bare
+// names; several get numbered ones.
+fn block_rule_names(set_name: &str, ips: &[String]) -> Vec<(String, String)> {
+    let outbound_rule_prefix = format!("Block-Domain-Outbound-{}", set_name);
+    let inbound_rule_prefix = format!("Block-Domain-Inbound-{}", set_name);
+
+    if ips.len() == 1 {
+        return vec![(outbound_rule_prefix, inbound_rule_prefix)];
+    }
+    (1..=ips.len())
+        .map(|i| (format!("{}-{}", outbound_rule_prefix, i), format!("{}-{}", inbound_rule_prefix, i)))
+        .collect()
+}
+
+// Every name earlier versions may have used for a set: the bare names, numbered ones up
+// to 19 and the original single `Block-Domain-<domain>` rule.
+fn legacy_rule_names(set_name: &str) -> Vec<String> {
+    let outbound_rule_prefix = format!("Block-Domain-Outbound-{}", set_name);
+    let inbound_rule_prefix = format!("Block-Domain-Inbound-{}", set_name);
+
+    let mut names = vec![outbound_rule_prefix.clone(), inbound_rule_prefix.clone()];
+    for i in 1..20 {
+        names.push(format!("{}-{}", outbound_rule_prefix, i));
+        names.push(format!("{}-{}", inbound_rule_prefix, i));
+    }
+    names.push(format!("Block-Domain-{}", set_name));
+    names
+}
+
+fn push_delete_commands(ps_script: &mut String, rule_names: &[String]) {
+    for name in rule_names {
+        ps_script.push_str(&format!(
+            "try {{ netsh advfirewall firewall delete rule name={}; $removed_count++ }} catch {{ Write-Host \"Rule not found\" }}\n",
+            ps_quote(name)
+        ));
+    }
+}
+
+fn build_remove_rules_script(rule_names: &[String]) -> String {
+    let mut ps_script = String::new();
+    ps_script.push_str("$ErrorActionPreference = 'Continue'\n$removed_count = 0\n\n");
+    push_delete_commands(&mut ps_script, rule_names);
+    ps_script.push_str("\nWrite-Host \"Removed $removed_count firewall rules\"\n");
+    ps_script
+}
+
+fn build_block_script(set: &IpSetBlock) -> String {
+    let (set_name, ips) = (set.name.as_str(), set.ips.as_slice());
+    // Clear out the rules from a previous block first, so re-blocking replaces the old addresses.
+    // The recorded names cover sets of any size; the guessed ones are only for sets blocked
+    // before names were recorded, so a recorded set costs one delete per rule, not forty.
+    let stale = if set.existing_rules.is_empty() {
+        legacy_rule_names(set_name)
+    } else {
+        set.existing_rules.clone()
+    };
+    let mut ps_script = String::new();
+    ps_script.push_str("$ErrorActionPreference = 'Continue'\n$removed_count = 0\n");
+    push_delete_commands(&mut ps_script, &stale);
+    ps_script.push_str("\n$ErrorActionPreference = 'Stop'\ntry {\n");
+
+    for (ip_address, (outbound_rule_name, inbound_rule_name)) in ips.iter().zip(block_rule_names(set_name, ips)) {
+        let outbound_description = format!("Blocks outgoing connections to domain: {} (IP: {})", set_name, ip_address);
+        let inbound_description = format!("Blocks incoming connections from domain: {} (IP: {})", set_name, ip_address);
+
+        ps_script.push_str(&format!(
+            "    netsh advfirewall firewall add rule name={} dir=out action=block enable=yes protocol=any description={} remoteip={}\n",
+            ps_quote(&outbound_rule_name), ps_quote(&outbound_description), ip_address
+        ));
+        ps_script.push_str("    if ($LASTEXITCODE -ne 0) { throw \"netsh exited with $LASTEXITCODE\" }\n");
+
+        ps_script.push_str(&format!(
+            "    netsh advfirewall firewall add rule name={} dir=in action=block enable=yes protocol=any description={} remoteip={}\n",
+            ps_quote(&inbound_rule_name), ps_quote(&inbound_description), ip_address
+        ));
+        ps_script.push_str("    if ($LASTEXITCODE -ne 0) { throw \"netsh exited with $LASTEXITCODE\" }\n");
+    }
+
+    ps_script.push_str("    Write-Host \"Successfully created all firewall rules\"\n");
+    ps_script.push_str("} catch {\n");
+    ps_script.push_str("    Write-Host \"Error creating firewall rules: $_\"\n");
+    ps_script.push_str("    exit 1\n");
+    ps_script.push_str("}\n");
+    ps_script
+}
+
+// netsh refuses to export over an existing file. The elevated script's output isn't captured,
+// so success is judged by the file it leaves behind.
+fn build_export_script(path: &str) -> String {
+    format!(
+        "$ErrorActionPreference = 'Stop'\nRemove-Item -LiteralPath {0} -ErrorAction SilentlyContinue\nnetsh advfirewall export {0}\nif ($LASTEXITCODE -ne 0) {{ exit 1 }}\n",
+        ps_quote(path)
+    )
+}
+
+fn build_import_script(path: &str) -> String {
+    format!(
+        "$ErrorActionPreference = 'Stop'\nnetsh advfirewall import {}\nif ($LASTEXITCODE -ne 0) {{ exit 1 }}\n",
+        ps_quote(path)
+    )
+}
+
+#[async_trait]
+impl FirewallBackend for NetshBackend {
+    fn name(&self) -> &'static str {
+        "netsh"
+    }
+
+    async fn list_rules(&self) -> Result<Vec<FirewallRuleInfo>, FirewallError> {
+        let output = run_netsh_command(self.runner.as_ref(), vec!["advfirewall", "firewall", "show", "rule", "name=all"]).await?;
+        Ok(parse_firewall_rules(&output))
+    }
+
+    async fn apply_rule_changes(&self, remove: &[String], add: &[FirewallRuleInfo]) -> Result<(), FirewallError> {
+        if remove.is_empty() && add.is_empty() {
+            return Ok(());
+        }
+
+        let script = build_rule_changes_script(remove, add);
+        run_elevated_powershell(self.runner.as_ref(), &script).await
+            .map_err(|e| e.context("Failed to apply firewall rule changes"))?;
+        Ok(())
+    }
+
+    async fn add_rule(&self, rule: &FirewallRuleInfo) -> Result<(), FirewallError> {
+        let args = build_add_rule_args(rule);
+        let followup = build_add_rule_followup(rule);
+
+        if followup.is_empty() {
+            let args_ref: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+            run_elevated_netsh_command(self.runner.as_ref(), args_ref).await?;
+            return Ok(());
+        }
+
+        let script = format!(
+            "$ErrorActionPreference = 'Stop'\nnetsh {}\nif ($LASTEXITCODE -ne 0) {{ exit 1 }}\n{}\n",
+            args.join(" "),
+            followup.join("\n")
+        );
+        run_elevated_powershell(self.runner.as_ref(), &script).await
+            .map_err(|e| e.context(&format!("Failed to add firewall rule {}", rule.name)))?;
+        Ok(())
+    }
+
+    async fn remove_rule(&self, rule_name: &str) -> Result<(), FirewallError> {
+        let args = [
+            "advfirewall".to_string(),
+            "firewall".to_string(),
+            "delete".to_string(),
+            "rule".to_string(),
+            format!("name={}", ps_quote(rule_name)),
+        ];
+        let args_ref: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+        run_elevated_netsh_command(self.runner.as_ref(), args_ref).await?;
+        Ok(())
+    }
+
+    async fn set_rule_enabled(&self, rule_name: &str, enable: bool) -> Result<(), FirewallError> {
+        let args = [
+            "advfirewall".to_string(),
+            "firewall".to_string(),
+            "set".to_string(),
+            "rule".to_string(),
+            format!("name={}", ps_quote(rule_name)),
+            "new".to_string(),
+            format!("enable={}", if enable { "yes" } else { "no" }),
+        ];
+        let args_ref: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+        run_elevated_netsh_command(self.runner.as_ref(), args_ref).await?;
+        Ok(())
+    }
+
+    // Each set's script exits on failure, so a batch stops at the first set that fails.
+    async fn block_ip_sets(&self, sets: &[IpSetBlock]) -> Result<(), FirewallError> {
+        if sets.is_empty() {
+            return Ok(());
+        }
+
+        let script: Vec<String> = sets.iter().map(build_block_script).collect();
+        run_elevated_powershell(self.runner.as_ref(), &script.join("\n")).await?;
+        Ok(())
+    }
+
+    fn ip_set_rule_names(&self, set_name: &str, ips: &[String]) -> Vec<String> {
+        block_rule_names(set_name, ips)
+            .into_iter()
+            .flat_map(|(outbound, inbound)| [outbound, inbound])
+            .collect()
+    }
+
+    fn legacy_ip_set_rule_names(&self, set_name: &str) -> Vec<String> {
+        legacy_rule_names(set_name)
+    }
+
+    async fn remove_rules(&self, rule_names: &[String]) -> Result<(), FirewallError> {
+        if rule_names.is_empty() {
+            return Ok(());
+        }
+
+        let script = build_remove_rules_script(rule_names);
+        run_elevated_powershell(self.runner.as_ref(), &script).await?;
+        Ok(())
+    }
+
+    async fn export_config(&self, path: &Path) -> Result<(), FirewallError> {
+        let path_str = path.to_string_lossy();
+        run_elevated_powershell(self.runner.as_ref(), &build_export_script(&path_str)).await
+            .map_err(|e| e.context("Failed to export firewall configuration"))?;
+
+        if !path.exists() {
+            return Err(FirewallError::CommandError(format!("Firewall export did not create {}", path_str)));
+        }
+        Ok(())
+    }
+
+    async fn import_config(&self, path: &Path) -> Result<(), FirewallError> {
+        run_elevated_powershell(self.runner.as_ref(), &build_import_script(&path.to_string_lossy())).await
+            .map_err(|e| e.context("Failed to import firewall configuration"))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall::command_runner::{CommandOutput, ScriptKind};
+    use crate::firewall::rule_model::{EdgeTraversal, FirewallProfile, InterfaceType, PortSpec};
+    use crate::firewall::testing::{FakeCommandRunner, Invocation};
+
+    const SHOW_RULES_OUTPUT: &str = "
+Rule Name:                            Allow HTTP
+----------------------------------------------------------------------
+Description:                          Web server
+Enabled:                              Yes
+Direction:                            In
+Profiles:                             Domain,Private,Public
+LocalIP:                              Any
+RemoteIP:                             Any
+Protocol:                             TCP
+LocalPort:                            80
+RemotePort:                           Any
+Action:                               Allow
+
+Rule Name:                            Block App
+----------------------------------------------------------------------
+Enabled:                              No
+Direction:                            Out
+Program:                              C:\\Tools\\app.exe
+Protocol:                             Any
+LocalPort:                            Any
+Action:                               Block
+
+Rule Name:                            Office Services
+----------------------------------------------------------------------
+Description:                          Intranet: web and RPC
+Enabled:                              Yes
+Direction:                            In
+Profiles:                             Domain,Private
+Grouping:                             Office
+LocalIP:                              Any
+RemoteIP:                             10.0.0.0/8,192.168.1.5/32,172.16.0.1-172.16.0.9,LocalSubnet
+Protocol:                             TCP
+LocalPort:                            80,443,1000-2000
+RemotePort:                           Any
+Edge traversal:                       Defer to user
+InterfaceTypes:                       Wireless,Lan
+Action:                               Allow
+Ok.
+";
+
+    fn backend() -> (Arc<FakeCommandRunner>, NetshBackend) {
+        let runner = Arc::new(FakeCommandRunner::new());
+        (runner.clone(), NetshBackend::new(runner))
+    }
+
+    fn rule(name: &str) -> FirewallRuleInfo {
+        FirewallRuleInfo {
+            name: name.to_string(),
+            description: String::new(),
+            application_path: None,
+            port: Some(443),
+            protocol: "TCP".to_string(),
+            direction: "Outbound".to_string(),
+            action: "Block".to_string(),
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn list_rules_parses_netsh_output() {
+        let (runner, backend) = backend();
+        runner.respond("netsh", CommandOutput::ok(SHOW_RULES_OUTPUT));
+
+        let rules = backend.list_rules().await.unwrap();
+
+        assert_eq!(runner.invocations()[0].argv().unwrap(), vec![
+            "netsh", "advfirewall", "firewall", "show", "rule", "name=all"
+        ]);
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[0].name, "Allow HTTP");
+        assert_eq!(rules[0].description, "Web server");
+        assert_eq!(rules[0].port, Some(80));
+        assert!(rules[0].enabled);
+        assert_eq!(rules[1].application_path.as_deref(), Some("C:\\Tools\\app.exe"));
+        assert_eq!(rules[1].action, "Block");
+        assert!(!rules[1].enabled);
+    }
+
+    #[test]
+    fn parses_address_lists_port_ranges_and_profiles() {
+        let rules = parse_firewall_rules(SHOW_RULES_OUTPUT);
+        let rule = &rules[2];
+
+        assert_eq!(rule.description, "Intranet: web and RPC");
+        assert_eq!(rule.port, None);
+        assert_eq!(format_list(&rule.local_ports), "80,443,1000-2000");
+        assert!(rule.remote_ports.is_empty());
+        assert_eq!(format_list(&rule.remote_addresses), "10.0.0.0/8,192.168.1.5,172.16.0.1-172.16.0.9,LocalSubnet");
+        assert_eq!(rule.profiles, vec![FirewallProfile::Domain, FirewallProfile::Private]);
+        assert_eq!(rule.interface_types, vec![InterfaceType::Wireless, InterfaceType::Lan]);
+        assert_eq!(rule.edge_traversal, Some(EdgeTraversal::DeferToUser));
+        assert_eq!(rule.group.as_deref(), Some("Office"));
+        assert!(rules[0].group.is_none());
+        assert_eq!(rules[0].profiles.len(), 3);
+    }
+
+    #[test]
+    fn keeps_unparseable_fields_as_text() {
+        let output = "Rule Name: Broken\nRemotePort: 80-\nRemoteIP: 10.0.0.1\nRule Name: Fine\nRemotePort: 443\n";
+
+        let rules = parse_firewall_rules(output);
+
+        assert_eq!(rules.len(), 2);
+        assert!(rules[0].remote_ports.is_empty());
+        assert_eq!(rules[0].unparsed_fields.get("RemotePort").map(String::as_str), Some("80-"));
+        assert_eq!(rules[0].remote_addresses.len(), 1);
+        assert!(rules[1].unparsed_fields.is_empty());
+        assert!(build_add_rule_args(&rules[0]).contains(&"remoteport='80-'".to_string()));
+    }
+
+    #[tokio::test]
+    async fn added_rules_list_back_the_same() {
+        let (runner, backend) = backend();
+        let original = parse_firewall_rules(SHOW_RULES_OUTPUT).remove(2);
+
+        backend.add_rule(&original).await.unwrap();
+
+        let script = &runner.scripts()[0];
+        assert!(script.contains(
+            "netsh advfirewall firewall add rule name='Office Services' dir=in action=allow description='Intranet: web and RPC' \
+             protocol=tcp localport=80,443,1000-2000 remoteip=10.0.0.0/8,192.168.1.5,172.16.0.1-172.16.0.9,LocalSubnet \
+             profile=domain,private edge=deferuser enable=yes"
+        ));
+        assert!(script.contains("Get-NetFirewallRule -DisplayName 'Office Services' | ForEach-Object { $_.Group = 'Office'; $_ | Set-NetFirewallRule }"));
+        assert!(script.contains("Set-NetFirewallRule -DisplayName 'Office Services' -InterfaceType Wireless,Wired"));
+
+        // What `netsh show rule` prints for the rule just added.
+        let shown = format!(
+            "Rule Name: {}\nDescription: {}\nEnabled: Yes\nDirection: In\nProfiles: Domain,Private\nGrouping: Office\n\
+             RemoteIP: {}\nProtocol: TCP\nLocalPort: {}\nEdge traversal: Defer to user\nInterfaceTypes: Wireless,Lan\nAction: Allow\n",
+            original.name,
+            original.description,
+            format_list(&original.remote_addresses),
+            format_list(&original.local_ports)
+        );
+        assert_eq!(parse_firewall_rules(&shown), vec![original]);
+    }
+
+    #[tokio::test]
+    async fn add_rule_accepts_remote_ports_and_single_interface_type() {
+        let (runner, backend) = backend();
+        let mut info = rule("DNS");
+        info.port = None;
+        info.protocol = "UDP".to_string();
+        info.remote_ports = vec![PortSpec::single(53)];
+        info.interface_types = vec![InterfaceType::Lan];
+        info.edge_traversal = Some(EdgeTraversal::Yes);
+
+        backend.add_rule(&info).await.unwrap();
+
+        assert_eq!(runner.scripts(), vec![
+            "netsh advfirewall firewall add rule name='DNS' dir=out action=block protocol=udp remoteport=53 interfacetype=lan enable=yes;".to_string()
+        ]);
+    }
+
+    #[tokio::test]
+    async fn rule_changes_run_in_one_script() {
+        let (runner, backend) = backend();
+
+        backend.apply_rule_changes(&["Old Policy".to_string()], &[rule("New A"), rule("New B")]).await.unwrap();
+
+        let scripts = runner.scripts();
+        assert_eq!(scripts.len(), 1);
+        let delete = scripts[0].find("delete rule name='Old Policy'").unwrap();
+        let add_a = scripts[0].find("add rule name='New A'").unwrap();
+        let add_b = scripts[0].find("add rule name='New B'").unwrap();
+        assert!(delete < add_a && add_a < add_b);
+
+        backend.apply_rule_changes(&[], &[]).await.unwrap();
+        assert_eq!(runner.scripts().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rule_names_are_not_expanded_by_powershell() {
+        let (runner, backend) = backend();
+        let name = "IT$(Remove-Item C:\\data)-Bob's";
+
+        backend.apply_rule_changes(&[name.to_string()], &[rule(name)]).await.unwrap();
+
+        let script = &runner.scripts()[0];
+        assert!(script.contains("delete rule name='IT$(Remove-Item C:\\data)-Bob''s'"));
+        assert!(script.contains("add rule name='IT$(Remove-Item C:\\data)-Bob''s'"));
+        assert!(script.contains("Write-Host 'Error creating firewall rule IT$(Remove-Item C:\\data)-Bob''s'"));
+        assert!(!script.contains("\"IT$("));
+    }
+
+    #[tokio::test]
+    async fn list_rules_reports_netsh_failure() {
+        let (runner, backend) = backend();
+        runner.respond("netsh", CommandOutput::failed(1, "The requested operation requires elevation."));
+
+        let err = backend.list_rules().await.unwrap_err();
+        assert!(err.to_string().contains("requires elevation"));
+    }
+
+    #[tokio::test]
+    async fn add_rule_issues_elevated_netsh_add() {
+        let (runner, backend) = backend();
+
+        backend.add_rule(&rule("Block HTTPS")).await.unwrap();
+
+        assert_eq!(runner.invocations(), vec![Invocation::Script {
+            kind: ScriptKind::PowerShell,
+            script: "netsh advfirewall firewall add rule name='Block HTTPS' dir=out action=block protocol=tcp localport=443 enable=yes;".to_string(),
+        }]);
+    }
+
+    #[tokio::test]
+    async fn add_rule_prefers_program_over_port() {
+        let (runner, backend) = backend();
+        let mut info = rule("App");
+        info.application_path = Some("C:\\app.exe".to_string());
+
+        backend.add_rule(&info).await.unwrap();
+
+        let script = &runner.scripts()[0];
+        assert!(script.contains("program='C:\\app.exe'"));
+        assert!(!script.contains("localport"));
+    }
+
+    #[tokio::test]
+    async fn remove_and_enable_rule_issue_expected_commands() {
+        let (runner, backend) = backend();
+
+        backend.remove_rule("Block HTTPS").await.unwrap();
+        backend.set_rule_enabled("Allow HTTP", false).await.unwrap();
+
+        assert_eq!(runner.scripts(), vec![
+            "netsh advfirewall firewall delete rule name='Block HTTPS';".to_string(),
+            "netsh advfirewall firewall set rule name='Allow HTTP' new enable=no;".to_string(),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn elevated_failure_is_reported() {
+        let (runner, backend) = backend();
+        runner.respond_to_script(Err("The operation was canceled by the user.".to_string()));
+
+        let err = backend.remove_rule("x").await.unwrap_err();
+        assert!(err.to_string().contains("canceled by the user"));
+    }
+
+    #[tokio::test]
+    async fn block_ip_set_creates_numbered_rules_per_ip() {
+        let (runner, backend) = backend();
+        let ips = vec!["93.184.216.34".to_string(), "2606:2800:220:1::".to_string()];
+
+        backend.block_ip_set("example.com", &ips, &[]).await.unwrap();
+
+        let scripts = runner.scripts();
+        assert_eq!(scripts.len(), 1);
+        let script = &scripts[0];
+        assert!(script.contains("name='Block-Domain-Outbound-example.com-1' dir=out action=block enable=yes protocol=any description='Blocks outgoing connections to domain: example.com (IP: 93.184.216.34)' remoteip=93.184.216.34"));
+        assert!(script.contains("name='Block-Domain-Inbound-example.com-2' dir=in"));
+        assert!(script.contains("remoteip=2606:2800:220:1::"));
+        assert_eq!(script.matches("if ($LASTEXITCODE -ne 0) { throw").count(), 4);
+    }
+
+    #[tokio::test]
+    async fn block_ip_set_uses_unnumbered_names_for_single_ip() {
+        let (runner, backend) = backend();
+
+        backend.block_ip_set("example.com", &["1.2.3.4".to_string()], &[]).await.unwrap();
+
+        let script = &runner.scripts()[0];
+        assert!(script.contains("add rule name='Block-Domain-Outbound-example.com'"));
+        assert!(!script.contains("add rule name='Block-Domain-Outbound-example.com-1'"));
+    }
+
+    #[tokio::test]
+    async fn block_ip_set_removes_previous_rules_before_adding() {
+        let (runner, backend) = backend();
+
+        backend.block_ip_set("example.com", &["1.2.3.4".to_string()], &[]).await.unwrap();
+
+        let script = &runner.scripts()[0];
+        let delete_at = script.find("delete rule name='Block-Domain-Outbound-example.com-19'").unwrap();
+        let add_at = script.find("add rule name='Block-Domain-Outbound-example.com'").unwrap();
+        assert!(delete_at < add_at);
+    }
+
+    #[tokio::test]
+    async fn block_ip_set_removes_recorded_rules_beyond_the_guessed_names() {
+        let (runner, backend) = backend();
+        let recorded = vec![
+            "Block-Domain-Outbound-example.com-25".to_string(),
+            "Block-Domain-Inbound-example.com-25".to_string(),
+        ];
+
+        backend.block_ip_set("example.com", &["1.2.3.4".to_string()], &recorded).await.unwrap();
+
+        let script = &runner.scripts()[0];
+        let delete_at = script.find("delete rule name='Block-Domain-Outbound-example.com-25'").unwrap();
+        assert!(script.contains("delete rule name='Block-Domain-Inbound-example.com-25'"));
+        assert!(delete_at < script.find("add rule name='Block-Domain-Outbound-example.com'").unwrap());
+        // With names on record there's nothing to guess.
+        assert_eq!(script.matches("delete rule").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn batch_changes_run_in_a_single_elevated_script() {
+        let (runner, backend) = backend();
+        let sets = vec![
+            IpSetBlock { name: "example.com".to_string(), ips: vec!["1.2.3.4".to_string()], ..Default::default() },
+            IpSetBlock { name: "example.org".to_string(), ips: vec!["5.6.7.8".to_string()], ..Default::default() },
+        ];
+
+        backend.block_ip_sets(&sets).await.unwrap();
+
+        let scripts = runner.scripts();
+        assert_eq!(scripts.len(), 1);
+        assert!(scripts[0].contains("add rule name='Block-Domain-Outbound-example.com'"));
+        assert!(scripts[0].contains("add rule name='Block-Domain-Outbound-example.org'"));
+    }
+
+    #[test]
+    fn rule_names_match_the_block_script() {
+        let (_, backend) = backend();
+        let ips = vec!["1.2.3.4".to_string(), "5.6.7.8".to_string()];
+
+        let names = backend.ip_set_rule_names("example.com", &ips);
+        let script = build_block_script(&IpSetBlock {
+            name: "example.com".to_string(),
+            ips: ips.clone(),
+            ..Default::default()
+        });
+
+        assert_eq!(names.len(), 4);
+        for name in &names {
+            assert!(script.contains(&format!("add rule name='{}'", name)));
+        }
+        assert_eq!(backend.ip_set_rule_names("example.com", &ips[..1]), vec![
+            "Block-Domain-Outbound-example.com",
+            "Block-Domain-Inbound-example.com",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn update_ip_set_replaces_the_whole_set() {
+        let (runner, backend) = backend();
+        let current = vec!["1.2.3.4".to_string(), "5.6.7.8".to_string()];
+
+        backend.update_ip_set("example.com", &current, &["5.6.7.8".to_string()], &["9.9.9.9".to_string()], &[]).await.unwrap();
+
+        let scripts = runner.scripts();
+        assert_eq!(scripts.len(), 1);
+        assert!(scripts[0].contains("remoteip=1.2.3.4"));
+        assert!(scripts[0].contains("remoteip=5.6.7.8"));
+        assert!(!scripts[0].contains("9.9.9.9"));
+    }
+
+    #[tokio::test]
+    async fn remove_rules_deletes_exactly_the_named_rules() {
+        let (runner, backend) = backend();
+        let names = vec!["Block-Domain-Outbound-example.com-21".to_string(), "Block-Domain-Inbound-example.com-21".to_string()];
+
+        backend.remove_rules(&names).await.unwrap();
+        backend.remove_rules(&[]).await.unwrap();
+
+        let scripts = runner.scripts();
+        assert_eq!(scripts.len(), 1);
+        assert_eq!(scripts[0].matches("delete rule").count(), 2);
+        assert!(scripts[0].contains("delete rule name='Block-Domain-Outbound-example.com-21'"));
+    }
+
+    #[test]
+    fn legacy_names_cover_numbered_and_original_rules() {
+        let (_, backend) = backend();
+        let names = backend.legacy_ip_set_rule_names("example.com");
+
+        assert!(names.contains(&"Block-Domain-Outbound-example.com".to_string()));
+        assert!(names.contains(&"Block-Domain-Inbound-example.com-19".to_string()));
+        assert!(names.contains(&"Block-Domain-example.com".to_string()));
+    }
+}
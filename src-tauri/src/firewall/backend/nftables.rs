@@ -0,0 +1,1151 @@
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use async_trait::async_trait;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use crate::firewall::common::{
+    FirewallError,
+    FirewallRuleInfo,
+    run_privileged_command,
+    run_elevated_nft_script
+};
+use crate::firewall::command_runner::CommandRunner;
+use crate::firewall::rule_model::{AddressSpec, EdgeTraversal, PortSpec};
+use super::{FirewallBackend, IpSetBlock};
+
+const TABLE_FAMILY: &str = "inet";
+const TABLE_NAME: &str = "security_smile";
+
+// Disabled rules are parked in regular chains without a hook, so nftables never evaluates them.
+const INPUT_CHAIN: &str = "input";
+const OUTPUT_CHAIN: &str = "output";
+const DISABLED_INPUT_CHAIN: &str = "disabled_input";
+const DISABLED_OUTPUT_CHAIN: &str = "disabled_output";
+
+pub struct NftablesBackend {
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl NftablesBackend {
+    pub fn new(runner: Arc<dyn CommandRunner>) -> Self {
+        NftablesBackend { runner }
+    }
+
+    async fn list_table(&self) -> Result<NftListing, FirewallError> {
+        let result = run_privileged_command(
+            self.runner.as_ref(),
+            "nft",
+            vec!["-j", "list", "table", TABLE_FAMILY, TABLE_NAME]
+        ).await;
+
+        match result {
+            Ok(output) => parse_listing(&output),
+            Err(FirewallError::CommandError(msg)) if msg.contains("No such file or directory") => {
+                Ok(NftListing::default())
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn apply(&self, commands: &[String]) -> Result<(), FirewallError> {
+        let mut script = table_skeleton();
+        for command in commands {
+            script.push_str(command);
+            script.push('\n');
+        }
+
+        run_elevated_nft_script(self.runner.as_ref(), &script).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct NftListing {
+    rules: Vec<NftRule>,
+    sets: Vec<String>,
+}
+
+#[derive(Debug)]
+struct NftRule {
+    chain: String,
+    handle: u64,
+    comment: Option<String>,
+    expr: Vec<Value>,
+}
+
+fn table_skeleton() -> String {
+    format!(
+        "table {family} {table} {{\n\
+         \x20   chain {input} {{ type filter hook input priority 0; policy accept; }}\n\
+         \x20   chain {output} {{ type filter hook output priority 0; policy accept; }}\n\
+         \x20   chain {disabled_input} {{ }}\n\
+         \x20   chain {disabled_output} {{ }}\n\
+         }}\n",
+        family = TABLE_FAMILY,
+        table = TABLE_NAME,
+        input = INPUT_CHAIN,
+        output = OUTPUT_CHAIN,
+        disabled_input = DISABLED_INPUT_CHAIN,
+        disabled_output = DISABLED_OUTPUT_CHAIN,
+    )
+}
+
+fn parse_listing(output: &str) -> Result<NftListing, FirewallError> {
+    let json: Value = serde_json::from_str(output)
+        .map_err(|e| FirewallError::ParseError(format!("Invalid nft JSON output: {}", e)))?;
+
+    let mut listing = NftListing::default();
+    let objects = json.get("nftables").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+
+    for object in objects {
+        if let Some(rule) = object.get("rule") {
+            listing.rules.push(NftRule {
+                chain: rule.get("chain").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+                handle: rule.get("handle").and_then(|v| v.as_u64()).unwrap_or(0),
+                comment: rule.get("comment").and_then(|v| v.as_str()).map(|s| s.to_string()),
+                expr: rule.get("expr").and_then(|v| v.as_array()).cloned().unwrap_or_default(),
+            });
+        } else if let Some(set) = object.get("set") {
+            if let Some(name) = set.get("name").and_then(|v| v.as_str()) {
+                listing.sets.push(name.to_string());
+            }
+        }
+    }
+
+    Ok(listing)
+}
+
+// Flattens the right-hand side of an nft match (a value, prefix, range or anonymous set)
+// into the address/port strings the rule model parses.
+fn match_values(right: &Value) -> Vec<String> {
+    if let Some(set) = right.get("set").and_then(|v| v.as_array()) {
+        return set.iter().flat_map(match_values).collect();
+    }
+    if let Some(prefix) = right.get("prefix") {
+        let addr = prefix.get("addr").and_then(|v| v.as_str()).unwrap_or("");
+        let len = prefix.get("len").and_then(|v| v.as_u64()).unwrap_or(0);
+        return vec![format!("{}/{}", addr, len)];
+    }
+    if let Some([start, end]) = right.get("range").and_then(|v| v.as_array()).map(|r| r.as_slice()) {
+        return vec![format!("{}-{}", scalar(start), scalar(end))];
+    }
+    vec![scalar(right)]
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+// Renders a listed rule's JSON expressions back into nft syntax, so a rule can be moved between
+// chains exactly as it is, including matches (like address sets) the rule model has no field for.
+fn rule_statement(rule: &NftRule) -> Result<String, FirewallError> {
+    let unsupported_expr = |expr: &Value| FirewallError::ParseError(format!(
+        "Can't move rule {} with unsupported nft expression {}",
+        rule.comment.as_deref().unwrap_or(""),
+        expr
+    ));
+
+    let mut statement = Vec::new();
+    for expr in &rule.expr {
+        if let Some(m) = expr.get("match") {
+            let left = m.get("left").unwrap_or(&Value::Null);
+            let left = if let Some(key) = left.get("meta").and_then(|meta| meta.get("key")).and_then(|v| v.as_str()) {
+                format!("meta {}", key)
+            } else if let Some(payload) = left.get("payload") {
+                match (
+                    payload.get("protocol").and_then(|v| v.as_str()),
+                    payload.get("field").and_then(|v| v.as_str()),
+                ) {
+                    (Some(protocol), Some(field)) => format!("{} {}", protocol, field),
+                    _ => return Err(unsupported_expr(expr)),
+                }
+            } else {
+                return Err(unsupported_expr(expr));
+            };
+            let right = statement_value(m.get("right").unwrap_or(&Value::Null)).ok_or_else(|| unsupported_expr(expr))?;
+            match m.get("op").and_then(|v| v.as_str()).unwrap_or("==") {
+                "==" | "in" => statement.push(format!("{} {}", left, right)),
+                "!=" => statement.push(format!("{} != {}", left, right)),
+                _ => return Err(unsupported_expr(expr)),
+            }
+        } else if let Some(verdict) = ["accept", "drop", "reject"].into_iter().find(|v| expr.get(*v).is_some()) {
+            statement.push(verdict.to_string());
+        } else {
+            return Err(unsupported_expr(expr));
+        }
+    }
+
+    Ok(statement.join(" "))
+}
+
+// The right-hand side of a match in nft syntax, or None if it isn't one we can write back.
+fn statement_value(right: &Value) -> Option<String> {
+    match right {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => {
+            if let Some(set) = right.get("set").and_then(|v| v.as_array()) {
+                let values = set.iter().map(statement_value).collect::<Option<Vec<_>>>()?;
+                return Some(format!("{{ {} }}", values.join(", ")));
+            }
+            if let Some(prefix) = right.get("prefix") {
+                return Some(format!("{}/{}", prefix.get("addr")?.as_str()?, prefix.get("len")?.as_u64()?));
+            }
+            if let Some([start, end]) = right.get("range").and_then(|v| v.as_array()).map(|r| r.as_slice()) {
+                return Some(format!("{}-{}", statement_value(start)?, statement_value(end)?));
+            }
+            None
+        }
+    }
+}
+
+fn rule_to_info(rule: &NftRule) -> FirewallRuleInfo {
+    let inbound = rule.chain == INPUT_CHAIN || rule.chain == DISABLED_INPUT_CHAIN;
+    let mut info = FirewallRuleInfo {
+        name: rule.comment.clone().unwrap_or_else(|| format!("nft-handle-{}", rule.handle)),
+        protocol: "Any".to_string(),
+        direction: if inbound { "In".to_string() } else { "Out".to_string() },
+        action: "Allow".to_string(),
+        enabled: rule.chain == INPUT_CHAIN || rule.chain == OUTPUT_CHAIN,
+        ..Default::default()
+    };
+    // Which end of the packet is "local" depends on the direction.
+    let (local_port, local_addr) = if inbound { ("dport", "daddr") } else { ("sport", "saddr") };
+
+    for expr in &rule.expr {
+        if let Some(m) = expr.get("match") {
+            let left = m.get("left").unwrap_or(&Value::Null);
+            let right = m.get("right").unwrap_or(&Value::Null);
+
+            if left.get("meta").and_then(|meta| meta.get("key")).and_then(|v| v.as_str()) == Some("l4proto") {
+                if let Some(proto) = right.as_str() {
+                    info.protocol = proto.to_uppercase();
+                }
+            } else if let Some(payload) = left.get("payload") {
+                let protocol = payload.get("protocol").and_then(|v| v.as_str()).unwrap_or("");
+                let field = payload.get("field").and_then(|v| v.as_str()).unwrap_or("");
+
+                match (protocol, field) {
+                    ("tcp" | "udp", "dport" | "sport") => {
+                        info.protocol = protocol.to_uppercase();
+                        let ports = match_values(right).iter().filter_map(|p| p.parse().ok()).collect();
+                        if field == local_port {
+                            info.set_local_ports(ports);
+                        } else {
+                            info.remote_ports = ports;
+                        }
+                    },
+                    ("ip" | "ip6", "daddr" | "saddr") => {
+                        if let Some(set) = right.as_str().and_then(|s| s.strip_prefix('@')) {
+                            info.description = format!("Blocks traffic for address set {}", set);
+                            continue;
+                        }
+                        let addresses = match_values(right).iter().filter_map(|a| a.parse().ok()).collect();
+                        if field == local_addr {
+                            info.local_addresses = addresses;
+                        } else {
+                            info.remote_addresses = addresses;
+                        }
+                    },
+                    _ => {}
+                }
+            }
+        } else if expr.get("drop").is_some() || expr.get("reject").is_some() {
+            info.action = "Block".to_string();
+        } else if expr.get("accept").is_some() {
+            info.action = "Allow".to_string();
+        }
+    }
+
+    info
+}
+
+// A rule added for both address families is stored as one nft rule per family under the
+// same comment; list it as one rule again.
+fn merge_family_rules(rules: Vec<FirewallRuleInfo>) -> Vec<FirewallRuleInfo> {
+    let mut merged: Vec<FirewallRuleInfo> = Vec::new();
+    for rule in rules {
+        match merged.iter_mut().find(|r| r.name == rule.name && r.enabled == rule.enabled) {
+            Some(existing) => {
+                existing.local_addresses.extend(rule.local_addresses);
+                existing.remote_addresses.extend(rule.remote_addresses);
+            },
+            None => merged.push(rule),
+        }
+    }
+    merged
+}
+
+fn is_inbound(direction: &str) -> Result<bool, FirewallError> {
+    match direction.to_lowercase().as_str() {
+        "inbound" | "in" => Ok(true),
+        "outbound" | "out" => Ok(false),
+        other => Err(FirewallError::ParseError(format!("Unknown rule direction: {}", other))),
+    }
+}
+
+fn chain_for(inbound: bool, enabled: bool) -> &'static str {
+    match (inbound, enabled) {
+        (true, true) => INPUT_CHAIN,
+        (false, true) => OUTPUT_CHAIN,
+        (true, false) => DISABLED_INPUT_CHAIN,
+        (false, false) => DISABLED_OUTPUT_CHAIN,
+    }
+}
+
+fn quote_comment(comment: &str) -> Result<String, FirewallError> {
+    if comment.contains('"') {
+        return Err(FirewallError::ParseError(format!("Rule name may not contain quotes: {}", comment)));
+    }
+    Ok(format!("\"{}\"", comment))
+}
+
+fn unsupported(what: &str) -> FirewallError {
+    FirewallError::CommandError(format!("{} are not supported by the nftables backend", what))
+}
+
+fn nft_values<T: std::fmt::Display>(values: &[T]) -> String {
+    match values {
+        [single] => single.to_string(),
+        _ => format!("{{ {} }}", values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+fn port_values(ports: &[PortSpec]) -> Result<String, FirewallError> {
+    if ports.iter().any(|p| matches!(p, PortSpec::Keyword(_))) {
+        return Err(unsupported("Port keywords"));
+    }
+    Ok(nft_values(ports))
+}
+
+// The addresses of one family, or None if the list is non-empty but has none of that family
+// (a rule for that family could never match).
+fn family_addresses(addresses: &[AddressSpec], ipv4: bool) -> Option<Vec<&AddressSpec>> {
+    let matching: Vec<&AddressSpec> = addresses.iter().filter(|a| a.is_ipv4() == Some(ipv4)).collect();
+    if matching.is_empty() && !addresses.is_empty() {
+        None
+    } else {
+        Some(matching)
+    }
+}
+
+// One command per address family the rule's addresses need (just one without addresses).
+fn build_add_rule_commands(rule: &FirewallRuleInfo) -> Result<Vec<String>, FirewallError> {
+    if rule.application_path.as_deref().is_some_and(|p| !p.is_empty()) {
+        return Err(unsupported("Program-based rules"));
+    }
+    if !rule.profiles.is_empty() {
+        return Err(unsupported("Firewall profiles"));
+    }
+    if !rule.interface_types.is_empty() {
+        return Err(unsupported("Interface types"));
+    }
+    if rule.edge_traversal.is_some_and(|e| e != EdgeTraversal::No) {
+        return Err(unsupported("Edge traversal settings"));
+    }
+    if rule.group.as_deref().is_some_and(|g| !g.is_empty()) {
+        return Err(unsupported("Rule groups"));
+    }
+    if rule.local_addresses.iter().chain(&rule.remote_addresses).any(|a| matches!(a, AddressSpec::Keyword(_))) {
+        return Err(unsupported("Address keywords"));
+    }
+
+    let inbound = is_inbound(&rule.direction)?;
+    let (local_port, remote_port, local_addr, remote_addr) = if inbound {
+        ("dport", "sport", "daddr", "saddr")
+    } else {
+        ("sport", "dport", "saddr", "daddr")
+    };
+
+    let protocol = rule.protocol.to_lowercase();
+    let local_ports = rule.effective_local_ports();
+    let has_ports = !local_ports.is_empty() || !rule.remote_ports.is_empty();
+    let mut l4proto = None;
+    let mut port_matches = Vec::new();
+    match protocol.as_str() {
+        "" | "any" => {},
+        "tcp" | "udp" => {
+            l4proto = Some(format!("meta l4proto {}", protocol));
+            if !local_ports.is_empty() {
+                port_matches.push(format!("{} {} {}", protocol, local_port, port_values(&local_ports)?));
+            }
+            if !rule.remote_ports.is_empty() {
+                port_matches.push(format!("{} {} {}", protocol, remote_port, port_values(&rule.remote_ports)?));
+            }
+        },
+        // netsh's names for ICMP.
+        "icmpv4" => l4proto = Some("meta l4proto icmp".to_string()),
+        "icmpv6" => l4proto = Some("meta l4proto ipv6-icmp".to_string()),
+        other => l4proto = Some(format!("meta l4proto {}", other)),
+    }
+    if has_ports && port_matches.is_empty() {
+        return Err(FirewallError::ParseError("Ports require the TCP or UDP protocol".to_string()));
+    }
+
+    let verdict = match rule.action.to_lowercase().as_str() {
+        "allow" => "accept",
+        "block" => "drop",
+        other => return Err(FirewallError::ParseError(format!("Unknown rule action: {}", other))),
+    };
+
+    let mut families = Vec::new();
+    if rule.local_addresses.is_empty() && rule.remote_addresses.is_empty() {
+        families.push(Vec::new());
+    } else {
+        for (ipv4, family) in [(true, "ip"), (false, "ip6")] {
+            let (Some(local), Some(remote)) = (
+                family_addresses(&rule.local_addresses, ipv4),
+                family_addresses(&rule.remote_addresses, ipv4),
+            ) else {
+                continue;
+            };
+            let mut matches = Vec::new();
+            if !local.is_empty() {
+                matches.push(format!("{} {} {}", family, local_addr, nft_values(&local)));
+            }
+            if !remote.is_empty() {
+                matches.push(format!("{} {} {}", family, remote_addr, nft_values(&remote)));
+            }
+            families.push(matches);
+        }
+        if families.is_empty() {
+            return Err(FirewallError::ParseError(
+                "Local and remote addresses have no address family in common".to_string()
+            ));
+        }
+    }
+
+    families
+        .into_iter()
+        .map(|addresses| {
+            let statement: Vec<String> = l4proto
+                .iter()
+                .cloned()
+                .chain(addresses)
+                .chain(port_matches.iter().cloned())
+                .chain(std::iter::once(verdict.to_string()))
+                .collect();
+            Ok(format!(
+                "add rule {} {} {} {} comment {}",
+                TABLE_FAMILY,
+                TABLE_NAME,
+                chain_for(inbound, rule.enabled),
+                statement.join(" "),
+                quote_comment(&rule.name)?
+            ))
+        })
+        .collect()
+}
+
+fn delete_rule_command(rule: &NftRule) -> String {
+    format!("delete rule {} {} {} handle {}", TABLE_FAMILY, TABLE_NAME, rule.chain, rule.handle)
+}
+
+fn split_families(ips: &[String]) -> Result<(Vec<String>, Vec<String>), FirewallError> {
+    let mut v4 = Vec::new();
+    let mut v6 = Vec::new();
+    for ip in ips {
+        match ip.parse::<IpAddr>() {
+            Ok(IpAddr::V4(addr)) => v4.push(addr.to_string()),
+            Ok(IpAddr::V6(addr)) => v6.push(addr.to_string()),
+            Err(_) => return Err(FirewallError::ParseError(format!("Invalid IP address: {}", ip))),
+        }
+    }
+    Ok((v4, v6))
+}
+
+// Set names are a short hash of the domain: replacing punctuation would make `a-b.com` and
+// `a.b.com` share a set, and long domains would exceed nft's set name limit. The rule comments
+// keep the readable domain.
+fn ip_set_base_name(set_name: &str) -> String {
+    let digest = format!("{:x}", Sha256::digest(set_name.to_lowercase().as_bytes()));
+    format!("blockset_{}", &digest[..16])
+}
+
+// The address sets a rule matches against.
+fn referenced_sets(rule: &NftRule) -> Vec<String> {
+    rule.expr
+        .iter()
+        .filter_map(|e| e.get("match")?.get("right")?.as_str()?.strip_prefix('@').map(String::from))
+        .collect()
+}
+
+fn ip_set_comment_names(set_name: &str) -> (String, String) {
+    (
+        format!("Block-Domain-Outbound-{}", set_name),
+        format!("Block-Domain-Inbound-{}", set_name),
+    )
+}
+
+fn block_ip_set_commands(listing: &NftListing, set: &IpSetBlock, v4: &[String], v6: &[String]) -> Result<Vec<String>, FirewallError> {
+    let set_name = set.name.as_str();
+    let mut commands = remove_ip_set_commands(listing, set_name, &set.existing_rules);
+
+    let base = ip_set_base_name(set_name);
+    let (outbound_name, inbound_name) = ip_set_comment_names(set_name);
+
+    for (suffix, family, set_type, addrs) in [("v4", "ip", "ipv4_addr", v4), ("v6", "ip6", "ipv6_addr", v6)] {
+        if addrs.is_empty() {
+            continue;
+        }
+        let name = format!("{}_{}", base, suffix);
+        commands.push(format!("add set {} {} {} {{ type {}; }}", TABLE_FAMILY, TABLE_NAME, name, set_type));
+        commands.push(format!("add element {} {} {} {{ {} }}", TABLE_FAMILY, TABLE_NAME, name, addrs.join(", ")));
+        commands.push(format!(
+            "add rule {} {} {} {} daddr @{} drop comment {}",
+            TABLE_FAMILY, TABLE_NAME, OUTPUT_CHAIN, family, name, quote_comment(&outbound_name)?
+        ));
+        commands.push(format!(
+            "add rule {} {} {} {} saddr @{} drop comment {}",
+            TABLE_FAMILY, TABLE_NAME, INPUT_CHAIN, family, name, quote_comment(&inbound_name)?
+        ));
+    }
+
+    Ok(commands)
+}
+
+// Deletes the named rules, then any of our address sets that no remaining rule refers to.
+fn remove_rules_commands(listing: &NftListing, rule_names: &[String]) -> Vec<String> {
+    let (removed, kept): (Vec<&NftRule>, Vec<&NftRule>) = listing.rules
+        .iter()
+        .partition(|r| matches!(&r.comment, Some(c) if rule_names.contains(c)));
+
+    let mut commands: Vec<String> = removed.into_iter().map(delete_rule_command).collect();
+    if commands.is_empty() {
+        return commands;
+    }
+
+    let referenced: String = kept.iter().map(|r| Value::Array(r.expr.clone()).to_string()).collect();
+    for set in listing.sets.iter().filter(|s| s.starts_with("blockset_")) {
+        if !referenced.contains(&format!("\"@{}\"", set)) {
+            commands.push(format!("delete set {} {} {}", TABLE_FAMILY, TABLE_NAME, set));
+        }
+    }
+
+    commands
+}
+
+fn remove_ip_set_commands(listing: &NftListing, set_name: &str, existing_rules: &[String]) -> Vec<String> {
+    let (outbound_name, inbound_name) = ip_set_comment_names(set_name);
+    let base = ip_set_base_name(set_name);
+
+    let rules: Vec<&NftRule> = listing.rules
+        .iter()
+        .filter(|r| matches!(
+            r.comment.as_deref(),
+            Some(c) if c == outbound_name || c == inbound_name || existing_rules.iter().any(|e| e == c)
+        ))
+        .collect();
+    let mut commands: Vec<String> = rules.iter().map(|r| delete_rule_command(r)).collect();
+
+    // The rules' own sets are deleted too, which also cleans up sets named by older versions.
+    let mut sets: Vec<String> = rules.iter().flat_map(|r| referenced_sets(r)).collect();
+    sets.extend(["v4", "v6"].map(|suffix| format!("{}_{}", base, suffix)));
+    sets.sort();
+    sets.dedup();
+    for name in sets.iter().filter(|s| listing.sets.contains(s)) {
+        commands.push(format!("delete set {} {} {}", TABLE_FAMILY, TABLE_NAME, name));
+    }
+
+    commands
+}
+
+#[async_trait]
+impl FirewallBackend for NftablesBackend {
+    fn name(&self) -> &'static str {
+        "nftables"
+    }
+
+    async fn list_rules(&self) -> Result<Vec<FirewallRuleInfo>, FirewallError> {
+        let listing = self.list_table().await?;
+        Ok(merge_family_rules(listing.rules.iter().map(rule_to_info).collect()))
+    }
+
+    async fn add_rule(&self, rule: &FirewallRuleInfo) -> Result<(), FirewallError> {
+        let commands = build_add_rule_commands(rule)?;
+        self.apply(&commands).await
+    }
+
+    async fn apply_rule_changes(&self, remove: &[String], add: &[FirewallRuleInfo]) -> Result<(), FirewallError> {
+        let mut additions = Vec::new();
+        for rule in add {
+            additions.extend(build_add_rule_commands(rule)?);
+        }
+
+        let mut commands = Vec::new();
+        if !remove.is_empty() {
+            let listing = self.list_table().await?;
+            commands = remove_rules_commands(&listing, remove);
+        }
+        commands.extend(additions);
+
+        if commands.is_empty() {
+            return Ok(());
+        }
+        self.apply(&commands).await
+    }
+
+    async fn remove_rule(&self, rule_name: &str) -> Result<(), FirewallError> {
+        let listing = self.list_table().await?;
+        let commands: Vec<String> = listing.rules
+            .iter()
+            .filter(|r| r.comment.as_deref() == Some(rule_name))
+            .map(delete_rule_command)
+            .collect();
+
+        if commands.is_empty() {
+            return Err(FirewallError::RuleNotFound(format!("No rules named {}", rule_name)));
+        }
+
+        self.apply(&commands).await
+    }
+
+    async fn set_rule_enabled(&self, rule_name: &str, enable: bool) -> Result<(), FirewallError> {
+        let listing = self.list_table().await?;
+        let mut commands = Vec::new();
+        let mut found = false;
+
+        // Rules are moved with their original expressions rather than rebuilt from the rule
+        // model, which can't describe every match (a domain block's address set, for one).
+        for rule in listing.rules.iter().filter(|r| r.comment.as_deref() == Some(rule_name)) {
+            found = true;
+            let inbound = rule.chain == INPUT_CHAIN || rule.chain == DISABLED_INPUT_CHAIN;
+            let target = chain_for(inbound, enable);
+            if rule.chain == target {
+                continue;
+            }
+            commands.push(delete_rule_command(rule));
+            commands.push(format!(
+                "add rule {} {} {} {} comment {}",
+                TABLE_FAMILY,
+                TABLE_NAME,
+                target,
+                rule_statement(rule)?,
+                quote_comment(rule_name)?
+            ));
+        }
+
+        if !found {
+            return Err(FirewallError::RuleNotFound(format!("No rules named {}", rule_name)));
+        }
+        if commands.is_empty() {
+            return Ok(());
+        }
+
+        self.apply(&commands).await
+    }
+
+    async fn block_ip_sets(&self, sets: &[IpSetBlock]) -> Result<(), FirewallError> {
+        if sets.is_empty() {
+            return Ok(());
+        }
+
+        let families = sets
+            .iter()
+            .map(|set| split_families(&set.ips))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let listing = self.list_table().await?;
+        let mut commands = Vec::new();
+        for (set, (v4, v6)) in sets.iter().zip(&families) {
+            commands.extend(block_ip_set_commands(&listing, set, v4, v6)?);
+        }
+
+        self.apply(&commands).await
+    }
+
+    async fn update_ip_set(
+        &self,
+        set_name: &str,
+        current: &[String],
+        added: &[String],
+        removed: &[String],
+        existing_rules: &[String]
+    ) -> Result<(), FirewallError> {
+        let (added_v4, added_v6) = split_families(added)?;
+        let (removed_v4, removed_v6) = split_families(removed)?;
+
+        let listing = self.list_table().await?;
+        let base = ip_set_base_name(set_name);
+
+        let mut commands = Vec::new();
+        for (suffix, added, removed) in [("v4", &added_v4, &removed_v4), ("v6", &added_v6, &removed_v6)] {
+            if added.is_empty() && removed.is_empty() {
+                continue;
+            }
+            let name = format!("{}_{}", base, suffix);
+            // A family that wasn't blocked before has no set or rules yet, so rebuild everything.
+            if !listing.sets.contains(&name) {
+                return self.block_ip_set(set_name, current, existing_rules).await;
+            }
+            if !removed.is_empty() {
+                commands.push(format!("delete element {} {} {} {{ {} }}", TABLE_FAMILY, TABLE_NAME, name, removed.join(", ")));
+            }
+            if !added.is_empty() {
+                commands.push(format!("add element {} {} {} {{ {} }}", TABLE_FAMILY, TABLE_NAME, name, added.join(", ")));
+            }
+        }
+
+        if commands.is_empty() {
+            return Ok(());
+        }
+        self.apply(&commands).await
+    }
+
+    // Both address families share the same rule comments, so a set always has two names.
+    fn ip_set_rule_names(&self, set_name: &str, _ips: &[String]) -> Vec<String> {
+        let (outbound_name, inbound_name) = ip_set_comment_names(set_name);
+        vec![outbound_name, inbound_name]
+    }
+
+    fn legacy_ip_set_rule_names(&self, set_name: &str) -> Vec<String> {
+        self.ip_set_rule_names(set_name, &[])
+    }
+
+    async fn remove_rules(&self, rule_names: &[String]) -> Result<(), FirewallError> {
+        if rule_names.is_empty() {
+            return Ok(());
+        }
+
+        let listing = self.list_table().await?;
+        let commands = remove_rules_commands(&listing, rule_names);
+
+        if commands.is_empty() {
+            return Ok(());
+        }
+
+        self.apply(&commands).await
+    }
+
+    async fn export_config(&self, path: &Path) -> Result<(), FirewallError> {
+        let ruleset = run_privileged_command(self.runner.as_ref(), "nft", vec!["list", "ruleset"]).await?;
+        std::fs::write(path, ruleset)
+            .map_err(|e| FirewallError::CommandError(format!("Failed to write firewall export: {}", e)))
+    }
+
+    // Flushing and loading happen in one script, which nft applies atomically.
+    async fn import_config(&self, path: &Path) -> Result<(), FirewallError> {
+        let ruleset = std::fs::read_to_string(path)
+            .map_err(|e| FirewallError::CommandError(format!("Failed to read firewall export: {}", e)))?;
+        run_elevated_nft_script(self.runner.as_ref(), &format!("flush ruleset\n{}", ruleset)).await
+            .map_err(|e| e.context("Failed to import firewall configuration"))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall::command_runner::{CommandOutput, ScriptKind};
+    use crate::firewall::rule_model::{parse_list, FirewallProfile};
+    use crate::firewall::testing::{FakeCommandRunner, Invocation};
+
+    const LISTING: &str = r#"{"nftables": [
+        {"metainfo": {"version": "1.0.6", "json_schema_version": 1}},
+        {"table": {"family": "inet", "name": "security_smile", "handle": 1}},
+        {"set": {"family": "inet", "name": "blockset_a379a6f6eeafb9a5_v4", "table": "security_smile", "type": "ipv4_addr", "handle": 5, "elem": ["93.184.216.34"]}},
+        {"rule": {"family": "inet", "table": "security_smile", "chain": "input", "handle": 7, "comment": "Allow SSH",
+            "expr": [{"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}}, "right": 22}}, {"accept": null}]}},
+        {"rule": {"family": "inet", "table": "security_smile", "chain": "disabled_output", "handle": 8, "comment": "Block DNS",
+            "expr": [{"match": {"op": "==", "left": {"meta": {"key": "l4proto"}}, "right": "udp"}}, {"drop": null}]}},
+        {"rule": {"family": "inet", "table": "security_smile", "chain": "output", "handle": 9, "comment": "Block-Domain-Outbound-example.com",
+            "expr": [{"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "daddr"}}, "right": "@blockset_a379a6f6eeafb9a5_v4"}}, {"drop": null}]}},
+        {"rule": {"family": "inet", "table": "security_smile", "chain": "input", "handle": 10, "comment": "Block-Domain-Inbound-example.com",
+            "expr": [{"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}}, "right": "@blockset_a379a6f6eeafb9a5_v4"}}, {"drop": null}]}}
+    ]}"#;
+
+    fn backend() -> (Arc<FakeCommandRunner>, NftablesBackend) {
+        let runner = Arc::new(FakeCommandRunner::new());
+        (runner.clone(), NftablesBackend::new(runner))
+    }
+
+    fn script_commands(script: &str) -> Vec<&str> {
+        script.lines().filter(|l| !l.starts_with(' ') && !l.starts_with("table") && *l != "}").collect()
+    }
+
+    #[tokio::test]
+    async fn list_rules_maps_chains_and_verdicts() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::ok(LISTING));
+
+        let rules = backend.list_rules().await.unwrap();
+
+        assert_eq!(runner.invocations()[0], Invocation::Command {
+            program: "nft".to_string(),
+            args: vec!["-j", "list", "table", "inet", "security_smile"].into_iter().map(String::from).collect(),
+            elevated: true,
+        });
+        assert_eq!(rules.len(), 4);
+        assert_eq!(rules[0].name, "Allow SSH");
+        assert_eq!(rules[0].direction, "In");
+        assert_eq!(rules[0].protocol, "TCP");
+        assert_eq!(rules[0].port, Some(22));
+        assert_eq!(rules[0].action, "Allow");
+        assert!(rules[0].enabled);
+        assert_eq!(rules[1].protocol, "UDP");
+        assert_eq!(rules[1].action, "Block");
+        assert!(!rules[1].enabled);
+        assert_eq!(rules[2].direction, "Out");
+        assert!(rules[2].description.contains("blockset_a379a6f6eeafb9a5_v4"));
+    }
+
+    #[tokio::test]
+    async fn list_rules_treats_missing_table_as_empty() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::failed(1, "Error: No such file or directory\nlist table inet security_smile"));
+
+        assert!(backend.list_rules().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn add_rule_creates_table_and_rule_in_one_script() {
+        let (runner, backend) = backend();
+        let rule = FirewallRuleInfo {
+            name: "Block HTTPS".to_string(),
+            description: String::new(),
+            application_path: None,
+            port: Some(443),
+            protocol: "TCP".to_string(),
+            direction: "Inbound".to_string(),
+            action: "Block".to_string(),
+            enabled: true,
+            ..Default::default()
+        };
+
+        backend.add_rule(&rule).await.unwrap();
+
+        let invocations = runner.invocations();
+        assert_eq!(invocations.len(), 1);
+        let Invocation::Script { kind, script } = &invocations[0] else {
+            panic!("expected an elevated script");
+        };
+        assert_eq!(*kind, ScriptKind::Nft);
+        assert!(script.starts_with("table inet security_smile {"));
+        assert!(script.contains("chain input { type filter hook input priority 0; policy accept; }"));
+        assert_eq!(script_commands(script), vec![
+            "add rule inet security_smile input meta l4proto tcp tcp dport 443 drop comment \"Block HTTPS\""
+        ]);
+    }
+
+    fn web_rule() -> FirewallRuleInfo {
+        FirewallRuleInfo {
+            name: "Office Web".to_string(),
+            protocol: "TCP".to_string(),
+            direction: "In".to_string(),
+            action: "Allow".to_string(),
+            enabled: true,
+            local_ports: parse_list("80,8000-8080").unwrap(),
+            remote_ports: parse_list("1024-65535").unwrap(),
+            remote_addresses: parse_list("10.0.0.0/8,192.168.1.5,2001:db8::/32").unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn add_rule_splits_address_families() {
+        let (runner, backend) = backend();
+
+        backend.add_rule(&web_rule()).await.unwrap();
+
+        assert_eq!(script_commands(&runner.scripts()[0]), vec![
+            "add rule inet security_smile input meta l4proto tcp ip saddr { 10.0.0.0/8, 192.168.1.5 } tcp dport { 80, 8000-8080 } tcp sport 1024-65535 accept comment \"Office Web\"",
+            "add rule inet security_smile input meta l4proto tcp ip6 saddr 2001:db8::/32 tcp dport { 80, 8000-8080 } tcp sport 1024-65535 accept comment \"Office Web\"",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn listed_rules_match_what_was_added() {
+        let (runner, backend) = backend();
+        // `nft -j` output for the two rules above.
+        runner.respond("nft", CommandOutput::ok(r#"{"nftables": [
+            {"rule": {"family": "inet", "table": "security_smile", "chain": "input", "handle": 3, "comment": "Office Web", "expr": [
+                {"match": {"op": "==", "left": {"meta": {"key": "l4proto"}}, "right": "tcp"}},
+                {"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}}, "right": {"set": [{"prefix": {"addr": "10.0.0.0", "len": 8}}, "192.168.1.5"]}}},
+                {"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}}, "right": {"set": [80, {"range": [8000, 8080]}]}}},
+                {"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "sport"}}, "right": {"range": [1024, 65535]}}},
+                {"accept": null}]}},
+            {"rule": {"family": "inet", "table": "security_smile", "chain": "input", "handle": 4, "comment": "Office Web", "expr": [
+                {"match": {"op": "==", "left": {"meta": {"key": "l4proto"}}, "right": "tcp"}},
+                {"match": {"op": "==", "left": {"payload": {"protocol": "ip6", "field": "saddr"}}, "right": {"prefix": {"addr": "2001:db8::", "len": 32}}}},
+                {"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}}, "right": {"set": [80, {"range": [8000, 8080]}]}}},
+                {"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "sport"}}, "right": {"range": [1024, 65535]}}},
+                {"accept": null}]}}
+        ]}"#));
+
+        let rules = backend.list_rules().await.unwrap();
+
+        assert_eq!(rules, vec![web_rule()]);
+    }
+
+    #[tokio::test]
+    async fn rule_changes_are_one_atomic_script() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::ok(LISTING));
+        let mut ping = web_rule();
+        ping.name = "Ping".to_string();
+        ping.protocol = "ICMPv4".to_string();
+        ping.local_ports.clear();
+        ping.remote_ports.clear();
+        ping.remote_addresses = parse_list("10.0.0.0/8").unwrap();
+
+        backend.apply_rule_changes(&["Allow SSH".to_string()], &[ping]).await.unwrap();
+
+        assert_eq!(script_commands(&runner.scripts()[0]), vec![
+            "delete rule inet security_smile input handle 7",
+            "add rule inet security_smile input meta l4proto icmp ip saddr 10.0.0.0/8 accept comment \"Ping\"",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn add_rule_rejects_windows_only_settings() {
+        let (runner, backend) = backend();
+        let mut with_profiles = web_rule();
+        with_profiles.profiles = vec![FirewallProfile::Domain];
+        let mut with_keyword = web_rule();
+        with_keyword.remote_addresses = parse_list("LocalSubnet").unwrap();
+        let mut mixed_families = web_rule();
+        mixed_families.local_addresses = parse_list("fe80::1").unwrap();
+        mixed_families.remote_addresses = parse_list("10.0.0.0/8").unwrap();
+
+        assert!(backend.add_rule(&with_profiles).await.unwrap_err().to_string().contains("profiles"));
+        assert!(backend.add_rule(&with_keyword).await.unwrap_err().to_string().contains("Address keywords"));
+        assert!(backend.add_rule(&mixed_families).await.unwrap_err().to_string().contains("address family"));
+        assert!(runner.invocations().is_empty());
+    }
+
+    #[tokio::test]
+    async fn add_rule_rejects_program_rules() {
+        let (runner, backend) = backend();
+        let rule = FirewallRuleInfo {
+            name: "App".to_string(),
+            description: String::new(),
+            application_path: Some("/usr/bin/curl".to_string()),
+            port: None,
+            protocol: "Any".to_string(),
+            direction: "Outbound".to_string(),
+            action: "Block".to_string(),
+            enabled: true,
+            ..Default::default()
+        };
+
+        assert!(backend.add_rule(&rule).await.is_err());
+        assert!(runner.invocations().is_empty());
+    }
+
+    #[tokio::test]
+    async fn remove_rule_deletes_by_handle() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::ok(LISTING));
+
+        backend.remove_rule("Allow SSH").await.unwrap();
+
+        assert_eq!(script_commands(&runner.scripts()[0]), vec![
+            "delete rule inet security_smile input handle 7"
+        ]);
+    }
+
+    #[tokio::test]
+    async fn remove_unknown_rule_fails_without_script() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::ok(LISTING));
+
+        assert!(backend.remove_rule("Nope").await.is_err());
+        assert!(runner.scripts().is_empty());
+    }
+
+    #[tokio::test]
+    async fn enable_rule_moves_it_into_hooked_chain() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::ok(LISTING));
+
+        backend.set_rule_enabled("Block DNS", true).await.unwrap();
+
+        assert_eq!(script_commands(&runner.scripts()[0]), vec![
+            "delete rule inet security_smile disabled_output handle 8",
+            "add rule inet security_smile output meta l4proto udp drop comment \"Block DNS\"",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn disabling_and_enabling_keeps_address_set_matches() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::ok(LISTING));
+        runner.respond("nft", CommandOutput::ok(r#"{"nftables": [
+            {"rule": {"family": "inet", "table": "security_smile", "chain": "disabled_output", "handle": 11, "comment": "Block-Domain-Outbound-example.com",
+                "expr": [{"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "daddr"}}, "right": "@blockset_a379a6f6eeafb9a5_v4"}}, {"drop": null}]}}
+        ]}"#));
+
+        backend.set_rule_enabled("Block-Domain-Outbound-example.com", false).await.unwrap();
+        backend.set_rule_enabled("Block-Domain-Outbound-example.com", true).await.unwrap();
+
+        let scripts = runner.scripts();
+        assert_eq!(script_commands(&scripts[0]), vec![
+            "delete rule inet security_smile output handle 9",
+            "add rule inet security_smile disabled_output ip daddr @blockset_a379a6f6eeafb9a5_v4 drop comment \"Block-Domain-Outbound-example.com\"",
+        ]);
+        assert_eq!(script_commands(&scripts[1]), vec![
+            "delete rule inet security_smile disabled_output handle 11",
+            "add rule inet security_smile output ip daddr @blockset_a379a6f6eeafb9a5_v4 drop comment \"Block-Domain-Outbound-example.com\"",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn set_rule_enabled_refuses_rules_it_cannot_rewrite() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::ok(r#"{"nftables": [
+            {"rule": {"family": "inet", "table": "security_smile", "chain": "input", "handle": 3, "comment": "Tracked",
+                "expr": [{"match": {"op": "in", "left": {"ct": {"key": "state"}}, "right": "established"}}, {"accept": null}]}}
+        ]}"#));
+
+        assert!(backend.set_rule_enabled("Tracked", false).await.is_err());
+        assert!(runner.scripts().is_empty());
+    }
+
+    #[test]
+    fn set_names_are_distinct_and_short() {
+        let long = format!("{}.example.com", "a".repeat(200));
+        let names = [ip_set_base_name("a-b.com"), ip_set_base_name("a.b.com"), ip_set_base_name(&long)];
+
+        assert_ne!(names[0], names[1]);
+        assert!(names.iter().all(|n| n.len() + "_v4".len() <= 31));
+        assert_eq!(ip_set_base_name("Example.com"), ip_set_base_name("example.com"));
+    }
+
+    #[tokio::test]
+    async fn block_ip_set_removes_sets_with_older_names() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::ok(&LISTING.replace("blockset_a379a6f6eeafb9a5_v4", "blockset_example_com_v4")));
+
+        backend.block_ip_set("example.com", &["1.2.3.4".to_string()], &[]).await.unwrap();
+
+        assert!(runner.scripts()[0].contains("delete set inet security_smile blockset_example_com_v4"));
+    }
+
+    #[tokio::test]
+    async fn block_ip_set_replaces_existing_sets() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::ok(LISTING));
+        let ips = vec!["1.2.3.4".to_string(), "2001:db8::1".to_string()];
+
+        backend.block_ip_set("example.com", &ips, &[]).await.unwrap();
+
+        assert_eq!(script_commands(&runner.scripts()[0]), vec![
+            "delete rule inet security_smile output handle 9",
+            "delete rule inet security_smile input handle 10",
+            "delete set inet security_smile blockset_a379a6f6eeafb9a5_v4",
+            "add set inet security_smile blockset_a379a6f6eeafb9a5_v4 { type ipv4_addr; }",
+            "add element inet security_smile blockset_a379a6f6eeafb9a5_v4 { 1.2.3.4 }",
+            "add rule inet security_smile output ip daddr @blockset_a379a6f6eeafb9a5_v4 drop comment \"Block-Domain-Outbound-example.com\"",
+            "add rule inet security_smile input ip saddr @blockset_a379a6f6eeafb9a5_v4 drop comment \"Block-Domain-Inbound-example.com\"",
+            "add set inet security_smile blockset_a379a6f6eeafb9a5_v6 { type ipv6_addr; }",
+            "add element inet security_smile blockset_a379a6f6eeafb9a5_v6 { 2001:db8::1 }",
+            "add rule inet security_smile output ip6 daddr @blockset_a379a6f6eeafb9a5_v6 drop comment \"Block-Domain-Outbound-example.com\"",
+            "add rule inet security_smile input ip6 saddr @blockset_a379a6f6eeafb9a5_v6 drop comment \"Block-Domain-Inbound-example.com\"",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn block_ip_sets_applies_every_set_in_one_script() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::ok(LISTING));
+        let sets = vec![
+            IpSetBlock { name: "example.com".to_string(), ips: vec!["1.2.3.4".to_string()], ..Default::default() },
+            IpSetBlock { name: "example.org".to_string(), ips: vec!["5.6.7.8".to_string()], ..Default::default() },
+        ];
+
+        backend.block_ip_sets(&sets).await.unwrap();
+
+        let scripts = runner.scripts();
+        assert_eq!(scripts.len(), 1);
+        assert!(scripts[0].contains("delete set inet security_smile blockset_a379a6f6eeafb9a5_v4"));
+        assert!(scripts[0].contains("add element inet security_smile blockset_a379a6f6eeafb9a5_v4 { 1.2.3.4 }"));
+        assert!(scripts[0].contains("add element inet security_smile blockset_bfabc37432958b06_v4 { 5.6.7.8 }"));
+    }
+
+    #[tokio::test]
+    async fn block_ip_sets_validates_before_touching_the_firewall() {
+        let (runner, backend) = backend();
+        let sets = vec![
+            IpSetBlock { name: "example.com".to_string(), ips: vec!["1.2.3.4".to_string()], ..Default::default() },
+            IpSetBlock { name: "example.org".to_string(), ips: vec!["bogus".to_string()], ..Default::default() },
+        ];
+
+        assert!(backend.block_ip_sets(&sets).await.is_err());
+        assert!(runner.invocations().is_empty());
+    }
+
+    #[tokio::test]
+    async fn block_ip_set_rejects_invalid_addresses() {
+        let (_, backend) = backend();
+
+        assert!(backend.block_ip_set("example.com", &["not-an-ip".to_string()], &[]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn update_ip_set_patches_existing_set_elements() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::ok(LISTING));
+        let current = vec!["5.6.7.8".to_string()];
+
+        backend.update_ip_set("example.com", &current, &["5.6.7.8".to_string()], &["1.2.3.4".to_string()], &[]).await.unwrap();
+
+        assert_eq!(script_commands(&runner.scripts()[0]), vec![
+            "delete element inet security_smile blockset_a379a6f6eeafb9a5_v4 { 1.2.3.4 }",
+            "add element inet security_smile blockset_a379a6f6eeafb9a5_v4 { 5.6.7.8 }",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn update_ip_set_rebuilds_when_a_new_family_appears() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::ok(LISTING));
+        runner.respond("nft", CommandOutput::ok(LISTING));
+        let current = vec!["1.2.3.4".to_string(), "2001:db8::1".to_string()];
+
+        backend.update_ip_set("example.com", &current, &["2001:db8::1".to_string()], &[], &[]).await.unwrap();
+
+        let script = &runner.scripts()[0];
+        assert!(script.contains("add set inet security_smile blockset_a379a6f6eeafb9a5_v6 { type ipv6_addr; }"));
+        assert!(script.contains("add element inet security_smile blockset_a379a6f6eeafb9a5_v4 { 1.2.3.4 }"));
+    }
+
+    #[tokio::test]
+    async fn remove_rules_deletes_named_rules_and_unused_sets() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::ok(LISTING));
+        let names = backend.ip_set_rule_names("example.com", &[]);
+
+        backend.remove_rules(&names).await.unwrap();
+
+        assert_eq!(script_commands(&runner.scripts()[0]), vec![
+            "delete rule inet security_smile output handle 9",
+            "delete rule inet security_smile input handle 10",
+            "delete set inet security_smile blockset_a379a6f6eeafb9a5_v4",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn remove_rules_keeps_sets_still_in_use() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::ok(LISTING));
+
+        backend.remove_rules(&["Block-Domain-Inbound-example.com".to_string()]).await.unwrap();
+
+        assert_eq!(script_commands(&runner.scripts()[0]), vec![
+            "delete rule inet security_smile input handle 10",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn remove_unknown_rules_is_a_no_op() {
+        let (runner, backend) = backend();
+        runner.respond("nft", CommandOutput::ok(LISTING));
+
+        backend.remove_rules(&["Block-Domain-Outbound-other.org".to_string()]).await.unwrap();
+        assert!(runner.scripts().is_empty());
+    }
+}
@@ -0,0 +1,369 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use async_trait::async_trait;
+use tauri::AppHandle;
+use tauri_plugin_shell::ShellExt;
+use crate::app_data::app_data_dir;
+use crate::firewall::common::FirewallError;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub success: bool,
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+// pkexec exits with these when authorization fails or its prompt is dismissed.
+const PKEXEC_NOT_AUTHORIZED: i32 = 127;
+const PKEXEC_DISMISSED: i32 = 126;
+
+// How netsh, PowerShell's RunAs and nft report missing privileges.
+const ELEVATION_FAILURE_MARKERS: &[&str] = &[
+    "requires elevation",
+    "run as administrator",
+    "access is denied",
+    "the operation was canceled by the user",
+    "operation not permitted",
+];
+
+// What netsh prints when asked to change a rule that doesn't exist.
+const RULE_NOT_FOUND_MARKER: &str = "no rules match the specified criteria";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptKind {
+    PowerShell,
+    Nft,
+}
+
+// Everything the firewall code runs on the host goes through this trait, so it can be
+// swapped for a fake in tests.
+#[async_trait]
+pub trait CommandRunner: Send + Sync {
+    async fn output(&self, program: &str, args: &[String]) -> Result<CommandOutput, FirewallError>;
+
+    // Runs the command with root privileges (pkexec on Linux when not already root).
+    async fn output_elevated(&self, program: &str, args: &[String]) -> Result<CommandOutput, FirewallError>;
+
+    // Runs a whole script in one elevated session, so a batch of changes only prompts once.
+    async fn run_elevated_script(&self, kind: ScriptKind, script: &str) -> Result<String, FirewallError>;
+}
+
+pub fn command_failure_message(output: &CommandOutput) -> String {
+    if !output.stderr.trim().is_empty() {
+        format!("Command failed: {}", output.stderr.trim())
+    } else if !output.stdout.trim().is_empty() {
+        format!("Command failed (no stderr): {}", output.stdout.trim())
+    } else {
+        "Command failed: Unknown error (no output)".to_string()
+    }
+}
+
+// Turns a failed run into the matching error, so missing privileges surface as AdminRequired
+// rather than a generic command failure.
+pub fn failure_error(output: &CommandOutput) -> FirewallError {
+    let message = command_failure_message(output);
+    let text = format!("{}\n{}", output.stderr, output.stdout).to_lowercase();
+
+    if ELEVATION_FAILURE_MARKERS.iter().any(|marker| text.contains(marker)) {
+        FirewallError::AdminRequired(message)
+    } else if text.contains(RULE_NOT_FOUND_MARKER) {
+        FirewallError::RuleNotFound(message)
+    } else {
+        FirewallError::CommandError(message)
+    }
+}
+
+fn spawn_error(program: &str, error: std::io::Error) -> FirewallError {
+    match error.kind() {
+        std::io::ErrorKind::NotFound => {
+            FirewallError::BackendMissing(format!("{} is not installed or not on PATH", program))
+        },
+        std::io::ErrorKind::PermissionDenied => {
+            FirewallError::AdminRequired(format!("Not allowed to run {}: {}", program, error))
+        },
+        _ => FirewallError::CommandError(format!("Command execution failed: {}", error)),
+    }
+}
+
+// Runs commands through Tauri's shell plugin; used by the desktop app.
+pub struct ShellCommandRunner {
+    app: AppHandle,
+}
+
+impl ShellCommandRunner {
+    pub fn new(app: AppHandle) -> Self {
+        ShellCommandRunner { app }
+    }
+}
+
+// Runs commands as plain child processes, for the CLI where there is no app to go through.
+pub struct ProcessCommandRunner {
+    script_dir: PathBuf,
+}
+
+impl ProcessCommandRunner {
+    pub fn new(script_dir: PathBuf) -> Self {
+        ProcessCommandRunner { script_dir }
+    }
+}
+
+// Writes the script to a file of its own, so concurrent runs (the app, the CLI and the daemon
+// share the directory) never execute each other's commands.
+fn write_script(script_dir: &Path, extension: &str, contents: &str) -> Result<PathBuf, FirewallError> {
+    if !script_dir.exists() {
+        fs::create_dir_all(script_dir)
+            .map_err(|e| FirewallError::CommandError(format!("Failed to create app data directory: {}", e)))?;
+    }
+
+    loop {
+        let file_name = format!("firewall_commands-{}-{:016x}.{}", std::process::id(), rand::random::<u64>(), extension);
+        let path = script_dir.join(file_name);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(contents.as_bytes())
+                    .map_err(|e| FirewallError::CommandError(format!("Failed to write script file: {}", e)))?;
+                return Ok(path);
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(FirewallError::CommandError(format!("Failed to write script file: {}", e))),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_running_as_root() -> bool {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata("/proc/self").map(|m| m.uid() == 0).unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_running_as_root() -> bool {
+    false
+}
+
+#[cfg(windows)]
+fn is_running_elevated() -> bool {
+    use windows::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows::Win32::Security::{GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY};
+    use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    unsafe {
+        let mut token = HANDLE::default();
+        if !OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token).as_bool() {
+            return false;
+        }
+        let mut elevation = TOKEN_ELEVATION::default();
+        let mut size = 0u32;
+        let queried = GetTokenInformation(
+            token,
+            TokenElevation,
+            Some(&mut elevation as *mut TOKEN_ELEVATION as *mut std::ffi::c_void),
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut size
+        ).as_bool();
+        CloseHandle(token);
+        queried && elevation.TokenIsElevated != 0
+    }
+}
+
+#[cfg(not(windows))]
+fn is_running_elevated() -> bool {
+    is_running_as_root()
+}
+
+// Whether elevated commands run without prompting anyone. Otherwise every one of them shows
+// a pkexec or UAC prompt, which only makes sense for changes the user asked for.
+pub fn can_elevate_unattended() -> bool {
+    is_running_elevated()
+}
+
+async fn output_elevated_with(runner: &dyn CommandRunner, program: &str, args: &[String]) -> Result<CommandOutput, FirewallError> {
+    if cfg!(windows) || is_running_as_root() {
+        return runner.output(program, args).await;
+    }
+
+    let mut full_args = vec![program.to_string()];
+    full_args.extend(args.iter().cloned());
+    let output = runner.output("pkexec", &full_args).await?;
+
+    match output.code {
+        Some(PKEXEC_DISMISSED) => Err(FirewallError::AdminRequired(
+            "The administrator authentication prompt was dismissed".to_string()
+        )),
+        // pkexec also uses 127 when the program itself can't be found.
+        Some(PKEXEC_NOT_AUTHORIZED) if output.stderr.contains("Cannot run program") => {
+            Err(FirewallError::BackendMissing(format!("{} is not installed or not on PATH", program)))
+        },
+        Some(PKEXEC_NOT_AUTHORIZED) => Err(FirewallError::AdminRequired(
+            format!("Not authorized to run {} as root: {}", program, output.stderr.trim())
+        )),
+        _ => Ok(output),
+    }
+}
+
+async fn run_elevated_script_with(
+    runner: &dyn CommandRunner,
+    script_dir: &Path,
+    kind: ScriptKind,
+    script: &str
+) -> Result<String, FirewallError> {
+    match kind {
+        ScriptKind::PowerShell => {
+            let script_path = write_script(script_dir, "ps1", &format!(
+                "# Firewall commands script\n{}\n\nWrite-Host \"Commands executed successfully.\"",
+                script
+            ))?;
+
+            // Start-Process doesn't pass on the elevated script's exit code by itself, so a failed
+            // script would otherwise look like a success.
+            let powershell_command = format!(
+                "$p = Start-Process PowerShell -ArgumentList '-ExecutionPolicy Bypass -WindowStyle Hidden -File \"{}\"' -Verb RunAs -PassThru -Wait -WindowStyle Hidden; exit $p.ExitCode",
+                script_path.to_string_lossy().replace("\\", "\\\\")
+            );
+
+            let args = [
+                "-WindowStyle".to_string(),
+                "Hidden".to_string(),
+                "-Command".to_string(),
+                powershell_command,
+            ];
+            let output = runner.output("powershell", &args).await
+                .map_err(|e| e.context("Failed to execute PowerShell"));
+
+            let _ = fs::remove_file(script_path);
+            let output = output?;
+
+            if !output.success {
+                return Err(failure_error(&output).context("PowerShell execution failed"));
+            }
+
+            Ok(output.stdout)
+        },
+        ScriptKind::Nft => {
+            let script_path = write_script(script_dir, "nft", script)?;
+
+            let args = ["-f".to_string(), script_path.to_string_lossy().to_string()];
+            let output = output_elevated_with(runner, "nft", &args).await;
+
+            let _ = fs::remove_file(script_path);
+            let output = output?;
+
+            if !output.success {
+                return Err(failure_error(&output).context("nft script execution failed"));
+            }
+
+            Ok(output.stdout)
+        },
+    }
+}
+
+#[async_trait]
+impl CommandRunner for ShellCommandRunner {
+    async fn output(&self, program: &str, args: &[String]) -> Result<CommandOutput, FirewallError> {
+        let output = self.app.shell()
+            .command(program)
+            .args(args)
+            .output()
+            .await
+            .map_err(|e| match e {
+                tauri_plugin_shell::Error::Io(e) => spawn_error(program, e),
+                e => FirewallError::CommandError(format!("Command execution failed: {}", e)),
+            })?;
+
+        Ok(CommandOutput {
+            success: output.status.success(),
+            code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        })
+    }
+
+    async fn output_elevated(&self, program: &str, args: &[String]) -> Result<CommandOutput, FirewallError> {
+        output_elevated_with(self, program, args).await
+    }
+
+    async fn run_elevated_script(&self, kind: ScriptKind, script: &str) -> Result<String, FirewallError> {
+        run_elevated_script_with(self, &app_data_dir(&self.app), kind, script).await
+    }
+}
+
+#[async_trait]
+impl CommandRunner for ProcessCommandRunner {
+    async fn output(&self, program: &str, args: &[String]) -> Result<CommandOutput, FirewallError> {
+        let output = tokio::process::Command::new(program)
+            .args(args)
+            .output()
+            .await
+            .map_err(|e| spawn_error(program, e))?;
+
+        Ok(CommandOutput {
+            success: output.status.success(),
+            code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        })
+    }
+
+    async fn output_elevated(&self, program: &str, args: &[String]) -> Result<CommandOutput, FirewallError> {
+        output_elevated_with(self, program, args).await
+    }
+
+    async fn run_elevated_script(&self, kind: ScriptKind, script: &str) -> Result<String, FirewallError> {
+        run_elevated_script_with(self, &self.script_dir, kind, script).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall::testing::FakeCommandRunner;
+
+    #[test]
+    fn classifies_elevation_and_missing_programs() {
+        let denied = CommandOutput::failed(1, "The requested operation requires elevation (Run as administrator).");
+        assert!(matches!(failure_error(&denied), FirewallError::AdminRequired(_)));
+
+        let missing_rule = CommandOutput::failed(1, "No rules match the specified criteria.");
+        assert!(matches!(failure_error(&missing_rule), FirewallError::RuleNotFound(_)));
+
+        let other = CommandOutput::failed(1, "Error: syntax error, unexpected junk");
+        assert!(matches!(failure_error(&other), FirewallError::CommandError(_)));
+
+        let not_installed = std::io::Error::new(std::io::ErrorKind::NotFound, "No such file or directory");
+        assert_eq!(
+            spawn_error("nft", not_installed).to_string(),
+            "Backend Missing: nft is not installed or not on PATH"
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_elevated_powershell_scripts_are_errors() {
+        let dir = std::env::temp_dir().join(format!("security-smile-runner-{}", std::process::id()));
+        let runner = FakeCommandRunner::new();
+        runner.respond("powershell", CommandOutput::failed(1, ""));
+
+        let result = run_elevated_script_with(&runner, &dir, ScriptKind::PowerShell, "exit 1").await;
+
+        assert!(result.is_err());
+        let argv = runner.invocations()[0].argv().unwrap();
+        let command = argv.last().unwrap();
+        assert!(command.starts_with("$p = Start-Process PowerShell"));
+        assert!(command.ends_with("-PassThru -Wait -WindowStyle Hidden; exit $p.ExitCode"));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn each_script_gets_its_own_file() {
+        let dir = std::env::temp_dir().join(format!("security-smile-scripts-{}", std::process::id()));
+
+        let first = write_script(&dir, "nft", "first").unwrap();
+        let second = write_script(&dir, "nft", "second").unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(fs::read_to_string(&first).unwrap(), "first");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "second");
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -1,16 +1,35 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::fmt;
-use tauri::AppHandle;
-use tauri::Manager;
-use tauri_plugin_shell::ShellExt;
 use serde::{Serialize, Deserialize};
-use std::fs;
+use crate::app_data::data_file_path;
+use crate::firewall::command_runner::{failure_error, CommandRunner, ScriptKind};
+use crate::firewall::domain_blocking::matcher::DomainTrie;
+use crate::firewall::domain_blocking::resolver::ResolvedDomain;
+use crate::firewall::rule_model::{AddressSpec, EdgeTraversal, FirewallProfile, InterfaceType, PortSpec};
 
 #[derive(Debug)]
 pub enum FirewallError {
     CommandError(String),
     ParseError(String),
     AdminRequired(String),
+    BackendMissing(String),
+    RuleNotFound(String),
+}
+
+impl FirewallError {
+    // Prefixes the message without losing the variant.
+    pub fn context(self, context: &str) -> Self {
+        let wrap = |msg: String| format!("{}: {}", context, msg);
+        match self {
+            FirewallError::CommandError(msg) => FirewallError::CommandError(wrap(msg)),
+            FirewallError::ParseError(msg) => FirewallError::ParseError(wrap(msg)),
+            FirewallError::AdminRequired(msg) => FirewallError::AdminRequired(wrap(msg)),
+            FirewallError::BackendMissing(msg) => FirewallError::BackendMissing(wrap(msg)),
+            FirewallError::RuleNotFound(msg) => FirewallError::RuleNotFound(wrap(msg)),
+        }
+    }
 }
 
 impl fmt::Display for FirewallError {
@@ -19,20 +38,62 @@ impl fmt::Display for FirewallError {
             FirewallError::CommandError(msg) => write!(f, "Command Error: {}", msg),
             FirewallError::ParseError(msg) => write!(f, "Parse Error: {}", msg),
             FirewallError::AdminRequired(msg) => write!(f, "Admin Required: {}", msg),
+            FirewallError::BackendMissing(msg) => write!(f, "Backend Missing: {}", msg),
+            FirewallError::RuleNotFound(msg) => write!(f, "Rule Not Found: {}", msg),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct FirewallRuleInfo {
     pub name: String,
     pub description: String,
     pub application_path: Option<String>,
+    // A single local port; `local_ports` takes over when it is set.
     pub port: Option<u16>,
     pub protocol: String,
     pub direction: String,
     pub action: String,
     pub enabled: bool,
+    // Empty lists and `None` mean "any".
+    #[serde(default)]
+    pub local_ports: Vec<PortSpec>,
+    #[serde(default)]
+    pub remote_ports: Vec<PortSpec>,
+    #[serde(default)]
+    pub local_addresses: Vec<AddressSpec>,
+    #[serde(default)]
+    pub remote_addresses: Vec<AddressSpec>,
+    #[serde(default)]
+    pub profiles: Vec<FirewallProfile>,
+    #[serde(default)]
+    pub interface_types: Vec<InterfaceType>,
+    #[serde(default)]
+    pub edge_traversal: Option<EdgeTraversal>,
+    #[serde(default)]
+    pub group: Option<String>,
+    // Fields the backend listed but that couldn't be parsed, by field name, as raw text.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub unparsed_fields: HashMap<String, String>,
+}
+
+impl FirewallRuleInfo {
+    pub fn effective_local_ports(&self) -> Vec<PortSpec> {
+        if self.local_ports.is_empty() {
+            self.port.map(PortSpec::single).into_iter().collect()
+        } else {
+            self.local_ports.clone()
+        }
+    }
+
+    // Keeps `port` filled in for callers that only know about a single port.
+    pub fn set_local_ports(&mut self, ports: Vec<PortSpec>) {
+        self.port = match ports.as_slice() {
+            [PortSpec::Range { start, end }] if start == end => Some(*start),
+            _ => None,
+        };
+        self.local_ports = ports;
+    }
 }
 
 pub struct FirewallState {
@@ -49,17 +110,87 @@ impl Default for FirewallState {
 
 pub struct BlockedDomains {
     pub domains: Arc<Mutex<Vec<String>>>,
+    pub resolutions: Arc<Mutex<HashMap<String, ResolvedDomain>>>,
+    // Lookup index over `domains`; only change the list through the methods below.
+    pub index: Arc<Mutex<DomainTrie>>,
+    // Held while an entry's firewall rules change along with the entry, so a refresh can't
+    // re-create the rules of a domain that is being unblocked.
+    pub changing: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl Default for BlockedDomains {
     fn default() -> Self {
         BlockedDomains {
             domains: Arc::new(Mutex::new(Vec::new())),
+            resolutions: Arc::new(Mutex::new(HashMap::new())),
+            index: Arc::new(Mutex::new(DomainTrie::default())),
+            changing: Arc::new(tokio::sync::Mutex::new(())),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl BlockedDomains {
+    pub fn add_domain(&self, entry: &str) -> bool {
+        let mut domains = self.domains.lock().unwrap();
+        if domains.iter().any(|d| d == entry) {
+            return false;
+        }
+        domains.push(entry.to_string());
+        self.index.lock().unwrap().insert(entry);
+        true
+    }
+
+    pub fn remove_domain(&self, entry: &str) -> bool {
+        let mut domains = self.domains.lock().unwrap();
+        let before = domains.len();
+        domains.retain(|d| d != entry);
+        self.index.lock().unwrap().remove(entry);
+        domains.len() != before
+    }
+
+    // Bulk variants for large lists; each returns the entries that actually changed.
+    pub fn add_domains(&self, entries: &[String]) -> Vec<String> {
+        let mut domains = self.domains.lock().unwrap();
+        let mut index = self.index.lock().unwrap();
+        let mut existing: HashSet<String> = domains.iter().cloned().collect();
+
+        let mut added = Vec::new();
+        for entry in entries {
+            if existing.insert(entry.clone()) {
+                domains.push(entry.clone());
+                index.insert(entry);
+                added.push(entry.clone());
+            }
+        }
+        added
+    }
+
+    pub fn remove_domains(&self, entries: &[String]) -> Vec<String> {
+        let mut domains = self.domains.lock().unwrap();
+        let mut index = self.index.lock().unwrap();
+        let to_remove: HashSet<&String> = entries.iter().collect();
+
+        let mut removed = Vec::new();
+        domains.retain(|d| {
+            if to_remove.contains(d) {
+                index.remove(d);
+                removed.push(d.clone());
+                false
+            } else {
+                true
+            }
+        });
+        removed
+    }
+
+    pub fn set_domains(&self, entries: Vec<String>) {
+        let mut domains = self.domains.lock().unwrap();
+        *self.index.lock().unwrap() = DomainTrie::from_entries(&entries);
+        *domains = entries;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NotificationSettings {
     pub domain_blocked_delay_seconds: u64,
     pub cooldown_seconds: u64,
@@ -76,6 +207,28 @@ impl Default for NotificationSettings {
     }
 }
 
+const NOTIFICATION_SETTINGS_FILE: &str = "notification_settings.json";
+
+// The defaults until settings have been saved once.
+pub fn load_notification_settings(data_dir: &Path) -> Result<NotificationSettings, String> {
+    let file_path = data_file_path(data_dir, NOTIFICATION_SETTINGS_FILE);
+    if !file_path.exists() {
+        return Ok(NotificationSettings::default());
+    }
+
+    let json = std::fs::read_to_string(&file_path)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to deserialize notification settings: {}", e))
+}
+
+pub fn save_notification_settings(data_dir: &Path, settings: &NotificationSettings) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(settings)
+        .map_err(|e| format!("Failed to serialize notification settings: {}", e))?;
+    std::fs::write(data_file_path(data_dir, NOTIFICATION_SETTINGS_FILE), json)
+        .map_err(|e| format!("Failed to write to file: {}", e))
+}
+
 pub struct NotificationState {
     pub settings: Arc<Mutex<NotificationSettings>>,
 }
@@ -87,99 +240,44 @@ impl Default for NotificationState {
         }
     }
 }
-pub async fn run_netsh_command(app: &AppHandle, args: Vec<&str>) -> Result<String, FirewallError> {
-    let output = app.shell()
-        .command("netsh")
-        .args(args)
-        .output()
-        .await
-        .map_err(|e| FirewallError::CommandError(e.to_string()))?;
-    
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-    
-    if !output.status.success() {
-        let err_msg = if !stderr.trim().is_empty() {
-            format!("Command failed: {}", stderr.trim())
-        } else if !stdout.trim().is_empty() {
-            format!("Command failed (no stderr): {}", stdout.trim())
-        } else {
-            "Command failed: Unknown error (no output)".to_string()
-        };
-        return Err(FirewallError::CommandError(err_msg));
-    }
-    
-    Ok(stdout)
+fn to_args(args: Vec<&str>) -> Vec<String> {
+    args.into_iter().map(|s| s.to_string()).collect()
 }
 
-pub async fn run_shell_command(app: &AppHandle, cmd: &str, args: Vec<&str>) -> Result<String, String> {
-    let output = app.shell()
-        .command(cmd)
-        .args(args)
-        .output()
-        .await
-        .map_err(|e| format!("Command execution failed: {}", e))?;
+pub async fn run_netsh_command(runner: &dyn CommandRunner, args: Vec<&str>) -> Result<String, FirewallError> {
+    let output = runner.output("netsh", &to_args(args)).await?;
     
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-      if !output.status.success() {
-        let _stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        return Err(format!("Command failed with exit code: {:?}", output.status.code()));
+    if !output.success {
+        return Err(failure_error(&output));
     }
     
-    Ok(stdout)
+    Ok(output.stdout)
 }
 
-pub async fn run_elevated_powershell(app: &AppHandle, script: &str) -> Result<String, String> {
-    let app_data_dir = app.path().app_data_dir()
-        .map_err(|e| format!("Failed to get app data directory: {}", e))?;
-    
-    if !app_data_dir.exists() {
-        fs::create_dir_all(&app_data_dir)
-            .map_err(|e| format!("Failed to create app data directory: {}", e))?;
-    }
-    
-    let script_path = app_data_dir.join("firewall_commands.ps1");
-    
-    fs::write(&script_path, format!(
-        "# Firewall commands script\n{}\n\nWrite-Host \"Commands executed successfully.\"",
-        script
-    ))
-    .map_err(|e| format!("Failed to write script file: {}", e))?;
-    
-    let powershell_command = format!(
-        "Start-Process PowerShell -ArgumentList '-ExecutionPolicy Bypass -WindowStyle Hidden -File \"{}\"' -Verb RunAs -Wait -WindowStyle Hidden",
-        script_path.to_string_lossy().replace("\\", "\\\\")
-    );
-    
-    let output = app.shell()
-        .command("powershell")
-        .args(["-WindowStyle", "Hidden", "-Command", &powershell_command])
-        .output()
-        .await
-        .map_err(|e| format!("Failed to execute PowerShell: {}", e))?;
-    
-    let _ = fs::remove_file(script_path);
-    
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        return Err(format!("PowerShell execution failed: {}", stderr));
-    }
-    
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    Ok(stdout)
+pub async fn run_elevated_powershell(runner: &dyn CommandRunner, script: &str) -> Result<String, FirewallError> {
+    runner.run_elevated_script(ScriptKind::PowerShell, script).await
 }
 
-pub async fn run_elevated_netsh_command(app: &AppHandle, args: Vec<&str>) -> Result<String, FirewallError> {
+pub async fn run_elevated_netsh_command(runner: &dyn CommandRunner, args: Vec<&str>) -> Result<String, FirewallError> {
     let netsh_cmd = format!("netsh {}", args.join(" "));
     let script = format!("{};", netsh_cmd);
 
-    match run_elevated_powershell(app, &script).await {
-        Ok(stdout) => Ok(stdout),
-        Err(e) => {
-            let err_msg = format!("Elevated netsh command failed: {}\nCommand: {}", e, netsh_cmd);
-            Err(FirewallError::CommandError(err_msg))
-        }
+    run_elevated_powershell(runner, &script).await
+        .map_err(|e| e.context(&format!("Elevated netsh command failed ({})", netsh_cmd)))
+}
+
+pub async fn run_privileged_command(runner: &dyn CommandRunner, cmd: &str, args: Vec<&str>) -> Result<String, FirewallError> {
+    let output = runner.output_elevated(cmd, &to_args(args)).await?;
+
+    if !output.success {
+        return Err(failure_error(&output));
     }
+
+    Ok(output.stdout)
+}
+
+pub async fn run_elevated_nft_script(runner: &dyn CommandRunner, script: &str) -> Result<String, FirewallError> {
+    runner.run_elevated_script(ScriptKind::Nft, script).await
 }
 
 pub fn elevate_at_startup() {
@@ -0,0 +1,538 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use tauri::{AppHandle, Emitter, State};
+use tokio::sync::Semaphore;
+use tokio::task::{self, JoinSet};
+use tracing::warn;
+use crate::app_data::app_data_dir;
+use crate::audit::audited;
+use crate::daemon::ipc::DaemonRequest;
+use crate::daemon::{forward, running_daemon};
+use crate::firewall::backend::{FirewallBackend, FirewallBackendState, IpSetBlock};
+use crate::firewall::common::BlockedDomains;
+use crate::firewall::ownership::RuleManifestState;
+use crate::firewall::snapshots::{snapshot_before_change, ChangeSource};
+use super::matcher::DomainPattern;
+use super::resolver::{DnsResolver, DnsResolverState, ResolvedDomain};
+use super::{save_domains_to_file, save_resolutions_to_file};
+use crate::error::{AppError, ErrorKind};
+
+const MAX_CONCURRENT_RESOLUTIONS: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DomainBatchResult {
+    pub domain: String,
+    pub success: bool,
+    pub error: Option<AppError>,
+    pub resolution: Option<ResolvedDomain>,
+}
+
+impl DomainBatchResult {
+    fn ok(domain: &str, resolution: Option<ResolvedDomain>) -> Self {
+        DomainBatchResult {
+            domain: domain.to_string(),
+            success: true,
+            error: None,
+            resolution,
+        }
+    }
+
+    fn failed(domain: &str, error: AppError) -> Self {
+        DomainBatchResult {
+            domain: domain.to_string(),
+            success: false,
+            error: Some(error),
+            resolution: None,
+        }
+    }
+}
+
+struct ParsedEntries {
+    // One per requested entry; None for invalid and repeated ones.
+    patterns: Vec<Option<DomainPattern>>,
+    results: Vec<DomainBatchResult>,
+    // (repeat, first): a repeated entry shares the result of its first occurrence.
+    duplicates: Vec<(usize, usize)>,
+}
+
+// Normalizes the requested entries, failing invalid ones.
+fn parse_entries(domains: &[String], include_subdomains: bool) -> ParsedEntries {
+    let mut first_seen = HashMap::new();
+    let mut patterns = Vec::new();
+    let mut results = Vec::new();
+    let mut duplicates = Vec::new();
+
+    for domain in domains {
+        match DomainPattern::parse(domain) {
+            Some(pattern) => {
+                let pattern = pattern.with_subdomains(include_subdomains);
+                let entry = pattern.entry();
+                match first_seen.get(&entry) {
+                    Some(&first) => {
+                        duplicates.push((results.len(), first));
+                        patterns.push(None);
+                    },
+                    None => {
+                        first_seen.insert(entry.clone(), results.len());
+                        patterns.push(Some(pattern));
+                    },
+                }
+                results.push(DomainBatchResult::ok(&entry, None));
+            },
+            None => {
+                results.push(DomainBatchResult::failed(domain, AppError::invalid_input(format!("Invalid domain format: {}", domain))));
+                patterns.push(None);
+            },
+        }
+    }
+
+    ParsedEntries { patterns, results, duplicates }
+}
+
+fn copy_duplicate_results(results: &mut [DomainBatchResult], duplicates: &[(usize, usize)]) {
+    for &(index, first) in duplicates {
+        results[index] = results[first].clone();
+    }
+}
+
+async fn resolve_concurrently(resolver: &DnsResolver, hosts: Vec<(usize, String)>) -> Vec<(usize, Result<ResolvedDomain, AppError>)> {
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_RESOLUTIONS));
+    let mut tasks = JoinSet::new();
+    // A task that panics only reports its task id, so this maps ids back to entries.
+    let mut spawned = HashMap::new();
+
+    for (index, host) in hosts {
+        let resolver = resolver.clone();
+        let permits = permits.clone();
+        let handle = tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let result = resolver.resolve(&host).await
+                .map_err(|e| e.context(&format!("Failed to resolve domain {}", host)))
+                .and_then(|resolved| {
+                    if resolved.addresses.is_empty() {
+                        Err(AppError::not_found(format!("No IP addresses found for domain: {}", host)))
+                    } else {
+                        Ok(resolved)
+                    }
+                });
+            (index, result)
+        });
+        spawned.insert(handle.id(), index);
+    }
+
+    join_indexed(tasks, &spawned).await
+}
+
+// Waits for every task; one that panicked fails the entry it was spawned for.
+async fn join_indexed<T: 'static>(
+    mut tasks: JoinSet<(usize, Result<T, AppError>)>,
+    spawned: &HashMap<task::Id, usize>
+) -> Vec<(usize, Result<T, AppError>)> {
+    let mut joined = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        match result {
+            Ok(result) => joined.push(result),
+            Err(e) => {
+                warn!("Domain resolution task failed: {}", e);
+                if let Some(index) = spawned.get(&e.id()) {
+                    joined.push((*index, Err(AppError::new(ErrorKind::Other, format!("Domain resolution failed: {}", e)))));
+                }
+            },
+        }
+    }
+    joined
+}
+
+// Resolves every entry concurrently and blocks all of them in one firewall transaction,
+// replacing the rules recorded in `owned` for entries that were blocked before.
+// Results come back in request order, one per requested entry. If the transaction fails,
+// every entry in it fails.
+pub async fn apply_domain_blocks(
+    resolver: &DnsResolver,
+    backend: &dyn FirewallBackend,
+    domains: &[String],
+    include_subdomains: bool,
+    owned: &HashMap<String, Vec<String>>
+) -> Vec<DomainBatchResult> {
+    let ParsedEntries { patterns, mut results, duplicates } = parse_entries(domains, include_subdomains);
+
+    let hosts: Vec<(usize, String)> = patterns
+        .iter()
+        .enumerate()
+        .filter_map(|(i, p)| p.as_ref().filter(|p| p.covers_apex()).map(|p| (i, p.domain.clone())))
+        .collect();
+    let resolved = resolve_concurrently(resolver, hosts).await;
+
+    let mut sets = Vec::new();
+    let mut in_transaction = Vec::new();
+    for (index, result) in resolved {
+        match result {
+            Ok(resolution) => {
+                let name = results[index].domain.clone();
+                sets.push(IpSetBlock {
+                    existing_rules: owned.get(&name).cloned().unwrap_or_default(),
+                    ips: resolution.ips(),
+                    name,
+                });
+                results[index].resolution = Some(resolution);
+                in_transaction.push(index);
+            },
+            Err(e) => results[index] = DomainBatchResult::failed(&results[index].domain, e),
+        }
+    }
+
+    if let Err(e) = backend.block_ip_sets(&sets).await {
+        let error = AppError::from(e).context("Failed to create firewall rules");
+        for index in in_transaction {
+            results[index] = DomainBatchResult::failed(&results[index].domain, error.clone());
+        }
+    }
+
+    copy_duplicate_results(&mut results, &duplicates);
+    results
+}
+
+// Removes the rules recorded in `owned` for each entry (every possible name for entries
+// with no record) in one firewall transaction.
+pub async fn remove_domain_blocks(
+    backend: &dyn FirewallBackend,
+    domains: &[String],
+    owned: &HashMap<String, Vec<String>>
+) -> Vec<DomainBatchResult> {
+    let mut first_seen = HashMap::new();
+    let mut duplicates = Vec::new();
+    let mut results = Vec::new();
+    let mut rule_names = Vec::new();
+    let mut in_transaction = Vec::new();
+
+    for domain in domains {
+        if let Some(&first) = first_seen.get(domain) {
+            duplicates.push((results.len(), first));
+            results.push(DomainBatchResult::ok(domain, None));
+            continue;
+        }
+        first_seen.insert(domain.clone(), results.len());
+        // Wildcard-only entries never had firewall rules.
+        if !matches!(DomainPattern::parse(domain), Some(pattern) if !pattern.covers_apex()) {
+            match owned.get(domain) {
+                Some(names) => rule_names.extend(names.iter().cloned()),
+                None => rule_names.extend(backend.legacy_ip_set_rule_names(domain)),
+            }
+            in_transaction.push(results.len());
+        }
+        results.push(DomainBatchResult::ok(domain, None));
+    }
+
+    if let Err(e) = backend.remove_rules(&rule_names).await {
+        let error = AppError::from(e).context("Failed to remove firewall rules");
+        for index in in_transaction {
+            results[index] = DomainBatchResult::failed(&results[index].domain, error.clone());
+        }
+    }
+
+    copy_duplicate_results(&mut results, &duplicates);
+    results
+}
+
+// Blocks `domains` and records the ones that succeeded in the blocked list, their
+// resolutions and the rule manifest.
+pub(crate) async fn block_and_record(
+    data_dir: &Path,
+    resolver: &DnsResolver,
+    backend: &dyn FirewallBackend,
+    state: &BlockedDomains,
+    manifest: &RuleManifestState,
+    domains: &[String],
+    include_subdomains: bool
+) -> Vec<DomainBatchResult> {
+    let _changing = state.changing.lock().await;
+    let owned = { manifest.manifest.lock().unwrap().domains.clone() };
+    let results = apply_domain_blocks(resolver, backend, domains, include_subdomains, &owned).await;
+
+    let blocked: Vec<String> = results.iter().filter(|r| r.success).map(|r| r.domain.clone()).collect();
+    if blocked.is_empty() {
+        return results;
+    }
+
+    state.add_domains(&blocked);
+    let domains_clone = { state.domains.lock().unwrap().clone() };
+    if let Err(e) = save_domains_to_file(data_dir, &domains_clone).await {
+        warn!("Failed to save blocked domains: {}", e);
+    }
+
+    let resolutions_clone = {
+        let mut resolutions = state.resolutions.lock().unwrap();
+        for result in results.iter().filter(|r| r.success) {
+            if let Some(resolution) = &result.resolution {
+                resolutions.insert(result.domain.clone(), resolution.clone());
+                manifest.record_domain(&result.domain, backend.ip_set_rule_names(&result.domain, &resolution.ips()));
+            }
+        }
+        resolutions.clone()
+    };
+    if let Err(e) = save_resolutions_to_file(data_dir, &resolutions_clone) {
+        warn!("Failed to save domain resolutions: {}", e);
+    }
+    if let Err(e) = manifest.save(data_dir) {
+        warn!("Failed to save rule manifest: {}", e);
+    }
+
+    results
+}
+
+pub(crate) fn emit_blocked(app: &AppHandle, results: &[DomainBatchResult]) {
+    for result in results.iter().filter(|r| r.success) {
+        if let Err(e) = app.emit("domain-blocked-notification", &result.domain) {
+            warn!("Failed to emit domain blocked event: {}", e);
+        }
+    }
+}
+
+// The only result of a one-entry batch, as an error when that entry failed.
+pub(crate) fn single_result(results: Vec<DomainBatchResult>) -> Result<DomainBatchResult, AppError> {
+    let mut result = results.into_iter().next()
+        .ok_or_else(|| AppError::from("The background service returned no result"))?;
+    match result.error.take() {
+        Some(error) => Err(error),
+        None => Ok(result),
+    }
+}
+
+// Unblocks `domains` and drops the ones that succeeded from the blocked list, their
+// resolutions and the rule manifest.
+pub(crate) async fn unblock_and_record(
+    data_dir: &Path,
+    backend: &dyn FirewallBackend,
+    state: &BlockedDomains,
+    manifest: &RuleManifestState,
+    domains: &[String]
+) -> Vec<DomainBatchResult> {
+    let _changing = state.changing.lock().await;
+    let owned = { manifest.manifest.lock().unwrap().domains.clone() };
+    let results = remove_domain_blocks(backend, domains, &owned).await;
+
+    let unblocked: Vec<String> = results.iter().filter(|r| r.success).map(|r| r.domain.clone()).collect();
+    if unblocked.is_empty() {
+        return results;
+    }
+
+    state.remove_domains(&unblocked);
+    manifest.forget_domains(&unblocked);
+    if let Err(e) = manifest.save(data_dir) {
+        warn!("Failed to save rule manifest: {}", e);
+    }
+    let domains_clone = { state.domains.lock().unwrap().clone() };
+    if let Err(e) = save_domains_to_file(data_dir, &domains_clone).await {
+        warn!("Failed to save blocked domains: {}", e);
+    }
+
+    let resolutions_clone = {
+        let mut resolutions = state.resolutions.lock().unwrap();
+        for domain in &unblocked {
+            resolutions.remove(domain);
+        }
+        resolutions.clone()
+    };
+    if let Err(e) = save_resolutions_to_file(data_dir, &resolutions_clone) {
+        warn!("Failed to save domain resolutions: {}", e);
+    }
+
+    results
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn block_domains(
+    app: AppHandle,
+    domains: Vec<String>,
+    include_subdomains: Option<bool>,
+    resolver: State<'_, DnsResolverState>,
+    backend: State<'_, FirewallBackendState>,
+    state: State<'_, BlockedDomains>,
+    manifest: State<'_, RuleManifestState>
+) -> Result<Vec<DomainBatchResult>, AppError> {
+    if let Some(socket) = running_daemon().await {
+        let request = DaemonRequest::BlockDomains { domains, include_subdomains: include_subdomains.unwrap_or(false) };
+        let results: Vec<DomainBatchResult> = forward(&socket, &request).await?;
+        emit_blocked(&app, &results);
+        return Ok(results);
+    }
+
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "domains": domains, "include_subdomains": include_subdomains });
+    audited(&data_dir, ChangeSource::Desktop, "block_domains", params, async {
+        snapshot_before_change(
+            &data_dir,
+            backend.backend.as_ref(),
+            &manifest,
+            &state,
+            ChangeSource::Desktop,
+            &format!("block_domains {}", domains.join(", "))
+        ).await?;
+        let results = block_and_record(
+            &data_dir,
+            &resolver.resolver(),
+            backend.backend.as_ref(),
+            &state,
+            &manifest,
+            &domains,
+            include_subdomains.unwrap_or(false)
+        ).await;
+        emit_blocked(&app, &results);
+        Ok(results)
+    }).await
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn unblock_domains(
+    app: AppHandle,
+    domains: Vec<String>,
+    backend: State<'_, FirewallBackendState>,
+    state: State<'_, BlockedDomains>,
+    manifest: State<'_, RuleManifestState>
+) -> Result<Vec<DomainBatchResult>, AppError> {
+    if let Some(socket) = running_daemon().await {
+        return forward(&socket, &DaemonRequest::UnblockDomains { domains }).await;
+    }
+
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "domains": domains });
+    audited(&data_dir, ChangeSource::Desktop, "unblock_domains", params, async {
+        snapshot_before_change(
+            &data_dir,
+            backend.backend.as_ref(),
+            &manifest,
+            &state,
+            ChangeSource::Desktop,
+            &format!("unblock_domains {}", domains.join(", "))
+        ).await?;
+        Ok(unblock_and_record(&data_dir, backend.backend.as_ref(), &state, &manifest, &domains).await)
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall::backend::netsh::NetshBackend;
+    use crate::firewall::testing::{resolver_for, FakeCommandRunner, StubDnsServer};
+
+    fn domains(list: &[&str]) -> Vec<String> {
+        list.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn blocks_resolvable_domains_in_one_elevated_run() {
+        let server = StubDnsServer::start().await;
+        server
+            .add_a("one.example.com", "1.1.1.1", 300)
+            .add_a("two.example.com", "2.2.2.2", 300);
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+        let request = domains(&["one.example.com", "missing.example.com", "bad domain", "*.ads.example.com", "ONE.example.com", "two.example.com"]);
+
+        let results = apply_domain_blocks(&resolver_for(&server), &backend, &request, false, &HashMap::new()).await;
+
+        let summary: Vec<(&str, bool)> = results.iter().map(|r| (r.domain.as_str(), r.success)).collect();
+        assert_eq!(summary, vec![
+            ("one.example.com", true),
+            ("missing.example.com", false),
+            ("bad domain", false),
+            ("*.ads.example.com", true),
+            ("one.example.com", true),
+            ("two.example.com", true),
+        ]);
+        assert_eq!(results[0].resolution.as_ref().unwrap().ips(), vec!["1.1.1.1"]);
+        assert!(results[3].resolution.is_none());
+        // The repeat shares the first entry's result but isn't blocked twice.
+        assert_eq!(results[4].resolution.as_ref().unwrap().ips(), vec!["1.1.1.1"]);
+        assert_eq!(results[1].error.as_ref().unwrap().kind, ErrorKind::NotFound);
+        assert_eq!(results[2].error.as_ref().unwrap().kind, ErrorKind::InvalidInput);
+
+        let scripts = runner.scripts();
+        assert_eq!(scripts.len(), 1);
+        assert_eq!(scripts[0].matches("remoteip=1.1.1.1").count(), 2);
+        assert!(scripts[0].contains("remoteip=2.2.2.2"));
+    }
+
+    #[tokio::test]
+    async fn refused_elevation_fails_every_domain_in_the_transaction() {
+        let server = StubDnsServer::start().await;
+        server.add_a("one.example.com", "1.1.1.1", 300);
+        let runner = Arc::new(FakeCommandRunner::new());
+        runner.respond_to_script(Err("The operation was canceled by the user.".to_string()));
+        let backend = NetshBackend::new(runner.clone());
+
+        let results = apply_domain_blocks(&resolver_for(&server), &backend, &domains(&["one.example.com", "*.ads.example.com"]), false, &HashMap::new()).await;
+
+        assert!(!results[0].success);
+        let error = results[0].error.as_ref().unwrap();
+        assert!(String::from(error.clone()).contains("canceled by the user"));
+        assert!(results[1].success);
+    }
+
+    #[tokio::test]
+    async fn unresolvable_domains_fail_without_touching_the_firewall() {
+        let server = StubDnsServer::start().await;
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+
+        let results = apply_domain_blocks(&resolver_for(&server), &backend, &domains(&["example.com"]), true, &HashMap::new()).await;
+
+        assert_eq!(results[0].domain, ".example.com");
+        assert!(!results[0].success);
+        assert_eq!(results[0].error.as_ref().unwrap().kind, ErrorKind::NotFound);
+        assert!(runner.invocations().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unblocks_in_one_elevated_run() {
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+
+        let owned = HashMap::from([("one.example.com".to_string(), domains(&["Block-Domain-Outbound-one.example.com"]))]);
+
+        let results = remove_domain_blocks(&backend, &domains(&["one.example.com", "*.ads.example.com", "two.example.com", "one.example.com"]), &owned).await;
+
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[3].domain, "one.example.com");
+        assert!(results.iter().all(|r| r.success));
+        let scripts = runner.scripts();
+        assert_eq!(scripts.len(), 1);
+        assert!(scripts[0].contains("Block-Domain-Outbound-one.example.com'"));
+        assert!(!scripts[0].contains("Block-Domain-Inbound-one.example.com"));
+        assert!(scripts[0].contains("Block-Domain-Outbound-two.example.com-19"));
+        assert!(!scripts[0].contains("ads.example.com"));
+    }
+
+    #[tokio::test]
+    async fn panicked_resolutions_fail_their_entry() {
+        let mut tasks = JoinSet::new();
+        let mut spawned = HashMap::new();
+        let handle = tasks.spawn(async { (0, Ok::<u32, AppError>(1)) });
+        spawned.insert(handle.id(), 0);
+        let handle = tasks.spawn(async { panic!("resolver bug") });
+        spawned.insert(handle.id(), 1);
+
+        let mut joined = join_indexed(tasks, &spawned).await;
+        joined.sort_by_key(|(index, _)| *index);
+
+        assert_eq!(joined.len(), 2);
+        assert_eq!(joined[0], (0, Ok(1)));
+        assert!(joined[1].1.as_ref().unwrap_err().message.contains("panicked"));
+    }
+
+    #[test]
+    fn forwarded_single_results_fail_with_their_error() {
+        let ok = single_result(vec![DomainBatchResult::ok("example.com", None)]).unwrap();
+        assert_eq!(ok.domain, "example.com");
+
+        let failed = single_result(vec![DomainBatchResult::failed("bad", AppError::invalid_input("Invalid domain format: bad"))]).unwrap_err();
+        assert_eq!(failed.kind, ErrorKind::InvalidInput);
+        assert_eq!(failed.message, "Invalid domain format: bad");
+        assert!(single_result(Vec::new()).is_err());
+    }
+}
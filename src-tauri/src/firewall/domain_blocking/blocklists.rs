@@ -0,0 +1,641 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use tauri::{AppHandle, State};
+use tracing::{info, warn};
+use crate::app_data::{app_data_dir, data_file_path};
+use crate::audit::audited;
+use crate::daemon::refuse_while_daemon_runs;
+use crate::firewall::backend::{FirewallBackend, FirewallBackendState};
+use crate::firewall::common::BlockedDomains;
+use crate::firewall::ownership::RuleManifestState;
+use crate::firewall::snapshots::ChangeSource;
+use super::matcher::DomainPattern;
+use super::batch::{remove_domain_blocks, DomainBatchResult};
+use super::{save_domains_to_file, save_resolutions_to_file};
+use crate::error::AppError;
+
+const MAX_REPORTED_REJECTIONS: usize = 50;
+// Names every hosts file maps to itself; they aren't blocks.
+const HOSTS_BUILTIN_NAMES: [&str; 10] = [
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-mcastprefix",
+    "ip6-allnodes",
+    "ip6-allrouters",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListFormat {
+    // Decide per line, so mixed lists work too.
+    #[default]
+    Auto,
+    Hosts,
+    AdBlock,
+    Plain,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParsedList {
+    pub entries: Vec<String>,
+    pub rejected: Vec<(usize, String)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlocklistSource {
+    pub name: String,
+    pub origin: String,
+    pub imported_at: u64,
+    pub entries: Vec<String>,
+    // Entries that were already blocked before this list first brought them in; removing
+    // the list leaves those alone.
+    pub preexisting: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlocklistSourceSummary {
+    pub name: String,
+    pub origin: String,
+    pub imported_at: u64,
+    pub entries: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub source: String,
+    pub accepted: usize,
+    pub duplicates: usize,
+    pub rejected: usize,
+    pub removed: usize,
+    // Line number and text of the first few rejected lines.
+    pub rejected_lines: Vec<(usize, String)>,
+}
+
+pub struct BlocklistSources {
+    pub sources: Arc<Mutex<HashMap<String, BlocklistSource>>>,
+}
+
+impl Default for BlocklistSources {
+    fn default() -> Self {
+        BlocklistSources {
+            sources: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+enum LineResult {
+    Entries(Vec<String>),
+    Skip,
+    Rejected,
+}
+
+fn parse_hosts_line(line: &str) -> LineResult {
+    let line = line.split('#').next().unwrap_or("").trim();
+    let mut tokens = line.split_whitespace();
+    let Some(ip) = tokens.next().and_then(|t| t.parse::<IpAddr>().ok()) else {
+        return LineResult::Rejected;
+    };
+    // Anything other than a null or loopback target is a redirect, not a block.
+    if !(ip.is_unspecified() || ip.is_loopback()) {
+        return LineResult::Rejected;
+    }
+
+    let mut entries = Vec::new();
+    for name in tokens {
+        if HOSTS_BUILTIN_NAMES.contains(&name.to_ascii_lowercase().as_str()) || name.parse::<IpAddr>().is_ok() {
+            continue;
+        }
+        match DomainPattern::parse(name) {
+            Some(pattern) => entries.push(pattern.entry()),
+            None => return LineResult::Rejected,
+        }
+    }
+
+    if entries.is_empty() {
+        LineResult::Skip
+    } else {
+        LineResult::Entries(entries)
+    }
+}
+
+// Only whole-domain network rules (`||example.com^`, optionally with options) translate to
+// a blocked domain; they cover subdomains too. Exceptions, paths and cosmetic rules don't.
+fn parse_adblock_line(line: &str) -> LineResult {
+    let Some(rule) = line.strip_prefix("||") else {
+        return LineResult::Rejected;
+    };
+    let Some((domain, rest)) = rule.split_once('^') else {
+        return LineResult::Rejected;
+    };
+    let rest = rest.strip_prefix('|').unwrap_or(rest);
+    if !rest.is_empty() {
+        let Some(options) = rest.strip_prefix('$') else {
+            return LineResult::Rejected;
+        };
+        if options.split(',').any(|o| o.starts_with("domain=") || o.starts_with("denyallow=")) {
+            return LineResult::Rejected;
+        }
+    }
+
+    match DomainPattern::parse(domain) {
+        Some(pattern) => LineResult::Entries(vec![pattern.with_subdomains(true).entry()]),
+        None => LineResult::Rejected,
+    }
+}
+
+fn parse_plain_line(line: &str) -> LineResult {
+    let line = line.split('#').next().unwrap_or("").trim();
+    if line.is_empty() {
+        return LineResult::Skip;
+    }
+    if line.split_whitespace().count() > 1 {
+        return LineResult::Rejected;
+    }
+    match DomainPattern::parse(line) {
+        Some(pattern) => LineResult::Entries(vec![pattern.entry()]),
+        None => LineResult::Rejected,
+    }
+}
+
+fn detect_line_format(line: &str) -> ListFormat {
+    if line.starts_with("||") || line.starts_with("@@") || line.contains("##") || line.contains('^') {
+        return ListFormat::AdBlock;
+    }
+    let mut tokens = line.split_whitespace();
+    match (tokens.next(), tokens.next()) {
+        (Some(first), Some(_)) if first.parse::<IpAddr>().is_ok() => ListFormat::Hosts,
+        _ => ListFormat::Plain,
+    }
+}
+
+pub fn parse_blocklist(content: &str, format: ListFormat) -> ParsedList {
+    let mut parsed = ParsedList::default();
+
+    for (index, raw) in content.lines().enumerate() {
+        let line = raw.trim();
+        // Comments in all three formats, plus the `[Adblock Plus 2.0]` header.
+        if line.is_empty() || line.starts_with('#') || line.starts_with('!') || line.starts_with('[') {
+            continue;
+        }
+
+        let line_format = match format {
+            ListFormat::Auto => detect_line_format(line),
+            other => other,
+        };
+        let result = match line_format {
+            ListFormat::Hosts => parse_hosts_line(line),
+            ListFormat::AdBlock => parse_adblock_line(line),
+            ListFormat::Plain | ListFormat::Auto => parse_plain_line(line),
+        };
+
+        match result {
+            LineResult::Entries(entries) => parsed.entries.extend(entries),
+            LineResult::Skip => {},
+            LineResult::Rejected => parsed.rejected.push((index + 1, line.to_string())),
+        }
+    }
+
+    parsed
+}
+
+// Entries the source may take with it: listed by no other source and not blocked before.
+fn removable_entries(sources: &HashMap<String, BlocklistSource>, source: &BlocklistSource, candidates: &[String]) -> Vec<String> {
+    let preexisting: HashSet<&String> = source.preexisting.iter().collect();
+    let listed_elsewhere: HashSet<&String> = sources
+        .values()
+        .filter(|other| other.name != source.name)
+        .flat_map(|other| other.entries.iter())
+        .collect();
+
+    candidates
+        .iter()
+        .filter(|e| !preexisting.contains(e) && !listed_elsewhere.contains(e))
+        .cloned()
+        .collect()
+}
+
+// Adds a parsed list to the blocked domains under `name`. Importing an existing name again
+// replaces it, dropping entries that are no longer listed.
+pub fn apply_import(
+    state: &BlockedDomains,
+    sources: &mut HashMap<String, BlocklistSource>,
+    name: &str,
+    origin: &str,
+    parsed: ParsedList
+) -> ImportReport {
+    let total = parsed.entries.len();
+    let mut seen = HashSet::new();
+    let mut unique = Vec::new();
+    for entry in parsed.entries {
+        if seen.insert(entry.clone()) {
+            unique.push(entry);
+        }
+    }
+    let in_file_duplicates = total - unique.len();
+
+    let previous = sources.remove(name);
+    // Only entries blocked outside of any list count as preexisting; ones from other lists
+    // are covered by those lists' own bookkeeping.
+    let listed_elsewhere: HashSet<&String> = sources.values().flat_map(|s| s.entries.iter()).collect();
+    let already_blocked: HashSet<String> = state.domains.lock().unwrap()
+        .iter()
+        .filter(|d| !listed_elsewhere.contains(d))
+        .cloned()
+        .collect();
+
+    // Keep the original baseline on re-import, so entries this list added earlier don't
+    // become "preexisting" just because they are blocked now.
+    let preexisting: Vec<String> = match &previous {
+        Some(previous) => {
+            let owned: HashSet<&String> = previous.entries.iter().collect();
+            let mut preexisting = previous.preexisting.clone();
+            preexisting.extend(unique.iter().filter(|e| already_blocked.contains(*e) && !owned.contains(e)).cloned());
+            preexisting
+        },
+        None => unique.iter().filter(|e| already_blocked.contains(*e)).cloned().collect(),
+    };
+
+    let source = BlocklistSource {
+        name: name.to_string(),
+        origin: origin.to_string(),
+        imported_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        entries: unique.clone(),
+        preexisting,
+    };
+
+    let removed = match &previous {
+        Some(previous) => {
+            let dropped: Vec<String> = previous.entries.iter().filter(|e| !seen.contains(*e)).cloned().collect();
+            state.remove_domains(&removable_entries(sources, &source, &dropped)).len()
+        },
+        None => 0,
+    };
+
+    let added = state.add_domains(&unique);
+    sources.insert(name.to_string(), source);
+
+    ImportReport {
+        source: name.to_string(),
+        accepted: added.len(),
+        duplicates: in_file_duplicates + (unique.len() - added.len()),
+        rejected: parsed.rejected.len(),
+        removed,
+        rejected_lines: parsed.rejected.into_iter().take(MAX_REPORTED_REJECTIONS).collect(),
+    }
+}
+
+pub fn apply_source_removal(
+    state: &BlockedDomains,
+    sources: &mut HashMap<String, BlocklistSource>,
+    name: &str
+) -> Result<Vec<String>, String> {
+    let source = sources.remove(name)
+        .ok_or_else(|| format!("Blocklist source not found: {}", name))?;
+    let removable = removable_entries(sources, &source, &source.entries);
+    Ok(state.remove_domains(&removable))
+}
+
+fn save_sources_to_file(data_dir: &Path, sources: &HashMap<String, BlocklistSource>) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(sources)
+        .map_err(|e| format!("Failed to serialize blocklist sources: {}", e))?;
+    fs::write(data_file_path(data_dir, "blocklist_sources.json"), json)
+        .map_err(|e| format!("Failed to write to file: {}", e))
+}
+
+fn load_sources_from_file(data_dir: &Path) -> Result<HashMap<String, BlocklistSource>, String> {
+    let file_path = data_file_path(data_dir, "blocklist_sources.json");
+    if !file_path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let json = fs::read_to_string(&file_path)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to deserialize blocklist sources: {}", e))
+}
+
+pub fn initialize_blocklist_sources(data_dir: &Path, state: &BlocklistSources) -> Result<(), String> {
+    let sources = load_sources_from_file(data_dir)?;
+    *state.sources.lock().unwrap() = sources;
+    Ok(())
+}
+
+// Imported entries join the blocked-domain list (and so the DNS sinkhole) but don't get
+// per-domain firewall rules; a list can hold far more names than is sensible to resolve.
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn import_blocklist(
+    app: AppHandle,
+    name: String,
+    path: String,
+    format: Option<ListFormat>,
+    state: State<'_, BlockedDomains>,
+    sources: State<'_, BlocklistSources>
+) -> Result<ImportReport, AppError> {
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "name": name, "path": path, "format": format });
+    audited(&data_dir, ChangeSource::Desktop, "import_blocklist", params, async {
+        refuse_while_daemon_runs("import blocklists").await?;
+        let name = name.trim().to_string();
+        if name.is_empty() {
+            return Err(AppError::invalid_input("A blocklist name is required"));
+        }
+
+        let content = fs::read_to_string(&path)
+            .map_err(|e| AppError::from(e).context(&format!("Failed to read blocklist {}", path)))?;
+        let parsed = parse_blocklist(&content, format.unwrap_or_default());
+
+        let (report, sources_clone) = {
+            let mut sources = sources.sources.lock().unwrap();
+            let report = apply_import(&state, &mut sources, &name, &path, parsed);
+            (report, sources.clone())
+        };
+
+        save_sources_to_file(&data_dir, &sources_clone)?;
+        let domains_clone = { state.domains.lock().unwrap().clone() };
+        save_domains_to_file(&data_dir, &domains_clone).await?;
+
+        info!(
+            "Imported blocklist {}: {} accepted, {} duplicates, {} rejected",
+            name, report.accepted, report.duplicates, report.rejected
+        );
+        Ok(report)
+    }).await
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_blocklist_sources(sources: State<'_, BlocklistSources>) -> Result<Vec<BlocklistSourceSummary>, AppError> {
+    let sources = sources.sources.lock().unwrap();
+    let mut summaries: Vec<BlocklistSourceSummary> = sources
+        .values()
+        .map(|s| BlocklistSourceSummary {
+            name: s.name.clone(),
+            origin: s.origin.clone(),
+            imported_at: s.imported_at,
+            entries: s.entries.len(),
+        })
+        .collect();
+    summaries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(summaries)
+}
+
+// An entry may also have been blocked individually after the import, with its own rules.
+// Those rules are deleted in one run; entries whose rules can't be deleted go back on the
+// blocked list and keep their resolution and manifest record.
+async fn unblock_removed_entries(
+    backend: &dyn FirewallBackend,
+    state: &BlockedDomains,
+    manifest: &RuleManifestState,
+    removed: &mut Vec<String>
+) -> (Vec<String>, Vec<DomainBatchResult>) {
+    let with_rules: Vec<String> = {
+        let resolutions = state.resolutions.lock().unwrap();
+        removed.iter().filter(|e| resolutions.contains_key(*e)).cloned().collect()
+    };
+    let owned = manifest.manifest.lock().unwrap().domains.clone();
+    let (unblocked, failed): (Vec<_>, Vec<_>) = remove_domain_blocks(backend, &with_rules, &owned)
+        .await
+        .into_iter()
+        .partition(|r| r.success);
+    let unblocked: Vec<String> = unblocked.into_iter().map(|r| r.domain).collect();
+
+    for result in &failed {
+        if let Some(error) = &result.error {
+            warn!("{}", error);
+        }
+        state.add_domain(&result.domain);
+    }
+    removed.retain(|entry| !failed.iter().any(|r| &r.domain == entry));
+
+    {
+        let mut resolutions = state.resolutions.lock().unwrap();
+        for entry in &unblocked {
+            resolutions.remove(entry);
+        }
+    }
+    manifest.forget_domains(&unblocked);
+    (unblocked, failed)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlocklistRemoval {
+    pub removed: Vec<String>,
+    // Entries whose firewall rules couldn't be deleted. They stay blocked, so unblocking them
+    // later retries.
+    pub failed: Vec<DomainBatchResult>,
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn remove_blocklist_source(
+    app: AppHandle,
+    name: String,
+    backend: State<'_, FirewallBackendState>,
+    state: State<'_, BlockedDomains>,
+    sources: State<'_, BlocklistSources>,
+    manifest: State<'_, RuleManifestState>
+) -> Result<BlocklistRemoval, AppError> {
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "name": name });
+    audited(&data_dir, ChangeSource::Desktop, "remove_blocklist_source", params, async {
+        refuse_while_daemon_runs("remove blocklists").await?;
+        let _changing = state.changing.lock().await;
+        let (mut removed, sources_clone) = {
+            let mut sources = sources.sources.lock().unwrap();
+            let removed = apply_source_removal(&state, &mut sources, &name)?;
+            (removed, sources.clone())
+        };
+
+        let (unblocked, failed) = unblock_removed_entries(backend.backend.as_ref(), &state, &manifest, &mut removed).await;
+        if !unblocked.is_empty() {
+            manifest.save(&data_dir)?;
+        }
+
+        save_sources_to_file(&data_dir, &sources_clone)?;
+        let domains_clone = { state.domains.lock().unwrap().clone() };
+        save_domains_to_file(&data_dir, &domains_clone).await?;
+        if !unblocked.is_empty() {
+            let resolutions_clone = { state.resolutions.lock().unwrap().clone() };
+            save_resolutions_to_file(&data_dir, &resolutions_clone)?;
+        }
+
+        Ok(BlocklistRemoval { removed, failed })
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall::backend::netsh::NetshBackend;
+    use crate::firewall::domain_blocking::resolver::{ResolvedAddress, ResolvedDomain};
+    use crate::firewall::testing::FakeCommandRunner;
+
+    fn entries(list: &[&str]) -> Vec<String> {
+        list.iter().map(|e| e.to_string()).collect()
+    }
+
+    fn parsed(list: &[&str]) -> ParsedList {
+        ParsedList { entries: entries(list), rejected: Vec::new() }
+    }
+
+    #[test]
+    fn parses_hosts_files() {
+        let content = "\
+# comment
+127.0.0.1 localhost
+::1 localhost ip6-localhost
+0.0.0.0 ads.example.com tracker.example.com # inline
+0.0.0.0 Ads.Example.com
+93.184.216.34 example.com
+";
+        let parsed = parse_blocklist(content, ListFormat::Hosts);
+
+        assert_eq!(parsed.entries, entries(&["ads.example.com", "tracker.example.com", "ads.example.com"]));
+        assert_eq!(parsed.rejected, vec![(6, "93.184.216.34 example.com".to_string())]);
+    }
+
+    #[test]
+    fn parses_adblock_domain_rules() {
+        let content = "\
+[Adblock Plus 2.0]
+! Title: test
+||ads.example.com^
+||cdn.example.org^$third-party
+||example.net^|
+@@||allowed.example.com^
+||example.com/banner.js
+example.com##.ad
+||shop.example.com^$domain=foo.com
+";
+        let parsed = parse_blocklist(content, ListFormat::AdBlock);
+
+        assert_eq!(parsed.entries, entries(&[".ads.example.com", ".cdn.example.org", ".example.net"]));
+        assert_eq!(parsed.rejected.iter().map(|(n, _)| *n).collect::<Vec<_>>(), vec![6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn parses_plain_lists_and_detects_mixed_formats() {
+        let content = "\
+example.com
+*.tracking.example.org
+not a domain
+0.0.0.0 hosts.example.com
+||adblock.example.com^
+";
+        let parsed = parse_blocklist(content, ListFormat::Auto);
+
+        assert_eq!(parsed.entries, entries(&[
+            "example.com",
+            "*.tracking.example.org",
+            "hosts.example.com",
+            ".adblock.example.com",
+        ]));
+        assert_eq!(parsed.rejected, vec![(3, "not a domain".to_string())]);
+    }
+
+    #[test]
+    fn import_reports_duplicates_and_keeps_preexisting_entries() {
+        let state = BlockedDomains::default();
+        state.add_domain("manual.example.com");
+        let mut sources = HashMap::new();
+
+        let report = apply_import(&state, &mut sources, "ads", "/lists/ads.txt",
+            parsed(&["a.example.com", "a.example.com", "manual.example.com", "b.example.com"]));
+
+        assert_eq!((report.accepted, report.duplicates, report.rejected), (2, 2, 0));
+        assert_eq!(sources["ads"].preexisting, entries(&["manual.example.com"]));
+
+        let removed = apply_source_removal(&state, &mut sources, "ads").unwrap();
+        assert_eq!(removed, entries(&["a.example.com", "b.example.com"]));
+        assert_eq!(*state.domains.lock().unwrap(), entries(&["manual.example.com"]));
+        assert!(sources.is_empty());
+    }
+
+    #[test]
+    fn removing_a_source_keeps_entries_listed_by_another() {
+        let state = BlockedDomains::default();
+        let mut sources = HashMap::new();
+        apply_import(&state, &mut sources, "one", "one.txt", parsed(&["shared.example.com", "one.example.com"]));
+        apply_import(&state, &mut sources, "two", "two.txt", parsed(&["shared.example.com"]));
+
+        let removed = apply_source_removal(&state, &mut sources, "one").unwrap();
+        assert_eq!(removed, entries(&["one.example.com"]));
+
+        let removed = apply_source_removal(&state, &mut sources, "two").unwrap();
+        assert_eq!(removed, entries(&["shared.example.com"]));
+        assert!(state.domains.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn reimport_replaces_the_list() {
+        let state = BlockedDomains::default();
+        let mut sources = HashMap::new();
+        apply_import(&state, &mut sources, "ads", "ads.txt", parsed(&["old.example.com", "kept.example.com"]));
+
+        let report = apply_import(&state, &mut sources, "ads", "ads.txt", parsed(&["kept.example.com", "new.example.com"]));
+
+        assert_eq!((report.accepted, report.duplicates, report.removed), (1, 1, 1));
+        assert_eq!(*state.domains.lock().unwrap(), entries(&["kept.example.com", "new.example.com"]));
+        assert!(sources["ads"].preexisting.is_empty());
+    }
+
+    #[tokio::test]
+    async fn entries_whose_rules_stay_are_kept_blocked() {
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+        let state = BlockedDomains::default();
+        let manifest = RuleManifestState::default();
+        let mut sources = HashMap::new();
+        apply_import(&state, &mut sources, "ads", "ads.txt", parsed(&["ads.example.com", "other.example.com"]));
+        state.resolutions.lock().unwrap().insert("ads.example.com".to_string(), ResolvedDomain {
+            domain: "ads.example.com".to_string(),
+            addresses: vec![ResolvedAddress { ip: "1.2.3.4".to_string(), ttl: 300 }],
+            cname_chain: Vec::new(),
+            min_ttl: 300,
+            resolved_at: 0,
+        });
+        manifest.record_domain("ads.example.com", entries(&["Block-Domain-Outbound-ads.example.com"]));
+        runner.respond_to_script(Err("Access is denied.".to_string()));
+
+        let mut removed = apply_source_removal(&state, &mut sources, "ads").unwrap();
+        let (unblocked, failed) = unblock_removed_entries(&backend, &state, &manifest, &mut removed).await;
+
+        assert!(unblocked.is_empty());
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].domain, "ads.example.com");
+        assert_eq!(removed, entries(&["other.example.com"]));
+        assert_eq!(*state.domains.lock().unwrap(), entries(&["ads.example.com"]));
+        assert!(state.resolutions.lock().unwrap().contains_key("ads.example.com"));
+        assert!(manifest.domain_rules("ads.example.com").is_some());
+
+        let mut removed = entries(&["ads.example.com"]);
+        state.remove_domains(&removed);
+        let (unblocked, failed) = unblock_removed_entries(&backend, &state, &manifest, &mut removed).await;
+
+        assert_eq!(unblocked, entries(&["ads.example.com"]));
+        assert!(failed.is_empty());
+        assert!(state.resolutions.lock().unwrap().is_empty());
+        assert!(manifest.domain_rules("ads.example.com").is_none());
+    }
+
+    #[test]
+    fn removing_unknown_source_fails() {
+        let state = BlockedDomains::default();
+        assert!(apply_source_removal(&state, &mut HashMap::new(), "missing").is_err());
+    }
+}
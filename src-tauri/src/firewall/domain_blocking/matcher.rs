@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use super::utils::is_valid_domain_format;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DomainMatch {
+    // example.com
+    Exact,
+    // *.example.com - any subdomain, but not example.com itself
+    Subdomains,
+    // .example.com - example.com and every subdomain (the "include subdomains" flag)
+    ApexAndSubdomains,
+}
+
+// A blocked-domain entry. Entries are stored as plain strings, with the match kind encoded
+// in the prefix, so the persisted list and the UI keep working with `string[]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomainPattern {
+    pub domain: String,
+    pub kind: DomainMatch,
+}
+
+impl DomainPattern {
+    pub fn parse(entry: &str) -> Option<Self> {
+        let entry = entry.trim().trim_end_matches('.').to_ascii_lowercase();
+        let (domain, kind) = if let Some(rest) = entry.strip_prefix("*.") {
+            (rest, DomainMatch::Subdomains)
+        } else if let Some(rest) = entry.strip_prefix('.') {
+            (rest, DomainMatch::ApexAndSubdomains)
+        } else {
+            (entry.as_str(), DomainMatch::Exact)
+        };
+
+        if !is_valid_domain_format(domain) {
+            return None;
+        }
+        Some(DomainPattern { domain: domain.to_string(), kind })
+    }
+
+    pub fn with_subdomains(mut self, include_subdomains: bool) -> Self {
+        if include_subdomains && self.kind == DomainMatch::Exact {
+            self.kind = DomainMatch::ApexAndSubdomains;
+        }
+        self
+    }
+
+    pub fn entry(&self) -> String {
+        match self.kind {
+            DomainMatch::Exact => self.domain.clone(),
+            DomainMatch::Subdomains => format!("*.{}", self.domain),
+            DomainMatch::ApexAndSubdomains => format!(".{}", self.domain),
+        }
+    }
+
+    // Only patterns covering the apex name have something to resolve for IP-based rules;
+    // pure wildcards are enforced by the DNS sinkhole alone.
+    pub fn covers_apex(&self) -> bool {
+        self.kind != DomainMatch::Subdomains
+    }
+
+    pub fn covers_subdomains(&self) -> bool {
+        self.kind != DomainMatch::Exact
+    }
+}
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: HashMap<String, TrieNode>,
+    exact: Vec<String>,
+    subdomains: Vec<String>,
+}
+
+impl TrieNode {
+    fn is_empty(&self) -> bool {
+        self.children.is_empty() && self.exact.is_empty() && self.subdomains.is_empty()
+    }
+}
+
+// Blocked entries keyed by their labels in reverse (com -> example -> cdn), so a lookup
+// costs one hash probe per label of the queried name no matter how many entries there are.
+#[derive(Debug, Default)]
+pub struct DomainTrie {
+    root: TrieNode,
+}
+
+fn labels(domain: &str) -> impl Iterator<Item = &str> {
+    domain.rsplit('.')
+}
+
+impl DomainTrie {
+    pub fn from_entries<'a>(entries: impl IntoIterator<Item = &'a String>) -> Self {
+        let mut trie = DomainTrie::default();
+        for entry in entries {
+            trie.insert(entry);
+        }
+        trie
+    }
+
+    // Returns false for entries that aren't valid patterns.
+    pub fn insert(&mut self, entry: &str) -> bool {
+        let Some(pattern) = DomainPattern::parse(entry) else {
+            return false;
+        };
+
+        let mut node = &mut self.root;
+        for label in labels(&pattern.domain) {
+            node = node.children.entry(label.to_string()).or_default();
+        }
+
+        let entry = entry.to_string();
+        if pattern.covers_apex() && !node.exact.contains(&entry) {
+            node.exact.push(entry.clone());
+        }
+        if pattern.covers_subdomains() && !node.subdomains.contains(&entry) {
+            node.subdomains.push(entry);
+        }
+        true
+    }
+
+    pub fn remove(&mut self, entry: &str) -> bool {
+        let Some(pattern) = DomainPattern::parse(entry) else {
+            return false;
+        };
+        let path: Vec<&str> = labels(&pattern.domain).collect();
+        remove_from(&mut self.root, &path, entry)
+    }
+
+    // The entry that blocks `name`, if any. An exact entry wins over a wildcard, and a
+    // deeper wildcard over a shallower one.
+    pub fn find(&self, name: &str) -> Option<&str> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let path: Vec<&str> = labels(&name).collect();
+
+        let mut node = &self.root;
+        let mut best = None;
+        for (depth, label) in path.iter().enumerate() {
+            match node.children.get(*label) {
+                Some(child) => node = child,
+                None => return best,
+            }
+            if depth + 1 == path.len() {
+                if let Some(entry) = node.exact.first() {
+                    return Some(entry);
+                }
+            } else if let Some(entry) = node.subdomains.first() {
+                best = Some(entry.as_str());
+            }
+        }
+        best
+    }
+}
+
+fn remove_from(node: &mut TrieNode, path: &[&str], entry: &str) -> bool {
+    let Some((label, rest)) = path.split_first() else {
+        let before = node.exact.len() + node.subdomains.len();
+        node.exact.retain(|e| e != entry);
+        node.subdomains.retain(|e| e != entry);
+        return node.exact.len() + node.subdomains.len() != before;
+    };
+
+    let Some(child) = node.children.get_mut(*label) else {
+        return false;
+    };
+    let removed = remove_from(child, rest, entry);
+    if child.is_empty() {
+        node.children.remove(*label);
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trie(entries: &[&str]) -> DomainTrie {
+        let entries: Vec<String> = entries.iter().map(|e| e.to_string()).collect();
+        DomainTrie::from_entries(&entries)
+    }
+
+    #[test]
+    fn parses_entry_prefixes() {
+        assert_eq!(DomainPattern::parse("Example.COM.").unwrap().entry(), "example.com");
+        assert_eq!(DomainPattern::parse("*.example.com").unwrap().kind, DomainMatch::Subdomains);
+        assert_eq!(DomainPattern::parse(".example.com").unwrap().kind, DomainMatch::ApexAndSubdomains);
+        assert_eq!(DomainPattern::parse("example.com").unwrap().with_subdomains(true).entry(), ".example.com");
+        assert!(DomainPattern::parse("*.com").is_none());
+        assert!(DomainPattern::parse("exa mple.com").is_none());
+        assert!(DomainPattern::parse("*example.com").is_none());
+    }
+
+    #[test]
+    fn exact_entries_match_only_the_name() {
+        let trie = trie(&["example.com"]);
+
+        assert_eq!(trie.find("example.com"), Some("example.com"));
+        assert_eq!(trie.find("EXAMPLE.com."), Some("example.com"));
+        assert_eq!(trie.find("cdn.example.com"), None);
+        assert_eq!(trie.find("com"), None);
+    }
+
+    #[test]
+    fn wildcards_match_subdomains_only() {
+        let trie = trie(&["*.example.com"]);
+
+        assert_eq!(trie.find("cdn.example.com"), Some("*.example.com"));
+        assert_eq!(trie.find("a.b.example.com"), Some("*.example.com"));
+        assert_eq!(trie.find("example.com"), None);
+        assert_eq!(trie.find("badexample.com"), None);
+    }
+
+    #[test]
+    fn include_subdomains_matches_apex_and_below() {
+        let trie = trie(&[".example.com", "ads.other.org"]);
+
+        assert_eq!(trie.find("example.com"), Some(".example.com"));
+        assert_eq!(trie.find("x.y.example.com"), Some(".example.com"));
+        assert_eq!(trie.find("other.org"), None);
+    }
+
+    #[test]
+    fn prefers_the_most_specific_entry() {
+        let trie = trie(&[".example.com", "*.cdn.example.com", "cdn.example.com"]);
+
+        assert_eq!(trie.find("cdn.example.com"), Some("cdn.example.com"));
+        assert_eq!(trie.find("img.cdn.example.com"), Some("*.cdn.example.com"));
+        assert_eq!(trie.find("www.example.com"), Some(".example.com"));
+    }
+
+    #[test]
+    fn remove_drops_only_that_entry() {
+        let mut trie = trie(&["example.com", "*.example.com"]);
+
+        assert!(trie.remove("*.example.com"));
+        assert!(!trie.remove("*.example.com"));
+        assert_eq!(trie.find("cdn.example.com"), None);
+        assert_eq!(trie.find("example.com"), Some("example.com"));
+
+        assert!(trie.remove("example.com"));
+        assert!(trie.root.is_empty());
+    }
+
+    #[test]
+    fn handles_large_lists() {
+        let entries: Vec<String> = (0..50_000).map(|i| format!("*.host{}.example.net", i)).collect();
+        let trie = DomainTrie::from_entries(&entries);
+
+        assert_eq!(trie.find("a.host49999.example.net"), Some("*.host49999.example.net"));
+        assert_eq!(trie.find("a.host50000.example.net"), None);
+    }
+}
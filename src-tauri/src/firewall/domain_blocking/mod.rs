@@ -1,33 +1,38 @@
 pub mod utils;
+pub mod matcher;
 pub mod monitor;
+pub mod resolver;
+pub mod refresher;
+pub mod sinkhole;
+pub mod blocklists;
+pub mod batch;
 
+use serde_json::json;
 use tauri::{AppHandle, State, Manager, Emitter};
+use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{Read, Write};
-use std::path::PathBuf;
-use crate::firewall::common::{BlockedDomains, run_elevated_powershell};
-use utils::log_debug;
-
-fn get_domains_file_path(app: &AppHandle) -> PathBuf {
-    let app_data_dir = match app.app_handle().path().app_data_dir() {
-        Ok(dir) => dir,
-        Err(_) => {
-            log_debug("Failed to get app data directory, using current directory");
-            std::env::current_dir().unwrap_or_default()
-        }
-    };
-    
-    if !app_data_dir.exists() {
-        if let Err(e) = fs::create_dir_all(&app_data_dir) {
-            log_debug(&format!("Failed to create app data directory: {}", e));
-        }
-    }
-    
-    app_data_dir.join("blocked_domains.json")
+use std::path::{Path, PathBuf};
+use tracing::warn;
+use crate::app_data::{app_data_dir, data_file_path};
+use crate::audit::audited;
+use crate::daemon::ipc::DaemonRequest;
+use crate::daemon::{forward, running_daemon};
+use crate::firewall::backend::{FirewallBackend, FirewallBackendState};
+use crate::firewall::common::BlockedDomains;
+use crate::firewall::ownership::RuleManifestState;
+use crate::firewall::snapshots::{snapshot_before_change, ChangeSource};
+use batch::{emit_blocked, single_result};
+use matcher::DomainPattern;
+use resolver::{DnsResolver, DnsResolverState, ResolvedDomain};
+use crate::error::{AppError, ErrorKind};
+
+fn get_domains_file_path(data_dir: &Path) -> PathBuf {
+    data_file_path(data_dir, "blocked_domains.json")
 }
 
-async fn save_domains_to_file(app: &AppHandle, domains: &Vec<String>) -> Result<(), String> {
-    let file_path = get_domains_file_path(app);
+pub(crate) async fn save_domains_to_file(data_dir: &Path, domains: &Vec<String>) -> Result<(), String> {
+    let file_path = get_domains_file_path(data_dir);
     let json = serde_json::to_string_pretty(domains)
         .map_err(|e| format!("Failed to serialize domains: {}", e))?;
     
@@ -41,8 +46,8 @@ async fn save_domains_to_file(app: &AppHandle, domains: &Vec<String>) -> Result<
     Ok(())
 }
 
-async fn load_domains_from_file(app: &AppHandle) -> Result<Vec<String>, String> {
-    let file_path = get_domains_file_path(app);
+async fn load_domains_from_file(data_dir: &Path) -> Result<Vec<String>, String> {
+    let file_path = get_domains_file_path(data_dir);
     
     if !file_path.exists() {
         return Ok(Vec::new());
@@ -61,160 +66,348 @@ async fn load_domains_from_file(app: &AppHandle) -> Result<Vec<String>, String>
     Ok(domains)
 }
 
-pub async fn initialize_blocked_domains(app: AppHandle) -> Result<(), String> {
-    match load_domains_from_file(&app).await {
-        Ok(domains) => {
-            let state = app.state::<BlockedDomains>();
-            let mut state_domains = state.domains.lock().unwrap();
-            *state_domains = domains;
-            Ok(())
-        },
-        Err(e) => Err(e)
+pub(crate) fn save_resolutions_to_file(data_dir: &Path, resolutions: &HashMap<String, ResolvedDomain>) -> Result<(), String> {
+    let file_path = data_file_path(data_dir, "blocked_domain_resolutions.json");
+    let json = serde_json::to_string_pretty(resolutions)
+        .map_err(|e| format!("Failed to serialize domain resolutions: {}", e))?;
+
+    fs::write(&file_path, json)
+        .map_err(|e| format!("Failed to write to file: {}", e))
+}
+
+fn load_resolutions_from_file(data_dir: &Path) -> Result<HashMap<String, ResolvedDomain>, String> {
+    let file_path = data_file_path(data_dir, "blocked_domain_resolutions.json");
+
+    if !file_path.exists() {
+        return Ok(HashMap::new());
     }
+
+    let json = fs::read_to_string(&file_path)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+
+    serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to deserialize domain resolutions: {}", e))
+}
+
+pub async fn load_blocked_domains(data_dir: &Path, state: &BlockedDomains) -> Result<(), String> {
+    let domains = load_domains_from_file(data_dir).await?;
+    let mut resolutions = load_resolutions_from_file(data_dir)?;
+    resolutions.retain(|domain, _| domains.contains(domain));
+
+    state.set_domains(domains);
+    *state.resolutions.lock().unwrap() = resolutions;
+    Ok(())
+}
+
+pub async fn initialize_blocked_domains(app: AppHandle) -> Result<(), String> {
+    let data_dir = app_data_dir(&app);
+    load_blocked_domains(&data_dir, &app.state::<BlockedDomains>()).await?;
+    blocklists::initialize_blocklist_sources(&data_dir, &app.state::<blocklists::BlocklistSources>())?;
+    crate::firewall::ownership::initialize_rule_manifest(&data_dir, &app.state::<RuleManifestState>())
 }
 
 #[tauri::command]
-pub async fn get_blocked_domains(state: State<'_, BlockedDomains>) -> Result<Vec<String>, String> {
+#[tracing::instrument(skip_all, err)]
+pub async fn get_blocked_domains(state: State<'_, BlockedDomains>) -> Result<Vec<String>, AppError> {
+    if let Some(socket) = running_daemon().await {
+        return forward(&socket, &DaemonRequest::BlockedDomains).await;
+    }
     let domains = state.domains.lock().unwrap();
     Ok(domains.clone())
 }
 
-#[tauri::command]
-pub async fn block_domain(
-    app: AppHandle,
-    domain: String,
-    state: State<'_, BlockedDomains>
-) -> Result<(), String> {
-    if !utils::is_valid_domain_format(&domain) {
-        return Err(format!("Invalid domain format: {}", domain));
+// Resolves the apex name of a blocked entry and blocks its addresses, using the entry itself
+// as the rule set name so `example.com` and `.example.com` don't share rules. `owned` are the
+// rules already recorded for the entry, replaced along with the set.
+pub async fn apply_domain_block(
+    resolver: &DnsResolver,
+    backend: &dyn FirewallBackend,
+    domain: &str,
+    owned: Option<&[String]>
+) -> Result<ResolvedDomain, AppError> {
+    let pattern = DomainPattern::parse(domain)
+        .ok_or_else(|| AppError::invalid_input(format!("Invalid domain format: {}", domain)))?;
+    if !pattern.covers_apex() {
+        return Err(AppError::invalid_input(format!("{} has no single host to resolve", domain)));
     }
     
-    let outbound_rule_prefix = format!("Block-Domain-Outbound-{}", domain);
-    let inbound_rule_prefix = format!("Block-Domain-Inbound-{}", domain);
+    let resolved = resolver.resolve(&pattern.domain).await
+        .map_err(|e| e.context(&format!("Failed to resolve domain {}", pattern.domain)))?;
     
-    let ip_addresses = match utils::resolve_domain_to_ips(&app, &domain).await {
-        Ok(ips) => ips,
-        Err(_) => utils::try_alternative_resolution(&app, &domain).await
-            .map_err(|e| format!("Failed to resolve domain {}: {}", domain, e))?,
-    };
-    
-    if ip_addresses.is_empty() {
-        return Err(format!("No IP addresses found for domain: {}", domain));
+    if resolved.addresses.is_empty() {
+        return Err(AppError::new(
+            ErrorKind::ResolutionFailed,
+            format!("No IP addresses found for domain: {}", pattern.domain)
+        ));
     }
     
-    let mut ps_script = String::new();
-    ps_script.push_str("$ErrorActionPreference = 'Stop'\ntry {\n");
+    backend.block_ip_set(domain, &resolved.ips(), owned.unwrap_or(&[])).await
+        .map_err(|e| AppError::from(e).context(&format!("Failed to create firewall rules for domain {}", domain)))?;
     
-    for (index, ip_address) in ip_addresses.iter().enumerate() {
-        let outbound_rule_name = if ip_addresses.len() > 1 {
-            format!("{}-{}", outbound_rule_prefix, index + 1)
-        } else {
-            outbound_rule_prefix.clone()
+    Ok(resolved)
+}
+
+// Deletes the rules recorded for the entry, or every name the backend may have used for it
+// when there's no record (entries blocked before rules were tracked).
+pub async fn remove_domain_block(
+    backend: &dyn FirewallBackend,
+    domain: &str,
+    owned: Option<&[String]>
+) -> Result<(), AppError> {
+    // Wildcard-only entries never had firewall rules.
+    if matches!(DomainPattern::parse(domain), Some(pattern) if !pattern.covers_apex()) {
+        return Ok(());
+    }
+    let rule_names = match owned {
+        Some(names) => names.to_vec(),
+        None => backend.legacy_ip_set_rule_names(domain),
+    };
+    backend.remove_rules(&rule_names).await
+        .map_err(|e| AppError::from(e).context(&format!("Failed to remove firewall rules for domain {}", domain)))
+}
+
+// Accepts `example.com`, `*.example.com` (subdomains only) or `.example.com` (both); the
+// `include_subdomains` flag turns a plain name into the latter. Wildcard-only entries are
+// enforced by the DNS sinkhole and return no resolution.
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn block_domain(
+    app: AppHandle,
+    domain: String,
+    include_subdomains: Option<bool>,
+    resolver: State<'_, DnsResolverState>,
+    backend: State<'_, FirewallBackendState>,
+    state: State<'_, BlockedDomains>,
+    manifest: State<'_, RuleManifestState>
+) -> Result<Option<ResolvedDomain>, AppError> {
+    if let Some(socket) = running_daemon().await {
+        let request = DaemonRequest::BlockDomains {
+            domains: vec![domain],
+            include_subdomains: include_subdomains.unwrap_or(false),
         };
-        
-        let inbound_rule_name = if ip_addresses.len() > 1 {
-            format!("{}-{}", inbound_rule_prefix, index + 1)
+        let result = single_result(forward(&socket, &request).await?)?;
+        emit_blocked(&app, std::slice::from_ref(&result));
+        return Ok(result.resolution);
+    }
+
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "domain": domain, "include_subdomains": include_subdomains });
+    audited(&data_dir, ChangeSource::Desktop, "block_domain", params, async {
+        let pattern = DomainPattern::parse(&domain)
+            .ok_or_else(|| AppError::invalid_input(format!("Invalid domain format: {}", domain)))?
+            .with_subdomains(include_subdomains.unwrap_or(false));
+        let entry = pattern.entry();
+        snapshot_before_change(
+            &data_dir,
+            backend.backend.as_ref(),
+            &manifest,
+            &state,
+            ChangeSource::Desktop,
+            &format!("block_domain {}", entry)
+        ).await?;
+
+        let _changing = state.changing.lock().await;
+        let resolved = if pattern.covers_apex() {
+            let owned = manifest.domain_rules(&entry);
+            Some(apply_domain_block(&resolver.resolver(), backend.backend.as_ref(), &entry, owned.as_deref()).await?)
         } else {
-            inbound_rule_prefix.clone()
+            None
         };
-        
-        let outbound_description = format!("Blocks outgoing connections to domain: {} (IP: {})", domain, ip_address);
-        let inbound_description = format!("Blocks incoming connections from domain: {} (IP: {})", domain, ip_address);
-        
-        ps_script.push_str(&format!(
-            "    netsh advfirewall firewall add rule name=\"{}\" dir=out action=block enable=yes protocol=any description=\"{}\" remoteip={}\n",
-            outbound_rule_name, outbound_description, ip_address
-        ));
-        
-        ps_script.push_str(&format!(
-            "    netsh advfirewall firewall add rule name=\"{}\" dir=in action=block enable=yes protocol=any description=\"{}\" remoteip={}\n",
-            inbound_rule_name, inbound_description, ip_address
-        ));
-    }
-    
-    ps_script.push_str("    Write-Host \"Successfully created all firewall rules\"\n");
-    ps_script.push_str("} catch {\n");
-    ps_script.push_str("    Write-Host \"Error creating firewall rules: $_\"\n");
-    ps_script.push_str("    exit 1\n");
-    ps_script.push_str("}\n");
-    
-    run_elevated_powershell(&app, &ps_script).await
-        .map_err(|e| format!("Failed to create firewall rules for domain {}: {}", domain, e))?;
     
-    {
-        let mut domains = state.domains.lock().unwrap();
-        if !domains.contains(&domain) {
-            domains.push(domain.clone());
+        state.add_domain(&entry);
+        let domains_clone = { state.domains.lock().unwrap().clone() };
+        if let Err(e) = save_domains_to_file(&data_dir, &domains_clone).await {
+            warn!("Failed to save blocked domains: {}", e);
         }
-    }
     
-    let domains_clone = { state.domains.lock().unwrap().clone() };
-    let _ = save_domains_to_file(&app, &domains_clone).await;
+        if let Some(resolved) = &resolved {
+            let resolutions_clone = {
+                let mut resolutions = state.resolutions.lock().unwrap();
+                resolutions.insert(entry.clone(), resolved.clone());
+                resolutions.clone()
+            };
+            if let Err(e) = save_resolutions_to_file(&data_dir, &resolutions_clone) {
+                warn!("Failed to save domain resolutions: {}", e);
+            }
+
+            manifest.record_domain(&entry, backend.backend.ip_set_rule_names(&entry, &resolved.ips()));
+            if let Err(e) = manifest.save(&data_dir) {
+                warn!("Failed to save rule manifest: {}", e);
+            }
+        }
     
-    if let Err(e) = app.emit("domain-blocked-notification", &domain) {
-        log_debug(&format!("Failed to emit domain blocked event: {}", e));
-    }
+        if let Err(e) = app.emit("domain-blocked-notification", &entry) {
+            warn!("Failed to emit domain blocked event: {}", e);
+        }
     
-    Ok(())
+        Ok(resolved)
+    }).await
 }
 
 #[tauri::command]
+#[tracing::instrument(skip_all, err)]
 pub async fn unblock_domain(
     app: AppHandle,
     domain: String,
-    state: State<'_, BlockedDomains>
-) -> Result<(), String> {
-    let outbound_rule_prefix = format!("Block-Domain-Outbound-{}", domain);
-    let inbound_rule_prefix = format!("Block-Domain-Inbound-{}", domain);
-    
-    let mut ps_script = String::new();
-    ps_script.push_str("$ErrorActionPreference = 'Continue'\n$removed_count = 0\n\n");
-    
-    // Add base rule deletion commands
-    ps_script.push_str(&format!(
-        "try {{ netsh advfirewall firewall delete rule name=\"{}\"; $removed_count++ }} catch {{ Write-Host \"Rule not found\" }}\n",
-        outbound_rule_prefix
-    ));
-    
-    ps_script.push_str(&format!(
-        "try {{ netsh advfirewall firewall delete rule name=\"{}\"; $removed_count++ }} catch {{ Write-Host \"Rule not found\" }}\n",
-        inbound_rule_prefix
-    ));
-    
-    // Add numbered rule deletion commands (up to 20)
-    for i in 1..20 {
-        let numbered_outbound_rule = format!("{}-{}", outbound_rule_prefix, i);
-        let numbered_inbound_rule = format!("{}-{}", inbound_rule_prefix, i);
-        
-        ps_script.push_str(&format!(
-            "try {{ netsh advfirewall firewall delete rule name=\"{}\"; $removed_count++ }} catch {{ Write-Host \"Rule not found\" }}\n",
-            numbered_outbound_rule
-        ));
-        
-        ps_script.push_str(&format!(
-            "try {{ netsh advfirewall firewall delete rule name=\"{}\"; $removed_count++ }} catch {{ Write-Host \"Rule not found\" }}\n",
-            numbered_inbound_rule
-        ));
+    backend: State<'_, FirewallBackendState>,
+    state: State<'_, BlockedDomains>,
+    manifest: State<'_, RuleManifestState>
+) -> Result<(), AppError> {
+    if let Some(socket) = running_daemon().await {
+        single_result(forward(&socket, &DaemonRequest::UnblockDomains { domains: vec![domain] }).await?)?;
+        return Ok(());
     }
+
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "domain": domain });
+    audited(&data_dir, ChangeSource::Desktop, "unblock_domain", params, async {
+        snapshot_before_change(
+            &data_dir,
+            backend.backend.as_ref(),
+            &manifest,
+            &state,
+            ChangeSource::Desktop,
+            &format!("unblock_domain {}", domain)
+        ).await?;
+        let _changing = state.changing.lock().await;
+        let owned = manifest.domain_rules(&domain);
+        remove_domain_block(backend.backend.as_ref(), &domain, owned.as_deref()).await?;
+        manifest.forget_domains(std::slice::from_ref(&domain));
+        if let Err(e) = manifest.save(&data_dir) {
+            warn!("Failed to save rule manifest: {}", e);
+        }
     
-    // Add legacy rule deletion
-    let old_rule_name = format!("Block-Domain-{}", domain);
-    ps_script.push_str(&format!(
-        "try {{ netsh advfirewall firewall delete rule name=\"{}\"; $removed_count++ }} catch {{ Write-Host \"Rule not found\" }}\n",
-        old_rule_name
-    ));
-    
-    ps_script.push_str(&format!("\nWrite-Host \"Removed $removed_count firewall rules for domain: {}\"\n", domain));
-      run_elevated_powershell(&app, &ps_script).await
-        .map_err(|e| format!("Failed to remove firewall rules for domain {}: {}", domain, e))?;
-    
-    let domains_clone = {
-        let mut domains = state.domains.lock().unwrap();
-        domains.retain(|d| d != &domain);
-        domains.clone()
-    };
+        state.remove_domain(&domain);
+        let domains_clone = { state.domains.lock().unwrap().clone() };
+        if let Err(e) = save_domains_to_file(&data_dir, &domains_clone).await {
+            warn!("Failed to save blocked domains: {}", e);
+        }
     
-    let _ = save_domains_to_file(&app, &domains_clone).await;
+        let resolutions_clone = {
+            let mut resolutions = state.resolutions.lock().unwrap();
+            resolutions.remove(&domain);
+            resolutions.clone()
+        };
+        if let Err(e) = save_resolutions_to_file(&data_dir, &resolutions_clone) {
+            warn!("Failed to save domain resolutions: {}", e);
+        }
     
-    Ok(())
-}
\ No newline at end of file
+        Ok(())
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::firewall::backend::netsh::NetshBackend;
+    use crate::firewall::testing::{resolver_for, FakeCommandRunner, StubDnsServer};
+
+    #[tokio::test]
+    async fn block_resolves_natively_and_applies_rules() {
+        let server = StubDnsServer::start().await;
+        server
+            .add_a("example.com", "93.184.216.34", 300)
+            .add_a("example.com", "2606:2800:220:1:248:1893:25c8:1946", 300);
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+
+        let resolved = apply_domain_block(&resolver_for(&server), &backend, "example.com", None).await.unwrap();
+
+        assert_eq!(resolved.ips(), vec!["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"]);
+        assert_eq!(resolved.min_ttl, 300);
+        let scripts = runner.scripts();
+        assert_eq!(scripts.len(), 1);
+        assert!(scripts[0].contains("Block-Domain-Outbound-example.com-2"));
+        assert!(scripts[0].contains("remoteip=93.184.216.34"));
+    }
+
+    #[tokio::test]
+    async fn block_fails_for_unknown_domain() {
+        let server = StubDnsServer::start().await;
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+
+        let err = apply_domain_block(&resolver_for(&server), &backend, "missing.example.com", None).await.unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::NotFound);
+        assert!(err.message.contains("Failed to resolve domain missing.example.com"));
+        assert!(runner.invocations().is_empty());
+    }
+
+    #[tokio::test]
+    async fn block_rejects_invalid_domain_without_querying() {
+        let server = StubDnsServer::start().await;
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+
+        assert!(apply_domain_block(&resolver_for(&server), &backend, "not a domain", None).await.is_err());
+        assert!(server.queries().is_empty());
+        assert!(runner.invocations().is_empty());
+    }
+
+    #[tokio::test]
+    async fn block_fails_when_firewall_change_is_refused() {
+        let server = StubDnsServer::start().await;
+        server.add_a("example.com", "93.184.216.34", 300);
+        let runner = Arc::new(FakeCommandRunner::new());
+        runner.respond_to_script(Err("The operation was canceled by the user.".to_string()));
+        let backend = NetshBackend::new(runner.clone());
+
+        let err = apply_domain_block(&resolver_for(&server), &backend, "example.com", None).await.unwrap_err();
+        assert_eq!(err.kind, ErrorKind::AdminRequired);
+        assert!(err.message.contains("Failed to create firewall rules for domain example.com"));
+    }
+
+    #[tokio::test]
+    async fn block_with_subdomains_resolves_apex_under_entry_name() {
+        let server = StubDnsServer::start().await;
+        server.add_a("example.com", "93.184.216.34", 300);
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+
+        let resolved = apply_domain_block(&resolver_for(&server), &backend, ".example.com", None).await.unwrap();
+
+        assert_eq!(resolved.domain, "example.com");
+        assert!(runner.scripts()[0].contains("add rule name='Block-Domain-Outbound-.example.com'"));
+    }
+
+    #[tokio::test]
+    async fn wildcard_entries_have_no_firewall_rules() {
+        let server = StubDnsServer::start().await;
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+
+        assert!(apply_domain_block(&resolver_for(&server), &backend, "*.example.com", None).await.is_err());
+        remove_domain_block(&backend, "*.example.com", None).await.unwrap();
+        assert!(server.queries().is_empty());
+        assert!(runner.invocations().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unblock_without_a_record_removes_every_possible_rule() {
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+
+        remove_domain_block(&backend, "example.com", None).await.unwrap();
+
+        let scripts = runner.scripts();
+        assert_eq!(scripts.len(), 1);
+        assert!(scripts[0].contains("delete rule name='Block-Domain-Inbound-example.com'"));
+        assert!(scripts[0].contains("delete rule name='Block-Domain-Outbound-example.com-19'"));
+    }
+
+    #[tokio::test]
+    async fn unblock_removes_only_the_recorded_rules() {
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+        let owned = backend.ip_set_rule_names("example.com", &["1.1.1.1".to_string(), "2.2.2.2".to_string()]);
+
+        remove_domain_block(&backend, "example.com", Some(&owned)).await.unwrap();
+
+        let script = &runner.scripts()[0];
+        assert_eq!(script.matches("delete rule name=").count(), 4);
+        assert!(script.contains("delete rule name='Block-Domain-Inbound-example.com-2'"));
+        assert!(!script.contains("example.com-3"));
+    }
+}
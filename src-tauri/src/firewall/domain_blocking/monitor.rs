@@ -1,11 +1,16 @@
 
 use tauri::{AppHandle, Manager, Emitter};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use tracing::warn;
 use crate::firewall::common::BlockedDomains;
-use super::utils::log_debug;
+use chrono::Utc;
+use crate::network_traffic_analysis::store::{AlertQuery, AlertSort, EventStore, EventStoreState};
+use crate::network_traffic_analysis::suricata::AlertEvent;
+use crate::error::AppError;
 
 // Structure to track recent access attempts to avoid spam
 #[derive(Clone)]
@@ -15,20 +20,33 @@ struct AccessAttempt {
     #[allow(dead_code)]
     ip: String,
     timestamp: u64,
+    last_alert: String,
     count: u32,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockedAccess {
+    pub domain: String,
+    pub ip: String,
+    pub timestamp: String,
+    pub signature: Option<String>,
+}
+
 // Global state for monitoring
 lazy_static::lazy_static! {
     static ref MONITOR_ACTIVE: Arc<Mutex<bool>> = Arc::new(Mutex::new(false));
     static ref RECENT_ATTEMPTS: Arc<Mutex<HashMap<String, AccessAttempt>>> = Arc::new(Mutex::new(HashMap::new()));
+    static ref LAST_CHECKED_ALERT: Mutex<Option<i64>> = Mutex::new(None);
 }
 
-const NOTIFICATION_COOLDOWN_SECONDS: u64 = 30;
-const CLEANUP_INTERVAL_SECONDS: u64 = 300;
+pub const MONITOR_INTERVAL_SECONDS: u64 = 5;
+pub const CLEANUP_INTERVAL_SECONDS: u64 = 300;
+const ATTEMPT_RETENTION_SECONDS: u64 = 600;
+const ALERT_PAGE_SIZE: usize = 1000;
 
 #[tauri::command]
-pub async fn start_domain_access_monitor(app: AppHandle) -> Result<(), String> {
+#[tracing::instrument(skip_all, err)]
+pub async fn start_domain_access_monitor(app: AppHandle) -> Result<(), AppError> {
     let mut is_active = MONITOR_ACTIVE.lock().unwrap();
     if *is_active {
         return Ok(());
@@ -45,14 +63,16 @@ pub async fn start_domain_access_monitor(app: AppHandle) -> Result<(), String> {
 }
 
 #[tauri::command]
-pub async fn stop_domain_access_monitor() -> Result<(), String> {
+#[tracing::instrument(skip_all, err)]
+pub async fn stop_domain_access_monitor() -> Result<(), AppError> {
     let mut is_active = MONITOR_ACTIVE.lock().unwrap();
     *is_active = false;
     Ok(())
 }
 
 #[tauri::command]
-pub async fn is_domain_access_monitor_active() -> Result<bool, String> {
+#[tracing::instrument(skip_all, err)]
+pub async fn is_domain_access_monitor_active() -> Result<bool, AppError> {
     let is_active = MONITOR_ACTIVE.lock().unwrap();
     Ok(*is_active)
 }
@@ -60,14 +80,9 @@ pub async fn is_domain_access_monitor_active() -> Result<bool, String> {
 fn monitor_domain_access_loop(app: AppHandle) {
     let mut last_cleanup = get_current_timestamp();
     
-    let rt = match tokio::runtime::Runtime::new() {
-        Ok(rt) => rt,
-        Err(_) => return,
-    };
-    
     while is_monitor_active() {
-        if let Err(e) = rt.block_on(check_for_blocked_domain_access(&app)) {
-            log_debug(&format!("Error checking for blocked domain access: {}", e));
+        if let Err(e) = notify_blocked_domain_access(&app) {
+            warn!("Error checking for blocked domain access: {}", e);
         }
           let current_time = get_current_timestamp();
         if current_time - last_cleanup > CLEANUP_INTERVAL_SECONDS {
@@ -75,102 +90,122 @@ fn monitor_domain_access_loop(app: AppHandle) {
             last_cleanup = current_time;
         }
         
-        thread::sleep(Duration::from_secs(5));
+        thread::sleep(Duration::from_secs(MONITOR_INTERVAL_SECONDS));
     }
 }
 
-async fn check_for_blocked_domain_access(app: &AppHandle) -> Result<(), String> {
-    let blocked_domains = get_blocked_domains_set(app)?;
-    
-    if blocked_domains.is_empty() {
-        return Ok(());
+fn notify_blocked_domain_access(app: &AppHandle) -> Result<(), String> {
+    use crate::firewall::common::NotificationState;
+
+    let cooldown_seconds = app.state::<NotificationState>().settings.lock().unwrap().cooldown_seconds;
+    let events = app.state::<EventStoreState>();
+    for access in check_for_blocked_domain_access(&app.state::<BlockedDomains>(), &events.store, cooldown_seconds)? {
+        send_blocked_domain_notification(app, &access)?;
     }
-    
-    match crate::network_traffic_analysis::suricata::read_alert_events() {
-        Ok(alerts) => {
-            for alert in alerts {
-                if let Some(dest_ip) = &alert.dest_ip {
-                    if let Some(domain) = check_ip_against_blocked_domains(app, dest_ip, &blocked_domains).await {
-                        handle_blocked_domain_access(app, &domain, dest_ip, &alert)?;
-                    }
-                }
-            }
-        },
-        Err(_) => {}
+    Ok(())
+}
+
+// Reads alerts stored since the last check and returns accesses to blocked domains that haven't
+// been reported within the cooldown.
+pub fn check_for_blocked_domain_access(
+    state: &BlockedDomains,
+    store: &EventStore,
+    cooldown_seconds: u64
+) -> Result<Vec<BlockedAccess>, String> {
+    if state.domains.lock().unwrap().is_empty() {
+        return Ok(Vec::new());
     }
     
-    Ok(())
+    let alerts = new_alerts(store).map_err(|e| format!("Failed to read Suricata alerts: {}", e))?;
+
+    let current_time = get_current_timestamp();
+    Ok(find_blocked_access(state, &alerts)
+        .into_iter()
+        .filter(|access| should_notify(access, cooldown_seconds, current_time))
+        .collect())
 }
 
-fn get_blocked_domains_set(app: &AppHandle) -> Result<HashSet<String>, String> {
-    let state = app.state::<BlockedDomains>();
-    let domains = state.domains.lock().unwrap();
-    Ok(domains.iter().cloned().collect())
+// Alerts only carry addresses, so they are matched against what the blocked domains resolved to.
+pub fn find_blocked_access(state: &BlockedDomains, alerts: &[AlertEvent]) -> Vec<BlockedAccess> {
+    let domains_by_ip: HashMap<String, String> = {
+        let resolutions = state.resolutions.lock().unwrap();
+        resolutions
+            .iter()
+            .flat_map(|(domain, resolved)| resolved.ips().into_iter().map(move |ip| (ip, domain.clone())))
+            .collect()
+    };
+
+    alerts
+        .iter()
+        .filter_map(|alert| {
+            let ip = alert.dest_ip.as_ref()?;
+            let domain = domains_by_ip.get(ip)?;
+            Some(BlockedAccess {
+                domain: domain.clone(),
+                ip: ip.clone(),
+                timestamp: alert.timestamp.clone(),
+                signature: alert.signature.clone(),
+            })
+        })
+        .collect()
 }
 
-async fn check_ip_against_blocked_domains(
-    _app: &AppHandle,
-    _ip: &str, 
-    _blocked_domains: &HashSet<String>
-) -> Option<String> {
-    None
+// Alerts are only looked at once. Older than the attempt history, they would count as new.
+fn new_alerts(store: &EventStore) -> Result<Vec<AlertEvent>, AppError> {
+    let mut last_checked = LAST_CHECKED_ALERT.lock().unwrap();
+    let mut alerts = Vec::new();
+    loop {
+        let page = store.query_alerts(&AlertQuery {
+            since: Some(Utc::now() - chrono::Duration::seconds(ATTEMPT_RETENTION_SECONDS as i64)),
+            after_id: *last_checked,
+            sort: AlertSort::Oldest,
+            limit: Some(ALERT_PAGE_SIZE),
+            ..Default::default()
+        })?;
+        let done = page.alerts.len() < ALERT_PAGE_SIZE;
+        if let Some(id) = page.alerts.last().and_then(|alert| alert.id) {
+            *last_checked = Some(id);
+        }
+        alerts.extend(page.alerts);
+        if done {
+            return Ok(alerts);
+        }
+    }
 }
 
-fn handle_blocked_domain_access(
-    app: &AppHandle,
-    domain: &str,
-    ip: &str,
-    alert: &crate::network_traffic_analysis::suricata::AlertEvent
-) -> Result<(), String> {
-    use tauri::Manager;
-    use crate::firewall::common::NotificationState;
-    
-    let current_time = get_current_timestamp();
-    let access_key = format!("{}:{}", domain, ip);
-    
-    let notification_state = app.state::<NotificationState>();
-    let settings = notification_state.settings.lock().unwrap();
-    let cooldown_seconds = settings.cooldown_seconds;
-    drop(settings);
-    
+fn should_notify(access: &BlockedAccess, cooldown_seconds: u64, current_time: u64) -> bool {
+    let access_key = format!("{}:{}", access.domain, access.ip);
     let mut recent_attempts = RECENT_ATTEMPTS.lock().unwrap();
-    let should_notify = match recent_attempts.get(&access_key) {
+    match recent_attempts.get_mut(&access_key) {
         Some(attempt) => {
+            // Alerts can arrive out of order; ones older than what was reported don't count again.
+            if access.timestamp <= attempt.last_alert {
+                return false;
+            }
             let last_timestamp = attempt.timestamp;
-            let mut updated_attempt = attempt.clone();
-            updated_attempt.count += 1;
-            updated_attempt.timestamp = current_time;
-            recent_attempts.insert(access_key.clone(), updated_attempt);
-            
-            current_time - last_timestamp > cooldown_seconds
+            attempt.count += 1;
+            attempt.last_alert = access.timestamp.clone();
+            if current_time - last_timestamp > cooldown_seconds {
+                attempt.timestamp = current_time;
+                true
+            } else {
+                false
+            }
         },
         None => {
             recent_attempts.insert(access_key, AccessAttempt {
-                domain: domain.to_string(),
-                ip: ip.to_string(),
+                domain: access.domain.clone(),
+                ip: access.ip.clone(),
                 timestamp: current_time,
+                last_alert: access.timestamp.clone(),
                 count: 1,
             });
             true
         }
-    };
-    
-    drop(recent_attempts);
-    
-    if should_notify {
-        send_blocked_domain_notification(app, domain, ip, alert)?;
     }
-    
-    Ok(())
 }
 
-fn send_blocked_domain_notification(
-    app: &AppHandle,
-    domain: &str,
-    ip: &str,
-    alert: &crate::network_traffic_analysis::suricata::AlertEvent
-) -> Result<(), String> {
-    use tauri::Manager;
+fn send_blocked_domain_notification(app: &AppHandle, access: &BlockedAccess) -> Result<(), String> {
     use crate::firewall::common::NotificationState;
     
     let notification_state = app.state::<NotificationState>();
@@ -184,22 +219,15 @@ fn send_blocked_domain_notification(
     drop(settings);
     
     let app_clone = app.clone();
-    let domain_clone = domain.to_string();
-    let ip_clone = ip.to_string();
-    let alert_clone = alert.clone();
+    let access = access.clone();
     
     tauri::async_runtime::spawn(async move {
         if delay_seconds > 0 {
             tokio::time::sleep(tokio::time::Duration::from_secs(delay_seconds)).await;
         }
         
-        if let Err(e) = app_clone.emit("domain-access-blocked-notification", serde_json::json!({
-            "domain": domain_clone,
-            "ip": ip_clone,
-            "timestamp": alert_clone.timestamp,
-            "signature": alert_clone.signature
-        })) {
-            log_debug(&format!("Failed to emit domain access blocked event: {}", e));
+        if let Err(e) = app_clone.emit("domain-access-blocked-notification", &access) {
+            warn!("Failed to emit domain access blocked event: {}", e);
         }
     });
     
@@ -218,13 +246,62 @@ fn get_current_timestamp() -> u64 {
         .as_secs()
 }
 
-fn cleanup_old_attempts() {
+pub fn cleanup_old_attempts() {
     let current_time = get_current_timestamp();
     let mut recent_attempts = RECENT_ATTEMPTS.lock().unwrap();
     
     recent_attempts.retain(|_, attempt| {
-        current_time - attempt.timestamp < 600
+        current_time - attempt.timestamp < ATTEMPT_RETENTION_SECONDS
     });
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall::domain_blocking::resolver::{ResolvedAddress, ResolvedDomain};
+
+    fn alert(timestamp: &str, dest_ip: &str) -> AlertEvent {
+        AlertEvent {
+            id: None,
+            timestamp: timestamp.to_string(),
+            src_ip: Some("10.0.0.5".to_string()),
+            dest_ip: Some(dest_ip.to_string()),
+            src_port: Some(51000),
+            dest_port: Some(443),
+            signature: Some("ET POLICY".to_string()),
+            category: None,
+            severity: Some(2),
+            flow_id: None,
+            community_id: None,
+        }
+    }
 
+    #[test]
+    fn alerts_to_resolved_addresses_are_reported_once() {
+        let state = BlockedDomains::default();
+        state.add_domain("monitor-test.example.com");
+        state.resolutions.lock().unwrap().insert("monitor-test.example.com".to_string(), ResolvedDomain {
+            domain: "monitor-test.example.com".to_string(),
+            addresses: vec![ResolvedAddress { ip: "203.0.113.7".to_string(), ttl: 300 }],
+            cname_chain: Vec::new(),
+            min_ttl: 300,
+            resolved_at: 0,
+        });
+        let alerts = vec![
+            alert("2025-01-01T10:00:00.000000+0000", "203.0.113.7"),
+            alert("2025-01-01T10:00:01.000000+0000", "198.51.100.1"),
+        ];
+
+        let found = find_blocked_access(&state, &alerts);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].domain, "monitor-test.example.com");
+
+        assert!(should_notify(&found[0], 30, 1000));
+        assert!(!should_notify(&found[0], 30, 2000));
+
+        let later = find_blocked_access(&state, &[alert("2025-01-01T10:05:00.000000+0000", "203.0.113.7")]);
+        assert!(!should_notify(&later[0], 30, 1010));
+        let after_cooldown = find_blocked_access(&state, &[alert("2025-01-01T10:06:00.000000+0000", "203.0.113.7")]);
+        assert!(should_notify(&after_cooldown[0], 30, 1031));
+    }
+}
@@ -0,0 +1,428 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, Manager, State};
+use tracing::{info, warn};
+use crate::app_data::app_data_dir;
+use crate::audit::audited;
+use crate::daemon::ipc::DaemonRequest;
+use crate::daemon::{daemon_running, forward, running_daemon};
+use crate::firewall::backend::{FirewallBackend, FirewallBackendState};
+use crate::firewall::command_runner::can_elevate_unattended;
+use crate::firewall::common::BlockedDomains;
+use crate::firewall::ownership::RuleManifestState;
+use crate::firewall::snapshots::ChangeSource;
+use super::matcher::DomainPattern;
+use super::resolver::{DnsResolver, DnsResolverState, ResolvedDomain};
+use super::save_resolutions_to_file;
+use crate::error::AppError;
+
+// Very short TTLs (common on CDNs) would otherwise have us re-resolving and touching the
+// firewall every few seconds.
+pub const MIN_REFRESH_SECONDS: u64 = 60;
+const FAILED_RETRY_SECONDS: u64 = 300;
+pub const TICK_SECONDS: u64 = 15;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DomainIpChange {
+    pub domain: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub ips: Vec<String>,
+    pub next_refresh_at: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RefreshSummary {
+    pub refreshed: Vec<String>,
+    pub changes: Vec<DomainIpChange>,
+    pub failed: Vec<(String, String)>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn next_refresh_at(resolved: &ResolvedDomain) -> u64 {
+    resolved.expires_at().max(resolved.resolved_at + MIN_REFRESH_SECONDS)
+}
+
+fn is_due(previous: Option<&ResolvedDomain>, retry_at: Option<u64>, now: u64) -> bool {
+    if let Some(retry_at) = retry_at {
+        return now >= retry_at;
+    }
+    match previous {
+        Some(resolved) => now >= next_refresh_at(resolved),
+        None => true,
+    }
+}
+
+// Re-resolves one blocked domain and brings its firewall rules (`existing_rules`, as recorded
+// in the manifest) in line with the new answer. Without a previous resolution we can't know
+// what the rules contain, so the set is rebuilt.
+pub async fn refresh_domain(
+    resolver: &DnsResolver,
+    backend: &dyn FirewallBackend,
+    domain: &str,
+    previous: Option<&ResolvedDomain>,
+    existing_rules: &[String]
+) -> Result<(ResolvedDomain, Option<DomainIpChange>), String> {
+    let host = DomainPattern::parse(domain)
+        .filter(|pattern| pattern.covers_apex())
+        .map(|pattern| pattern.domain)
+        .ok_or_else(|| format!("{} has no single host to resolve", domain))?;
+    let resolved = resolver.resolve(&host).await
+        .map_err(|e| format!("Failed to resolve domain {}: {}", host, e))?;
+
+    // An empty answer is more likely a resolver hiccup than the domain going away; keep the
+    // existing rules rather than opening the block up.
+    if resolved.addresses.is_empty() {
+        return Err(format!("No IP addresses found for domain: {}", domain));
+    }
+
+    let ips = resolved.ips();
+    let (added, removed) = match previous {
+        Some(previous) => {
+            let old_ips = previous.ips();
+            let old: HashSet<&String> = old_ips.iter().collect();
+            let new: HashSet<&String> = ips.iter().collect();
+            (
+                ips.iter().filter(|ip| !old.contains(ip)).cloned().collect::<Vec<_>>(),
+                old_ips.iter().filter(|ip| !new.contains(ip)).cloned().collect::<Vec<_>>(),
+            )
+        },
+        None => (ips.clone(), Vec::new()),
+    };
+
+    if added.is_empty() && removed.is_empty() {
+        return Ok((resolved, None));
+    }
+
+    let result = match previous {
+        Some(_) => backend.update_ip_set(domain, &ips, &added, &removed, existing_rules).await,
+        None => backend.block_ip_set(domain, &ips, existing_rules).await,
+    };
+    result.map_err(|e| format!("Failed to update firewall rules for domain {}: {}", domain, e))?;
+
+    let change = DomainIpChange {
+        domain: domain.to_string(),
+        added,
+        removed,
+        ips,
+        next_refresh_at: next_refresh_at(&resolved),
+    };
+    Ok((resolved, Some(change)))
+}
+
+// Refreshes every blocked domain whose records have expired (or all of them with `force`).
+// `retry_at` holds back domains that failed recently.
+pub async fn refresh_blocked_domains_once(
+    resolver: &DnsResolver,
+    backend: &dyn FirewallBackend,
+    state: &BlockedDomains,
+    manifest: &RuleManifestState,
+    retry_at: &mut HashMap<String, u64>,
+    force: bool
+) -> RefreshSummary {
+    let now = now_secs();
+    let due: Vec<String> = {
+        let domains = state.domains.lock().unwrap();
+        let resolutions = state.resolutions.lock().unwrap();
+        // Entries without a resolution have no IP rules to maintain (wildcards, imported lists).
+        domains
+            .iter()
+            .filter(|d| resolutions.contains_key(*d))
+            .filter(|d| force || is_due(resolutions.get(*d), retry_at.get(*d).copied(), now))
+            .cloned()
+            .collect()
+    };
+
+    let mut summary = RefreshSummary::default();
+    for domain in due {
+        let _changing = state.changing.lock().await;
+        // The domain may have been unblocked, or blocked again, since the list was taken.
+        if !state.domains.lock().unwrap().contains(&domain) {
+            continue;
+        }
+        let Some(previous) = state.resolutions.lock().unwrap().get(&domain).cloned() else {
+            continue;
+        };
+        let existing_rules = manifest.domain_rules(&domain).unwrap_or_default();
+        match refresh_domain(resolver, backend, &domain, Some(&previous), &existing_rules).await {
+            Ok((resolved, change)) => {
+                retry_at.remove(&domain);
+                state.resolutions.lock().unwrap().insert(domain.clone(), resolved);
+                // Recorded while the lock is held, so an unblock waiting on it forgets these
+                // names rather than having them recorded again after it ran. Re-blocking can
+                // change how many rules a set needs.
+                if let Some(change) = &change {
+                    manifest.record_domain(&domain, backend.ip_set_rule_names(&domain, &change.ips));
+                }
+                summary.refreshed.push(domain);
+                summary.changes.extend(change);
+            },
+            Err(e) => {
+                warn!("Failed to refresh blocked domain {}: {}", domain, e);
+                retry_at.insert(domain.clone(), now + FAILED_RETRY_SECONDS);
+                summary.failed.push((domain, e));
+            },
+        }
+    }
+
+    summary
+}
+
+// Saves the new resolutions and the rule manifest they were recorded in.
+pub fn persist_summary(
+    data_dir: &Path,
+    state: &BlockedDomains,
+    manifest: &RuleManifestState,
+    summary: &RefreshSummary
+) {
+    if summary.refreshed.is_empty() {
+        return;
+    }
+
+    let resolutions = state.resolutions.lock().unwrap().clone();
+    if let Err(e) = save_resolutions_to_file(data_dir, &resolutions) {
+        warn!("Failed to save domain resolutions: {}", e);
+    }
+
+    if !summary.changes.is_empty() {
+        if let Err(e) = manifest.save(data_dir) {
+            warn!("Failed to save rule manifest: {}", e);
+        }
+    }
+}
+
+fn publish_summary(app: &AppHandle, state: &BlockedDomains, summary: &RefreshSummary) {
+    persist_summary(&app_data_dir(app), state, &app.state::<RuleManifestState>(), summary);
+
+    for change in &summary.changes {
+        if let Err(e) = app.emit("blocked-domain-ips-changed", change) {
+            warn!("Failed to emit blocked domain change event: {}", e);
+        }
+    }
+}
+
+// Only runs where firewall changes need no prompt (as root, or the elevated background
+// service); elsewhere a refresh would pop up elevation prompts out of nowhere, so domains
+// are only refreshed on request through refresh_blocked_domains. Rounds are skipped while the
+// background service runs, since it refreshes the domains itself.
+pub fn start_refresher(app: AppHandle) {
+    if !can_elevate_unattended() {
+        info!("Not refreshing blocked domains in the background: firewall changes would need an elevation prompt");
+        return;
+    }
+    tauri::async_runtime::spawn(async move {
+        let mut retry_at = HashMap::new();
+        let mut interval = tokio::time::interval(Duration::from_secs(TICK_SECONDS));
+        loop {
+            interval.tick().await;
+            if daemon_running().await {
+                continue;
+            }
+
+            let resolver = app.state::<DnsResolverState>().resolver();
+            let backend = app.state::<FirewallBackendState>().backend.clone();
+            let state = app.state::<BlockedDomains>();
+
+            let manifest = app.state::<RuleManifestState>();
+            let summary = refresh_blocked_domains_once(&resolver, backend.as_ref(), &state, &manifest, &mut retry_at, false).await;
+            publish_summary(&app, &state, &summary);
+        }
+    });
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_blocked_domain_resolutions(
+    state: State<'_, BlockedDomains>
+) -> Result<HashMap<String, ResolvedDomain>, AppError> {
+    let resolutions = state.resolutions.lock().unwrap();
+    Ok(resolutions.clone())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn refresh_blocked_domains(
+    app: AppHandle,
+    resolver: State<'_, DnsResolverState>,
+    backend: State<'_, FirewallBackendState>,
+    state: State<'_, BlockedDomains>,
+    manifest: State<'_, RuleManifestState>
+) -> Result<RefreshSummary, AppError> {
+    if let Some(socket) = running_daemon().await {
+        return forward(&socket, &DaemonRequest::RefreshDomains).await;
+    }
+
+    let data_dir = app_data_dir(&app);
+    let params = Value::Null;
+    audited(&data_dir, ChangeSource::Desktop, "refresh_blocked_domains", params, async {
+        let summary = refresh_blocked_domains_once(
+            &resolver.resolver(),
+            backend.backend.as_ref(),
+            &state,
+            &manifest,
+            &mut HashMap::new(),
+            true
+        ).await;
+        publish_summary(&app, &state, &summary);
+        Ok(summary)
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::firewall::backend::netsh::NetshBackend;
+    use crate::firewall::domain_blocking::resolver::ResolvedAddress;
+    use crate::firewall::testing::{resolver_for, FakeCommandRunner, StubDnsServer};
+
+    fn resolution(domain: &str, ips: &[&str], resolved_at: u64, ttl: u32) -> ResolvedDomain {
+        ResolvedDomain {
+            domain: domain.to_string(),
+            addresses: ips.iter().map(|ip| ResolvedAddress { ip: ip.to_string(), ttl }).collect(),
+            cname_chain: Vec::new(),
+            min_ttl: ttl,
+            resolved_at,
+        }
+    }
+
+    fn blocked(domain: &str, previous: Option<ResolvedDomain>) -> BlockedDomains {
+        let state = BlockedDomains::default();
+        state.add_domain(domain);
+        if let Some(previous) = previous {
+            state.resolutions.lock().unwrap().insert(domain.to_string(), previous);
+        }
+        state
+    }
+
+    #[test]
+    fn due_after_ttl_with_minimum_interval() {
+        let resolved = resolution("example.com", &["1.2.3.4"], 1000, 5);
+
+        assert!(!is_due(Some(&resolved), None, 1030));
+        assert!(is_due(Some(&resolved), None, 1000 + MIN_REFRESH_SECONDS));
+        assert!(is_due(None, None, 0));
+        assert!(!is_due(None, Some(2000), 1999));
+    }
+
+    #[tokio::test]
+    async fn changed_records_update_the_firewall() {
+        let server = StubDnsServer::start().await;
+        server.add_a("example.com", "5.6.7.8", 300);
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+        let state = blocked("example.com", Some(resolution("example.com", &["1.2.3.4"], 0, 60)));
+        let manifest = RuleManifestState::default();
+
+        let summary = refresh_blocked_domains_once(&resolver_for(&server), &backend, &state, &manifest, &mut HashMap::new(), false).await;
+
+        assert_eq!(summary.changes.len(), 1);
+        assert_eq!(summary.changes[0].added, vec!["5.6.7.8"]);
+        assert_eq!(summary.changes[0].removed, vec!["1.2.3.4"]);
+        let scripts = runner.scripts();
+        assert_eq!(scripts.len(), 1);
+        assert!(scripts[0].contains("remoteip=5.6.7.8"));
+        assert_eq!(state.resolutions.lock().unwrap()["example.com"].ips(), vec!["5.6.7.8"]);
+        assert_eq!(manifest.domain_rules("example.com").unwrap(), backend.ip_set_rule_names("example.com", &["5.6.7.8".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn domains_unblocked_during_a_refresh_are_left_alone() {
+        let server = StubDnsServer::start().await;
+        server.add_a("example.com", "5.6.7.8", 300);
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+        let state = blocked("example.com", Some(resolution("example.com", &["1.2.3.4"], 0, 60)));
+
+        let resolver = resolver_for(&server);
+        let manifest = RuleManifestState::default();
+        let mut retry_at = HashMap::new();
+
+        // An unblock holding the lock finishes before the refresh gets to the domain.
+        let changing = state.changing.clone().lock_owned().await;
+        let unblock = async {
+            state.remove_domain("example.com");
+            state.resolutions.lock().unwrap().remove("example.com");
+            drop(changing);
+        };
+        let (summary, ()) = tokio::join!(
+            refresh_blocked_domains_once(&resolver, &backend, &state, &manifest, &mut retry_at, false),
+            unblock
+        );
+
+        assert!(summary.refreshed.is_empty());
+        assert!(runner.invocations().is_empty());
+        assert!(state.resolutions.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unchanged_records_leave_the_firewall_alone() {
+        let server = StubDnsServer::start().await;
+        server.add_a("example.com", "1.2.3.4", 300);
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+        let state = blocked("example.com", Some(resolution("example.com", &["1.2.3.4"], 0, 60)));
+
+        let summary = refresh_blocked_domains_once(&resolver_for(&server), &backend, &state, &RuleManifestState::default(), &mut HashMap::new(), false).await;
+
+        assert_eq!(summary.refreshed, vec!["example.com"]);
+        assert!(summary.changes.is_empty());
+        assert!(runner.invocations().is_empty());
+        assert!(state.resolutions.lock().unwrap()["example.com"].resolved_at > 0);
+    }
+
+    #[tokio::test]
+    async fn fresh_resolutions_are_skipped_unless_forced() {
+        let server = StubDnsServer::start().await;
+        server.add_a("example.com", "1.2.3.4", 300);
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+        let state = blocked("example.com", Some(resolution("example.com", &["1.2.3.4"], now_secs(), 300)));
+
+        let summary = refresh_blocked_domains_once(&resolver_for(&server), &backend, &state, &RuleManifestState::default(), &mut HashMap::new(), false).await;
+        assert!(summary.refreshed.is_empty());
+        assert!(server.queries().is_empty());
+
+        let summary = refresh_blocked_domains_once(&resolver_for(&server), &backend, &state, &RuleManifestState::default(), &mut HashMap::new(), true).await;
+        assert_eq!(summary.refreshed, vec!["example.com"]);
+    }
+
+    #[tokio::test]
+    async fn entries_without_ip_rules_are_not_refreshed() {
+        let server = StubDnsServer::start().await;
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+        let state = blocked("*.example.com", None);
+        state.add_domain("imported.example.com");
+
+        let summary = refresh_blocked_domains_once(&resolver_for(&server), &backend, &state, &RuleManifestState::default(), &mut HashMap::new(), true).await;
+
+        assert!(summary.refreshed.is_empty() && summary.failed.is_empty());
+        assert!(server.queries().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_resolution_keeps_rules_and_backs_off() {
+        let server = StubDnsServer::start().await;
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+        let state = blocked("example.com", Some(resolution("example.com", &["1.2.3.4"], 0, 60)));
+        let mut retry_at = HashMap::new();
+
+        let summary = refresh_blocked_domains_once(&resolver_for(&server), &backend, &state, &RuleManifestState::default(), &mut retry_at, false).await;
+
+        assert_eq!(summary.failed.len(), 1);
+        assert!(runner.invocations().is_empty());
+        assert!(retry_at["example.com"] > now_secs());
+        assert_eq!(state.resolutions.lock().unwrap()["example.com"].ips(), vec!["1.2.3.4"]);
+    }
+}
@@ -0,0 +1,572 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use hickory_proto::op::{Edns, Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use tauri::{AppHandle, State};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use crate::app_data::app_data_dir;
+use crate::audit::audited;
+use crate::firewall::snapshots::ChangeSource;
+use crate::error::{AppError, ErrorKind};
+
+const DEFAULT_FALLBACK_SERVERS: [&str; 2] = ["1.1.1.1:53", "8.8.8.8:53"];
+const MAX_UDP_PAYLOAD: u16 = 4096;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolverConfig {
+    pub upstream_servers: Vec<String>,
+    pub timeout_ms: u64,
+    pub attempts: u32,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        let mut upstream_servers = system_nameservers();
+        if upstream_servers.is_empty() {
+            upstream_servers = DEFAULT_FALLBACK_SERVERS.iter().map(|s| s.to_string()).collect();
+        }
+
+        ResolverConfig {
+            upstream_servers,
+            timeout_ms: 2000,
+            attempts: 2,
+        }
+    }
+}
+
+impl ResolverConfig {
+    pub fn server_addrs(&self) -> Result<Vec<SocketAddr>, String> {
+        self.upstream_servers
+            .iter()
+            .map(|s| parse_server_addr(s))
+            .collect()
+    }
+}
+
+pub struct DnsResolverState {
+    pub config: Arc<Mutex<ResolverConfig>>,
+}
+
+impl Default for DnsResolverState {
+    fn default() -> Self {
+        DnsResolverState {
+            config: Arc::new(Mutex::new(ResolverConfig::default())),
+        }
+    }
+}
+
+impl DnsResolverState {
+    pub fn resolver(&self) -> DnsResolver {
+        DnsResolver::new(self.config.lock().unwrap().clone())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolvedAddress {
+    pub ip: String,
+    pub ttl: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolvedDomain {
+    pub domain: String,
+    pub addresses: Vec<ResolvedAddress>,
+    pub cname_chain: Vec<String>,
+    pub min_ttl: u32,
+    pub resolved_at: u64,
+}
+
+impl ResolvedDomain {
+    pub fn ips(&self) -> Vec<String> {
+        self.addresses.iter().map(|a| a.ip.clone()).collect()
+    }
+
+    pub fn expires_at(&self) -> u64 {
+        self.resolved_at + self.min_ttl as u64
+    }
+}
+
+struct QueryAnswer {
+    cname_chain: Vec<(String, u32)>,
+    addresses: Vec<ResolvedAddress>,
+    nxdomain: bool,
+}
+
+#[derive(Clone)]
+pub struct DnsResolver {
+    config: ResolverConfig,
+}
+
+impl DnsResolver {
+    pub fn new(config: ResolverConfig) -> Self {
+        DnsResolver { config }
+    }
+
+    pub async fn resolve(&self, domain: &str) -> Result<ResolvedDomain, AppError> {
+        let mut name = Name::from_ascii(domain)
+            .map_err(|e| AppError::invalid_input(format!("Invalid domain name {}: {}", domain, e)))?;
+        name.set_fqdn(true);
+
+        let (v4, v6) = tokio::join!(
+            self.query(&name, RecordType::A),
+            self.query(&name, RecordType::AAAA)
+        );
+        let v4 = v4.map_err(|e| AppError::new(ErrorKind::ResolutionFailed, e))?;
+        let v6 = v6.map_err(|e| AppError::new(ErrorKind::ResolutionFailed, e))?;
+
+        if v4.nxdomain && v6.nxdomain {
+            return Err(AppError::not_found(format!("Domain {} does not exist", domain)));
+        }
+
+        let cname_chain = if v4.cname_chain.len() >= v6.cname_chain.len() {
+            v4.cname_chain
+        } else {
+            v6.cname_chain
+        };
+
+        let mut addresses = v4.addresses;
+        addresses.extend(v6.addresses);
+
+        let min_ttl = addresses
+            .iter()
+            .map(|a| a.ttl)
+            .chain(cname_chain.iter().map(|(_, ttl)| *ttl))
+            .min()
+            .unwrap_or(0);
+
+        Ok(ResolvedDomain {
+            domain: domain.to_string(),
+            addresses,
+            cname_chain: cname_chain.into_iter().map(|(target, _)| target).collect(),
+            min_ttl,
+            resolved_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        })
+    }
+
+    async fn query(&self, name: &Name, record_type: RecordType) -> Result<QueryAnswer, String> {
+        let servers = self.config.server_addrs()?;
+        if servers.is_empty() {
+            return Err("No upstream DNS servers configured".to_string());
+        }
+
+        let mut last_error = String::new();
+        for _ in 0..self.config.attempts.max(1) {
+            for server in &servers {
+                match self.exchange(*server, name, record_type).await {
+                    Ok(response) => match response.response_code() {
+                        ResponseCode::NoError => return Ok(read_answer(name, record_type, &response)),
+                        ResponseCode::NXDomain => return Ok(QueryAnswer {
+                            cname_chain: Vec::new(),
+                            addresses: Vec::new(),
+                            nxdomain: true,
+                        }),
+                        code => last_error = format!("{} answered {}", server, code),
+                    },
+                    Err(e) => last_error = e,
+                }
+            }
+        }
+
+        Err(format!("DNS query for {} {} failed: {}", name, record_type, last_error))
+    }
+
+    async fn exchange(&self, server: SocketAddr, name: &Name, record_type: RecordType) -> Result<Message, String> {
+        let bind_addr: SocketAddr = if server.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+        let socket = UdpSocket::bind(bind_addr).await
+            .map_err(|e| format!("Failed to bind DNS socket: {}", e))?;
+        socket.connect(server).await
+            .map_err(|e| format!("Failed to connect to {}: {}", server, e))?;
+
+        let id: u16 = rand::random();
+        let request = build_query(id, name, record_type)
+            .to_vec()
+            .map_err(|e| format!("Failed to encode DNS query: {}", e))?;
+        socket.send(&request).await
+            .map_err(|e| format!("Failed to send DNS query to {}: {}", server, e))?;
+
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let mut buf = vec![0u8; MAX_UDP_PAYLOAD as usize];
+
+        loop {
+            let len = tokio::time::timeout(timeout, socket.recv(&mut buf)).await
+                .map_err(|_| format!("Timed out waiting for {}", server))?
+                .map_err(|e| format!("Failed to read DNS response from {}: {}", server, e))?;
+
+            let response = match Message::from_vec(&buf[..len]) {
+                Ok(response) => response,
+                Err(_) => continue,
+            };
+
+            // Ignore stray datagrams that don't answer our query.
+            if response.id() == id && response.message_type() == MessageType::Response {
+                // A truncated answer is missing records; the full one only comes over TCP.
+                if response.truncated() {
+                    return exchange_tcp(server, &request, id, timeout).await;
+                }
+                return Ok(response);
+            }
+        }
+    }
+}
+
+async fn exchange_tcp(server: SocketAddr, request: &[u8], id: u16, timeout: Duration) -> Result<Message, String> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(server).await
+            .map_err(|e| format!("Failed to connect to {} over TCP: {}", server, e))?;
+
+        // DNS over TCP prefixes each message with its length.
+        let mut framed = (request.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(request);
+        stream.write_all(&framed).await
+            .map_err(|e| format!("Failed to send DNS query to {} over TCP: {}", server, e))?;
+
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).await
+            .map_err(|e| format!("Failed to read DNS response from {} over TCP: {}", server, e))?;
+        let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut buf).await
+            .map_err(|e| format!("Failed to read DNS response from {} over TCP: {}", server, e))?;
+
+        Message::from_vec(&buf).map_err(|e| format!("Invalid DNS response from {} over TCP: {}", server, e))
+    };
+
+    let response = tokio::time::timeout(timeout, exchange).await
+        .map_err(|_| format!("Timed out waiting for {} over TCP", server))??;
+    if response.id() != id || response.message_type() != MessageType::Response {
+        return Err(format!("{} answered a different query over TCP", server));
+    }
+    Ok(response)
+}
+
+pub fn build_query(id: u16, name: &Name, record_type: RecordType) -> Message {
+    let mut edns = Edns::new();
+    edns.set_max_payload(MAX_UDP_PAYLOAD);
+
+    let mut message = Message::new();
+    message
+        .set_id(id)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true)
+        .add_query(Query::query(name.clone(), record_type))
+        .set_edns(edns);
+    message
+}
+
+fn read_answer(name: &Name, record_type: RecordType, response: &Message) -> QueryAnswer {
+    let mut current = name.clone();
+    let mut cname_chain = Vec::new();
+
+    // Follow the CNAME chain from the queried name, guarding against loops.
+    while cname_chain.len() < 16 {
+        let next = response.answers().iter().find_map(|record| match record.data() {
+            Some(RData::CNAME(cname)) if record.name() == &current => Some((cname.0.clone(), record.ttl())),
+            _ => None,
+        });
+
+        match next {
+            Some((target, ttl)) => {
+                cname_chain.push((target.to_ascii().trim_end_matches('.').to_string(), ttl));
+                current = target;
+            },
+            None => break,
+        }
+    }
+
+    let addresses = response
+        .answers()
+        .iter()
+        .filter(|record| record.name() == &current && record.record_type() == record_type)
+        .filter_map(|record| {
+            let ip = match record.data() {
+                Some(RData::A(a)) => IpAddr::V4(a.0),
+                Some(RData::AAAA(aaaa)) => IpAddr::V6(aaaa.0),
+                _ => return None,
+            };
+            Some(ResolvedAddress { ip: ip.to_string(), ttl: record.ttl() })
+        })
+        .collect();
+
+    QueryAnswer {
+        cname_chain,
+        addresses,
+        nxdomain: false,
+    }
+}
+
+fn parse_server_addr(server: &str) -> Result<SocketAddr, String> {
+    let server = server.trim();
+    if let Ok(addr) = server.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    server
+        .parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, 53))
+        .map_err(|_| format!("Invalid DNS server address: {}", server))
+}
+
+#[cfg(unix)]
+fn system_nameservers() -> Vec<String> {
+    std::fs::read_to_string("/etc/resolv.conf")
+        .map(|contents| parse_resolv_conf(&contents))
+        .unwrap_or_default()
+}
+
+// Windows keeps no resolv.conf; each adapter carries its own DNS servers, which is where
+// intranet and split-horizon names get answered.
+#[cfg(windows)]
+fn system_nameservers() -> Vec<String> {
+    use windows::Win32::Foundation::{ERROR_BUFFER_OVERFLOW, NO_ERROR};
+    use windows::Win32::NetworkManagement::IpHelper::{
+        GetAdaptersAddresses, GAA_FLAG_SKIP_ANYCAST, GAA_FLAG_SKIP_MULTICAST, IP_ADAPTER_ADDRESSES_LH,
+        IP_ADAPTER_DNS_SERVER_ADDRESS_XP,
+    };
+    use windows::Win32::NetworkManagement::Ndis::IfOperStatusUp;
+    use windows::Win32::Networking::WinSock::AF_UNSPEC;
+
+    let mut size: u32 = 16 * 1024;
+    // Adapters can appear between the calls, so retry with the size Windows asks for.
+    for _ in 0..3 {
+        // Backed by u64s so the adapter structs are suitably aligned.
+        let mut buf = vec![0u64; (size as usize).div_ceil(8)];
+        let adapters = buf.as_mut_ptr() as *mut IP_ADAPTER_ADDRESSES_LH;
+        let status = unsafe {
+            GetAdaptersAddresses(
+                AF_UNSPEC.0 as u32,
+                GAA_FLAG_SKIP_ANYCAST | GAA_FLAG_SKIP_MULTICAST,
+                None,
+                Some(adapters),
+                &mut size,
+            )
+        };
+        if status == ERROR_BUFFER_OVERFLOW.0 {
+            continue;
+        }
+        if status != NO_ERROR.0 {
+            return Vec::new();
+        }
+
+        let mut servers = Vec::new();
+        let mut adapter: *const IP_ADAPTER_ADDRESSES_LH = adapters;
+        while let Some(current) = unsafe { adapter.as_ref() } {
+            if current.OperStatus == IfOperStatusUp {
+                let mut dns: *const IP_ADAPTER_DNS_SERVER_ADDRESS_XP = current.FirstDnsServerAddress;
+                while let Some(entry) = unsafe { dns.as_ref() } {
+                    if let Some(ip) = unsafe { sockaddr_ip(&entry.Address) } {
+                        let server = SocketAddr::new(ip, 53).to_string();
+                        if usable_nameserver(&ip) && !servers.contains(&server) {
+                            servers.push(server);
+                        }
+                    }
+                    dns = entry.Next;
+                }
+            }
+            adapter = current.Next;
+        }
+        return servers;
+    }
+    Vec::new()
+}
+
+#[cfg(windows)]
+unsafe fn sockaddr_ip(address: &windows::Win32::Networking::WinSock::SOCKET_ADDRESS) -> Option<IpAddr> {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use windows::Win32::Networking::WinSock::{AF_INET, AF_INET6, SOCKADDR_IN, SOCKADDR_IN6};
+
+    let sockaddr = address.lpSockaddr.as_ref()?;
+    match sockaddr.sa_family {
+        AF_INET => {
+            let v4 = &*(address.lpSockaddr as *const SOCKADDR_IN);
+            Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(v4.sin_addr.S_un.S_addr))))
+        },
+        AF_INET6 => {
+            let v6 = &*(address.lpSockaddr as *const SOCKADDR_IN6);
+            Some(IpAddr::V6(Ipv6Addr::from(v6.sin6_addr.u.Byte)))
+        },
+        _ => None,
+    }
+}
+
+// Link-local servers need a scope we can't carry in the config, and Windows lists the
+// deprecated fec0:0:0:ffff::1-3 site-local defaults on adapters nobody configured for IPv6.
+#[cfg(windows)]
+fn usable_nameserver(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(_) => true,
+        IpAddr::V6(v6) => !matches!(v6.segments()[0] & 0xffc0, 0xfe80 | 0xfec0),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn system_nameservers() -> Vec<String> {
+    Vec::new()
+}
+
+#[cfg_attr(not(unix), allow(dead_code))]
+fn parse_resolv_conf(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|rest| rest.split_whitespace().next())
+        .filter_map(|server| server.parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, 53).to_string())
+        .collect()
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_dns_resolver_config(state: State<'_, DnsResolverState>) -> Result<ResolverConfig, AppError> {
+    let config = state.config.lock().unwrap();
+    Ok(config.clone())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn set_dns_resolver_config(
+    app: AppHandle,
+    new_config: ResolverConfig,
+    state: State<'_, DnsResolverState>
+) -> Result<(), AppError> {
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "config": new_config });
+    audited(&data_dir, ChangeSource::Desktop, "set_dns_resolver_config", params, async {
+        let servers = new_config.server_addrs().map_err(AppError::invalid_input)?;
+        if servers.is_empty() {
+            return Err(AppError::invalid_input("At least one upstream DNS server is required"));
+        }
+
+        let mut config = state.config.lock().unwrap();
+        *config = ResolverConfig {
+            upstream_servers: servers.iter().map(|s| s.to_string()).collect(),
+            ..new_config
+        };
+        Ok(())
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall::testing::{resolver_using, StubDnsServer};
+
+    #[tokio::test]
+    async fn resolves_a_and_aaaa_with_ttls() {
+        let server = StubDnsServer::start().await;
+        server
+            .add_a("example.com", "93.184.216.34", 300)
+            .add_a("example.com", "2606:2800:220:1:248:1893:25c8:1946", 120);
+
+        let resolved = resolver_using(&[server.addr]).resolve("example.com").await.unwrap();
+
+        assert_eq!(resolved.addresses, vec![
+            ResolvedAddress { ip: "93.184.216.34".to_string(), ttl: 300 },
+            ResolvedAddress { ip: "2606:2800:220:1:248:1893:25c8:1946".to_string(), ttl: 120 },
+        ]);
+        assert!(resolved.cname_chain.is_empty());
+        assert_eq!(resolved.min_ttl, 120);
+
+        let mut queried: Vec<RecordType> = server.queries().into_iter().map(|(_, t)| t).collect();
+        queried.sort();
+        assert_eq!(queried, vec![RecordType::A, RecordType::AAAA]);
+    }
+
+    #[tokio::test]
+    async fn records_cname_chain_and_uses_shortest_ttl() {
+        let server = StubDnsServer::start().await;
+        server
+            .add_cname("www.example.com", "example.cdn.net", 60)
+            .add_cname("example.cdn.net", "edge-1.cdn.net", 3600)
+            .add_a("edge-1.cdn.net", "10.1.2.3", 900)
+            .add_a("unrelated.cdn.net", "10.9.9.9", 900);
+
+        let resolved = resolver_using(&[server.addr]).resolve("www.example.com").await.unwrap();
+
+        assert_eq!(resolved.cname_chain, vec!["example.cdn.net", "edge-1.cdn.net"]);
+        assert_eq!(resolved.ips(), vec!["10.1.2.3"]);
+        assert_eq!(resolved.min_ttl, 60);
+    }
+
+    #[tokio::test]
+    async fn keeps_private_addresses() {
+        let server = StubDnsServer::start().await;
+        server.add_a("intranet.example.com", "192.168.10.5", 300);
+
+        let resolved = resolver_using(&[server.addr]).resolve("intranet.example.com").await.unwrap();
+
+        assert_eq!(resolved.ips(), vec!["192.168.10.5"]);
+    }
+
+    #[tokio::test]
+    async fn retries_truncated_answers_over_tcp() {
+        let server = StubDnsServer::start().await;
+        server.add_a("big.example.com", "10.0.0.1", 300).add_a("big.example.com", "10.0.0.2", 300);
+        server.truncate_udp();
+
+        let resolved = resolver_using(&[server.addr]).resolve("big.example.com").await.unwrap();
+
+        assert_eq!(resolved.ips(), vec!["10.0.0.1", "10.0.0.2"]);
+        // Each record type is asked once over UDP and again over TCP.
+        assert_eq!(server.queries().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn reports_nxdomain() {
+        let server = StubDnsServer::start().await;
+
+        let err = resolver_using(&[server.addr]).resolve("missing.example.com").await.unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::NotFound);
+        assert!(err.message.contains("does not exist"));
+    }
+
+    #[tokio::test]
+    async fn falls_over_to_next_server_on_timeout() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server = StubDnsServer::start().await;
+        server.add_a("example.com", "1.2.3.4", 300);
+
+        let resolved = resolver_using(&[silent.local_addr().unwrap(), server.addr])
+            .resolve("example.com")
+            .await
+            .unwrap();
+
+        assert_eq!(resolved.ips(), vec!["1.2.3.4"]);
+    }
+
+    #[tokio::test]
+    async fn fails_when_no_server_answers() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let err = resolver_using(&[silent.local_addr().unwrap()]).resolve("example.com").await.unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::ResolutionFailed);
+        assert!(err.retryable);
+        assert!(err.message.contains("Timed out"));
+    }
+
+    #[test]
+    fn parses_server_addresses() {
+        assert_eq!(parse_server_addr("9.9.9.9").unwrap().to_string(), "9.9.9.9:53");
+        assert_eq!(parse_server_addr("127.0.0.1:5353").unwrap().to_string(), "127.0.0.1:5353");
+        assert_eq!(parse_server_addr("[::1]:53").unwrap().to_string(), "[::1]:53");
+        assert!(parse_server_addr("dns.google").is_err());
+    }
+
+    #[test]
+    fn parses_resolv_conf_nameservers() {
+        let contents = "# generated\nsearch lan\nnameserver 192.168.1.1\nnameserver  fe80::1%eth0\nnameserver ::1\noptions edns0\n";
+
+        assert_eq!(parse_resolv_conf(contents), vec!["192.168.1.1:53", "[::1]:53"]);
+    }
+}
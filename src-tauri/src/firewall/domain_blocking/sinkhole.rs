@@ -0,0 +1,590 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use hickory_proto::op::{Message, MessageType, ResponseCode};
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{RData, Record, RecordType};
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use crate::app_data::{app_data_dir, data_file_path};
+use crate::audit::audited;
+use crate::daemon::refuse_while_daemon_runs;
+use crate::firewall::common::BlockedDomains;
+use crate::firewall::snapshots::ChangeSource;
+use super::matcher::DomainTrie;
+use super::resolver::{DnsResolverState, ResolverConfig};
+use crate::error::AppError;
+
+const MAX_PACKET_SIZE: usize = 4096;
+const MAX_LOGGED_QUERIES: usize = 500;
+// Browsers retry and prefetch aggressively; one notification per domain per minute is plenty.
+const NOTIFICATION_COOLDOWN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkholeMode {
+    NxDomain,
+    Address,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SinkholeConfig {
+    pub enabled: bool,
+    pub listen_address: String,
+    pub listen_port: u16,
+    pub mode: SinkholeMode,
+    pub sinkhole_ipv4: String,
+    pub sinkhole_ipv6: String,
+    pub ttl: u32,
+}
+
+impl Default for SinkholeConfig {
+    fn default() -> Self {
+        SinkholeConfig {
+            enabled: false,
+            listen_address: "127.0.0.1".to_string(),
+            // 53 needs elevation and 5353 belongs to mDNS.
+            listen_port: 5300,
+            mode: SinkholeMode::NxDomain,
+            sinkhole_ipv4: "0.0.0.0".to_string(),
+            sinkhole_ipv6: "::".to_string(),
+            ttl: 60,
+        }
+    }
+}
+
+impl SinkholeConfig {
+    pub fn listen_addr(&self) -> Result<SocketAddr, String> {
+        let ip: IpAddr = self.listen_address.trim().parse()
+            .map_err(|_| format!("Invalid listen address: {}", self.listen_address))?;
+        Ok(SocketAddr::new(ip, self.listen_port))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.listen_addr()?;
+        self.sinkhole_ipv4.parse::<Ipv4Addr>()
+            .map_err(|_| format!("Invalid sinkhole IPv4 address: {}", self.sinkhole_ipv4))?;
+        self.sinkhole_ipv6.parse::<Ipv6Addr>()
+            .map_err(|_| format!("Invalid sinkhole IPv6 address: {}", self.sinkhole_ipv6))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockedQuery {
+    pub domain: String,
+    pub matched_entry: String,
+    pub query_type: String,
+    pub client: String,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SinkholeStatus {
+    pub running: bool,
+    pub listen_addr: Option<String>,
+    pub blocked_queries: usize,
+}
+
+pub type BlockCallback = Arc<dyn Fn(&BlockedQuery) + Send + Sync>;
+
+pub struct SinkholeServer {
+    pub addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl Drop for SinkholeServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+impl SinkholeServer {
+    // Answers queries for blocked names itself and relays everything else, byte for byte,
+    // to the resolver's upstream servers. Both lists are read live, so edits apply immediately.
+    pub async fn start(
+        config: SinkholeConfig,
+        blocked: Arc<Mutex<DomainTrie>>,
+        upstream: Arc<Mutex<ResolverConfig>>,
+        on_block: BlockCallback
+    ) -> Result<Self, String> {
+        config.validate()?;
+        let listen_addr = config.listen_addr()?;
+        let socket = UdpSocket::bind(listen_addr).await
+            .map_err(|e| format!("Failed to bind DNS sinkhole on {}: {}", listen_addr, e))?;
+        let addr = socket.local_addr()
+            .map_err(|e| format!("Failed to read DNS sinkhole address: {}", e))?;
+        let socket = Arc::new(socket);
+        let config = Arc::new(config);
+
+        let handle = tokio::spawn(async move {
+            let mut buf = vec![0u8; MAX_PACKET_SIZE];
+            loop {
+                let (len, client) = match socket.recv_from(&mut buf).await {
+                    Ok(received) => received,
+                    Err(e) => {
+                        // Windows reports ICMP port unreachable from earlier replies here; keep serving.
+                        warn!("DNS sinkhole receive error: {}", e);
+                        continue;
+                    }
+                };
+                let packet = buf[..len].to_vec();
+                let socket = socket.clone();
+                let config = config.clone();
+                let blocked = blocked.clone();
+                let upstream = upstream.clone();
+                let on_block = on_block.clone();
+
+                tokio::spawn(async move {
+                    let reply = handle_packet(&packet, client, addr, &config, &blocked, &upstream, &on_block).await;
+                    if let Some(reply) = reply {
+                        let _ = socket.send_to(&reply, client).await;
+                    }
+                });
+            }
+        });
+
+        Ok(SinkholeServer { addr, handle })
+    }
+}
+
+fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+
+async fn handle_packet(
+    packet: &[u8],
+    client: SocketAddr,
+    listen_addr: SocketAddr,
+    config: &SinkholeConfig,
+    blocked: &Mutex<DomainTrie>,
+    upstream: &Mutex<ResolverConfig>,
+    on_block: &BlockCallback
+) -> Option<Vec<u8>> {
+    let request = Message::from_vec(packet).ok()?;
+    if request.message_type() != MessageType::Query {
+        return None;
+    }
+
+    if let Some(query) = request.queries().first() {
+        let name = normalize_name(&query.name().to_ascii());
+        let matched_entry = blocked.lock().unwrap().find(&name).map(|e| e.to_string());
+        if let Some(matched_entry) = matched_entry {
+            on_block(&BlockedQuery {
+                domain: name,
+                matched_entry,
+                query_type: query.query_type().to_string(),
+                client: client.ip().to_string(),
+                timestamp: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            });
+            return blocked_response(&request, config).to_vec().ok();
+        }
+    }
+
+    let upstream = upstream.lock().unwrap().clone();
+    match forward(packet, request.id(), listen_addr, &upstream).await {
+        Ok(reply) => Some(reply),
+        Err(e) => {
+            warn!("DNS sinkhole forward failed: {}", e);
+            server_failure(&request).to_vec().ok()
+        }
+    }
+}
+
+fn response_for(request: &Message) -> Message {
+    let mut response = Message::new();
+    response
+        .set_id(request.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(request.op_code())
+        .set_recursion_desired(request.recursion_desired())
+        .set_recursion_available(true)
+        .add_queries(request.queries().to_vec());
+    response
+}
+
+fn server_failure(request: &Message) -> Message {
+    let mut response = response_for(request);
+    response.set_response_code(ResponseCode::ServFail);
+    response
+}
+
+fn blocked_response(request: &Message, config: &SinkholeConfig) -> Message {
+    let mut response = response_for(request);
+    let Some(query) = request.queries().first() else {
+        return response;
+    };
+
+    match config.mode {
+        SinkholeMode::NxDomain => {
+            response.set_response_code(ResponseCode::NXDomain);
+        },
+        SinkholeMode::Address => {
+            // Other record types get an empty NOERROR answer so clients don't fall back elsewhere.
+            let rdata = match query.query_type() {
+                RecordType::A => config.sinkhole_ipv4.parse().ok().map(|ip| RData::A(A(ip))),
+                RecordType::AAAA => config.sinkhole_ipv6.parse().ok().map(|ip| RData::AAAA(AAAA(ip))),
+                _ => None,
+            };
+            if let Some(rdata) = rdata {
+                response.add_answer(Record::from_rdata(query.name().clone(), config.ttl, rdata));
+            }
+        },
+    }
+    response
+}
+
+// Whether queries sent to `server` would reach this sinkhole. Listening on 0.0.0.0 or :: takes
+// in every local address on the port; an address is local if a socket can be bound to it.
+fn is_own_address(server: &SocketAddr, listen_addr: SocketAddr) -> bool {
+    if !listen_addr.ip().is_unspecified() {
+        return *server == listen_addr;
+    }
+    server.port() == listen_addr.port()
+        && (server.ip().is_unspecified()
+            || server.ip().is_loopback()
+            || std::net::UdpSocket::bind(SocketAddr::new(server.ip(), 0)).is_ok())
+}
+
+async fn forward(packet: &[u8], id: u16, listen_addr: SocketAddr, upstream: &ResolverConfig) -> Result<Vec<u8>, String> {
+    // Pointing the resolver at the sinkhole itself would loop forever.
+    let servers: Vec<SocketAddr> = upstream.server_addrs()?
+        .into_iter()
+        .filter(|server| !is_own_address(server, listen_addr))
+        .collect();
+    if servers.is_empty() {
+        return Err("No upstream DNS servers configured".to_string());
+    }
+
+    let timeout = Duration::from_millis(upstream.timeout_ms);
+    let mut last_error = String::new();
+    for _ in 0..upstream.attempts.max(1) {
+        for server in &servers {
+            match exchange_raw(*server, packet, id, timeout).await {
+                Ok(reply) => return Ok(reply),
+                Err(e) => last_error = e,
+            }
+        }
+    }
+    Err(last_error)
+}
+
+async fn exchange_raw(server: SocketAddr, packet: &[u8], id: u16, timeout: Duration) -> Result<Vec<u8>, String> {
+    let bind_addr: SocketAddr = if server.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind(bind_addr).await
+        .map_err(|e| format!("Failed to bind DNS socket: {}", e))?;
+    socket.connect(server).await
+        .map_err(|e| format!("Failed to connect to {}: {}", server, e))?;
+    socket.send(packet).await
+        .map_err(|e| format!("Failed to send DNS query to {}: {}", server, e))?;
+
+    let mut buf = vec![0u8; MAX_PACKET_SIZE];
+    loop {
+        let len = tokio::time::timeout(timeout, socket.recv(&mut buf)).await
+            .map_err(|_| format!("Timed out waiting for {}", server))?
+            .map_err(|e| format!("Failed to read DNS response from {}: {}", server, e))?;
+
+        // The id lives in the first two bytes; anything else is a stray datagram.
+        if len >= 2 && u16::from_be_bytes([buf[0], buf[1]]) == id {
+            return Ok(buf[..len].to_vec());
+        }
+    }
+}
+
+pub struct SinkholeState {
+    pub config: Arc<Mutex<SinkholeConfig>>,
+    pub server: Arc<Mutex<Option<SinkholeServer>>>,
+    pub blocked_queries: Arc<Mutex<VecDeque<BlockedQuery>>>,
+    last_notified: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl Default for SinkholeState {
+    fn default() -> Self {
+        SinkholeState {
+            config: Arc::new(Mutex::new(SinkholeConfig::default())),
+            server: Arc::new(Mutex::new(None)),
+            blocked_queries: Arc::new(Mutex::new(VecDeque::new())),
+            last_notified: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+fn block_callback(app: &AppHandle, state: &SinkholeState) -> BlockCallback {
+    let app = app.clone();
+    let blocked_queries = state.blocked_queries.clone();
+    let last_notified = state.last_notified.clone();
+
+    Arc::new(move |query: &BlockedQuery| {
+        debug!("DNS sinkhole blocked {} {} for {}", query.query_type, query.domain, query.client);
+
+        {
+            let mut log = blocked_queries.lock().unwrap();
+            if log.len() >= MAX_LOGGED_QUERIES {
+                log.pop_front();
+            }
+            log.push_back(query.clone());
+        }
+
+        let should_notify = {
+            let mut last_notified = last_notified.lock().unwrap();
+            let now = Instant::now();
+            match last_notified.get(&query.domain) {
+                Some(at) if now.duration_since(*at) < NOTIFICATION_COOLDOWN => false,
+                _ => {
+                    last_notified.insert(query.domain.clone(), now);
+                    true
+                }
+            }
+        };
+        if should_notify {
+            if let Err(e) = app.emit("domain-blocked-notification", &query.domain) {
+                warn!("Failed to emit domain blocked event: {}", e);
+            }
+        }
+    })
+}
+
+async fn start_sinkhole_server(app: &AppHandle) -> Result<SocketAddr, String> {
+    // It would answer from the desktop's blocked domains, which the service doesn't keep up to date.
+    refuse_while_daemon_runs("run the DNS sinkhole").await?;
+    let state = app.state::<SinkholeState>();
+    let config = state.config.lock().unwrap().clone();
+    let blocked = app.state::<BlockedDomains>().index.clone();
+    let upstream = app.state::<DnsResolverState>().config.clone();
+
+    // Release the port before binding again when restarting on the same address.
+    state.server.lock().unwrap().take();
+
+    let server = SinkholeServer::start(config, blocked, upstream, block_callback(app, &state)).await?;
+    let addr = server.addr;
+    *state.server.lock().unwrap() = Some(server);
+    Ok(addr)
+}
+
+fn save_sinkhole_config(data_dir: &Path, config: &SinkholeConfig) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(config)
+        .map_err(|e| format!("Failed to serialize sinkhole config: {}", e))?;
+    std::fs::write(data_file_path(data_dir, "dns_sinkhole.json"), json)
+        .map_err(|e| format!("Failed to write to file: {}", e))
+}
+
+// Loads the saved config and starts the forwarder if it was left enabled.
+pub async fn initialize_sinkhole(app: AppHandle) -> Result<(), String> {
+    let file_path = data_file_path(&app_data_dir(&app), "dns_sinkhole.json");
+    if !file_path.exists() {
+        return Ok(());
+    }
+
+    let json = std::fs::read_to_string(&file_path)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    let config: SinkholeConfig = serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to deserialize sinkhole config: {}", e))?;
+
+    let enabled = config.enabled;
+    *app.state::<SinkholeState>().config.lock().unwrap() = config;
+
+    if enabled {
+        start_sinkhole_server(&app).await?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_sinkhole_config(state: State<'_, SinkholeState>) -> Result<SinkholeConfig, AppError> {
+    let config = state.config.lock().unwrap();
+    Ok(config.clone())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn set_sinkhole_config(
+    app: AppHandle,
+    new_config: SinkholeConfig,
+    state: State<'_, SinkholeState>
+) -> Result<SinkholeStatus, AppError> {
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "config": new_config });
+    audited(&data_dir, ChangeSource::Desktop, "set_sinkhole_config", params, async {
+        new_config.validate()?;
+        *state.config.lock().unwrap() = new_config.clone();
+        save_sinkhole_config(&data_dir, &new_config)?;
+
+        if new_config.enabled {
+            start_sinkhole_server(&app).await?;
+        } else {
+            state.server.lock().unwrap().take();
+        }
+
+        get_sinkhole_status(state).await
+    }).await
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_sinkhole_status(state: State<'_, SinkholeState>) -> Result<SinkholeStatus, AppError> {
+    let listen_addr = state.server.lock().unwrap().as_ref().map(|s| s.addr.to_string());
+    Ok(SinkholeStatus {
+        running: listen_addr.is_some(),
+        listen_addr,
+        blocked_queries: state.blocked_queries.lock().unwrap().len(),
+    })
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_sinkhole_blocked_queries(
+    limit: Option<usize>,
+    state: State<'_, SinkholeState>
+) -> Result<Vec<BlockedQuery>, AppError> {
+    let log = state.blocked_queries.lock().unwrap();
+    let limit = limit.unwrap_or(log.len());
+    Ok(log.iter().rev().take(limit).cloned().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall::domain_blocking::resolver::DnsResolver;
+    use crate::firewall::testing::StubDnsServer;
+
+    struct Harness {
+        upstream: StubDnsServer,
+        sinkhole: SinkholeServer,
+        blocked_log: Arc<Mutex<Vec<BlockedQuery>>>,
+    }
+
+    impl Harness {
+        async fn start(mode: SinkholeMode) -> Self {
+            let upstream = StubDnsServer::start().await;
+            upstream.add_a("allowed.example.com", "93.184.216.34", 300);
+            upstream.add_a("blocked.example.com", "93.184.216.35", 300);
+
+            let entries = vec!["Blocked.example.com".to_string(), "*.ads.example.com".to_string()];
+            let blocked = Arc::new(Mutex::new(DomainTrie::from_entries(&entries)));
+            let upstream_config = Arc::new(Mutex::new(ResolverConfig {
+                upstream_servers: vec![upstream.addr.to_string()],
+                timeout_ms: 500,
+                attempts: 1,
+            }));
+            let blocked_log = Arc::new(Mutex::new(Vec::new()));
+            let on_block: BlockCallback = {
+                let blocked_log = blocked_log.clone();
+                Arc::new(move |q: &BlockedQuery| blocked_log.lock().unwrap().push(q.clone()))
+            };
+
+            let config = SinkholeConfig {
+                enabled: true,
+                listen_port: 0,
+                mode,
+                ..SinkholeConfig::default()
+            };
+            let sinkhole = SinkholeServer::start(config, blocked, upstream_config, on_block).await.unwrap();
+
+            Harness { upstream, sinkhole, blocked_log }
+        }
+
+        fn client(&self) -> DnsResolver {
+            DnsResolver::new(ResolverConfig {
+                upstream_servers: vec![self.sinkhole.addr.to_string()],
+                timeout_ms: 1000,
+                attempts: 1,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn forwards_unblocked_names_upstream() {
+        let harness = Harness::start(SinkholeMode::NxDomain).await;
+
+        let resolved = harness.client().resolve("allowed.example.com").await.unwrap();
+
+        assert_eq!(resolved.ips(), vec!["93.184.216.34"]);
+        assert!(harness.upstream.queries().iter().any(|(name, _)| name == "allowed.example.com"));
+        assert!(harness.blocked_log.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn answers_nxdomain_for_blocked_names_without_forwarding() {
+        let harness = Harness::start(SinkholeMode::NxDomain).await;
+
+        let err = harness.client().resolve("blocked.example.com").await.unwrap_err();
+
+        assert!(err.message.contains("does not exist"));
+        assert!(harness.upstream.queries().is_empty());
+        let log = harness.blocked_log.lock().unwrap();
+        assert_eq!(log.len(), 2);
+        assert!(log.iter().all(|q| q.domain == "blocked.example.com" && q.client == "127.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn blocks_subdomains_of_wildcard_entries() {
+        let harness = Harness::start(SinkholeMode::NxDomain).await;
+
+        assert!(harness.client().resolve("tracker.ads.example.com").await.is_err());
+
+        let log = harness.blocked_log.lock().unwrap();
+        assert_eq!(log[0].domain, "tracker.ads.example.com");
+        assert_eq!(log[0].matched_entry, "*.ads.example.com");
+    }
+
+    #[tokio::test]
+    async fn answers_sinkhole_addresses_in_address_mode() {
+        let harness = Harness::start(SinkholeMode::Address).await;
+
+        let resolved = harness.client().resolve("blocked.example.com").await.unwrap();
+
+        assert_eq!(resolved.ips(), vec!["0.0.0.0", "::"]);
+        assert_eq!(resolved.min_ttl, 60);
+    }
+
+    #[tokio::test]
+    async fn unreachable_upstream_yields_servfail() {
+        let harness = Harness::start(SinkholeMode::NxDomain).await;
+        drop(harness.upstream);
+        let client = DnsResolver::new(ResolverConfig {
+            upstream_servers: vec![harness.sinkhole.addr.to_string()],
+            timeout_ms: 2000,
+            attempts: 1,
+        });
+
+        let err = client.resolve("allowed.example.com").await.unwrap_err();
+
+        assert!(err.message.contains("Server Failure"), "{}", err);
+    }
+
+    #[test]
+    fn recognizes_its_own_address_on_every_interface() {
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+
+        assert!(is_own_address(&addr("127.0.0.1:5300"), addr("127.0.0.1:5300")));
+        assert!(!is_own_address(&addr("127.0.0.2:5300"), addr("127.0.0.1:5300")));
+        assert!(is_own_address(&addr("127.0.0.1:5300"), addr("0.0.0.0:5300")));
+        assert!(is_own_address(&addr("[::1]:5300"), addr("[::]:5300")));
+        assert!(!is_own_address(&addr("127.0.0.1:53"), addr("0.0.0.0:5300")));
+        // TEST-NET-1 is never assigned to a local interface.
+        assert!(!is_own_address(&addr("192.0.2.1:5300"), addr("0.0.0.0:5300")));
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let config = SinkholeConfig { sinkhole_ipv4: "::1".to_string(), ..SinkholeConfig::default() };
+        assert!(config.validate().is_err());
+        let config = SinkholeConfig { listen_address: "localhost".to_string(), ..SinkholeConfig::default() };
+        assert!(config.validate().is_err());
+    }
+}
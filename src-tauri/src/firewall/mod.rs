@@ -1,8 +1,17 @@
 mod rules;
+pub mod backend;
+pub mod command_runner;
 pub mod common;
 pub mod domain_blocking;
+pub mod ownership;
+pub mod rule_model;
+pub mod snapshots;
+pub mod tentative;
 
 pub use rules::{get_firewall_rules, add_firewall_rule, remove_firewall_rule, enable_disable_rule};
 pub use domain_blocking::{get_blocked_domains, block_domain, unblock_domain};
 pub use common::{FirewallState, BlockedDomains};
+pub use backend::{FirewallBackendState, get_firewall_backend};
 
+#[cfg(test)]
+pub mod testing;
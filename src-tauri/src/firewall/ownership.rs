@@ -0,0 +1,352 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use tauri::{AppHandle, State};
+use crate::app_data::{app_data_dir, data_file_path};
+use crate::audit::audited;
+use crate::daemon::refuse_while_daemon_runs;
+use crate::firewall::backend::FirewallBackendState;
+use crate::firewall::common::BlockedDomains;
+use crate::firewall::snapshots::{snapshot_before_change, ChangeSource};
+use crate::error::AppError;
+
+const MANIFEST_FILE: &str = "rule_manifest.json";
+pub const DOMAIN_RULE_PREFIX: &str = "Block-Domain-";
+pub const POLICY_RULE_PREFIX: &str = "Dept-Policy-";
+
+// The firewall rules this app created, so unblocking deletes exactly those instead of
+// guessing names, and anything else carrying our prefix can be recognized as left over.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleManifest {
+    // Blocked entry -> the rule names created for it.
+    pub domains: HashMap<String, Vec<String>>,
+    // Rules added through add_firewall_rule.
+    pub manual_rules: Vec<String>,
+    // Rules generated from the department policy.
+    #[serde(default)]
+    pub policy_rules: Vec<String>,
+}
+
+impl RuleManifest {
+    // Every rule name the app owns.
+    pub fn rule_names(&self) -> HashSet<String> {
+        self.domains
+            .values()
+            .flatten()
+            .chain(self.manual_rules.iter())
+            .chain(self.policy_rules.iter())
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Default)]
+pub struct RuleManifestState {
+    pub manifest: Arc<Mutex<RuleManifest>>,
+}
+
+impl RuleManifestState {
+    pub fn record_domain(&self, entry: &str, rule_names: Vec<String>) {
+        self.manifest.lock().unwrap().domains.insert(entry.to_string(), rule_names);
+    }
+
+    pub fn forget_domains(&self, entries: &[String]) {
+        let mut manifest = self.manifest.lock().unwrap();
+        for entry in entries {
+            manifest.domains.remove(entry);
+        }
+    }
+
+    pub fn domain_rules(&self, entry: &str) -> Option<Vec<String>> {
+        self.manifest.lock().unwrap().domains.get(entry).cloned()
+    }
+
+    pub fn record_manual_rule(&self, rule_name: &str) {
+        let mut manifest = self.manifest.lock().unwrap();
+        if !manifest.manual_rules.iter().any(|r| r == rule_name) {
+            manifest.manual_rules.push(rule_name.to_string());
+        }
+    }
+
+    pub fn forget_manual_rule(&self, rule_name: &str) {
+        self.manifest.lock().unwrap().manual_rules.retain(|r| r != rule_name);
+    }
+
+    pub fn set_manual_rules(&self, rule_names: Vec<String>) {
+        self.manifest.lock().unwrap().manual_rules = rule_names;
+    }
+
+    pub fn policy_rules(&self) -> Vec<String> {
+        self.manifest.lock().unwrap().policy_rules.clone()
+    }
+
+    pub fn set_policy_rules(&self, rule_names: Vec<String>) {
+        self.manifest.lock().unwrap().policy_rules = rule_names;
+    }
+
+    pub fn save(&self, data_dir: &Path) -> Result<(), String> {
+        let manifest = self.manifest.lock().unwrap().clone();
+        save_manifest_to_file(data_dir, &manifest)
+    }
+}
+
+fn save_manifest_to_file(data_dir: &Path, manifest: &RuleManifest) -> Result<(), String> {
+    let file_path = data_file_path(data_dir, MANIFEST_FILE);
+    let json = serde_json::to_string_pretty(manifest)
+        .map_err(|e| format!("Failed to serialize rule manifest: {}", e))?;
+
+    fs::write(&file_path, json)
+        .map_err(|e| format!("Failed to write to file: {}", e))
+}
+
+fn load_manifest_from_file(data_dir: &Path) -> Result<RuleManifest, String> {
+    let file_path = data_file_path(data_dir, MANIFEST_FILE);
+
+    if !file_path.exists() {
+        return Ok(RuleManifest::default());
+    }
+
+    let json = fs::read_to_string(&file_path)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+
+    serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to deserialize rule manifest: {}", e))
+}
+
+pub fn initialize_rule_manifest(data_dir: &Path, state: &RuleManifestState) -> Result<(), String> {
+    let manifest = load_manifest_from_file(data_dir)?;
+    *state.manifest.lock().unwrap() = manifest;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReconcileReport {
+    // Rules carrying one of our prefixes that nothing in the manifest owns.
+    pub orphaned_rules: Vec<String>,
+    // Rules the manifest lists that are no longer in the firewall.
+    pub missing_rules: Vec<String>,
+    pub removed: bool,
+}
+
+// The blocked entry a `Block-Domain-*` rule name was derived from, if it is one we know.
+// Handles the numbered (`-2`) names and the original single-rule naming.
+fn rule_entry<'a>(rule_name: &str, blocked: &HashSet<&'a str>) -> Option<&'a str> {
+    let rest = rule_name.strip_prefix(DOMAIN_RULE_PREFIX)?;
+    let rest = rest
+        .strip_prefix("Outbound-")
+        .or_else(|| rest.strip_prefix("Inbound-"))
+        .unwrap_or(rest);
+
+    let mut candidates = vec![rest];
+    if let Some((base, suffix)) = rest.rsplit_once('-') {
+        if !suffix.is_empty() && suffix.chars().all(|c| c.is_ascii_digit()) {
+            candidates.push(base);
+        }
+    }
+    candidates.into_iter().find_map(|c| blocked.get(c).copied())
+}
+
+// Compares the firewall against the manifest. A `Block-Domain-*` rule is owned when the
+// manifest lists it under a blocked entry, or when it belongs to a blocked entry the
+// manifest has never recorded (rules created before the manifest existed). A
+// `Dept-Policy-*` rule is owned when it is part of the current department policy.
+pub fn find_discrepancies(
+    existing_rules: &[String],
+    manifest: &RuleManifest,
+    blocked: &[String]
+) -> ReconcileReport {
+    let blocked: HashSet<&str> = blocked.iter().map(|d| d.as_str()).collect();
+    let owned: HashSet<&str> = manifest.domains
+        .iter()
+        .filter(|(entry, _)| blocked.contains(entry.as_str()))
+        .flat_map(|(_, names)| names.iter().map(|n| n.as_str()))
+        .collect();
+
+    let mut orphaned_rules: Vec<String> = existing_rules
+        .iter()
+        .filter(|name| name.starts_with(DOMAIN_RULE_PREFIX) && !owned.contains(name.as_str()))
+        .filter(|name| match rule_entry(name, &blocked) {
+            Some(entry) => manifest.domains.contains_key(entry),
+            None => true,
+        })
+        .cloned()
+        .collect();
+    orphaned_rules.extend(
+        existing_rules
+            .iter()
+            .filter(|name| name.starts_with(POLICY_RULE_PREFIX) && !manifest.policy_rules.contains(name))
+            .cloned()
+    );
+
+    let existing: HashSet<&str> = existing_rules.iter().map(|n| n.as_str()).collect();
+    let mut missing_rules: Vec<String> = owned
+        .iter()
+        .copied()
+        .chain(manifest.manual_rules.iter().map(|n| n.as_str()))
+        .chain(manifest.policy_rules.iter().map(|n| n.as_str()))
+        .filter(|name| !existing.contains(name))
+        .map(|name| name.to_string())
+        .collect();
+    missing_rules.sort();
+    missing_rules.dedup();
+
+    ReconcileReport { orphaned_rules, missing_rules, removed: false }
+}
+
+// Lists orphaned `Block-Domain-*` and `Dept-Policy-*` rules and removes them in one elevated run unless
+// `dry_run` is set. Manifest entries for unblocked domains and manual rules deleted
+// outside the app are dropped; missing domain rules are only reported, since a forced
+// refresh recreates them.
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn reconcile_firewall_rules(
+    app: AppHandle,
+    dry_run: Option<bool>,
+    backend: State<'_, FirewallBackendState>,
+    state: State<'_, BlockedDomains>,
+    manifest_state: State<'_, RuleManifestState>
+) -> Result<ReconcileReport, AppError> {
+    let existing: Vec<String> = backend.backend.list_rules().await
+        .map_err(|e| AppError::from(e).context("Failed to list firewall rules"))?
+        .into_iter()
+        .map(|rule| rule.name)
+        .collect();
+    let blocked = state.domains.lock().unwrap().clone();
+    let manifest = manifest_state.manifest.lock().unwrap().clone();
+
+    let mut report = find_discrepancies(&existing, &manifest, &blocked);
+    if dry_run.unwrap_or(false) {
+        return Ok(report);
+    }
+
+    let data_dir = app_data_dir(&app);
+    audited(&data_dir, ChangeSource::Desktop, "reconcile_firewall_rules", Value::Null, async {
+        refuse_while_daemon_runs("remove orphaned firewall rules").await?;
+        if !report.orphaned_rules.is_empty() {
+            snapshot_before_change(
+                &data_dir,
+                backend.backend.as_ref(),
+                &manifest_state,
+                &state,
+                ChangeSource::Desktop,
+                "reconcile_firewall_rules"
+            ).await?;
+        }
+        backend.backend.remove_rules(&report.orphaned_rules).await
+            .map_err(|e| AppError::from(e).context("Failed to remove orphaned firewall rules"))?;
+        report.removed = true;
+
+        {
+            let mut manifest = manifest_state.manifest.lock().unwrap();
+            manifest.domains.retain(|entry, _| blocked.contains(entry));
+            manifest.manual_rules.retain(|name| !report.missing_rules.contains(name));
+        }
+        manifest_state.save(&data_dir)?;
+
+        Ok(report)
+    }).await
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_rule_manifest(manifest_state: State<'_, RuleManifestState>) -> Result<RuleManifest, AppError> {
+    Ok(manifest_state.manifest.lock().unwrap().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn manifest(domains: &[(&str, &[&str])]) -> RuleManifest {
+        RuleManifest {
+            domains: domains.iter().map(|(d, n)| (d.to_string(), names(n))).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rules_of_unblocked_domains_are_orphaned() {
+        let existing = names(&[
+            "Block-Domain-Outbound-example.com",
+            "Block-Domain-Inbound-example.com",
+            "Block-Domain-Outbound-gone.org-1",
+            "Block-Domain-Outbound-gone.org-2",
+            "Allow SSH",
+        ]);
+        let manifest = manifest(&[
+            ("example.com", &["Block-Domain-Outbound-example.com", "Block-Domain-Inbound-example.com"]),
+            ("gone.org", &["Block-Domain-Outbound-gone.org-1", "Block-Domain-Outbound-gone.org-2"]),
+        ]);
+
+        let report = find_discrepancies(&existing, &manifest, &names(&["example.com"]));
+
+        assert_eq!(report.orphaned_rules, names(&["Block-Domain-Outbound-gone.org-1", "Block-Domain-Outbound-gone.org-2"]));
+        assert!(report.missing_rules.is_empty());
+    }
+
+    #[test]
+    fn stale_numbered_rules_of_a_blocked_domain_are_orphaned() {
+        let existing = names(&["Block-Domain-Outbound-example.com-1", "Block-Domain-Outbound-example.com-3"]);
+        let manifest = manifest(&[("example.com", &["Block-Domain-Outbound-example.com-1"])]);
+
+        let report = find_discrepancies(&existing, &manifest, &names(&["example.com"]));
+
+        assert_eq!(report.orphaned_rules, names(&["Block-Domain-Outbound-example.com-3"]));
+    }
+
+    #[test]
+    fn rules_from_before_the_manifest_are_kept() {
+        let existing = names(&["Block-Domain-example.com", "Block-Domain-Outbound-.example.org-2"]);
+
+        let report = find_discrepancies(&existing, &RuleManifest::default(), &names(&["example.com", ".example.org"]));
+
+        assert!(report.orphaned_rules.is_empty());
+    }
+
+    #[test]
+    fn policy_rules_outside_the_current_policy_are_orphaned() {
+        let existing = names(&["Dept-Policy-IT-BA-Allow-In-TCP", "Dept-Policy-IT-HR-Block-Out-Any", "Allow SSH"]);
+        let manifest = RuleManifest {
+            policy_rules: names(&["Dept-Policy-IT-BA-Allow-In-TCP", "Dept-Policy-IT-BA-Allow-Out-TCP"]),
+            ..Default::default()
+        };
+
+        let report = find_discrepancies(&existing, &manifest, &[]);
+
+        assert_eq!(report.orphaned_rules, names(&["Dept-Policy-IT-HR-Block-Out-Any"]));
+        assert_eq!(report.missing_rules, names(&["Dept-Policy-IT-BA-Allow-Out-TCP"]));
+    }
+
+    #[test]
+    fn reports_rules_deleted_outside_the_app() {
+        let mut manifest = manifest(&[("example.com", &["Block-Domain-Outbound-example.com"])]);
+        manifest.manual_rules = names(&["Allow SSH"]);
+
+        let report = find_discrepancies(&[], &manifest, &names(&["example.com"]));
+
+        assert!(report.orphaned_rules.is_empty());
+        assert_eq!(report.missing_rules, names(&["Allow SSH", "Block-Domain-Outbound-example.com"]));
+    }
+
+    #[test]
+    fn state_records_and_forgets_rules() {
+        let state = RuleManifestState::default();
+        state.record_domain("example.com", names(&["a", "b"]));
+        state.record_manual_rule("Allow SSH");
+        state.record_manual_rule("Allow SSH");
+
+        assert_eq!(state.domain_rules("example.com"), Some(names(&["a", "b"])));
+        assert_eq!(state.manifest.lock().unwrap().manual_rules.len(), 1);
+
+        state.forget_domains(&names(&["example.com"]));
+        state.forget_manual_rule("Allow SSH");
+        assert_eq!(*state.manifest.lock().unwrap(), RuleManifest::default());
+    }
+}
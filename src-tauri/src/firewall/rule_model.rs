@@ -0,0 +1,337 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use ipnet::IpNet;
+use serde::{Serialize, Deserialize};
+
+// Address and port values travel as the strings netsh prints ("10.0.0.0/8", "1000-2000",
+// "LocalSubnet"), so the UI can show and edit them as text while the backend gets them typed.
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum AddressSpec {
+    // A single address is a host-length network.
+    Network(IpNet),
+    Range { start: IpAddr, end: IpAddr },
+    // Named sets such as LocalSubnet, DNS or DefaultGateway.
+    Keyword(String),
+}
+
+impl AddressSpec {
+    pub fn is_ipv4(&self) -> Option<bool> {
+        match self {
+            AddressSpec::Network(net) => Some(matches!(net, IpNet::V4(_))),
+            AddressSpec::Range { start, .. } => Some(start.is_ipv4()),
+            AddressSpec::Keyword(_) => None,
+        }
+    }
+}
+
+impl FromStr for AddressSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(net) = s.parse::<IpNet>() {
+            return Ok(AddressSpec::Network(net.trunc()));
+        }
+        if let Ok(addr) = s.parse::<IpAddr>() {
+            return Ok(AddressSpec::Network(IpNet::from(addr)));
+        }
+        if let Some((start, end)) = s.split_once('-') {
+            if let (Ok(start), Ok(end)) = (start.trim().parse::<IpAddr>(), end.trim().parse::<IpAddr>()) {
+                if start.is_ipv4() != end.is_ipv4() || start > end {
+                    return Err(format!("Invalid address range: {}", s));
+                }
+                return Ok(AddressSpec::Range { start, end });
+            }
+        }
+        if let Some(keyword) = keyword(s, &ADDRESS_KEYWORDS) {
+            return Ok(AddressSpec::Keyword(keyword.to_string()));
+        }
+        Err(format!("Invalid address: {}", s))
+    }
+}
+
+impl fmt::Display for AddressSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressSpec::Network(net) if net.prefix_len() == net.max_prefix_len() => write!(f, "{}", net.addr()),
+            AddressSpec::Network(net) => write!(f, "{}", net),
+            AddressSpec::Range { start, end } => write!(f, "{}-{}", start, end),
+            AddressSpec::Keyword(keyword) => write!(f, "{}", keyword),
+        }
+    }
+}
+
+impl TryFrom<String> for AddressSpec {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<AddressSpec> for String {
+    fn from(value: AddressSpec) -> Self {
+        value.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum PortSpec {
+    // A single port is a range with start == end.
+    Range { start: u16, end: u16 },
+    // Windows port keywords such as RPC or IPHTTPS.
+    Keyword(String),
+}
+
+impl PortSpec {
+    pub fn single(port: u16) -> Self {
+        PortSpec::Range { start: port, end: port }
+    }
+}
+
+impl FromStr for PortSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(port) = s.parse::<u16>() {
+            return Ok(PortSpec::single(port));
+        }
+        if let Some((start, end)) = s.split_once('-') {
+            if let (Ok(start), Ok(end)) = (start.trim().parse::<u16>(), end.trim().parse::<u16>()) {
+                if start > end {
+                    return Err(format!("Invalid port range: {}", s));
+                }
+                return Ok(PortSpec::Range { start, end });
+            }
+        }
+        if let Some(keyword) = keyword(s, &PORT_KEYWORDS) {
+            return Ok(PortSpec::Keyword(keyword.to_string()));
+        }
+        Err(format!("Invalid port: {}", s))
+    }
+}
+
+impl fmt::Display for PortSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortSpec::Range { start, end } if start == end => write!(f, "{}", start),
+            PortSpec::Range { start, end } => write!(f, "{}-{}", start, end),
+            PortSpec::Keyword(keyword) => write!(f, "{}", keyword),
+        }
+    }
+}
+
+impl TryFrom<String> for PortSpec {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<PortSpec> for String {
+    fn from(value: PortSpec) -> Self {
+        value.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FirewallProfile {
+    Domain,
+    Private,
+    Public,
+}
+
+impl FirewallProfile {
+    pub fn netsh_name(&self) -> &'static str {
+        match self {
+            FirewallProfile::Domain => "domain",
+            FirewallProfile::Private => "private",
+            FirewallProfile::Public => "public",
+        }
+    }
+}
+
+impl FromStr for FirewallProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "domain" => Ok(FirewallProfile::Domain),
+            "private" => Ok(FirewallProfile::Private),
+            "public" => Ok(FirewallProfile::Public),
+            other => Err(format!("Unknown firewall profile: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InterfaceType {
+    Lan,
+    Wireless,
+    RemoteAccess,
+}
+
+impl InterfaceType {
+    pub fn netsh_name(&self) -> &'static str {
+        match self {
+            InterfaceType::Lan => "lan",
+            InterfaceType::Wireless => "wireless",
+            InterfaceType::RemoteAccess => "ras",
+        }
+    }
+
+    pub fn powershell_name(&self) -> &'static str {
+        match self {
+            InterfaceType::Lan => "Wired",
+            InterfaceType::Wireless => "Wireless",
+            InterfaceType::RemoteAccess => "RemoteAccess",
+        }
+    }
+}
+
+impl FromStr for InterfaceType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "lan" | "wired" => Ok(InterfaceType::Lan),
+            "wireless" => Ok(InterfaceType::Wireless),
+            "ras" | "remoteaccess" => Ok(InterfaceType::RemoteAccess),
+            other => Err(format!("Unknown interface type: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EdgeTraversal {
+    No,
+    Yes,
+    DeferToApp,
+    DeferToUser,
+}
+
+impl EdgeTraversal {
+    pub fn netsh_name(&self) -> &'static str {
+        match self {
+            EdgeTraversal::No => "no",
+            EdgeTraversal::Yes => "yes",
+            EdgeTraversal::DeferToApp => "deferapp",
+            EdgeTraversal::DeferToUser => "deferuser",
+        }
+    }
+}
+
+impl FromStr for EdgeTraversal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "no" => Ok(EdgeTraversal::No),
+            "yes" => Ok(EdgeTraversal::Yes),
+            "deferapp" | "defer to application" => Ok(EdgeTraversal::DeferToApp),
+            "deferuser" | "defer to user" => Ok(EdgeTraversal::DeferToUser),
+            other => Err(format!("Unknown edge traversal setting: {}", other)),
+        }
+    }
+}
+
+// The names netsh takes in place of addresses (remoteip/localip) and ports (localport/remoteport).
+// Any other word would reach netsh unchecked, so it's refused like a malformed address.
+const ADDRESS_KEYWORDS: [&str; 5] = ["LocalSubnet", "DNS", "DHCP", "WINS", "DefaultGateway"];
+const PORT_KEYWORDS: [&str; 5] = ["RPC", "RPC-EPMap", "IPHTTPS", "Teredo", "Edge-Traversal"];
+
+// netsh doesn't care about case; the known spelling is kept so equal keywords compare equal.
+fn keyword(s: &str, known: &[&'static str]) -> Option<&'static str> {
+    known.iter().find(|k| k.eq_ignore_ascii_case(s)).copied()
+}
+
+// Rule names and descriptions end up in elevated PowerShell and nft scripts; these characters
+// quote or expand text there, so they're refused before anything is built from them.
+pub fn check_script_text(what: &str, text: &str) -> Result<(), String> {
+    match text.chars().find(|c| matches!(c, '"' | '$' | '`') || c.is_control()) {
+        Some(c) => Err(format!("{} may not contain {:?}: {}", what, c, text)),
+        None => Ok(()),
+    }
+}
+
+// Parses a comma-separated netsh field; "Any" (or nothing) is the empty list.
+pub fn parse_list<T: FromStr<Err = String>>(value: &str) -> Result<Vec<T>, String> {
+    let value = value.trim();
+    if value.is_empty() || value.eq_ignore_ascii_case("any") {
+        return Ok(Vec::new());
+    }
+    value.split(',').map(|item| item.parse()).collect()
+}
+
+pub fn format_list<T: fmt::Display>(items: &[T]) -> String {
+    items.iter().map(|item| item.to_string()).collect::<Vec<_>>().join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_addresses() {
+        let parsed: Vec<AddressSpec> = parse_list("10.0.0.0/8,192.168.1.5/32,10.0.0.1-10.0.0.9,2001:db8::/32,LocalSubnet").unwrap();
+
+        assert_eq!(format_list(&parsed), "10.0.0.0/8,192.168.1.5,10.0.0.1-10.0.0.9,2001:db8::/32,LocalSubnet");
+        assert_eq!(parsed[0].is_ipv4(), Some(true));
+        assert_eq!(parsed[3].is_ipv4(), Some(false));
+        assert_eq!(parsed[4].is_ipv4(), None);
+        assert_eq!("10.1.2.3/8".parse::<AddressSpec>().unwrap().to_string(), "10.0.0.0/8");
+        assert!("10.0.0.9-10.0.0.1".parse::<AddressSpec>().is_err());
+        assert!("10.0.0.1-::1".parse::<AddressSpec>().is_err());
+        assert!("10.0.0.0/33".parse::<AddressSpec>().is_err());
+        assert_eq!("defaultgateway".parse::<AddressSpec>().unwrap().to_string(), "DefaultGateway");
+        assert!("Intranet".parse::<AddressSpec>().is_err());
+        assert!("RPC".parse::<AddressSpec>().is_err());
+    }
+
+    #[test]
+    fn parses_ports() {
+        let parsed: Vec<PortSpec> = parse_list("80, 443,1000-2000,RPC-EPMap").unwrap();
+
+        assert_eq!(parsed[0], PortSpec::single(80));
+        assert_eq!(parsed[2], PortSpec::Range { start: 1000, end: 2000 });
+        assert_eq!(format_list(&parsed), "80,443,1000-2000,RPC-EPMap");
+        assert!(parse_list::<PortSpec>("Any").unwrap().is_empty());
+        assert!("2000-1000".parse::<PortSpec>().is_err());
+        assert!("70000".parse::<PortSpec>().is_err());
+        assert_eq!("iphttps".parse::<PortSpec>().unwrap().to_string(), "IPHTTPS");
+        assert!("http".parse::<PortSpec>().is_err());
+        assert!("LocalSubnet".parse::<PortSpec>().is_err());
+    }
+
+    #[test]
+    fn serializes_as_strings() {
+        let ports = vec![PortSpec::single(22), PortSpec::Range { start: 8000, end: 8080 }];
+        let json = serde_json::to_string(&ports).unwrap();
+
+        assert_eq!(json, r#"["22","8000-8080"]"#);
+        assert_eq!(serde_json::from_str::<Vec<PortSpec>>(&json).unwrap(), ports);
+        assert!(serde_json::from_str::<AddressSpec>(r#""not an address""#).is_err());
+    }
+
+    #[test]
+    fn parses_windows_names() {
+        assert_eq!(parse_list::<FirewallProfile>("Domain,Private").unwrap(), vec![FirewallProfile::Domain, FirewallProfile::Private]);
+        assert_eq!("LAN".parse::<InterfaceType>().unwrap(), InterfaceType::Lan);
+        assert_eq!("Defer to user".parse::<EdgeTraversal>().unwrap(), EdgeTraversal::DeferToUser);
+    }
+
+    #[test]
+    fn refuses_text_that_scripts_would_interpret() {
+        assert!(check_script_text("Rule name", "Allow HTTPS (web)").is_ok());
+        assert!(check_script_text("Rule name", "IT$(whoami)").unwrap_err().contains("'$'"));
+        assert!(check_script_text("Rule name", "say \"hi\"").is_err());
+        assert!(check_script_text("Rule name", "a`b").is_err());
+        assert!(check_script_text("Rule name", "two\nlines").is_err());
+    }
+}
@@ -1,191 +1,140 @@
+use serde_json::json;
 use tauri::{AppHandle, State};
-use crate::firewall::common::{FirewallError, FirewallRuleInfo, FirewallState, run_netsh_command, run_elevated_netsh_command};
-
-fn parse_firewall_rules(output: &str) -> Result<Vec<FirewallRuleInfo>, FirewallError> {
-    let mut rules = Vec::new();
-    let mut current_rule: Option<FirewallRuleInfo> = None;
-    
-    for line in output.lines() {
-        let line = line.trim();
-        
-        if line.starts_with("Rule Name:") {
-            if let Some(rule) = current_rule.take() {
-                rules.push(rule);
-            }
-            
-            let name = line.trim_start_matches("Rule Name:").trim().to_string();
-            current_rule = Some(FirewallRuleInfo {
-                name,
-                description: String::new(),
-                application_path: None,
-                port: None,
-                protocol: "Any".to_string(),
-                direction: "Inbound".to_string(),
-                action: "Block".to_string(),
-                enabled: false,
-            });
-        } else if let Some(ref mut rule) = current_rule {
-            if line.starts_with("Description:") {
-                rule.description = line.trim_start_matches("Description:").trim().to_string();
-            } else if line.starts_with("Enabled:") {
-                let enabled_str = line.trim_start_matches("Enabled:").trim();
-                rule.enabled = enabled_str.eq_ignore_ascii_case("Yes");
-            } else if line.starts_with("Direction:") {
-                rule.direction = line.trim_start_matches("Direction:").trim().to_string();
-            } else if line.starts_with("Action:") {
-                rule.action = line.trim_start_matches("Action:").trim().to_string();
-            } else if line.starts_with("Protocol:") {
-                rule.protocol = line.trim_start_matches("Protocol:").trim().to_string();
-            } else if line.starts_with("Program:") {
-                let program = line.trim_start_matches("Program:").trim();
-                if program != "Any" {
-                    rule.application_path = Some(program.to_string());
-                }
-            } else if line.starts_with("LocalPort:") {
-                let port_str = line.trim_start_matches("LocalPort:").trim();
-                if port_str != "Any" {
-                    rule.port = port_str.parse::<u16>().ok();
-                }
-            }
-        }
-    }
-    
-    if let Some(rule) = current_rule {
-        rules.push(rule);
-    }
-    
-    Ok(rules)
-}
-
-fn quote_if_needed(s: &str) -> String {
-    if s.starts_with('"') && s.ends_with('"') {
-        s.to_string()
-    } else {
-        format!("\"{}\"", s)
-    }
-}
+use crate::app_data::app_data_dir;
+use crate::audit::audited;
+use crate::daemon::ipc::DaemonRequest;
+use crate::daemon::{forward, refuse_while_daemon_runs, running_daemon};
+use crate::firewall::backend::FirewallBackendState;
+use crate::firewall::common::{BlockedDomains, FirewallRuleInfo, FirewallState};
+use crate::firewall::ownership::RuleManifestState;
+use crate::firewall::snapshots::{snapshot_before_change, ChangeSource};
+use crate::firewall::tentative::{begin_pending_change, validate_confirm_timeout, PendingChange};
+use crate::error::AppError;
 
 #[tauri::command]
-pub async fn get_firewall_rules(app: AppHandle, state: State<'_, FirewallState>) -> Result<Vec<FirewallRuleInfo>, String> {
-    let output = run_netsh_command(&app, vec!["advfirewall", "firewall", "show", "rule", "name=all"]).await
-        .map_err(|e| e.to_string())?;
-    
-    match parse_firewall_rules(&output) {
-        Ok(rules) => {
-            let mut state_rules = state.rules.lock().unwrap();
-            *state_rules = rules.clone();
-            Ok(rules)
-        },
-        Err(e) => Err(e.to_string())
-    }
+#[tracing::instrument(skip_all, err)]
+pub async fn get_firewall_rules(
+    backend: State<'_, FirewallBackendState>,
+    state: State<'_, FirewallState>
+) -> Result<Vec<FirewallRuleInfo>, AppError> {
+    let rules = match running_daemon().await {
+        Some(socket) => forward(&socket, &DaemonRequest::ListRules).await?,
+        None => backend.backend.list_rules().await?,
+    };
+
+    let mut state_rules = state.rules.lock().unwrap();
+    *state_rules = rules.clone();
+    Ok(rules)
 }
 
 #[tauri::command]
+#[tracing::instrument(skip_all, err)]
 pub async fn add_firewall_rule(
     app: AppHandle,
     rule_info: FirewallRuleInfo,
-    state: State<'_, FirewallState>
-) -> Result<(), String> {
-    let direction_lower = match rule_info.direction.to_lowercase().as_str() {
-        "inbound" => "in".to_string(),
-        "outbound" => "out".to_string(),
-        other => other.to_string(),
-    };
-    let action_lower = rule_info.action.to_lowercase();
-    let protocol_lower = if rule_info.protocol != "Any" {
-        Some(rule_info.protocol.to_lowercase())
-    } else {
-        None
-    };
-    let port_str = rule_info.port.map(|p| p.to_string());
+    confirm_timeout_seconds: Option<u64>,
+    backend: State<'_, FirewallBackendState>,
+    state: State<'_, FirewallState>,
+    manifest: State<'_, RuleManifestState>,
+    blocked: State<'_, BlockedDomains>
+) -> Result<Option<PendingChange>, AppError> {
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "rule": rule_info, "confirm_timeout_seconds": confirm_timeout_seconds });
+    audited(&data_dir, ChangeSource::Desktop, "add_firewall_rule", params, async {
+        refuse_while_daemon_runs("add firewall rules").await?;
+        let timeout = confirm_timeout_seconds.map(validate_confirm_timeout).transpose()?;
+        let trigger = format!("add_firewall_rule {}", rule_info.name);
+        let snapshot = snapshot_before_change(
+            &data_dir,
+            backend.backend.as_ref(),
+            &manifest,
+            &blocked,
+            ChangeSource::Desktop,
+            &trigger
+        ).await?;
+        backend.backend.add_rule(&rule_info).await?;
 
-    let mut args = vec![
-        "advfirewall".to_string(),
-        "firewall".to_string(),
-        "add".to_string(),
-        "rule".to_string(),
-        format!("name={}", quote_if_needed(&rule_info.name)),
-        format!("dir={}", direction_lower),
-        format!("action={}", action_lower),
-    ];
-    
-    if !rule_info.description.is_empty() {
-        args.push(format!("description={}", rule_info.description));
-    }
-    
-    if let Some(path) = &rule_info.application_path {
-        if !path.is_empty() {
-            args.push(format!("program={}", quote_if_needed(path)));
-        }
-    } else {
-        if let Some(ref proto) = protocol_lower {
-            args.push(format!("protocol={}", proto));
-        }
-        if let Some(ref port) = port_str {
-            args.push(format!("localport={}", port));
-        }
-    }
-    
-    args.push(format!("enable={}", if rule_info.enabled { "yes" } else { "no" }));
-    
-    let args_ref: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-    run_elevated_netsh_command(&app, args_ref).await
-        .map_err(|e| e.to_string())?;
-    
-    let mut rules = state.rules.lock().unwrap();
-    rules.push(rule_info);
-    Ok(())
+        manifest.record_manual_rule(&rule_info.name);
+        manifest.save(&data_dir)?;
+
+        state.rules.lock().unwrap().push(rule_info);
+        Ok(timeout.map(|t| begin_pending_change(&data_dir, &snapshot, &trigger, t)).transpose()?)
+    }).await
 }
 
 #[tauri::command]
+#[tracing::instrument(skip_all, err)]
 pub async fn remove_firewall_rule(
     app: AppHandle,
     rule_name: String,
-    state: State<'_, FirewallState>
-) -> Result<(), String> {    let args = vec![
-        "advfirewall".to_string(),
-        "firewall".to_string(),
-        "delete".to_string(),
-        "rule".to_string(),
-        format!("name={}", quote_if_needed(&rule_name)),
-    ];
-    let args_ref: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-    
-    run_elevated_netsh_command(&app, args_ref).await
-        .map_err(|e| e.to_string())?;
-    
-    let mut rules = state.rules.lock().unwrap();
-    rules.retain(|r| r.name != rule_name);
-    Ok(())
+    confirm_timeout_seconds: Option<u64>,
+    backend: State<'_, FirewallBackendState>,
+    state: State<'_, FirewallState>,
+    manifest: State<'_, RuleManifestState>,
+    blocked: State<'_, BlockedDomains>
+) -> Result<Option<PendingChange>, AppError> {
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "rule_name": rule_name, "confirm_timeout_seconds": confirm_timeout_seconds });
+    audited(&data_dir, ChangeSource::Desktop, "remove_firewall_rule", params, async {
+        refuse_while_daemon_runs("remove firewall rules").await?;
+        let timeout = confirm_timeout_seconds.map(validate_confirm_timeout).transpose()?;
+        let trigger = format!("remove_firewall_rule {}", rule_name);
+        let snapshot = snapshot_before_change(
+            &data_dir,
+            backend.backend.as_ref(),
+            &manifest,
+            &blocked,
+            ChangeSource::Desktop,
+            &trigger
+        ).await?;
+        backend.backend.remove_rule(&rule_name).await?;
+
+        manifest.forget_manual_rule(&rule_name);
+        manifest.save(&data_dir)?;
+
+        state.rules.lock().unwrap().retain(|r| r.name != rule_name);
+        Ok(timeout.map(|t| begin_pending_change(&data_dir, &snapshot, &trigger, t)).transpose()?)
+    }).await
 }
 
+#[allow(clippy::too_many_arguments)]
 #[tauri::command]
+#[tracing::instrument(skip_all, err)]
 pub async fn enable_disable_rule(
     app: AppHandle,
     rule_name: String,
     enable: bool,
-    state: State<'_, FirewallState>
-) -> Result<(), String> {    let args = vec![
-        "advfirewall".to_string(),
-        "firewall".to_string(),
-        "set".to_string(),
-        "rule".to_string(),
-        format!("name={}", quote_if_needed(&rule_name)),
-        "new".to_string(),
-        format!("enable={}", if enable { "yes" } else { "no" }),
-    ];
-    let args_ref: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-    
-    run_elevated_netsh_command(&app, args_ref).await
-        .map_err(|e| e.to_string())?;
-    
-    let mut rules = state.rules.lock().unwrap();
-    for r in rules.iter_mut() {
-        if r.name == rule_name {
-            r.enabled = enable;
-            break;
+    confirm_timeout_seconds: Option<u64>,
+    backend: State<'_, FirewallBackendState>,
+    state: State<'_, FirewallState>,
+    manifest: State<'_, RuleManifestState>,
+    blocked: State<'_, BlockedDomains>
+) -> Result<Option<PendingChange>, AppError> {
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "rule_name": rule_name, "enable": enable, "confirm_timeout_seconds": confirm_timeout_seconds });
+    audited(&data_dir, ChangeSource::Desktop, "enable_disable_rule", params, async {
+        refuse_while_daemon_runs("enable or disable firewall rules").await?;
+        let timeout = confirm_timeout_seconds.map(validate_confirm_timeout).transpose()?;
+        let trigger = format!("{} {}", if enable { "enable_rule" } else { "disable_rule" }, rule_name);
+        let snapshot = snapshot_before_change(
+            &data_dir,
+            backend.backend.as_ref(),
+            &manifest,
+            &blocked,
+            ChangeSource::Desktop,
+            &trigger
+        ).await?;
+        backend.backend.set_rule_enabled(&rule_name, enable).await?;
+
+        {
+            let mut rules = state.rules.lock().unwrap();
+            for r in rules.iter_mut() {
+                if r.name == rule_name {
+                    r.enabled = enable;
+                    break;
+                }
+            }
         }
-    }
-    Ok(())
+        Ok(timeout.map(|t| begin_pending_change(&data_dir, &snapshot, &trigger, t)).transpose()?)
+    }).await
 }
-
@@ -0,0 +1,756 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use tauri::{AppHandle, State};
+use crate::app_data::{app_data_dir, data_file_path};
+use crate::audit::{audited, record};
+use crate::daemon::refuse_while_daemon_runs;
+use crate::firewall::backend::{FirewallBackend, FirewallBackendState, IpSetBlock};
+use crate::firewall::common::{BlockedDomains, FirewallRuleInfo};
+use crate::firewall::domain_blocking::{save_domains_to_file, save_resolutions_to_file};
+use crate::firewall::domain_blocking::resolver::ResolvedDomain;
+use crate::firewall::tentative::{ensure_no_pending_change, pending_snapshot_id};
+use crate::firewall::ownership::{RuleManifest, RuleManifestState, DOMAIN_RULE_PREFIX, POLICY_RULE_PREFIX};
+use crate::error::{AppError, ErrorKind};
+
+const SNAPSHOT_DIR: &str = "snapshots";
+const SETTINGS_FILE: &str = "snapshot_settings.json";
+
+// Who made a change, recorded with the snapshot taken before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeSource {
+    Desktop,
+    Cli,
+    Daemon,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotSettings {
+    // Also export the whole firewall before every change. On Windows this costs an extra
+    // elevation prompt per change.
+    pub full_export: bool,
+    pub max_snapshots: usize,
+}
+
+impl Default for SnapshotSettings {
+    fn default() -> Self {
+        SnapshotSettings {
+            full_export: false,
+            max_snapshots: 50,
+        }
+    }
+}
+
+// The rules the app manages plus the state that says who owns them, as it was before a change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirewallSnapshot {
+    pub id: String,
+    pub created_at: String,
+    pub source: ChangeSource,
+    pub user: Option<String>,
+    pub trigger: String,
+    pub backend: String,
+    pub rules: Vec<FirewallRuleInfo>,
+    pub manifest: RuleManifest,
+    pub blocked_domains: Vec<String>,
+    #[serde(default)]
+    pub resolutions: HashMap<String, ResolvedDomain>,
+    // Blocked entry -> the addresses its rules block. Domain rules can match address sets that
+    // `rules` has no way to describe, so they are kept out of `rules` and rebuilt from this.
+    #[serde(default)]
+    pub domain_sets: HashMap<String, Vec<String>>,
+    // File name of the full firewall export stored next to the snapshot, if one was taken.
+    #[serde(default)]
+    pub export_file: Option<String>,
+}
+
+impl FirewallSnapshot {
+    // Snapshots taken before domain_sets was recorded still have the manifest and resolutions.
+    pub fn blocked_sets(&self) -> HashMap<String, Vec<String>> {
+        if self.domain_sets.is_empty() {
+            domain_sets(&self.manifest, &self.resolutions)
+        } else {
+            self.domain_sets.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotSummary {
+    pub id: String,
+    pub created_at: String,
+    pub source: ChangeSource,
+    pub user: Option<String>,
+    pub trigger: String,
+    pub backend: String,
+    pub rule_count: usize,
+    pub has_export: bool,
+}
+
+impl From<&FirewallSnapshot> for SnapshotSummary {
+    fn from(snapshot: &FirewallSnapshot) -> Self {
+        SnapshotSummary {
+            id: snapshot.id.clone(),
+            created_at: snapshot.created_at.clone(),
+            source: snapshot.source,
+            user: snapshot.user.clone(),
+            trigger: snapshot.trigger.clone(),
+            backend: snapshot.backend.clone(),
+            rule_count: snapshot.rules.len(),
+            has_export: snapshot.export_file.is_some(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RestoreReport {
+    pub snapshot_id: String,
+    // The snapshot taken just before restoring, so the restore can be undone too.
+    pub backup_id: String,
+    pub removed: Vec<String>,
+    pub added: Vec<String>,
+    pub full: bool,
+}
+
+fn snapshot_dir(data_dir: &Path) -> Result<PathBuf, String> {
+    let dir = data_dir.join(SNAPSHOT_DIR);
+    fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create snapshot directory: {}", e))?;
+    Ok(dir)
+}
+
+// Ids name files, so anything that could leave the snapshot directory is rejected.
+fn snapshot_path(data_dir: &Path, id: &str) -> Result<PathBuf, String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(format!("Invalid snapshot id: {}", id));
+    }
+    Ok(snapshot_dir(data_dir)?.join(format!("{}.json", id)))
+}
+
+fn new_snapshot_id(data_dir: &Path) -> Result<String, String> {
+    let base = Utc::now().format("%Y%m%d-%H%M%S-%3f").to_string();
+    let mut id = base.clone();
+    let mut counter = 1;
+    while snapshot_path(data_dir, &id)?.exists() {
+        counter += 1;
+        id = format!("{}-{}", base, counter);
+    }
+    Ok(id)
+}
+
+pub(crate) fn current_user() -> Option<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+        .filter(|user| !user.is_empty())
+}
+
+pub fn load_snapshot_settings(data_dir: &Path) -> Result<SnapshotSettings, String> {
+    let file_path = data_file_path(data_dir, SETTINGS_FILE);
+    if !file_path.exists() {
+        return Ok(SnapshotSettings::default());
+    }
+
+    let json = fs::read_to_string(&file_path)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to deserialize snapshot settings: {}", e))
+}
+
+fn save_snapshot_settings(data_dir: &Path, settings: &SnapshotSettings) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(settings)
+        .map_err(|e| format!("Failed to serialize snapshot settings: {}", e))?;
+    fs::write(data_file_path(data_dir, SETTINGS_FILE), json)
+        .map_err(|e| format!("Failed to write to file: {}", e))
+}
+
+// Rules the manifest owns, plus anything carrying one of our prefixes so left-over rules
+// are captured too.
+fn is_managed(rule_name: &str, owned: &HashSet<String>) -> bool {
+    owned.contains(rule_name)
+        || rule_name.starts_with(DOMAIN_RULE_PREFIX)
+        || rule_name.starts_with(POLICY_RULE_PREFIX)
+}
+
+fn is_domain_rule(rule_name: &str, manifest: &RuleManifest) -> bool {
+    rule_name.starts_with(DOMAIN_RULE_PREFIX) || manifest.domains.values().flatten().any(|r| r == rule_name)
+}
+
+// The addresses each blocked entry with firewall rules is blocking.
+fn domain_sets(manifest: &RuleManifest, resolutions: &HashMap<String, ResolvedDomain>) -> HashMap<String, Vec<String>> {
+    manifest.domains
+        .keys()
+        .filter_map(|entry| Some((entry.clone(), resolutions.get(entry)?.ips())))
+        .collect()
+}
+
+pub async fn capture_snapshot(
+    data_dir: &Path,
+    backend: &dyn FirewallBackend,
+    manifest: &RuleManifestState,
+    blocked: &BlockedDomains,
+    source: ChangeSource,
+    trigger: &str,
+    full_export: bool
+) -> Result<SnapshotSummary, AppError> {
+    let manifest = manifest.manifest.lock().unwrap().clone();
+    let owned = manifest.rule_names();
+    let rules: Vec<FirewallRuleInfo> = backend.list_rules().await
+        .map_err(|e| AppError::from(e).context("Failed to read firewall rules for snapshot"))?
+        .into_iter()
+        .filter(|rule| is_managed(&rule.name, &owned) && !is_domain_rule(&rule.name, &manifest))
+        .collect();
+    let resolutions = blocked.resolutions.lock().unwrap().clone();
+    let domain_sets = domain_sets(&manifest, &resolutions);
+
+    let id = new_snapshot_id(data_dir)?;
+    let export_file = if full_export {
+        let file_name = format!("{}.{}", id, backend.name());
+        backend.export_config(&snapshot_dir(data_dir)?.join(&file_name)).await?;
+        Some(file_name)
+    } else {
+        None
+    };
+
+    let snapshot = FirewallSnapshot {
+        created_at: Utc::now().to_rfc3339(),
+        source,
+        user: current_user(),
+        trigger: trigger.to_string(),
+        backend: backend.name().to_string(),
+        rules,
+        manifest,
+        blocked_domains: blocked.domains.lock().unwrap().clone(),
+        resolutions,
+        domain_sets,
+        export_file,
+        id,
+    };
+
+    let json = serde_json::to_string_pretty(&snapshot)
+        .map_err(|e| format!("Failed to serialize snapshot: {}", e))?;
+    fs::write(snapshot_path(data_dir, &snapshot.id)?, json)
+        .map_err(|e| format!("Failed to write snapshot: {}", e))?;
+
+    Ok(SnapshotSummary::from(&snapshot))
+}
+
+// Called before every change to the firewall. A change that can't be snapshotted doesn't go ahead.
+pub async fn snapshot_before_change(
+    data_dir: &Path,
+    backend: &dyn FirewallBackend,
+    manifest: &RuleManifestState,
+    blocked: &BlockedDomains,
+    source: ChangeSource,
+    trigger: &str
+) -> Result<SnapshotSummary, AppError> {
+    ensure_no_pending_change(data_dir)?;
+    let settings = load_snapshot_settings(data_dir)?;
+    let summary = capture_snapshot(data_dir, backend, manifest, blocked, source, trigger, settings.full_export).await
+        .map_err(|e| e.context("Failed to snapshot the firewall before changing it"))?;
+    prune_snapshots(data_dir, settings.max_snapshots)?;
+    Ok(summary)
+}
+
+pub fn load_snapshot(data_dir: &Path, id: &str) -> Result<FirewallSnapshot, AppError> {
+    let path = snapshot_path(data_dir, id)?;
+    if !path.exists() {
+        return Err(AppError::not_found(format!("Snapshot {} not found", id)));
+    }
+
+    let json = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read snapshot {}: {}", id, e))?;
+    serde_json::from_str(&json)
+        .map_err(|e| AppError::new(ErrorKind::ParseError, format!("Failed to deserialize snapshot {}: {}", id, e)))
+}
+
+// Newest first.
+pub fn list_snapshots(data_dir: &Path) -> Result<Vec<SnapshotSummary>, String> {
+    let entries = fs::read_dir(snapshot_dir(data_dir)?)
+        .map_err(|e| format!("Failed to read snapshot directory: {}", e))?;
+
+    let mut summaries: Vec<SnapshotSummary> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                return None;
+            }
+            let id = path.file_stem()?.to_str()?.to_string();
+            load_snapshot(data_dir, &id).ok().map(|snapshot| SnapshotSummary::from(&snapshot))
+        })
+        .collect();
+
+    summaries.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| b.id.cmp(&a.id)));
+    Ok(summaries)
+}
+
+// The snapshot a pending change would revert to is kept until the change is settled.
+pub fn delete_snapshot(data_dir: &Path, id: &str) -> Result<(), AppError> {
+    if pending_snapshot_id(data_dir)?.as_deref() == Some(id) {
+        return Err(AppError::new(
+            ErrorKind::Conflict,
+            format!("Snapshot {} is needed to revert the change waiting for confirmation", id)
+        ));
+    }
+    let snapshot = load_snapshot(data_dir, id)?;
+    if let Some(export_file) = &snapshot.export_file {
+        let _ = fs::remove_file(snapshot_dir(data_dir)?.join(export_file));
+    }
+    fs::remove_file(snapshot_path(data_dir, id)?)
+        .map_err(|e| AppError::from(e).context(&format!("Failed to delete snapshot {}", id)))
+}
+
+fn prune_snapshots(data_dir: &Path, max_snapshots: usize) -> Result<(), String> {
+    let pending = pending_snapshot_id(data_dir)?;
+    for summary in list_snapshots(data_dir)?.iter().skip(max_snapshots.max(1)) {
+        if pending.as_deref() != Some(summary.id.as_str()) {
+            delete_snapshot(data_dir, &summary.id)?;
+        }
+    }
+    Ok(())
+}
+
+// The changes that bring the managed rules back to the snapshot. A name whose rules differ
+// in any way is removed and re-added from the snapshot; rules the app never managed in
+// either state are left alone. Domain rules are left to plan_domain_restore.
+pub fn plan_restore(
+    current: &[FirewallRuleInfo],
+    current_manifest: &RuleManifest,
+    snapshot: &FirewallSnapshot
+) -> (Vec<String>, Vec<FirewallRuleInfo>) {
+    let mut owned = current_manifest.rule_names();
+    owned.extend(snapshot.manifest.rule_names());
+    owned.extend(snapshot.rules.iter().map(|r| r.name.clone()));
+
+    let mut names: Vec<String> = current
+        .iter()
+        .map(|r| r.name.clone())
+        .filter(|name| is_managed(name, &owned))
+        .chain(snapshot.rules.iter().map(|r| r.name.clone()))
+        .filter(|name| !is_domain_rule(name, current_manifest) && !is_domain_rule(name, &snapshot.manifest))
+        .collect();
+    names.sort();
+    names.dedup();
+
+    let mut remove = Vec::new();
+    let mut add = Vec::new();
+    for name in names {
+        let now: Vec<&FirewallRuleInfo> = current.iter().filter(|r| r.name == name).collect();
+        let then: Vec<&FirewallRuleInfo> = snapshot.rules.iter().filter(|r| r.name == name).collect();
+        if now.len() == then.len() && now.iter().all(|r| then.contains(r)) {
+            continue;
+        }
+        if !now.is_empty() {
+            remove.push(name.clone());
+        }
+        add.extend(then.into_iter().cloned());
+    }
+
+    (remove, add)
+}
+
+// The domain rules to remove and the entries to block again to get back to the snapshot's
+// domain blocks. Entries blocking the same addresses with all their rules in place are left
+// alone; every other domain rule is removed, and the snapshot's entries are blocked again.
+pub fn plan_domain_restore(
+    current: &[FirewallRuleInfo],
+    current_manifest: &RuleManifest,
+    current_sets: &HashMap<String, Vec<String>>,
+    snapshot: &FirewallSnapshot
+) -> (Vec<String>, Vec<IpSetBlock>) {
+    let present: HashSet<&str> = current.iter().map(|r| r.name.as_str()).collect();
+    let sorted = |ips: &Vec<String>| {
+        let mut ips = ips.clone();
+        ips.sort();
+        ips
+    };
+
+    let mut kept = HashSet::new();
+    let mut block = Vec::new();
+    let mut entries: Vec<(String, Vec<String>)> = snapshot.blocked_sets().into_iter().collect();
+    entries.sort();
+    for (entry, ips) in entries {
+        let rules = current_manifest.domains.get(&entry);
+        let unchanged = current_sets.get(&entry).map(sorted) == Some(sorted(&ips))
+            && rules.is_some_and(|rules| rules.iter().all(|r| present.contains(r.as_str())));
+        match rules {
+            Some(rules) if unchanged => kept.extend(rules.iter().cloned()),
+            _ => block.push(IpSetBlock {
+                existing_rules: rules.cloned().unwrap_or_default(),
+                name: entry,
+                ips,
+            }),
+        }
+    }
+
+    let mut remove: Vec<String> = current
+        .iter()
+        .map(|r| r.name.clone())
+        .filter(|name| is_domain_rule(name, current_manifest) && !kept.contains(name))
+        .collect();
+    remove.sort();
+    remove.dedup();
+
+    (remove, block)
+}
+
+// Brings the firewall back to a snapshot. With `full` and a stored export the whole firewall
+// configuration is imported; otherwise only the managed rules are changed. Either way the rule
+// manifest and blocked domains go back to what they were.
+pub async fn restore_snapshot(
+    data_dir: &Path,
+    backend: &dyn FirewallBackend,
+    manifest: &RuleManifestState,
+    blocked: &BlockedDomains,
+    source: ChangeSource,
+    id: &str,
+    full: bool
+) -> Result<RestoreReport, AppError> {
+    let snapshot = load_snapshot(data_dir, id)?;
+    if snapshot.backend != backend.name() {
+        return Err(AppError::invalid_input(format!(
+            "Snapshot {} was taken with the {} backend, but {} is in use",
+            id, snapshot.backend, backend.name()
+        )));
+    }
+    let export_path = match (&snapshot.export_file, full) {
+        (Some(export_file), true) => Some(snapshot_dir(data_dir)?.join(export_file)),
+        (None, true) => return Err(AppError::invalid_input(format!("Snapshot {} has no full firewall export", id))),
+        (_, false) => None,
+    };
+
+    let _changing = blocked.changing.lock().await;
+    let backup = capture_snapshot(
+        data_dir,
+        backend,
+        manifest,
+        blocked,
+        source,
+        &format!("restore {}", id),
+        export_path.is_some()
+    ).await?;
+
+    let mut report = RestoreReport {
+        snapshot_id: id.to_string(),
+        backup_id: backup.id,
+        removed: Vec::new(),
+        added: Vec::new(),
+        full: export_path.is_some(),
+    };
+
+    match export_path {
+        Some(path) => {
+            backend.import_config(&path).await?;
+        },
+        None => {
+            let current = backend.list_rules().await?;
+            let current_manifest = manifest.manifest.lock().unwrap().clone();
+            let current_sets = domain_sets(&current_manifest, &blocked.resolutions.lock().unwrap());
+            let (mut remove, add) = plan_restore(&current, &current_manifest, &snapshot);
+            let (domain_remove, domain_block) = plan_domain_restore(&current, &current_manifest, &current_sets, &snapshot);
+            remove.extend(domain_remove);
+
+            backend.apply_rule_changes(&remove, &add).await
+                .map_err(|e| AppError::from(e).context("Failed to restore firewall rules"))?;
+            // Domain blocks are rebuilt the way they were made, so address set matches survive.
+            backend.block_ip_sets(&domain_block).await
+                .map_err(|e| AppError::from(e).context("Failed to restore domain blocks"))?;
+
+            report.removed = remove;
+            report.added = add.into_iter().map(|r| r.name).collect();
+            report.added.extend(domain_block.iter().flat_map(|set| backend.ip_set_rule_names(&set.name, &set.ips)));
+        },
+    }
+
+    *manifest.manifest.lock().unwrap() = snapshot.manifest.clone();
+    manifest.save(data_dir)?;
+    blocked.set_domains(snapshot.blocked_domains.clone());
+    *blocked.resolutions.lock().unwrap() = snapshot.resolutions.clone();
+    save_domains_to_file(data_dir, &snapshot.blocked_domains).await?;
+    save_resolutions_to_file(data_dir, &snapshot.resolutions)?;
+
+    Ok(report)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn list_firewall_snapshots(app: AppHandle) -> Result<Vec<SnapshotSummary>, AppError> {
+    Ok(list_snapshots(&app_data_dir(&app))?)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_firewall_snapshot(app: AppHandle, id: String) -> Result<FirewallSnapshot, AppError> {
+    load_snapshot(&app_data_dir(&app), &id)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn create_firewall_snapshot(
+    app: AppHandle,
+    label: Option<String>,
+    include_export: Option<bool>,
+    backend: State<'_, FirewallBackendState>,
+    manifest: State<'_, RuleManifestState>,
+    blocked: State<'_, BlockedDomains>
+) -> Result<SnapshotSummary, AppError> {
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "label": label, "include_export": include_export });
+    audited(&data_dir, ChangeSource::Desktop, "create_firewall_snapshot", params, async {
+        let trigger = match label.filter(|l| !l.trim().is_empty()) {
+            Some(label) => format!("manual: {}", label.trim()),
+            None => "manual".to_string(),
+        };
+        capture_snapshot(
+            &data_dir,
+            backend.backend.as_ref(),
+            &manifest,
+            &blocked,
+            ChangeSource::Desktop,
+            &trigger,
+            include_export.unwrap_or(false)
+        ).await
+    }).await
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn restore_firewall_snapshot(
+    app: AppHandle,
+    id: String,
+    full: Option<bool>,
+    backend: State<'_, FirewallBackendState>,
+    manifest: State<'_, RuleManifestState>,
+    blocked: State<'_, BlockedDomains>
+) -> Result<RestoreReport, AppError> {
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "id": id, "full": full });
+    audited(&data_dir, ChangeSource::Desktop, "restore_firewall_snapshot", params, async {
+        refuse_while_daemon_runs("restore a firewall snapshot").await?;
+        restore_snapshot(
+            &data_dir,
+            backend.backend.as_ref(),
+            &manifest,
+            &blocked,
+            ChangeSource::Desktop,
+            &id,
+            full.unwrap_or(false)
+        ).await
+    }).await
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn delete_firewall_snapshot(app: AppHandle, id: String) -> Result<(), AppError> {
+    let data_dir = app_data_dir(&app);
+    let result = delete_snapshot(&data_dir, &id);
+    record(&data_dir, ChangeSource::Desktop, "delete_firewall_snapshot", json!({ "id": id }), &result);
+    result
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_snapshot_settings(app: AppHandle) -> Result<SnapshotSettings, AppError> {
+    Ok(load_snapshot_settings(&app_data_dir(&app))?)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn set_snapshot_settings(app: AppHandle, settings: SnapshotSettings) -> Result<(), AppError> {
+    let data_dir = app_data_dir(&app);
+    let result = save_snapshot_settings(&data_dir, &settings).map_err(AppError::from);
+    record(&data_dir, ChangeSource::Desktop, "set_snapshot_settings", json!({ "settings": settings }), &result);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::firewall::backend::netsh::NetshBackend;
+    use crate::firewall::backend::nftables::NftablesBackend;
+    use crate::firewall::domain_blocking::resolver::ResolvedAddress;
+    use crate::firewall::command_runner::CommandOutput;
+    use crate::firewall::testing::FakeCommandRunner;
+
+    fn rule(name: &str, enabled: bool) -> FirewallRuleInfo {
+        FirewallRuleInfo {
+            name: name.to_string(),
+            protocol: "TCP".to_string(),
+            direction: "Inbound".to_string(),
+            action: "Allow".to_string(),
+            enabled,
+            ..Default::default()
+        }
+    }
+
+    fn snapshot(rules: Vec<FirewallRuleInfo>, manual_rules: &[&str]) -> FirewallSnapshot {
+        FirewallSnapshot {
+            id: "20250101-000000-000".to_string(),
+            created_at: "2025-01-01T00:00:00+00:00".to_string(),
+            source: ChangeSource::Desktop,
+            user: None,
+            trigger: "test".to_string(),
+            backend: "netsh".to_string(),
+            rules,
+            manifest: RuleManifest {
+                manual_rules: manual_rules.iter().map(|r| r.to_string()).collect(),
+                ..Default::default()
+            },
+            blocked_domains: Vec::new(),
+            resolutions: HashMap::new(),
+            domain_sets: HashMap::new(),
+            export_file: None,
+        }
+    }
+
+    #[test]
+    fn restore_plan_only_touches_managed_rules() {
+        let current = vec![rule("Web", false), rule("Added Later", true), rule("Windows Default", true)];
+        let current_manifest = RuleManifest {
+            manual_rules: vec!["Web".to_string(), "Added Later".to_string()],
+            ..Default::default()
+        };
+        let before = snapshot(vec![rule("Web", true), rule("Removed Since", true)], &["Web", "Removed Since"]);
+
+        let (remove, add) = plan_restore(&current, &current_manifest, &before);
+
+        assert_eq!(remove, vec!["Added Later", "Web"]);
+        let added: Vec<&str> = add.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(added, vec!["Removed Since", "Web"]);
+        assert!(add.iter().all(|r| r.enabled));
+    }
+
+    #[test]
+    fn unchanged_rules_are_left_alone() {
+        let current = vec![rule("Web", true), rule("Dept-Policy-Engineering-1", true)];
+        let before = snapshot(vec![rule("Web", true)], &["Web"]);
+
+        let (remove, add) = plan_restore(&current, &RuleManifest::default(), &before);
+
+        assert_eq!(remove, vec!["Dept-Policy-Engineering-1"]);
+        assert!(add.is_empty());
+    }
+
+    #[test]
+    fn domain_restore_keeps_unchanged_blocks_and_drops_the_rest() {
+        let current = vec![
+            rule("Block-Domain-Outbound-kept.com", true),
+            rule("Block-Domain-Outbound-later.com", true),
+            rule("Block-Domain-Outbound-leftover.com", true),
+        ];
+        let current_manifest = RuleManifest {
+            domains: HashMap::from([
+                ("kept.com".to_string(), vec!["Block-Domain-Outbound-kept.com".to_string()]),
+                ("later.com".to_string(), vec!["Block-Domain-Outbound-later.com".to_string()]),
+            ]),
+            ..Default::default()
+        };
+        let current_sets = HashMap::from([
+            ("kept.com".to_string(), vec!["1.1.1.1".to_string()]),
+            ("later.com".to_string(), vec!["2.2.2.2".to_string()]),
+        ]);
+        let mut before = snapshot(Vec::new(), &[]);
+        before.domain_sets = HashMap::from([
+            ("kept.com".to_string(), vec!["1.1.1.1".to_string()]),
+            ("gone.com".to_string(), vec!["3.3.3.3".to_string()]),
+        ]);
+
+        let (remove, block) = plan_domain_restore(&current, &current_manifest, &current_sets, &before);
+
+        assert_eq!(remove, vec!["Block-Domain-Outbound-later.com", "Block-Domain-Outbound-leftover.com"]);
+        assert_eq!(block, vec![IpSetBlock {
+            name: "gone.com".to_string(),
+            ips: vec!["3.3.3.3".to_string()],
+            existing_rules: Vec::new(),
+        }]);
+    }
+
+    #[tokio::test]
+    async fn nftables_restore_rebuilds_address_sets() {
+        let dir = std::env::temp_dir().join(format!("security-smile-snapshots-nft-{}", std::process::id()));
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NftablesBackend::new(runner.clone());
+        let manifest = RuleManifestState::default();
+        let blocked = BlockedDomains::default();
+        let ips = vec!["93.184.216.34".to_string()];
+        blocked.add_domain("example.com");
+        blocked.resolutions.lock().unwrap().insert("example.com".to_string(), ResolvedDomain {
+            domain: "example.com".to_string(),
+            addresses: vec![ResolvedAddress { ip: ips[0].clone(), ttl: 300 }],
+            cname_chain: Vec::new(),
+            min_ttl: 300,
+            resolved_at: 0,
+        });
+        manifest.record_domain("example.com", backend.ip_set_rule_names("example.com", &ips));
+        runner.respond("nft", CommandOutput::ok(r#"{"nftables": [
+            {"set": {"family": "inet", "name": "blockset_a379a6f6eeafb9a5_v4", "table": "security_smile", "type": "ipv4_addr", "handle": 5}},
+            {"rule": {"family": "inet", "table": "security_smile", "chain": "output", "handle": 9, "comment": "Block-Domain-Outbound-example.com",
+                "expr": [{"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "daddr"}}, "right": "@blockset_a379a6f6eeafb9a5_v4"}}, {"drop": null}]}}
+        ]}"#));
+        let before = snapshot_before_change(&dir, &backend, &manifest, &blocked, ChangeSource::Cli, "unblock").await.unwrap();
+        assert!(load_snapshot(&dir, &before.id).unwrap().rules.is_empty());
+
+        // The domain was unblocked since, so its set and rules are gone.
+        manifest.forget_domains(&["example.com".to_string()]);
+        blocked.remove_domains(&["example.com".to_string()]);
+        blocked.resolutions.lock().unwrap().clear();
+        for _ in 0..3 {
+            runner.respond("nft", CommandOutput::ok(r#"{"nftables": []}"#));
+        }
+        restore_snapshot(&dir, &backend, &manifest, &blocked, ChangeSource::Cli, &before.id, false).await.unwrap();
+
+        let scripts = runner.scripts();
+        assert_eq!(scripts.len(), 1);
+        assert!(scripts[0].contains("add element inet security_smile blockset_a379a6f6eeafb9a5_v4 { 93.184.216.34 }"));
+        assert!(scripts[0].contains("add rule inet security_smile output ip daddr @blockset_a379a6f6eeafb9a5_v4 drop"));
+        assert!(scripts[0].lines().filter(|l| l.starts_with("add rule")).all(|l| l.contains(" @blockset_")));
+        assert!(manifest.domain_rules("example.com").is_some());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn snapshot_ids_cannot_leave_the_directory() {
+        let dir = std::env::temp_dir();
+        assert!(snapshot_path(&dir, "../blocked_domains").is_err());
+        assert!(snapshot_path(&dir, "").is_err());
+        assert!(snapshot_path(&dir, "20250101-000000-000").is_ok());
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_listed_pruned_and_restored() {
+        let dir = std::env::temp_dir().join(format!("security-smile-snapshots-{}", std::process::id()));
+        let runner = Arc::new(FakeCommandRunner::new());
+        let backend = NetshBackend::new(runner.clone());
+        let manifest = RuleManifestState::default();
+        let blocked = BlockedDomains::default();
+        blocked.add_domain("ads.example.com");
+        save_snapshot_settings(&dir, &SnapshotSettings { full_export: false, max_snapshots: 2 }).unwrap();
+
+        let first = snapshot_before_change(&dir, &backend, &manifest, &blocked, ChangeSource::Cli, "first").await.unwrap();
+        blocked.add_domain("tracker.example.com");
+        snapshot_before_change(&dir, &backend, &manifest, &blocked, ChangeSource::Cli, "second").await.unwrap();
+        snapshot_before_change(&dir, &backend, &manifest, &blocked, ChangeSource::Cli, "third").await.unwrap();
+
+        let triggers: Vec<String> = list_snapshots(&dir).unwrap().into_iter().map(|s| s.trigger).collect();
+        assert_eq!(triggers, vec!["third", "second"]);
+        assert!(load_snapshot(&dir, &first.id).is_err());
+
+        let second = list_snapshots(&dir).unwrap().pop().unwrap();
+        blocked.add_domain("later.example.com");
+        runner.respond("netsh", CommandOutput::ok(""));
+        let report = restore_snapshot(&dir, &backend, &manifest, &blocked, ChangeSource::Cli, &second.id, false).await.unwrap();
+
+        assert_eq!(*blocked.domains.lock().unwrap(), vec!["ads.example.com", "tracker.example.com"]);
+        assert!(load_snapshot(&dir, &report.backup_id).unwrap().blocked_domains.contains(&"later.example.com".to_string()));
+        assert!(restore_snapshot(&dir, &backend, &manifest, &blocked, ChangeSource::Cli, &second.id, true).await.is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
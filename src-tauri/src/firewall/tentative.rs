@@ -0,0 +1,382 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tracing::warn;
+use crate::app_data::{app_data_dir, data_file_path};
+use crate::audit::{audited, record};
+use crate::daemon::{daemon_running, refuse_while_daemon_runs};
+use crate::firewall::backend::{FirewallBackend, FirewallBackendState};
+use crate::firewall::common::BlockedDomains;
+use crate::firewall::ownership::RuleManifestState;
+use crate::firewall::snapshots::{restore_snapshot, ChangeSource, RestoreReport, SnapshotSummary};
+use crate::error::{AppError, ErrorKind};
+
+const PENDING_FILE: &str = "pending_change.json";
+// The pending change is moved here by whoever reverts it, so two watchers (say the desktop
+// app and the daemon) can't both restore it. It is moved back if the restore fails.
+const REVERTING_FILE: &str = "pending_change.reverting.json";
+pub const MIN_CONFIRM_SECONDS: u64 = 10;
+pub const MAX_CONFIRM_SECONDS: u64 = 3600;
+pub const WATCH_INTERVAL_SECONDS: u64 = 1;
+
+// A change applied tentatively: unless it is confirmed before the deadline, the firewall is
+// restored to the snapshot taken just before it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingChange {
+    pub snapshot_id: String,
+    pub description: String,
+    pub source: ChangeSource,
+    pub created_at: DateTime<Utc>,
+    pub deadline: DateTime<Utc>,
+}
+
+impl PendingChange {
+    pub fn seconds_left(&self, now: DateTime<Utc>) -> i64 {
+        (self.deadline - now).num_seconds().max(0)
+    }
+}
+
+pub fn validate_confirm_timeout(seconds: u64) -> Result<u64, AppError> {
+    if !(MIN_CONFIRM_SECONDS..=MAX_CONFIRM_SECONDS).contains(&seconds) {
+        return Err(AppError::invalid_input(format!(
+            "The confirmation timeout must be between {} and {} seconds",
+            MIN_CONFIRM_SECONDS, MAX_CONFIRM_SECONDS
+        )));
+    }
+    Ok(seconds)
+}
+
+fn read_pending_file(path: &Path) -> Result<Option<PendingChange>, String> {
+    let json = match fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read pending change: {}", e)),
+    };
+    serde_json::from_str(&json)
+        .map(Some)
+        .map_err(|e| format!("Failed to deserialize pending change: {}", e))
+}
+
+pub fn load_pending_change(data_dir: &Path) -> Result<Option<PendingChange>, String> {
+    read_pending_file(&data_file_path(data_dir, PENDING_FILE))
+}
+
+// The snapshot a pending change (or one being reverted right now) will be restored to.
+pub fn pending_snapshot_id(data_dir: &Path) -> Result<Option<String>, String> {
+    let pending = match load_pending_change(data_dir)? {
+        Some(pending) => Some(pending),
+        None => read_pending_file(&data_file_path(data_dir, REVERTING_FILE))?,
+    };
+    Ok(pending.map(|p| p.snapshot_id))
+}
+
+// Reverting restores the snapshot taken before the pending change, which would also undo
+// anything changed in the meantime, so other changes wait until it is settled.
+pub fn ensure_no_pending_change(data_dir: &Path) -> Result<(), AppError> {
+    match load_pending_change(data_dir)? {
+        Some(pending) => Err(AppError::new(ErrorKind::Conflict, format!(
+            "\"{}\" is waiting for confirmation; confirm or revert it before making other changes",
+            pending.description
+        ))),
+        None => Ok(()),
+    }
+}
+
+pub fn begin_pending_change(
+    data_dir: &Path,
+    snapshot: &SnapshotSummary,
+    description: &str,
+    timeout_seconds: u64
+) -> Result<PendingChange, String> {
+    let now = Utc::now();
+    let pending = PendingChange {
+        snapshot_id: snapshot.id.clone(),
+        description: description.to_string(),
+        source: snapshot.source,
+        created_at: now,
+        deadline: now + chrono::Duration::seconds(timeout_seconds as i64),
+    };
+
+    let json = serde_json::to_string_pretty(&pending)
+        .map_err(|e| format!("Failed to serialize pending change: {}", e))?;
+    fs::write(data_file_path(data_dir, PENDING_FILE), json)
+        .map_err(|e| format!("Failed to write pending change: {}", e))?;
+    Ok(pending)
+}
+
+pub fn confirm_pending(data_dir: &Path) -> Result<PendingChange, AppError> {
+    let pending = load_pending_change(data_dir)?
+        .ok_or_else(|| AppError::not_found("No change is waiting for confirmation"))?;
+    match fs::remove_file(data_file_path(data_dir, PENDING_FILE)) {
+        Ok(()) => Ok(pending),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            Err(AppError::new(ErrorKind::Conflict, format!("\"{}\" has already been reverted", pending.description)))
+        },
+        Err(e) => Err(AppError::from(e).context("Failed to confirm pending change")),
+    }
+}
+
+fn claim_pending_change(data_dir: &Path) -> Result<Option<PendingChange>, String> {
+    let reverting = data_file_path(data_dir, REVERTING_FILE);
+    match fs::rename(data_file_path(data_dir, PENDING_FILE), &reverting) {
+        Ok(()) => {},
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to claim pending change: {}", e)),
+    }
+    let pending = read_pending_file(&reverting);
+    if !matches!(pending, Ok(Some(_))) {
+        let _ = fs::remove_file(&reverting);
+    }
+    pending
+}
+
+// Ends a claim: a reverted change is gone, a failed one is pending again so it can be retried.
+fn release_claim(data_dir: &Path, reverted: bool) {
+    let reverting = data_file_path(data_dir, REVERTING_FILE);
+    let result = if reverted {
+        fs::remove_file(&reverting)
+    } else {
+        fs::rename(&reverting, data_file_path(data_dir, PENDING_FILE))
+    };
+    if let Err(e) = result {
+        warn!("Failed to release the claimed pending change: {}", e);
+    }
+}
+
+async fn restore_before(
+    pending: PendingChange,
+    data_dir: &Path,
+    backend: &dyn FirewallBackend,
+    manifest: &RuleManifestState,
+    blocked: &BlockedDomains,
+    source: ChangeSource
+) -> Result<(PendingChange, RestoreReport), AppError> {
+    let result = restore_snapshot(data_dir, backend, manifest, blocked, source, &pending.snapshot_id, false).await;
+    release_claim(data_dir, result.is_ok());
+    let report = result.map_err(|e| e.context(&format!("Failed to revert \"{}\"", pending.description)))?;
+    Ok((pending, report))
+}
+
+pub async fn revert_pending(
+    data_dir: &Path,
+    backend: &dyn FirewallBackend,
+    manifest: &RuleManifestState,
+    blocked: &BlockedDomains,
+    source: ChangeSource
+) -> Result<(PendingChange, RestoreReport), AppError> {
+    let pending = claim_pending_change(data_dir)?
+        .ok_or_else(|| AppError::not_found("No change is waiting for confirmation"))?;
+    restore_before(pending, data_dir, backend, manifest, blocked, source).await
+}
+
+pub async fn revert_if_expired(
+    data_dir: &Path,
+    backend: &dyn FirewallBackend,
+    manifest: &RuleManifestState,
+    blocked: &BlockedDomains,
+    source: ChangeSource
+) -> Result<Option<(PendingChange, RestoreReport)>, AppError> {
+    match load_pending_change(data_dir)? {
+        Some(pending) if pending.deadline <= Utc::now() => {},
+        _ => return Ok(None),
+    }
+    // Confirmed or reverted by someone else since we looked.
+    let Some(pending) = claim_pending_change(data_dir)? else {
+        return Ok(None);
+    };
+    let params = json!({ "snapshot_id": pending.snapshot_id, "description": pending.description });
+    let result = restore_before(pending, data_dir, backend, manifest, blocked, source).await;
+    let outcome = match &result {
+        Ok((_, report)) => Ok(report),
+        Err(e) => Err(e.clone()),
+    };
+    record(data_dir, source, "revert_expired_change", params, &outcome);
+    result.map(Some)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RevertedChange {
+    pub change: PendingChange,
+    pub report: RestoreReport,
+}
+
+pub fn start_pending_change_watcher(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let data_dir = app_data_dir(&app);
+        let mut interval = tokio::time::interval(Duration::from_secs(WATCH_INTERVAL_SECONDS));
+        loop {
+            interval.tick().await;
+            // The service reverts its own pending changes.
+            if daemon_running().await {
+                continue;
+            }
+
+            let backend = app.state::<FirewallBackendState>().backend.clone();
+            let manifest = app.state::<RuleManifestState>();
+            let blocked = app.state::<BlockedDomains>();
+            match revert_if_expired(&data_dir, backend.as_ref(), &manifest, &blocked, ChangeSource::Desktop).await {
+                Ok(Some((change, report))) => {
+                    if let Err(e) = app.emit("tentative-change-reverted", RevertedChange { change, report }) {
+                        warn!("Failed to emit tentative change event: {}", e);
+                    }
+                },
+                Ok(None) => {},
+                Err(e) => warn!("Failed to revert tentative change: {}", e),
+            }
+        }
+    });
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_pending_change(app: AppHandle) -> Result<Option<PendingChange>, AppError> {
+    Ok(load_pending_change(&app_data_dir(&app))?)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn confirm_pending_change(app: AppHandle) -> Result<PendingChange, AppError> {
+    let data_dir = app_data_dir(&app);
+    let result = confirm_pending(&data_dir);
+    record(&data_dir, ChangeSource::Desktop, "confirm_pending_change", Value::Null, &result);
+    result
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn revert_pending_change(
+    app: AppHandle,
+    backend: State<'_, FirewallBackendState>,
+    manifest: State<'_, RuleManifestState>,
+    blocked: State<'_, BlockedDomains>
+) -> Result<RevertedChange, AppError> {
+    let data_dir = app_data_dir(&app);
+    audited(&data_dir, ChangeSource::Desktop, "revert_pending_change", Value::Null, async {
+        refuse_while_daemon_runs("revert a pending change").await?;
+        let (change, report) = revert_pending(
+            &data_dir,
+            backend.backend.as_ref(),
+            &manifest,
+            &blocked,
+            ChangeSource::Desktop
+        ).await?;
+        Ok(RevertedChange { change, report })
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::firewall::backend::netsh::NetshBackend;
+    use crate::firewall::snapshots::{delete_snapshot, snapshot_before_change};
+    use crate::firewall::testing::FakeCommandRunner;
+
+    fn summary(id: &str) -> SnapshotSummary {
+        SnapshotSummary {
+            id: id.to_string(),
+            created_at: Utc::now().to_rfc3339(),
+            source: ChangeSource::Cli,
+            user: None,
+            trigger: "test".to_string(),
+            backend: "netsh".to_string(),
+            rule_count: 0,
+            has_export: false,
+        }
+    }
+
+    #[test]
+    fn timeouts_are_bounded() {
+        assert!(validate_confirm_timeout(0).is_err());
+        assert!(validate_confirm_timeout(MAX_CONFIRM_SECONDS + 1).is_err());
+        assert_eq!(validate_confirm_timeout(60), Ok(60));
+    }
+
+    #[test]
+    fn confirming_clears_the_pending_change() {
+        let dir = std::env::temp_dir().join(format!("security-smile-tentative-confirm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        assert!(confirm_pending(&dir).is_err());
+        let pending = begin_pending_change(&dir, &summary("20250101-000000-000"), "rules add Web", 60).unwrap();
+        assert_eq!(pending.source, ChangeSource::Cli);
+        assert!(pending.seconds_left(pending.created_at) >= 59);
+        let err = ensure_no_pending_change(&dir).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Conflict);
+        assert!(err.message.contains("rules add Web"));
+
+        assert_eq!(confirm_pending(&dir).unwrap(), pending);
+        assert!(load_pending_change(&dir).unwrap().is_none());
+        assert!(ensure_no_pending_change(&dir).is_ok());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn unexpired_changes_are_not_reverted() {
+        let dir = std::env::temp_dir().join(format!("security-smile-tentative-revert-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let backend = NetshBackend::new(Arc::new(FakeCommandRunner::new()));
+        let manifest = RuleManifestState::default();
+        let blocked = BlockedDomains::default();
+
+        begin_pending_change(&dir, &summary("20250101-000000-000"), "rules add Web", 60).unwrap();
+        let reverted = revert_if_expired(&dir, &backend, &manifest, &blocked, ChangeSource::Daemon).await.unwrap();
+        assert!(reverted.is_none());
+        assert!(load_pending_change(&dir).unwrap().is_some());
+
+        // Only one caller gets to claim it.
+        assert!(claim_pending_change(&dir).unwrap().is_some());
+        assert!(claim_pending_change(&dir).unwrap().is_none());
+        assert!(confirm_pending(&dir).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn expired_changes_are_reverted() {
+        let dir = std::env::temp_dir().join(format!("security-smile-tentative-expired-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let backend = NetshBackend::new(Arc::new(FakeCommandRunner::new()));
+        let manifest = RuleManifestState::default();
+        let blocked = BlockedDomains::default();
+        blocked.add_domain("ads.example.com");
+
+        let before = snapshot_before_change(&dir, &backend, &manifest, &blocked, ChangeSource::Cli, "rules add Web").await.unwrap();
+        blocked.add_domain("tracker.example.com");
+        begin_pending_change(&dir, &before, "rules add Web", 0).unwrap();
+        assert_eq!(delete_snapshot(&dir, &before.id).unwrap_err().kind, ErrorKind::Conflict);
+
+        let (change, report) = revert_if_expired(&dir, &backend, &manifest, &blocked, ChangeSource::Daemon).await.unwrap().unwrap();
+        assert_eq!(change.description, "rules add Web");
+        assert_eq!(report.snapshot_id, before.id);
+        assert_eq!(*blocked.domains.lock().unwrap(), vec!["ads.example.com"]);
+        assert!(pending_snapshot_id(&dir).unwrap().is_none());
+        assert!(delete_snapshot(&dir, &before.id).is_ok());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn failed_reverts_stay_pending() {
+        let dir = std::env::temp_dir().join(format!("security-smile-tentative-failed-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let backend = NetshBackend::new(Arc::new(FakeCommandRunner::new()));
+        let manifest = RuleManifestState::default();
+        let blocked = BlockedDomains::default();
+
+        // The snapshot is missing, so restoring it fails.
+        begin_pending_change(&dir, &summary("20250101-000000-000"), "rules add Web", 0).unwrap();
+        assert!(revert_if_expired(&dir, &backend, &manifest, &blocked, ChangeSource::Daemon).await.is_err());
+        assert!(load_pending_change(&dir).unwrap().is_some());
+        assert!(revert_pending(&dir, &backend, &manifest, &blocked, ChangeSource::Cli).await.is_err());
+        assert!(load_pending_change(&dir).unwrap().is_some());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
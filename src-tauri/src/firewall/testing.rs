@@ -0,0 +1,324 @@
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use hickory_proto::op::{Message, MessageType, ResponseCode};
+use hickory_proto::rr::rdata::{A, AAAA, CNAME};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::task::JoinHandle;
+use crate::firewall::command_runner::{failure_error, CommandOutput, CommandRunner, ScriptKind};
+use crate::firewall::common::FirewallError;
+use crate::firewall::domain_blocking::resolver::{DnsResolver, ResolverConfig};
+
+impl CommandOutput {
+    pub fn ok(stdout: &str) -> Self {
+        CommandOutput {
+            success: true,
+            code: Some(0),
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+        }
+    }
+
+    pub fn failed(code: i32, stderr: &str) -> Self {
+        CommandOutput {
+            success: false,
+            code: Some(code),
+            stdout: String::new(),
+            stderr: stderr.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invocation {
+    Command { program: String, args: Vec<String>, elevated: bool },
+    Script { kind: ScriptKind, script: String },
+}
+
+impl Invocation {
+    pub fn argv(&self) -> Option<Vec<String>> {
+        match self {
+            Invocation::Command { program, args, .. } => {
+                let mut argv = vec![program.clone()];
+                argv.extend(args.iter().cloned());
+                Some(argv)
+            },
+            Invocation::Script { .. } => None,
+        }
+    }
+
+    pub fn script(&self) -> Option<&str> {
+        match self {
+            Invocation::Script { script, .. } => Some(script),
+            Invocation::Command { .. } => None,
+        }
+    }
+}
+
+// Records every invocation and replays canned responses in FIFO order per program.
+// Anything without a queued response succeeds with empty output.
+#[derive(Default)]
+pub struct FakeCommandRunner {
+    invocations: Mutex<Vec<Invocation>>,
+    command_responses: Mutex<Vec<(String, VecDeque<CommandOutput>)>>,
+    script_responses: Mutex<VecDeque<Result<String, String>>>,
+}
+
+impl FakeCommandRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn respond(&self, program: &str, output: CommandOutput) -> &Self {
+        let mut responses = self.command_responses.lock().unwrap();
+        match responses.iter_mut().find(|(p, _)| p == program) {
+            Some((_, queue)) => queue.push_back(output),
+            None => responses.push((program.to_string(), VecDeque::from([output]))),
+        }
+        self
+    }
+
+    pub fn respond_to_script(&self, result: Result<String, String>) -> &Self {
+        self.script_responses.lock().unwrap().push_back(result);
+        self
+    }
+
+    pub fn invocations(&self) -> Vec<Invocation> {
+        self.invocations.lock().unwrap().clone()
+    }
+
+    pub fn scripts(&self) -> Vec<String> {
+        self.invocations()
+            .iter()
+            .filter_map(|i| i.script().map(|s| s.to_string()))
+            .collect()
+    }
+
+    fn next_command_response(&self, program: &str) -> CommandOutput {
+        let mut responses = self.command_responses.lock().unwrap();
+        responses
+            .iter_mut()
+            .find(|(p, _)| p == program)
+            .and_then(|(_, queue)| queue.pop_front())
+            .unwrap_or_else(|| CommandOutput::ok(""))
+    }
+
+    fn record(&self, invocation: Invocation) {
+        self.invocations.lock().unwrap().push(invocation);
+    }
+}
+
+#[async_trait]
+impl CommandRunner for FakeCommandRunner {
+    async fn output(&self, program: &str, args: &[String]) -> Result<CommandOutput, FirewallError> {
+        self.record(Invocation::Command {
+            program: program.to_string(),
+            args: args.to_vec(),
+            elevated: false,
+        });
+        Ok(self.next_command_response(program))
+    }
+
+    async fn output_elevated(&self, program: &str, args: &[String]) -> Result<CommandOutput, FirewallError> {
+        self.record(Invocation::Command {
+            program: program.to_string(),
+            args: args.to_vec(),
+            elevated: true,
+        });
+        Ok(self.next_command_response(program))
+    }
+
+    async fn run_elevated_script(&self, kind: ScriptKind, script: &str) -> Result<String, FirewallError> {
+        self.record(Invocation::Script {
+            kind,
+            script: script.to_string(),
+        });
+        self.script_responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| Ok(String::new()))
+            // Classified the way a real script's stderr would be.
+            .map_err(|stderr| failure_error(&CommandOutput::failed(1, &stderr)))
+    }
+}
+
+// A tiny authoritative-only DNS server on 127.0.0.1 that answers from an in-memory record list,
+// following CNAMEs within its own data. Unknown names get NXDOMAIN.
+pub struct StubDnsServer {
+    pub addr: SocketAddr,
+    records: Arc<Mutex<Vec<Record>>>,
+    queries: Arc<Mutex<Vec<(String, RecordType)>>>,
+    truncate_udp: Arc<AtomicBool>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+fn fqdn(name: &str) -> Name {
+    let mut name = Name::from_str(name).unwrap();
+    name.set_fqdn(true);
+    name
+}
+
+impl StubDnsServer {
+    pub async fn start() -> Self {
+        // Serve UDP and TCP on the same port, like a real server, so truncated answers can be retried.
+        let (socket, listener) = loop {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            if let Ok(socket) = UdpSocket::bind(listener.local_addr().unwrap()).await {
+                break (socket, listener);
+            }
+        };
+        let addr = socket.local_addr().unwrap();
+        let records: Arc<Mutex<Vec<Record>>> = Arc::new(Mutex::new(Vec::new()));
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let truncate_udp = Arc::new(AtomicBool::new(false));
+
+        let udp = {
+            let records = records.clone();
+            let queries = queries.clone();
+            let truncate_udp = truncate_udp.clone();
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 4096];
+                loop {
+                    let Ok((len, peer)) = socket.recv_from(&mut buf).await else { break };
+                    let Ok(request) = Message::from_vec(&buf[..len]) else { continue };
+                    let Some(mut response) = respond(&request, &records, &queries) else { continue };
+                    if truncate_udp.load(Ordering::SeqCst) {
+                        response.take_answers();
+                        response.set_truncated(true);
+                    }
+                    let _ = socket.send_to(&response.to_vec().unwrap(), peer).await;
+                }
+            })
+        };
+
+        let tcp = {
+            let records = records.clone();
+            let queries = queries.clone();
+            tokio::spawn(async move {
+                loop {
+                    let Ok((mut stream, _)) = listener.accept().await else { break };
+                    let mut len = [0u8; 2];
+                    if stream.read_exact(&mut len).await.is_err() { continue };
+                    let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+                    if stream.read_exact(&mut buf).await.is_err() { continue };
+                    let Ok(request) = Message::from_vec(&buf) else { continue };
+                    let Some(response) = respond(&request, &records, &queries) else { continue };
+
+                    let response = response.to_vec().unwrap();
+                    let mut framed = (response.len() as u16).to_be_bytes().to_vec();
+                    framed.extend_from_slice(&response);
+                    let _ = stream.write_all(&framed).await;
+                }
+            })
+        };
+
+        StubDnsServer { addr, records, queries, truncate_udp, handles: vec![udp, tcp] }
+    }
+
+    pub fn add_a(&self, name: &str, ip: &str, ttl: u32) -> &Self {
+        let ip: IpAddr = ip.parse().unwrap();
+        let rdata = match ip {
+            IpAddr::V4(v4) => RData::A(A(v4)),
+            IpAddr::V6(v6) => RData::AAAA(AAAA(v6)),
+        };
+        self.records.lock().unwrap().push(Record::from_rdata(fqdn(name), ttl, rdata));
+        self
+    }
+
+    pub fn add_cname(&self, name: &str, target: &str, ttl: u32) -> &Self {
+        self.records.lock().unwrap().push(Record::from_rdata(fqdn(name), ttl, RData::CNAME(CNAME(fqdn(target)))));
+        self
+    }
+
+    // Answer UDP queries with an empty, truncated response so clients have to ask over TCP.
+    pub fn truncate_udp(&self) {
+        self.truncate_udp.store(true, Ordering::SeqCst);
+    }
+
+    pub fn clear(&self) {
+        self.records.lock().unwrap().clear();
+    }
+
+    pub fn queries(&self) -> Vec<(String, RecordType)> {
+        self.queries.lock().unwrap().clone()
+    }
+}
+
+// A resolver that gives up quickly, so tests of unanswered queries stay fast.
+pub fn resolver_using(servers: &[SocketAddr]) -> DnsResolver {
+    DnsResolver::new(ResolverConfig {
+        upstream_servers: servers.iter().map(|s| s.to_string()).collect(),
+        timeout_ms: 200,
+        attempts: 1,
+    })
+}
+
+pub fn resolver_for(server: &StubDnsServer) -> DnsResolver {
+    resolver_using(&[server.addr])
+}
+
+impl Drop for StubDnsServer {
+    fn drop(&mut self) {
+        for handle in &self.handles {
+            handle.abort();
+        }
+    }
+}
+
+fn respond(
+    request: &Message,
+    records: &Mutex<Vec<Record>>,
+    queries: &Mutex<Vec<(String, RecordType)>>,
+) -> Option<Message> {
+    let query = request.queries().first()?;
+    queries.lock().unwrap().push((
+        query.name().to_ascii().trim_end_matches('.').to_string(),
+        query.query_type(),
+    ));
+    Some(answer(request, &records.lock().unwrap()))
+}
+
+fn answer(request: &Message, records: &[Record]) -> Message {
+    let query = &request.queries()[0];
+    let mut response = Message::new();
+    response
+        .set_id(request.id())
+        .set_message_type(MessageType::Response)
+        .set_recursion_desired(request.recursion_desired())
+        .set_recursion_available(true)
+        .add_query(query.clone());
+
+    let mut current = query.name().clone();
+    let mut found_name = false;
+    for _ in 0..16 {
+        let cname = records.iter().find(|r| r.name() == &current && r.record_type() == RecordType::CNAME);
+        match cname {
+            Some(record) => {
+                found_name = true;
+                response.add_answer(record.clone());
+                if let Some(RData::CNAME(target)) = record.data() {
+                    current = target.0.clone();
+                }
+            },
+            None => break,
+        }
+    }
+
+    for record in records.iter().filter(|r| r.name() == &current) {
+        found_name = true;
+        if record.record_type() == query.query_type() {
+            response.add_answer(record.clone());
+        }
+    }
+
+    if !found_name {
+        response.set_response_code(ResponseCode::NXDomain);
+    }
+    response
+}
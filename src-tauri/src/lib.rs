@@ -1,10 +1,19 @@
+mod app_data;
+mod audit;
+pub mod cli;
+mod daemon;
+mod error;
 mod network_traffic_analysis;
 mod firewall;
 mod tray;
 mod assistant;
+mod departments;
+mod policy_file;
+mod logging;
 
 use firewall::{
     FirewallState, 
+    FirewallBackendState,
     BlockedDomains,
     get_firewall_rules, 
     add_firewall_rule, 
@@ -12,17 +21,81 @@ use firewall::{
     enable_disable_rule,
     get_blocked_domains,
     block_domain,
-    unblock_domain
+    unblock_domain,
+    get_firewall_backend
 };
-use firewall::common::{NotificationState, NotificationSettings};
-use tauri::AppHandle;
+use firewall::common::{load_notification_settings, save_notification_settings, NotificationState, NotificationSettings};
+use firewall::domain_blocking::resolver::{
+    DnsResolverState,
+    get_dns_resolver_config,
+    set_dns_resolver_config
+};
+use firewall::domain_blocking::refresher::{
+    get_blocked_domain_resolutions,
+    refresh_blocked_domains
+};
+use firewall::domain_blocking::batch::{block_domains, unblock_domains};
+use firewall::domain_blocking::monitor::{
+    start_domain_access_monitor,
+    stop_domain_access_monitor,
+    is_domain_access_monitor_active
+};
+use firewall::ownership::{RuleManifestState, reconcile_firewall_rules, get_rule_manifest};
+use firewall::snapshots::{
+    list_firewall_snapshots,
+    get_firewall_snapshot,
+    create_firewall_snapshot,
+    restore_firewall_snapshot,
+    delete_firewall_snapshot,
+    get_snapshot_settings,
+    set_snapshot_settings,
+    ChangeSource
+};
+use firewall::tentative::{get_pending_change, confirm_pending_change, revert_pending_change};
+use audit::{get_audit_log, verify_audit_log};
+use logging::{get_recent_logs, get_log_settings, set_log_settings};
+use departments::{
+    DepartmentState,
+    get_departments,
+    create_department,
+    update_department,
+    delete_department,
+    add_department_device,
+    remove_department_device
+};
+use error::AppError;
+use departments::policy::{apply_department_policy, get_connection_rules};
+use policy_file::{plan_policy_file, apply_policy_file};
+use daemon::{get_daemon_status, send_daemon_request};
+use firewall::domain_blocking::blocklists::{
+    BlocklistSources,
+    import_blocklist,
+    get_blocklist_sources,
+    remove_blocklist_source
+};
+use firewall::domain_blocking::sinkhole::{
+    SinkholeState,
+    get_sinkhole_config,
+    set_sinkhole_config,
+    get_sinkhole_status,
+    get_sinkhole_blocked_queries
+};
+use firewall::command_runner::{CommandRunner, ShellCommandRunner};
+use std::sync::Arc;
+use tauri::{AppHandle, Manager};
+use tracing::error;
 use network_traffic_analysis::suricata::{
     is_suricata_active,
     run_suricata,
     kill_suricata,
+    get_suricata_status,
     read_alert_events,
     extract_and_handle_events
 };
+use network_traffic_analysis::supervisor::SuricataSupervisor;
+use network_traffic_analysis::tailer::EveTailerState;
+use network_traffic_analysis::store::{EventStoreState, query_alert_events};
+use network_traffic_analysis::live::{LiveEventState, subscribe_live_events, unsubscribe_live_events};
 use network_traffic_analysis::report::{
     read_flow_report,
     generate_flow_report,
@@ -38,37 +111,44 @@ use tray::{
 };
 
 #[tauri::command]
+#[tracing::instrument(skip_all)]
 fn check_tray_status() -> bool {
     is_tray_active()
 }
 
 #[tauri::command]
-fn set_tray_tooltip(message: String) -> Result<(), String> {
-    update_tray_tooltip(&message)
+#[tracing::instrument(skip_all, err)]
+fn set_tray_tooltip(message: String) -> Result<(), AppError> {
+    Ok(update_tray_tooltip(&message)?)
 }
 
 #[tauri::command]
-fn toggle_tray_status(app: AppHandle) -> Result<TrayState, String> {
-    toggle_tray_state(&app).map_err(|e| e.to_string())
+#[tracing::instrument(skip_all, err)]
+fn toggle_tray_status(app: AppHandle) -> Result<TrayState, AppError> {
+    Ok(toggle_tray_state(&app).map_err(|e| e.to_string())?)
 }
 
 #[tauri::command]
-fn set_tray_status(app: AppHandle, state: TrayState) -> Result<(), String> {
-    set_tray_state(&app, state).map_err(|e| e.to_string())
+#[tracing::instrument(skip_all, err)]
+fn set_tray_status(app: AppHandle, state: TrayState) -> Result<(), AppError> {
+    Ok(set_tray_state(&app, state).map_err(|e| e.to_string())?)
 }
 
 #[tauri::command]
+#[tracing::instrument(skip_all)]
 fn get_tray_status() -> TrayState {
     get_tray_state()
 }
 
 #[tauri::command]
+#[tracing::instrument(skip_all)]
 fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
 #[tauri::command]
-async fn send_notification(title: String, message: String, app: AppHandle) -> Result<(), String> {
+#[tracing::instrument(skip_all, err)]
+async fn send_notification(title: String, message: String, app: AppHandle) -> Result<(), AppError> {
     use tauri_plugin_notification::NotificationExt;
     
     app.notification()
@@ -82,11 +162,12 @@ async fn send_notification(title: String, message: String, app: AppHandle) -> Re
 }
 
 #[tauri::command]
+#[tracing::instrument(skip_all, err)]
 async fn show_domain_blocked_notification(
     domain: String, 
     app: AppHandle,
     state: tauri::State<'_, NotificationState>
-) -> Result<(), String> {
+) -> Result<(), AppError> {
     use tauri_plugin_notification::NotificationExt;
     use tokio::time::{sleep, Duration};
       let delay_seconds = {
@@ -111,12 +192,13 @@ async fn show_domain_blocked_notification(
 }
 
 #[tauri::command]
+#[tracing::instrument(skip_all, err)]
 async fn create_popup_alert(
     title: String, 
     message: String, 
     alert_type: String,
     app: AppHandle
-) -> Result<(), String> {
+) -> Result<(), AppError> {
     use tauri::{WebviewWindowBuilder, WebviewUrl};
     
     let window_label = format!("popup-alert-{}", 
@@ -153,25 +235,34 @@ async fn create_popup_alert(
 }
 
 #[tauri::command]
+#[tracing::instrument(skip_all, err)]
 async fn get_notification_settings(
     state: tauri::State<'_, NotificationState>
-) -> Result<NotificationSettings, String> {
+) -> Result<NotificationSettings, AppError> {
     let settings = state.settings.lock().unwrap();
     Ok(settings.clone())
 }
 
 #[tauri::command]
+#[tracing::instrument(skip_all, err)]
 async fn set_notification_settings(
+    app: AppHandle,
     new_settings: NotificationSettings,
     state: tauri::State<'_, NotificationState>
-) -> Result<(), String> {
-    let mut settings = state.settings.lock().unwrap();
-    *settings = new_settings;
-    Ok(())
+) -> Result<(), AppError> {
+    let data_dir = app_data::app_data_dir(&app);
+    let params = serde_json::json!({ "settings": new_settings });
+    let result = save_notification_settings(&data_dir, &new_settings);
+    if result.is_ok() {
+        *state.settings.lock().unwrap() = new_settings;
+    }
+    audit::record(&data_dir, ChangeSource::Desktop, "set_notification_settings", params, &result);
+    Ok(result?)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    let events = EventStoreState::default();
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_process::init())
@@ -179,20 +270,79 @@ pub fn run() {
         .plugin(tauri_plugin_notification::init())
         .manage(FirewallState::default())
         .manage(BlockedDomains::default())
+        .manage(DnsResolverState::default())
+        .manage(SinkholeState::default())
+        .manage(BlocklistSources::default())
+        .manage(RuleManifestState::default())
+        .manage(DepartmentState::default())
         .manage(NotificationState::default())
+        .manage(EveTailerState::new(&app_data::events_dir(), events.store.clone()))
+        .manage(events)
+        .manage(LiveEventState::default())
+        .manage(SuricataSupervisor::default())
         .invoke_handler(tauri::generate_handler![
             greet,
             get_firewall_rules,
             add_firewall_rule,
             remove_firewall_rule,
             enable_disable_rule,
+            get_firewall_backend,
+            reconcile_firewall_rules,
+            get_rule_manifest,
+            list_firewall_snapshots,
+            get_firewall_snapshot,
+            create_firewall_snapshot,
+            restore_firewall_snapshot,
+            delete_firewall_snapshot,
+            get_snapshot_settings,
+            set_snapshot_settings,
+            get_pending_change,
+            confirm_pending_change,
+            revert_pending_change,
+            get_audit_log,
+            verify_audit_log,
+            get_recent_logs,
+            get_log_settings,
+            set_log_settings,
             get_blocked_domains,
             block_domain,
             unblock_domain,
+            block_domains,
+            unblock_domains,
+            start_domain_access_monitor,
+            stop_domain_access_monitor,
+            is_domain_access_monitor_active,
+            get_dns_resolver_config,
+            set_dns_resolver_config,
+            get_blocked_domain_resolutions,
+            refresh_blocked_domains,
+            import_blocklist,
+            get_blocklist_sources,
+            remove_blocklist_source,
+            get_sinkhole_config,
+            set_sinkhole_config,
+            get_sinkhole_status,
+            get_sinkhole_blocked_queries,
+            get_departments,
+            create_department,
+            update_department,
+            delete_department,
+            add_department_device,
+            remove_department_device,
+            apply_department_policy,
+            get_connection_rules,
+            plan_policy_file,
+            apply_policy_file,
+            get_daemon_status,
+            send_daemon_request,
             is_suricata_active,
             run_suricata,
             kill_suricata,
+            get_suricata_status,
             read_alert_events,
+            query_alert_events,
+            subscribe_live_events,
+            unsubscribe_live_events,
             extract_and_handle_events,
             ask_ai,
             send_notification,
@@ -210,14 +360,37 @@ pub fn run() {
         ])
         .setup(|app| {
             let app_handle = app.handle().clone();
+            let data_dir = app_data::app_data_dir(&app_handle);
+            if let Err(e) = logging::init_logging(&data_dir, "desktop", cfg!(debug_assertions)) {
+                eprintln!("{}", e);
+            }
+            let runner: Arc<dyn CommandRunner> = Arc::new(ShellCommandRunner::new(app_handle.clone()));
+            app.manage(FirewallBackendState::new(runner));
             
             let rt = tokio::runtime::Runtime::new()
                 .expect("Failed to create Tokio runtime");
-              rt.block_on(async {
-                if let Err(_) = firewall::domain_blocking::initialize_blocked_domains(app_handle.clone()).await {
-                    // Failed to initialize blocked domains from file
-                }            
+            rt.block_on(async {
+                if let Err(e) = firewall::domain_blocking::initialize_blocked_domains(app_handle.clone()).await {
+                    error!("Failed to load blocked domains: {}", e);
+                }
+            });
+            if let Err(e) = departments::initialize_departments(&data_dir, &app_handle.state::<DepartmentState>()) {
+                error!("Failed to load departments: {}", e);
+            }
+            match load_notification_settings(&data_dir) {
+                Ok(settings) => *app_handle.state::<NotificationState>().settings.lock().unwrap() = settings,
+                Err(e) => error!("Failed to load notification settings: {}", e),
+            }
+            firewall::domain_blocking::refresher::start_refresher(app_handle.clone());
+            let sinkhole_handle = app_handle.clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = firewall::domain_blocking::sinkhole::initialize_sinkhole(sinkhole_handle).await {
+                    error!("Failed to start the DNS sinkhole: {}", e);
+                }
             });
+            firewall::tentative::start_pending_change_watcher(app_handle.clone());
+            network_traffic_analysis::live::start_live_events(app_handle.clone());
+            network_traffic_analysis::supervisor::start_supervision(app_handle.clone());
 
             let app_handle = app.handle();
             tray::cleanup_tray();
@@ -225,6 +398,12 @@ pub fn run() {
             
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            // Suricata started from the app doesn't outlive it.
+            if let tauri::RunEvent::Exit = event {
+                let _ = app.state::<SuricataSupervisor>().stop();
+            }
+        });
 }
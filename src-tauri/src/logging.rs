@@ -0,0 +1,268 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use once_cell::sync::OnceCell;
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+use tauri::AppHandle;
+use tracing::Level;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+use crate::app_data::{app_data_dir, data_file_path};
+use crate::error::AppError;
+
+const LOG_DIR: &str = "logs";
+const SETTINGS_FILE: &str = "log_settings.json";
+// Overrides the saved level, with the usual filter syntax (e.g. `security_smile_lib=debug`).
+const LOG_ENV_VAR: &str = "SECURITY_SMILE_LOG";
+const DEFAULT_RECENT_LINES: usize = 200;
+
+static FILTER: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogSettings {
+    // A level (`info`) or a filter directive (`info,security_smile_lib::daemon=debug`).
+    pub level: String,
+    // Daily files kept per process before the oldest is deleted.
+    pub max_files: usize,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings {
+            level: "info".to_string(),
+            max_files: 7,
+        }
+    }
+}
+
+// One line from a log file, for the troubleshooting view.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogLine {
+    pub timestamp: String,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+    // The desktop app, the CLI and the daemon each write their own files.
+    pub process: String,
+    #[serde(default)]
+    pub span: Option<String>,
+    #[serde(default)]
+    pub fields: Map<String, Value>,
+}
+
+pub fn log_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join(LOG_DIR)
+}
+
+pub fn load_log_settings(data_dir: &Path) -> Result<LogSettings, String> {
+    let file_path = data_file_path(data_dir, SETTINGS_FILE);
+    if !file_path.exists() {
+        return Ok(LogSettings::default());
+    }
+
+    let json = fs::read_to_string(&file_path)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    serde_json::from_str(&json)
+        .map_err(|e| format!("Failed to deserialize log settings: {}", e))
+}
+
+fn save_log_settings(data_dir: &Path, settings: &LogSettings) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(settings)
+        .map_err(|e| format!("Failed to serialize log settings: {}", e))?;
+    fs::write(data_file_path(data_dir, SETTINGS_FILE), json)
+        .map_err(|e| format!("Failed to write to file: {}", e))
+}
+
+fn parse_filter(level: &str) -> Result<EnvFilter, String> {
+    EnvFilter::try_new(level).map_err(|e| format!("Invalid log level {}: {}", level, e))
+}
+
+// Sends logs to daily files under `logs/` as JSON lines, and to stderr when `to_stderr` is set
+// (the daemon, whose stderr ends up in the journal). Call once per process.
+pub fn init_logging(data_dir: &Path, process: &str, to_stderr: bool) -> Result<(), String> {
+    let settings = load_log_settings(data_dir).unwrap_or_default();
+    let filter = match std::env::var(LOG_ENV_VAR).ok().filter(|f| !f.trim().is_empty()) {
+        Some(directive) => parse_filter(&directive)?,
+        None => parse_filter(&settings.level).unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(process)
+        .filename_suffix("log")
+        .max_log_files(settings.max_files.max(1))
+        .build(log_dir(data_dir))
+        .map_err(|e| format!("Failed to open log file: {}", e))?;
+    let file_layer = fmt::layer()
+        .json()
+        .with_current_span(true)
+        .with_span_list(false)
+        .with_writer(appender);
+    let stderr_layer = to_stderr.then(|| fmt::layer().with_writer(std::io::stderr));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(file_layer)
+        .with(stderr_layer)
+        .try_init()
+        .map_err(|e| format!("Failed to start logging: {}", e))?;
+    let _ = FILTER.set(handle);
+    Ok(())
+}
+
+pub fn set_log_level(data_dir: &Path, level: &str) -> Result<LogSettings, String> {
+    let filter = parse_filter(level)?;
+    if let Some(handle) = FILTER.get() {
+        handle.reload(filter).map_err(|e| format!("Failed to change log level: {}", e))?;
+    }
+    let settings = LogSettings {
+        level: level.to_string(),
+        ..load_log_settings(data_dir)?
+    };
+    save_log_settings(data_dir, &settings)?;
+    Ok(settings)
+}
+
+fn parse_log_line(line: &str, process: &str) -> Option<LogLine> {
+    let mut value: Map<String, Value> = serde_json::from_str(line).ok()?;
+    let text = |value: &mut Map<String, Value>, key: &str| match value.remove(key) {
+        Some(Value::String(s)) => s,
+        _ => String::new(),
+    };
+    let mut fields = match value.remove("fields") {
+        Some(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    };
+    let message = match fields.remove("message") {
+        Some(Value::String(message)) => message,
+        _ => String::new(),
+    };
+    let span = value.remove("span")
+        .and_then(|span| span.get("name").and_then(|n| n.as_str()).map(str::to_string));
+
+    Some(LogLine {
+        timestamp: text(&mut value, "timestamp"),
+        level: text(&mut value, "level"),
+        target: text(&mut value, "target"),
+        message,
+        process: process.to_string(),
+        span,
+        fields,
+    })
+}
+
+fn level_of(line: &LogLine) -> Level {
+    line.level.parse().unwrap_or(Level::TRACE)
+}
+
+// The last `limit` lines at `min_level` or above across every process's logs, oldest first.
+pub fn read_recent_logs(data_dir: &Path, limit: usize, min_level: Option<Level>) -> Result<Vec<LogLine>, String> {
+    let dir = log_dir(data_dir);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut files: Vec<(String, PathBuf)> = fs::read_dir(&dir)
+        .map_err(|e| format!("Failed to read log directory: {}", e))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            // `<process>.<date>.log`
+            let process = name.split('.').next()?.to_string();
+            name.ends_with(".log").then(|| (process, entry.path()))
+        })
+        .collect();
+    // Dates sort lexically, so newest first per process.
+    files.sort_by(|a, b| b.1.cmp(&a.1));
+
+    let mut lines = Vec::new();
+    let mut taken: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+    for (process, path) in files {
+        let count = taken.entry(process.clone()).or_default();
+        if *count >= limit {
+            continue;
+        }
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let matching: Vec<LogLine> = text
+            .lines()
+            .filter_map(|line| parse_log_line(line, &process))
+            .filter(|line| min_level.is_none_or(|min| level_of(line) <= min))
+            .collect();
+        let skip = matching.len().saturating_sub(limit - *count);
+        *count += matching.len() - skip;
+        lines.extend(matching.into_iter().skip(skip));
+    }
+
+    lines.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    let skip = lines.len().saturating_sub(limit);
+    Ok(lines.split_off(skip))
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_recent_logs(
+    app: AppHandle,
+    limit: Option<usize>,
+    level: Option<String>
+) -> Result<Vec<LogLine>, AppError> {
+    let min_level = level
+        .map(|l| l.parse::<Level>().map_err(|_| AppError::invalid_input(format!("Invalid log level: {}", l))))
+        .transpose()?;
+    Ok(read_recent_logs(&app_data_dir(&app), limit.unwrap_or(DEFAULT_RECENT_LINES), min_level)?)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn get_log_settings(app: AppHandle) -> Result<LogSettings, AppError> {
+    Ok(load_log_settings(&app_data_dir(&app))?)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn set_log_settings(app: AppHandle, level: String) -> Result<LogSettings, AppError> {
+    set_log_level(&app_data_dir(&app), &level).map_err(AppError::invalid_input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_lines_are_parsed() {
+        let line = r#"{"timestamp":"2025-01-31T09:00:00.000000Z","level":"WARN","fields":{"message":"Failed to save blocked domains: disk full","domain":"ads.example.com"},"target":"security_smile_lib::firewall","span":{"name":"block_domain"}}"#;
+        let parsed = parse_log_line(line, "desktop").unwrap();
+        assert_eq!(parsed.level, "WARN");
+        assert_eq!(parsed.message, "Failed to save blocked domains: disk full");
+        assert_eq!(parsed.span.as_deref(), Some("block_domain"));
+        assert_eq!(parsed.fields.get("domain").and_then(|d| d.as_str()), Some("ads.example.com"));
+        assert!(parse_log_line("not json", "desktop").is_none());
+    }
+
+    #[test]
+    fn recent_logs_merge_processes_and_filter_levels() {
+        let dir = std::env::temp_dir().join(format!("security-smile-logs-{}", std::process::id()));
+        let logs = log_dir(&dir);
+        fs::create_dir_all(&logs).unwrap();
+        let line = |time: &str, level: &str, message: &str| format!(
+            r#"{{"timestamp":"2025-01-31T09:00:0{}Z","level":"{}","fields":{{"message":"{}"}},"target":"t"}}"#,
+            time, level, message
+        );
+        fs::write(logs.join("desktop.2025-01-30.log"), line("0", "INFO", "old")).unwrap();
+        fs::write(logs.join("desktop.2025-01-31.log"), [line("1", "INFO", "started"), line("4", "ERROR", "broke")].join("\n")).unwrap();
+        fs::write(logs.join("daemon.2025-01-31.log"), [line("2", "DEBUG", "tick"), line("3", "WARN", "slow")].join("\n")).unwrap();
+
+        let recent = read_recent_logs(&dir, 3, None).unwrap();
+        let messages: Vec<&str> = recent.iter().map(|l| l.message.as_str()).collect();
+        assert_eq!(messages, vec!["tick", "slow", "broke"]);
+        assert_eq!(recent[0].process, "daemon");
+
+        let warnings = read_recent_logs(&dir, 10, Some(Level::WARN)).unwrap();
+        let messages: Vec<&str> = warnings.iter().map(|l| l.message.as_str()).collect();
+        assert_eq!(messages, vec!["slow", "broke"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
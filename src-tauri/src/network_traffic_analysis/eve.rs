@@ -0,0 +1,328 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+// One eve.json record. The fields every event type shares sit at the top level, as in eve.json;
+// the type-specific part is under the key named after the type ("alert", "dns", ...).
+// Unknown fields are ignored, and missing or mistyped ones are left empty, so newer Suricata versions still parse.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EveEvent {
+    pub timestamp: String,
+    pub event_type: String,
+    // Shared by every event Suricata logs for the same flow in this run.
+    pub flow_id: Option<u64>,
+    // Hash of the 5-tuple; matches across restarts and other tools (Zeek) when enabled.
+    pub community_id: Option<String>,
+    pub in_iface: Option<String>,
+    pub src_ip: Option<String>,
+    pub src_port: Option<u16>,
+    pub dest_ip: Option<String>,
+    pub dest_port: Option<u16>,
+    pub proto: Option<String>,
+    pub app_proto: Option<String>,
+    #[serde(flatten)]
+    pub payload: EvePayload,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvePayload {
+    Alert(AlertInfo),
+    Flow(FlowInfo),
+    Dns(DnsInfo),
+    Http(HttpInfo),
+    Tls(TlsInfo),
+    Fileinfo(FileInfo),
+    Anomaly(AnomalyInfo),
+    Stats(StatsInfo),
+    // Event types without a model yet, or whose body isn't an object; the name is in `event_type`.
+    Other,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertInfo {
+    pub action: Option<String>,
+    pub gid: Option<u64>,
+    pub signature_id: Option<u64>,
+    pub rev: Option<u64>,
+    pub signature: Option<String>,
+    pub category: Option<String>,
+    pub severity: Option<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlowInfo {
+    pub pkts_toserver: u64,
+    pub pkts_toclient: u64,
+    pub bytes_toserver: u64,
+    pub bytes_toclient: u64,
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub age: Option<u64>,
+    pub state: Option<String>,
+    pub reason: Option<String>,
+    pub alerted: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsInfo {
+    // "query" or "answer".
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub id: Option<u64>,
+    pub rrname: Option<String>,
+    pub rrtype: Option<String>,
+    pub rcode: Option<String>,
+    pub answers: Vec<DnsAnswer>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsAnswer {
+    pub rrname: Option<String>,
+    pub rrtype: Option<String>,
+    pub ttl: Option<u64>,
+    pub rdata: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpInfo {
+    pub hostname: Option<String>,
+    pub url: Option<String>,
+    pub http_method: Option<String>,
+    pub http_user_agent: Option<String>,
+    pub http_content_type: Option<String>,
+    pub http_refer: Option<String>,
+    pub protocol: Option<String>,
+    pub status: Option<u16>,
+    pub length: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsInfo {
+    pub sni: Option<String>,
+    pub version: Option<String>,
+    pub subject: Option<String>,
+    pub issuerdn: Option<String>,
+    pub serial: Option<String>,
+    pub fingerprint: Option<String>,
+    pub notbefore: Option<String>,
+    pub notafter: Option<String>,
+    pub ja3: Option<JaHash>,
+    pub ja3s: Option<JaHash>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JaHash {
+    pub hash: Option<String>,
+    pub string: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileInfo {
+    pub filename: Option<String>,
+    pub magic: Option<String>,
+    pub state: Option<String>,
+    pub md5: Option<String>,
+    pub sha1: Option<String>,
+    pub sha256: Option<String>,
+    pub size: Option<u64>,
+    pub stored: bool,
+    pub gaps: bool,
+    pub tx_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnomalyInfo {
+    // "decode", "stream" or "applayer".
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub event: Option<String>,
+    pub layer: Option<String>,
+    pub code: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatsInfo {
+    pub uptime: u64,
+    pub capture: CaptureStats,
+    pub decoder: DecoderStats,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureStats {
+    pub kernel_packets: u64,
+    pub kernel_drops: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DecoderStats {
+    pub pkts: u64,
+    pub bytes: u64,
+}
+
+// Parses one eve.json line. Returns None for lines that aren't JSON objects with an event_type.
+pub fn parse_eve_line(line: &str) -> Option<EveEvent> {
+    let json: Value = serde_json::from_str(line).ok()?;
+    let event_type = json.get("event_type")?.as_str()?.to_string();
+    let payload = parse_payload(&event_type, &json).unwrap_or(EvePayload::Other);
+
+    let text = |key: &str| json.get(key).and_then(|v| v.as_str()).map(|s| s.to_string());
+    let port = |key: &str| json.get(key).and_then(|v| v.as_u64()).and_then(|n| u16::try_from(n).ok());
+    Some(EveEvent {
+        timestamp: text("timestamp").unwrap_or_default(),
+        flow_id: json.get("flow_id").and_then(|v| v.as_u64()),
+        community_id: text("community_id"),
+        in_iface: text("in_iface"),
+        src_ip: text("src_ip"),
+        src_port: port("src_port"),
+        dest_ip: text("dest_ip"),
+        dest_port: port("dest_port"),
+        proto: text("proto"),
+        app_proto: text("app_proto"),
+        event_type,
+        payload,
+    })
+}
+
+fn parse_payload(event_type: &str, json: &Value) -> Option<EvePayload> {
+    fn body<T: DeserializeOwned>(json: &Value, key: &str) -> Option<T> {
+        let body = json.get(key)?;
+        if let Ok(parsed) = T::deserialize(body) {
+            return Some(parsed);
+        }
+        // One field of an unexpected type shouldn't cost the rest of the body: keep the fields that
+        // parse on their own and leave the others empty.
+        let fields = body.as_object()?;
+        let valid: Map<String, Value> = fields
+            .iter()
+            .filter(|(name, value)| {
+                let single = Map::from_iter([((*name).clone(), (*value).clone())]);
+                T::deserialize(&Value::Object(single)).is_ok()
+            })
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        T::deserialize(&Value::Object(valid)).ok()
+    }
+
+    Some(match event_type {
+        "alert" => EvePayload::Alert(body(json, "alert")?),
+        "flow" => EvePayload::Flow(body(json, "flow")?),
+        "dns" => EvePayload::Dns(body(json, "dns")?),
+        "http" => EvePayload::Http(body(json, "http")?),
+        "tls" => EvePayload::Tls(body(json, "tls")?),
+        "fileinfo" => EvePayload::Fileinfo(body(json, "fileinfo")?),
+        "anomaly" => EvePayload::Anomaly(body(json, "anomaly")?),
+        "stats" => EvePayload::Stats(body(json, "stats")?),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("fixtures/eve_sample.json");
+
+    fn fixture_events() -> Vec<EveEvent> {
+        FIXTURE.lines().filter_map(parse_eve_line).collect()
+    }
+
+    fn find<'a>(events: &'a [EveEvent], event_type: &str) -> &'a EveEvent {
+        events.iter().find(|e| e.event_type == event_type).unwrap()
+    }
+
+    #[test]
+    fn parses_every_event_type_in_the_fixture() {
+        let events = fixture_events();
+        let types: Vec<&str> = events.iter().map(|e| e.event_type.as_str()).collect();
+        assert_eq!(types, vec!["alert", "flow", "dns", "http", "tls", "fileinfo", "anomaly", "stats", "smb"]);
+
+        let EvePayload::Alert(alert) = &find(&events, "alert").payload else { panic!("not an alert") };
+        assert_eq!(alert.signature_id, Some(2013028));
+        assert_eq!(alert.severity, Some(2));
+
+        let EvePayload::Dns(dns) = &find(&events, "dns").payload else { panic!("not dns") };
+        assert_eq!(dns.kind.as_deref(), Some("answer"));
+        assert_eq!(dns.answers[0].rdata.as_deref(), Some("93.184.216.34"));
+
+        let EvePayload::Tls(tls) = &find(&events, "tls").payload else { panic!("not tls") };
+        assert_eq!(tls.sni.as_deref(), Some("example.com"));
+        assert_eq!(tls.ja3.as_ref().and_then(|j| j.hash.as_deref()), Some("e7d705a3286e19ea42f587b344ee6865"));
+
+        let EvePayload::Stats(stats) = &find(&events, "stats").payload else { panic!("not stats") };
+        assert_eq!(stats.capture.kernel_drops, 12);
+        assert_eq!(find(&events, "stats").src_ip, None);
+
+        // Known to Suricata but not modelled here: kept, with its common fields.
+        let smb = find(&events, "smb");
+        assert_eq!(smb.payload, EvePayload::Other);
+        assert_eq!(smb.dest_port, Some(445));
+    }
+
+    #[test]
+    fn events_of_one_connection_share_flow_and_community_ids() {
+        let events = fixture_events();
+        let alert = find(&events, "alert");
+        let flow = find(&events, "flow");
+        assert_eq!(alert.flow_id, flow.flow_id);
+        assert_eq!(alert.community_id, flow.community_id);
+        assert_eq!(find(&events, "http").flow_id, alert.flow_id);
+        assert_ne!(find(&events, "dns").flow_id, alert.flow_id);
+    }
+
+    #[test]
+    fn tolerates_unknown_and_mistyped_fields() {
+        let event = parse_eve_line(
+            r#"{"timestamp":"t","event_type":"http","future_field":[1,2],"http":{"hostname":"a.example","status":"teapot"}}"#
+        ).unwrap();
+        assert_eq!(event.event_type, "http");
+        let EvePayload::Http(http) = event.payload else { panic!("not http") };
+        assert_eq!(http.hostname.as_deref(), Some("a.example"));
+        assert_eq!(http.status, None);
+
+        let event = parse_eve_line(
+            r#"{"event_type":"alert","alert":{"signature":"x","signature_id":2000001,"severity":"high"}}"#,
+        )
+        .unwrap();
+        let EvePayload::Alert(alert) = event.payload else { panic!("not an alert") };
+        assert_eq!(alert.signature_id, Some(2000001));
+        assert_eq!(alert.severity, None);
+
+        let event = parse_eve_line(r#"{"event_type":"flow","flow":{"pkts_toserver":3,"alerted":"yes"}}"#).unwrap();
+        let EvePayload::Flow(flow) = event.payload else { panic!("not a flow") };
+        assert_eq!(flow.pkts_toserver, 3);
+        assert!(!flow.alerted);
+
+        let event = parse_eve_line(r#"{"event_type":"alert","alert":"garbled"}"#).unwrap();
+        assert_eq!(event.payload, EvePayload::Other);
+
+        let event = parse_eve_line(r#"{"event_type":"alert","alert":{"signature":"x","new_thing":true}}"#).unwrap();
+        let EvePayload::Alert(alert) = event.payload else { panic!("not an alert") };
+        assert_eq!(alert.signature.as_deref(), Some("x"));
+        assert_eq!(alert.severity, None);
+
+        assert!(parse_eve_line("not json").is_none());
+        assert!(parse_eve_line(r#"{"timestamp":"t"}"#).is_none());
+    }
+
+    #[test]
+    fn serializes_back_in_the_eve_layout() {
+        let events = fixture_events();
+        let json = serde_json::to_value(find(&events, "alert")).unwrap();
+        assert_eq!(json["event_type"], "alert");
+        assert_eq!(json["alert"]["signature"], "ET POLICY Example signature");
+        assert_eq!(json["flow_id"], 1234567890123456u64);
+    }
+}
@@ -0,0 +1,215 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tracing::{debug, warn};
+use crate::error::{AppError, ErrorKind};
+use crate::network_traffic_analysis::store::{AlertQuery, AlertSort, EventStore, EventStoreState};
+use crate::network_traffic_analysis::suricata::{AlertEvent, FlowEvent};
+use crate::network_traffic_analysis::tailer::EveTailerState;
+
+pub const ALERTS_EVENT: &str = "suricata-alerts";
+pub const FLOWS_EVENT: &str = "suricata-flows";
+const PUSH_INTERVAL_MILLIS: u64 = 1000;
+// Everything stored between two pushes goes out as one event. In a burst only the newest this
+// many are sent and the rest are counted, so the UI can page through them with query_alert_events.
+const MAX_PUSH_SIZE: usize = 200;
+
+// What the UI wants pushed. Alerts always are, flows only when asked for.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LiveEventFilter {
+    // Suricata's 1 is the most severe, so 2 pushes severities 1 and 2.
+    #[serde(default)]
+    pub min_severity: Option<u8>,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub include_flows: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertBatch {
+    // Oldest first.
+    pub alerts: Vec<AlertEvent>,
+    // Matching alerts stored since the last push that were left out of this one.
+    pub skipped: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowBatch {
+    pub flows: Vec<FlowEvent>,
+    pub skipped: u64,
+}
+
+// A subscription and how far into the event store it has been pushed.
+#[derive(Debug, Clone)]
+struct LiveSubscription {
+    filter: LiveEventFilter,
+    last_alert_id: i64,
+    last_flow_id: i64,
+}
+
+#[derive(Default)]
+pub struct LiveEventState {
+    subscription: Mutex<Option<LiveSubscription>>,
+}
+
+// Starts pushing alerts stored from now on that match `filter`, replacing any earlier subscription.
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub fn subscribe_live_events(
+    filter: LiveEventFilter,
+    state: State<'_, LiveEventState>,
+    events: State<'_, EventStoreState>
+) -> Result<(), AppError> {
+    *state.subscription.lock().unwrap() = Some(LiveSubscription {
+        filter,
+        last_alert_id: events.store.last_alert_id()?.unwrap_or(0),
+        last_flow_id: events.store.last_flow_id()?.unwrap_or(0),
+    });
+    Ok(())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub fn unsubscribe_live_events(state: State<'_, LiveEventState>) -> Result<(), AppError> {
+    *state.subscription.lock().unwrap() = None;
+    Ok(())
+}
+
+// Keeps reading eve.json into the event store and pushes what's new to the subscribed UI. The
+// background service may be the one reading eve.json; the store has its events either way.
+pub fn start_live_events(app: AppHandle) {
+    thread::spawn(move || loop {
+        let ingested = app.state::<EveTailerState>().tailer.lock().unwrap().poll();
+        if let Err(e) = ingested {
+            if e.kind != ErrorKind::SuricataNotRunning {
+                debug!("Failed to read eve.json: {}", e);
+            }
+        }
+        if let Err(e) = push_live_events(&app) {
+            warn!("Failed to push live Suricata events: {}", e);
+        }
+        thread::sleep(Duration::from_millis(PUSH_INTERVAL_MILLIS));
+    });
+}
+
+fn push_live_events(app: &AppHandle) -> Result<(), AppError> {
+    let state = app.state::<LiveEventState>();
+    let mut subscription = state.subscription.lock().unwrap();
+    let Some(subscription) = subscription.as_mut() else {
+        return Ok(());
+    };
+
+    let (alerts, flows) = next_batches(&app.state::<EventStoreState>().store, subscription)?;
+    if let Some(alerts) = alerts {
+        app.emit(ALERTS_EVENT, alerts).map_err(|e| format!("Failed to emit alerts: {}", e))?;
+    }
+    if let Some(flows) = flows {
+        app.emit(FLOWS_EVENT, flows).map_err(|e| format!("Failed to emit flows: {}", e))?;
+    }
+    Ok(())
+}
+
+// What to push for everything stored since the last push, moving the subscription past it.
+fn next_batches(
+    store: &EventStore,
+    subscription: &mut LiveSubscription
+) -> Result<(Option<AlertBatch>, Option<FlowBatch>), AppError> {
+    let page = store.query_alerts(&AlertQuery {
+        min_severity: subscription.filter.min_severity,
+        category: subscription.filter.category.clone(),
+        after_id: Some(subscription.last_alert_id),
+        sort: AlertSort::Newest,
+        limit: Some(MAX_PUSH_SIZE),
+        ..Default::default()
+    })?;
+    let alerts = if page.alerts.is_empty() {
+        None
+    } else {
+        subscription.last_alert_id = page.alerts.iter().filter_map(|a| a.id).max().unwrap_or(subscription.last_alert_id);
+        let skipped = page.total - page.alerts.len() as u64;
+        let mut alerts = page.alerts;
+        alerts.reverse();
+        Some(AlertBatch { alerts, skipped })
+    };
+
+    let flows = if subscription.filter.include_flows {
+        let page = store.flows_after(subscription.last_flow_id, MAX_PUSH_SIZE)?;
+        page.flows.last().and_then(|f| f.id).map(|last_id| {
+            subscription.last_flow_id = last_id;
+            FlowBatch { skipped: page.total - page.flows.len() as u64, flows: page.flows }
+        })
+    } else {
+        None
+    };
+    Ok((alerts, flows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_traffic_analysis::eve::{parse_eve_line, EveEvent};
+
+    fn alert(second: usize, category: &str, severity: u8) -> EveEvent {
+        let timestamp = chrono::Utc::now().format("%Y-%m-%dT%H:%M:00.000000+0000").to_string();
+        let raw = format!(
+            r#"{{"timestamp":"{}","event_type":"alert","src_ip":"10.0.0.5","dest_ip":"203.0.113.9","alert":{{"signature":"Alert {}","category":"{}","severity":{}}}}}"#,
+            timestamp.replace(":00.", &format!(":{:02}.", second % 60)), second, category, severity
+        );
+        parse_eve_line(&raw).unwrap()
+    }
+
+    fn signatures(batch: &AlertBatch) -> Vec<String> {
+        batch.alerts.iter().map(|a| a.signature.clone().unwrap_or_default()).collect()
+    }
+
+    #[test]
+    fn pushes_only_new_matching_alerts() {
+        let dir = std::env::temp_dir().join(format!("security-smile-live-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = EventStore::in_dir(&dir);
+        store.insert(&[alert(0, "Scan", 1)]).unwrap();
+
+        let filter = LiveEventFilter { min_severity: Some(2), category: Some("Scan".into()), include_flows: false };
+        let mut subscription = LiveSubscription { filter, last_alert_id: store.last_alert_id().unwrap().unwrap(), last_flow_id: 0 };
+        assert!(next_batches(&store, &mut subscription).unwrap().0.is_none());
+
+        store.insert(&[alert(1, "Scan", 1), alert(2, "Scan", 3), alert(3, "Policy", 1), alert(4, "Scan", 2)]).unwrap();
+        let batch = next_batches(&store, &mut subscription).unwrap().0.unwrap();
+        assert_eq!(signatures(&batch), vec!["Alert 1", "Alert 4"]);
+        assert_eq!(batch.skipped, 0);
+        assert!(next_batches(&store, &mut subscription).unwrap().0.is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn coalesces_bursts() {
+        let dir = std::env::temp_dir().join(format!("security-smile-live-burst-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = EventStore::in_dir(&dir);
+        let flow = parse_eve_line(&format!(
+            r#"{{"timestamp":"{}","event_type":"flow","proto":"TCP","flow":{{"bytes_toserver":1}}}}"#,
+            chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.6f+0000")
+        )).unwrap();
+        let mut subscription = LiveSubscription {
+            filter: LiveEventFilter { include_flows: true, ..Default::default() },
+            last_alert_id: 0,
+            last_flow_id: 0,
+        };
+
+        let burst: Vec<EveEvent> = (0..MAX_PUSH_SIZE + 50).map(|i| alert(i / 10, "Scan", 2)).chain([flow]).collect();
+        store.insert(&burst).unwrap();
+        let (alerts, flows) = next_batches(&store, &mut subscription).unwrap();
+        let alerts = alerts.unwrap();
+        assert_eq!((alerts.alerts.len(), alerts.skipped), (MAX_PUSH_SIZE, 50));
+        // The newest are kept, oldest first.
+        assert_eq!(alerts.alerts.last().unwrap().signature.as_deref(), Some("Alert 24"));
+        assert_eq!(flows.unwrap().flows.len(), 1);
+
+        let (alerts, flows) = next_batches(&store, &mut subscription).unwrap();
+        assert!(alerts.is_none() && flows.is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
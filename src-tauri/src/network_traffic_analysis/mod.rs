@@ -1,2 +1,7 @@
 pub mod suricata;
-pub mod report;
\ No newline at end of file
+pub mod report;
+pub mod eve;
+pub mod tailer;
+pub mod store;
+pub mod live;
+pub mod supervisor;
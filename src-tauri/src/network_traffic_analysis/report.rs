@@ -2,7 +2,9 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::net::Ipv6Addr;
 use serde::{Serialize, Deserialize};
-use crate::network_traffic_analysis::suricata::{read_flow_events};
+use tauri::State;
+use crate::network_traffic_analysis::store::{EventStore, EventStoreState};
+use crate::error::{AppError, ErrorKind};
 
 #[derive(Serialize, Deserialize)]
 struct TopEntry {
@@ -23,9 +25,16 @@ pub struct FlowReport {
 }
 
 #[tauri::command]
-pub fn generate_flow_report() -> Result<(), String> {
-    let flow_events = read_flow_events()?;    if flow_events.is_empty() {
-        return Err("No flow events found.".to_string());
+#[tracing::instrument(skip_all, err)]
+pub fn generate_flow_report(events: State<'_, EventStoreState>) -> Result<(), AppError> {
+    write_flow_report(&events.store)
+}
+
+// Summarises every stored flow into report.json.
+pub fn write_flow_report(store: &EventStore) -> Result<(), AppError> {
+    let flow_events = store.flows()?;
+    if flow_events.is_empty() {
+        return Err(AppError::not_found("No flow events found."));
     }
 
     let mut src_ip_freq: HashMap<String, u64> = HashMap::new();
@@ -118,15 +127,16 @@ pub fn generate_flow_report() -> Result<(), String> {
 }
 
 #[tauri::command]
-pub fn read_flow_report() -> Result<FlowReport, String> {
+#[tracing::instrument(skip_all, err)]
+pub fn read_flow_report() -> Result<FlowReport, AppError> {
     let mut log_dir = std::env::temp_dir();
     log_dir.push("suricata_logs");
     let mut report_path = log_dir.clone();
     report_path.push("report.json");
 
     let file = File::open(&report_path)
-        .map_err(|e| format!("Failed to open report.json: {}", e))?;
+        .map_err(|e| AppError::from(e).context("Failed to open report.json"))?;
     serde_json::from_reader(file)
-        .map_err(|e| format!("Failed to parse report.json: {}", e))
+        .map_err(|e| AppError::new(ErrorKind::ParseError, format!("Failed to parse report.json: {}", e)))
 }
 
@@ -0,0 +1,591 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use rusqlite::{params, params_from_iter, Connection, ToSql};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use crate::error::AppError;
+use crate::network_traffic_analysis::eve::{EveEvent, EvePayload};
+use crate::app_data::{create_shared_dir, events_dir, share_with_group};
+use crate::network_traffic_analysis::suricata::{AlertEvent, FlowEvent};
+
+const EVENT_DB_FILE: &str = "events.db";
+const DEFAULT_QUERY_LIMIT: usize = 100;
+const MAX_QUERY_LIMIT: usize = 1000;
+// Alerts and flows older than this are deleted, at most once an hour.
+const MAX_EVENT_AGE_DAYS: i64 = 30;
+const PRUNE_INTERVAL_SECONDS: u64 = 3600;
+
+// Addresses are also stored as 16 bytes (IPv4 mapped into IPv6) so a CIDR block is a range scan.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS alerts (
+    id INTEGER PRIMARY KEY,
+    ts INTEGER NOT NULL,
+    timestamp TEXT NOT NULL,
+    flow_id INTEGER,
+    community_id TEXT,
+    src_ip TEXT,
+    src_addr BLOB,
+    src_port INTEGER,
+    dest_ip TEXT,
+    dest_addr BLOB,
+    dest_port INTEGER,
+    proto TEXT,
+    signature_id INTEGER,
+    signature TEXT,
+    category TEXT,
+    severity INTEGER
+);
+CREATE INDEX IF NOT EXISTS alerts_ts ON alerts(ts);
+CREATE INDEX IF NOT EXISTS alerts_src_addr ON alerts(src_addr);
+CREATE INDEX IF NOT EXISTS alerts_dest_addr ON alerts(dest_addr);
+CREATE INDEX IF NOT EXISTS alerts_src_port ON alerts(src_port);
+CREATE INDEX IF NOT EXISTS alerts_dest_port ON alerts(dest_port);
+CREATE INDEX IF NOT EXISTS alerts_signature ON alerts(signature);
+
+CREATE TABLE IF NOT EXISTS flows (
+    id INTEGER PRIMARY KEY,
+    ts INTEGER NOT NULL,
+    flow_id INTEGER,
+    community_id TEXT,
+    src_ip TEXT,
+    src_addr BLOB,
+    src_port INTEGER,
+    dest_ip TEXT,
+    dest_addr BLOB,
+    dest_port INTEGER,
+    proto TEXT,
+    bytes_toserver INTEGER NOT NULL,
+    bytes_toclient INTEGER NOT NULL,
+    pkts_toserver INTEGER NOT NULL,
+    pkts_toclient INTEGER NOT NULL,
+    start TEXT,
+    end TEXT
+);
+CREATE INDEX IF NOT EXISTS flows_ts ON flows(ts);
+CREATE INDEX IF NOT EXISTS flows_src_addr ON flows(src_addr);
+CREATE INDEX IF NOT EXISTS flows_dest_addr ON flows(dest_addr);
+CREATE INDEX IF NOT EXISTS flows_dest_port ON flows(dest_port);
+";
+
+const ALERT_COLUMNS: &str =
+    "id, timestamp, src_ip, dest_ip, src_port, dest_port, signature, category, severity, flow_id, community_id";
+const FLOW_COLUMNS: &str = "id, src_ip, dest_ip, src_port, dest_port, proto, bytes_toserver, bytes_toclient,
+    pkts_toserver, pkts_toclient, start, end, flow_id, community_id";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertSort {
+    #[default]
+    Newest,
+    Oldest,
+    // Most severe first, newest first within a severity.
+    Severity,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AlertQuery {
+    #[serde(default)]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+    // Suricata's 1 is the most severe, so 2 keeps severities 1 and 2.
+    #[serde(default)]
+    pub min_severity: Option<u8>,
+    // An address or CIDR block, matched against either end of the connection.
+    #[serde(default)]
+    pub ip: Option<String>,
+    // Matched against either end of the connection.
+    #[serde(default)]
+    pub port: Option<u16>,
+    // Matches signatures containing this text, ignoring case.
+    #[serde(default)]
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub category: Option<String>,
+    // Only alerts stored after this id, for picking up new alerts.
+    #[serde(default)]
+    pub after_id: Option<i64>,
+    #[serde(default)]
+    pub sort: AlertSort,
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertPage {
+    // Matching alerts before offset and limit were applied.
+    pub total: u64,
+    pub alerts: Vec<AlertEvent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowPage {
+    pub total: u64,
+    pub flows: Vec<FlowEvent>,
+}
+
+// Alerts and flows from eve.json in SQLite, next to the tailer's cursor so every process that
+// ingests events writes to the same database.
+pub struct EventStore {
+    path: PathBuf,
+    conn: Mutex<Option<Connection>>,
+    last_pruned_at: Mutex<u64>,
+}
+
+impl EventStore {
+    // The database is opened on first use.
+    pub fn new(path: PathBuf) -> Self {
+        EventStore {
+            path,
+            conn: Mutex::new(None),
+            last_pruned_at: Mutex::new(0),
+        }
+    }
+
+    pub fn in_dir(dir: &Path) -> Self {
+        EventStore::new(dir.join(EVENT_DB_FILE))
+    }
+
+    fn with_conn<T>(&self, f: impl FnOnce(&mut Connection) -> rusqlite::Result<T>) -> Result<T, AppError> {
+        let mut conn = self.conn.lock().unwrap();
+        if conn.is_none() {
+            *conn = Some(open_connection(&self.path)?);
+        }
+        Ok(f(conn.as_mut().unwrap())?)
+    }
+
+    // Stores the alerts and flows among `events` and returns how many that was.
+    pub fn insert(&self, events: &[EveEvent]) -> Result<usize, AppError> {
+        let inserted = self.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            let mut inserted = 0;
+            {
+                let mut insert_alert = tx.prepare_cached(
+                    "INSERT INTO alerts (ts, timestamp, flow_id, community_id, src_ip, src_addr, src_port,
+                        dest_ip, dest_addr, dest_port, proto, signature_id, signature, category, severity)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)"
+                )?;
+                let mut insert_flow = tx.prepare_cached(
+                    "INSERT INTO flows (ts, flow_id, community_id, src_ip, src_addr, src_port, dest_ip, dest_addr,
+                        dest_port, proto, bytes_toserver, bytes_toclient, pkts_toserver, pkts_toclient, start, end)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)"
+                )?;
+                for event in events {
+                    let ts = event_millis(&event.timestamp);
+                    match &event.payload {
+                        EvePayload::Alert(alert) => {
+                            insert_alert.execute(params![
+                                ts,
+                                event.timestamp,
+                                event.flow_id.map(|id| id as i64),
+                                event.community_id,
+                                event.src_ip,
+                                address_key(event.src_ip.as_deref()),
+                                event.src_port,
+                                event.dest_ip,
+                                address_key(event.dest_ip.as_deref()),
+                                event.dest_port,
+                                event.proto,
+                                alert.signature_id.map(|id| id as i64),
+                                alert.signature,
+                                alert.category,
+                                alert.severity,
+                            ])?;
+                        },
+                        EvePayload::Flow(flow) => {
+                            insert_flow.execute(params![
+                                ts,
+                                event.flow_id.map(|id| id as i64),
+                                event.community_id,
+                                event.src_ip,
+                                address_key(event.src_ip.as_deref()),
+                                event.src_port,
+                                event.dest_ip,
+                                address_key(event.dest_ip.as_deref()),
+                                event.dest_port,
+                                event.proto,
+                                flow.bytes_toserver as i64,
+                                flow.bytes_toclient as i64,
+                                flow.pkts_toserver as i64,
+                                flow.pkts_toclient as i64,
+                                flow.start,
+                                flow.end,
+                            ])?;
+                        },
+                        _ => continue,
+                    }
+                    inserted += 1;
+                }
+            }
+            tx.commit()?;
+            Ok(inserted)
+        })?;
+        self.prune_if_due()?;
+        Ok(inserted)
+    }
+
+    pub fn query_alerts(&self, query: &AlertQuery) -> Result<AlertPage, AppError> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<Box<dyn ToSql>> = Vec::new();
+
+        if let Some(since) = query.since {
+            conditions.push("ts >= ?");
+            values.push(Box::new(since.timestamp_millis()));
+        }
+        if let Some(until) = query.until {
+            conditions.push("ts <= ?");
+            values.push(Box::new(until.timestamp_millis()));
+        }
+        if let Some(severity) = query.min_severity {
+            conditions.push("severity <= ?");
+            values.push(Box::new(severity));
+        }
+        if let Some(ip) = &query.ip {
+            let (first, last) = address_range(ip)?;
+            conditions.push("((src_addr BETWEEN ? AND ?) OR (dest_addr BETWEEN ? AND ?))");
+            for _ in 0..2 {
+                values.push(Box::new(first.to_vec()));
+                values.push(Box::new(last.to_vec()));
+            }
+        }
+        if let Some(port) = query.port {
+            conditions.push("(src_port = ? OR dest_port = ?)");
+            values.push(Box::new(port));
+            values.push(Box::new(port));
+        }
+        if let Some(signature) = &query.signature {
+            conditions.push("signature LIKE ? ESCAPE '\\'");
+            values.push(Box::new(format!("%{}%", escape_like(signature))));
+        }
+        if let Some(category) = &query.category {
+            conditions.push("category = ?");
+            values.push(Box::new(category.clone()));
+        }
+        if let Some(after_id) = query.after_id {
+            conditions.push("id > ?");
+            values.push(Box::new(after_id));
+        }
+
+        let filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", conditions.join(" AND "))
+        };
+        let order = match query.sort {
+            AlertSort::Newest => "ts DESC, id DESC",
+            AlertSort::Oldest => "ts ASC, id ASC",
+            AlertSort::Severity => "severity ASC NULLS LAST, ts DESC, id DESC",
+        };
+        let limit = query.limit.unwrap_or(DEFAULT_QUERY_LIMIT);
+
+        self.with_conn(|conn| {
+            let total: u64 = conn.query_row(
+                &format!("SELECT COUNT(*) FROM alerts{}", filter),
+                params_from_iter(values.iter()),
+                |row| row.get(0)
+            )?;
+
+            values.push(Box::new(limit as i64));
+            values.push(Box::new(query.offset as i64));
+            let mut statement = conn.prepare(&format!(
+                "SELECT {} FROM alerts{} ORDER BY {} LIMIT ? OFFSET ?",
+                ALERT_COLUMNS, filter, order
+            ))?;
+            let alerts = statement
+                .query_map(params_from_iter(values.iter()), alert_from_row)?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(AlertPage { total, alerts })
+        })
+    }
+
+    // The latest `limit` alerts, oldest first.
+    pub fn recent_alerts(&self, limit: usize) -> Result<Vec<AlertEvent>, AppError> {
+        let mut alerts = self.query_alerts(&AlertQuery { limit: Some(limit), ..Default::default() })?.alerts;
+        alerts.reverse();
+        Ok(alerts)
+    }
+
+    pub fn flows(&self) -> Result<Vec<FlowEvent>, AppError> {
+        self.with_conn(|conn| {
+            let mut statement = conn.prepare(&format!("SELECT {} FROM flows ORDER BY ts ASC, id ASC", FLOW_COLUMNS))?;
+            let flows = statement.query_map([], flow_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(flows)
+        })
+    }
+
+    // The latest `limit` flows stored after `after_id`, oldest first, and how many there were in all.
+    pub fn flows_after(&self, after_id: i64, limit: usize) -> Result<FlowPage, AppError> {
+        self.with_conn(|conn| {
+            let total: u64 = conn.query_row("SELECT COUNT(*) FROM flows WHERE id > ?1", [after_id], |row| row.get(0))?;
+            let mut statement = conn.prepare(&format!(
+                "SELECT {} FROM flows WHERE id > ?1 ORDER BY id DESC LIMIT ?2",
+                FLOW_COLUMNS
+            ))?;
+            let mut flows = statement
+                .query_map(params![after_id, limit as i64], flow_from_row)?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            flows.reverse();
+            Ok(FlowPage { total, flows })
+        })
+    }
+
+    pub fn last_alert_id(&self) -> Result<Option<i64>, AppError> {
+        self.with_conn(|conn| {
+            conn.query_row("SELECT MAX(id) FROM alerts", [], |row| row.get(0))
+        })
+    }
+
+    pub fn last_flow_id(&self) -> Result<Option<i64>, AppError> {
+        self.with_conn(|conn| {
+            conn.query_row("SELECT MAX(id) FROM flows", [], |row| row.get(0))
+        })
+    }
+
+    fn prune_if_due(&self) -> Result<(), AppError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        {
+            let mut last_pruned_at = self.last_pruned_at.lock().unwrap();
+            if now - *last_pruned_at < PRUNE_INTERVAL_SECONDS {
+                return Ok(());
+            }
+            *last_pruned_at = now;
+        }
+        let cutoff = (now as i64 - MAX_EVENT_AGE_DAYS * 24 * 3600) * 1000;
+        self.with_conn(|conn| {
+            conn.execute("DELETE FROM alerts WHERE ts < ?1", [cutoff])?;
+            conn.execute("DELETE FROM flows WHERE ts < ?1", [cutoff])?;
+            Ok(())
+        })
+    }
+}
+
+pub struct EventStoreState {
+    pub store: Arc<EventStore>,
+}
+
+impl Default for EventStoreState {
+    fn default() -> Self {
+        EventStoreState {
+            store: Arc::new(EventStore::in_dir(&events_dir())),
+        }
+    }
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub fn query_alert_events(
+    query: AlertQuery,
+    state: State<'_, EventStoreState>
+) -> Result<AlertPage, AppError> {
+    let limit = query.limit.unwrap_or(DEFAULT_QUERY_LIMIT).min(MAX_QUERY_LIMIT);
+    state.store.query_alerts(&AlertQuery { limit: Some(limit), ..query })
+}
+
+fn open_connection(path: &Path) -> Result<Connection, AppError> {
+    if let Some(dir) = path.parent() {
+        create_shared_dir(dir)
+            .map_err(|e| AppError::from(e).context("Failed to create the event store directory"))?;
+    }
+    let conn = Connection::open(path)?;
+    // SQLite gives the -wal and -shm files the database file's mode and owner.
+    share_with_group(path, 0o660);
+    // The desktop app, CLI and daemon may all write to the database.
+    conn.busy_timeout(std::time::Duration::from_secs(5))?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+fn alert_from_row(row: &rusqlite::Row) -> rusqlite::Result<AlertEvent> {
+    Ok(AlertEvent {
+        id: row.get(0)?,
+        timestamp: row.get(1)?,
+        src_ip: row.get(2)?,
+        dest_ip: row.get(3)?,
+        src_port: row.get(4)?,
+        dest_port: row.get(5)?,
+        signature: row.get(6)?,
+        category: row.get(7)?,
+        severity: row.get(8)?,
+        flow_id: row.get::<_, Option<i64>>(9)?.map(|id| id as u64),
+        community_id: row.get(10)?,
+    })
+}
+
+fn flow_from_row(row: &rusqlite::Row) -> rusqlite::Result<FlowEvent> {
+    Ok(FlowEvent {
+        id: row.get(0)?,
+        sourceip: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+        destinationip: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+        sourceport: row.get::<_, Option<u16>>(3)?.unwrap_or(0),
+        destinationport: row.get::<_, Option<u16>>(4)?.unwrap_or(0),
+        protocol: row.get::<_, Option<String>>(5)?.unwrap_or_default(),
+        bytes_in: row.get::<_, i64>(6)? as u64,
+        bytes_out: row.get::<_, i64>(7)? as u64,
+        packets_in: row.get::<_, i64>(8)? as u32,
+        packets_out: row.get::<_, i64>(9)? as u32,
+        start_time: row.get::<_, Option<String>>(10)?.unwrap_or_default(),
+        end_time: row.get::<_, Option<String>>(11)?.unwrap_or_default(),
+        flow_id: row.get::<_, Option<i64>>(12)?.map(|id| id as u64),
+        community_id: row.get(13)?,
+    })
+}
+
+// Suricata writes e.g. 2025-01-31T10:15:02.114562+0000; events with an unreadable time are
+// filed under the time they were stored.
+fn event_millis(timestamp: &str) -> i64 {
+    DateTime::parse_from_str(timestamp, "%Y-%m-%dT%H:%M:%S%.f%z")
+        .map(|t| t.timestamp_millis())
+        .unwrap_or_else(|_| Utc::now().timestamp_millis())
+}
+
+fn address_key(ip: Option<&str>) -> Option<Vec<u8>> {
+    ip?.parse::<IpAddr>().ok().map(|ip| ip_bytes(ip).to_vec())
+}
+
+fn ip_bytes(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped().octets(),
+        IpAddr::V6(v6) => v6.octets(),
+    }
+}
+
+fn address_range(value: &str) -> Result<([u8; 16], [u8; 16]), AppError> {
+    let net = match value.parse::<IpNet>() {
+        Ok(net) => net,
+        Err(_) => value.parse::<IpAddr>()
+            .map(IpNet::from)
+            .map_err(|_| AppError::invalid_input(format!("Invalid address or CIDR block: {}", value)))?,
+    };
+    Ok((ip_bytes(net.network()), ip_bytes(net.broadcast())))
+}
+
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_traffic_analysis::eve::parse_eve_line;
+
+    // Events from the last hour, so they aren't pruned as soon as they're stored.
+    fn at(seconds: i64) -> DateTime<Utc> {
+        let start = Utc::now() - chrono::Duration::hours(1);
+        DateTime::from_timestamp(start.timestamp() + seconds, 0).unwrap()
+    }
+
+    fn eve_time(seconds: i64) -> String {
+        at(seconds).format("%Y-%m-%dT%H:%M:%S%.6f+0000").to_string()
+    }
+
+    fn alert(seconds: i64, src: &str, dest: &str, dest_port: u16, signature: &str, severity: u8) -> EveEvent {
+        let raw = format!(
+            r#"{{"timestamp":"{}","event_type":"alert","src_ip":"{}","src_port":51000,"dest_ip":"{}","dest_port":{},"proto":"TCP","alert":{{"signature_id":1,"signature":"{}","category":"Misc","severity":{}}}}}"#,
+            eve_time(seconds), src, dest, dest_port, signature, severity
+        );
+        parse_eve_line(&raw).unwrap()
+    }
+
+    fn store(name: &str) -> (EventStore, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("security-smile-store-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        (EventStore::in_dir(&dir), dir)
+    }
+
+    fn filled_store(name: &str) -> (EventStore, PathBuf) {
+        let (store, dir) = store(name);
+        let flow = format!(
+            r#"{{"timestamp":"{}","event_type":"flow","src_ip":"10.0.0.5","dest_ip":"1.1.1.1","proto":"UDP","flow":{{"bytes_toserver":80,"bytes_toclient":120,"pkts_toserver":1,"pkts_toclient":1}}}}"#,
+            eve_time(9)
+        );
+        let old = r#"{"timestamp":"2020-01-31T10:00:00.000000+0000","event_type":"alert","alert":{"signature":"Old"}}"#;
+        let events = vec![
+            alert(0, "10.0.0.5", "93.184.216.34", 443, "ET POLICY TLS to example", 2),
+            alert(5, "10.0.0.7", "203.0.113.9", 22, "ET SCAN SSH brute force", 1),
+            alert(10, "10.0.1.2", "2001:db8::1", 80, "ET INFO 100%_legit", 3),
+            parse_eve_line(&flow).unwrap(),
+            parse_eve_line(old).unwrap(),
+        ];
+        // The alert from 2020 is stored, then pruned as too old.
+        assert_eq!(store.insert(&events).unwrap(), 5);
+        (store, dir)
+    }
+
+    fn signatures(page: &AlertPage) -> Vec<String> {
+        page.alerts.iter().map(|a| a.signature.clone().unwrap_or_default()).collect()
+    }
+
+    #[test]
+    fn filters_by_time_severity_address_and_signature() {
+        let (store, dir) = filled_store("filters");
+        let query = |query: AlertQuery| store.query_alerts(&query).unwrap();
+
+        assert_eq!(query(AlertQuery::default()).total, 3);
+        assert_eq!(query(AlertQuery { since: Some(at(5)), ..Default::default() }).total, 2);
+        assert_eq!(query(AlertQuery { since: Some(at(1)), until: Some(at(6)), ..Default::default() }).total, 1);
+        assert_eq!(
+            signatures(&query(AlertQuery { min_severity: Some(2), ..Default::default() })),
+            vec!["ET SCAN SSH brute force", "ET POLICY TLS to example"]
+        );
+        assert_eq!(query(AlertQuery { ip: Some("10.0.0.0/24".into()), ..Default::default() }).total, 2);
+        assert_eq!(query(AlertQuery { ip: Some("203.0.113.9".into()), ..Default::default() }).total, 1);
+        assert_eq!(query(AlertQuery { ip: Some("2001:db8::/32".into()), ..Default::default() }).total, 1);
+        assert_eq!(query(AlertQuery { port: Some(22), ..Default::default() }).total, 1);
+        assert_eq!(
+            signatures(&query(AlertQuery { signature: Some("et scan".into()), ..Default::default() })),
+            vec!["ET SCAN SSH brute force"]
+        );
+        // LIKE wildcards in the search text are matched literally.
+        assert_eq!(query(AlertQuery { signature: Some("0%_".into()), ..Default::default() }).total, 1);
+        assert_eq!(query(AlertQuery { signature: Some("T_S".into()), ..Default::default() }).total, 0);
+
+        let err = store.query_alerts(&AlertQuery { ip: Some("10.0.0.0/33".into()), ..Default::default() }).unwrap_err();
+        assert!(err.message.contains("Invalid address"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sorts_and_pages() {
+        let (store, dir) = filled_store("pages");
+
+        let page = store.query_alerts(&AlertQuery { sort: AlertSort::Severity, limit: Some(2), ..Default::default() }).unwrap();
+        assert_eq!(page.total, 3);
+        assert_eq!(signatures(&page), vec!["ET SCAN SSH brute force", "ET POLICY TLS to example"]);
+
+        let page = store.query_alerts(&AlertQuery { sort: AlertSort::Oldest, offset: 2, ..Default::default() }).unwrap();
+        assert_eq!(signatures(&page), vec!["ET INFO 100%_legit"]);
+
+        let recent = store.recent_alerts(2).unwrap();
+        assert_eq!(recent[1].signature.as_deref(), Some("ET INFO 100%_legit"));
+        let first_id = recent[0].id.unwrap();
+        let newer = store.query_alerts(&AlertQuery { after_id: Some(first_id), ..Default::default() }).unwrap();
+        assert_eq!(newer.total, 1);
+        assert_eq!(store.last_alert_id().unwrap(), recent[1].id);
+
+        let flows = store.flows().unwrap();
+        assert_eq!(flows.len(), 1);
+        assert_eq!((flows[0].protocol.as_str(), flows[0].bytes_out), ("UDP", 120));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn database_and_wal_are_group_writable() {
+        use std::os::unix::fs::PermissionsExt;
+        let (store, dir) = filled_store("shared");
+        let mode = |name: &str| std::fs::metadata(dir.join(name)).unwrap().permissions().mode() & 0o7777;
+
+        assert_eq!(mode(EVENT_DB_FILE), 0o660);
+        assert_eq!(mode(&format!("{}-wal", EVENT_DB_FILE)), 0o660);
+        assert_eq!(std::fs::metadata(&dir).unwrap().permissions().mode() & 0o7777, 0o2770);
+        drop(store);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tracing::{debug, info, warn};
+use crate::error::{AppError, ErrorKind};
+use crate::network_traffic_analysis::suricata::{spawn_error, suricata_command, suricata_pids};
+
+pub const CHECK_INTERVAL_SECONDS: u64 = 2;
+// Restarts wait this long after a crash, doubling each time up to MAX_BACKOFF. A run that lasts
+// STABLE_AFTER starts the doubling over.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+// How long Suricata gets to exit after SIGTERM before it is killed.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_LOG_LINES: usize = 200;
+// How long to let the output readers catch up after Suricata exits, for its last words.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuricataState {
+    Stopped,
+    Running,
+    // Exited or failed to start, and waiting out the backoff before the next attempt.
+    Restarting,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuricataStatus {
+    pub state: SuricataState,
+    pub pid: Option<u32>,
+    pub started_at: Option<u64>,
+    pub uptime_seconds: Option<u64>,
+    pub restart_count: u32,
+    pub last_error: Option<String>,
+    pub last_exit_at: Option<u64>,
+    pub restart_in_seconds: Option<u64>,
+    // Suricata's own output, oldest first.
+    pub recent_log: Vec<String>,
+    // Suricata processes this supervisor didn't start.
+    pub other_pids: Vec<u32>,
+}
+
+#[derive(Default)]
+struct Supervised {
+    // Whether Suricata should be running, so a crash is restarted and a stop isn't.
+    wanted: bool,
+    child: Option<Child>,
+    readers: Vec<JoinHandle<()>>,
+    started: Option<(Instant, u64)>,
+    restart_count: u32,
+    last_error: Option<String>,
+    last_exit_at: Option<u64>,
+    backoff: Duration,
+    restart_at: Option<Instant>,
+}
+
+// Owns the Suricata process: starts it with its output captured, notices when it exits and
+// restarts it with backoff until it is stopped. `check` has to be called periodically.
+pub struct SuricataSupervisor {
+    command: fn() -> Result<Command, AppError>,
+    inner: Mutex<Supervised>,
+    log: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl Default for SuricataSupervisor {
+    fn default() -> Self {
+        SuricataSupervisor::with_command(suricata_command)
+    }
+}
+
+impl SuricataSupervisor {
+    fn with_command(command: fn() -> Result<Command, AppError>) -> Self {
+        SuricataSupervisor {
+            command,
+            inner: Mutex::new(Supervised { backoff: INITIAL_BACKOFF, ..Default::default() }),
+            log: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    // Starts Suricata and keeps it running. When it can't be started the error is returned and
+    // starting is retried with backoff, e.g. until the network interface comes up.
+    pub fn start(&self) -> Result<SuricataStatus, AppError> {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.child.is_none() {
+                if let Some(pid) = suricata_pids().first() {
+                    return Err(AppError::new(
+                        ErrorKind::Conflict,
+                        format!("Suricata is already running outside Security Smile (pid {})", pid)
+                    ));
+                }
+                inner.wanted = true;
+                inner.backoff = INITIAL_BACKOFF;
+                let now = Instant::now();
+                if let Err(e) = self.spawn(&mut inner, now) {
+                    inner.last_error = Some(e.message.clone());
+                    inner.restart_at = Some(now + inner.backoff);
+                    return Err(e);
+                }
+            }
+            inner.wanted = true;
+        }
+        Ok(self.status())
+    }
+
+    // Stops Suricata, waiting for it to exit, and cancels any pending restart.
+    pub fn stop(&self) -> Result<(), AppError> {
+        let mut inner = self.inner.lock().unwrap();
+        let was_wanted = std::mem::take(&mut inner.wanted);
+        inner.restart_at = None;
+        let Some(mut child) = inner.child.take() else {
+            if was_wanted {
+                return Ok(());
+            }
+            return Err(AppError::new(ErrorKind::SuricataNotRunning, "Suricata is not running"));
+        };
+        inner.started = None;
+        drop(inner);
+
+        info!("Stopping Suricata (pid {})", child.id());
+        terminate_child(&mut child);
+        Ok(())
+    }
+
+    pub fn check(&self) {
+        self.check_at(Instant::now());
+    }
+
+    fn check_at(&self, now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(child) = inner.child.as_mut() {
+            let exit = match child.try_wait() {
+                Ok(None) => {
+                    if inner.started.is_some_and(|(at, _)| now.duration_since(at) >= STABLE_AFTER) {
+                        inner.backoff = INITIAL_BACKOFF;
+                    }
+                    return;
+                },
+                Ok(Some(status)) => status.to_string(),
+                Err(e) => format!("unknown status ({})", e),
+            };
+            drain_output(std::mem::take(&mut inner.readers));
+
+            let last_line = self.log.lock().unwrap().back().cloned();
+            let error = match last_line {
+                Some(line) => format!("Suricata exited with {}: {}", exit, line),
+                None => format!("Suricata exited with {}", exit),
+            };
+            warn!("{}; restarting in {}s", error, inner.backoff.as_secs());
+            inner.last_error = Some(error);
+            inner.last_exit_at = Some(now_secs());
+            inner.child = None;
+            inner.started = None;
+            inner.restart_at = Some(now + inner.backoff);
+            return;
+        }
+
+        if !inner.wanted || inner.restart_at.is_none_or(|at| now < at) {
+            return;
+        }
+        inner.restart_count += 1;
+        inner.backoff = (inner.backoff * 2).min(MAX_BACKOFF);
+        if let Err(e) = self.spawn(&mut inner, now) {
+            warn!("Failed to restart Suricata: {}", e);
+            inner.last_error = Some(e.message);
+            inner.restart_at = Some(now + inner.backoff);
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.inner.lock().unwrap().child.is_some()
+    }
+
+    pub fn status(&self) -> SuricataStatus {
+        let inner = self.inner.lock().unwrap();
+        let pid = inner.child.as_ref().map(|c| c.id());
+        let now = Instant::now();
+        let state = match (&inner.child, inner.wanted) {
+            (Some(_), _) => SuricataState::Running,
+            (None, true) => SuricataState::Restarting,
+            (None, false) => SuricataState::Stopped,
+        };
+        SuricataStatus {
+            state,
+            pid,
+            started_at: inner.started.map(|(_, at)| at),
+            uptime_seconds: inner.started.map(|(at, _)| now.duration_since(at).as_secs()),
+            restart_count: inner.restart_count,
+            last_error: inner.last_error.clone(),
+            last_exit_at: inner.last_exit_at,
+            restart_in_seconds: inner.restart_at.filter(|_| inner.wanted).map(|at| at.saturating_duration_since(now).as_secs()),
+            recent_log: self.log.lock().unwrap().iter().cloned().collect(),
+            other_pids: suricata_pids().into_iter().filter(|p| Some(*p) != pid).collect(),
+        }
+    }
+
+    fn spawn(&self, inner: &mut Supervised, now: Instant) -> Result<(), AppError> {
+        let mut child = (self.command)()?
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(spawn_error)?;
+        info!("Started Suricata (pid {})", child.id());
+        inner.readers.clear();
+        if let Some(stdout) = child.stdout.take() {
+            inner.readers.push(capture_output(stdout, self.log.clone()));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            inner.readers.push(capture_output(stderr, self.log.clone()));
+        }
+        inner.child = Some(child);
+        inner.started = Some((now, now_secs()));
+        inner.restart_at = None;
+        Ok(())
+    }
+}
+
+// Checks on Suricata every few seconds for as long as the desktop app runs.
+pub fn start_supervision(app: AppHandle) {
+    thread::spawn(move || loop {
+        app.state::<SuricataSupervisor>().check();
+        thread::sleep(Duration::from_secs(CHECK_INTERVAL_SECONDS));
+    });
+}
+
+fn capture_output(output: impl Read + Send + 'static, log: Arc<Mutex<VecDeque<String>>>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines().map_while(Result::ok) {
+            debug!(target: "suricata", "{}", line);
+            let mut log = log.lock().unwrap();
+            log.push_back(line);
+            while log.len() > MAX_LOG_LINES {
+                log.pop_front();
+            }
+        }
+    })
+}
+
+fn drain_output(readers: Vec<JoinHandle<()>>) {
+    let deadline = Instant::now() + OUTPUT_DRAIN_TIMEOUT;
+    while Instant::now() < deadline && !readers.iter().all(|r| r.is_finished()) {
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn terminate_child(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        use sysinfo::{Pid, Signal, System};
+        let pid = Pid::from_u32(child.id());
+        let mut sys = System::new();
+        sys.refresh_process(pid);
+        if let Some(process) = sys.process(pid) {
+            process.kill_with(Signal::Term);
+        }
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        warn!("Suricata didn't exit within {}s, killing it", STOP_TIMEOUT.as_secs());
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn crashing() -> Result<Command, AppError> {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo 'E: pcap: eth9: No such device'; exit 1");
+        Ok(command)
+    }
+
+    fn long_running() -> Result<Command, AppError> {
+        let mut command = Command::new("sleep");
+        command.arg("30");
+        Ok(command)
+    }
+
+    fn wait_for_exit(supervisor: &SuricataSupervisor) {
+        for _ in 0..50 {
+            supervisor.check();
+            if !supervisor.is_running() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("child didn't exit");
+    }
+
+    #[test]
+    fn restarts_a_crashed_process_with_backoff() {
+        let supervisor = SuricataSupervisor::with_command(crashing);
+        supervisor.start().unwrap();
+        wait_for_exit(&supervisor);
+
+        let status = supervisor.status();
+        assert_eq!(status.state, SuricataState::Restarting);
+        assert_eq!(status.last_error.as_deref(), Some("Suricata exited with exit status: 1: E: pcap: eth9: No such device"));
+        assert!(status.last_exit_at.is_some());
+
+        // Not before the backoff has passed.
+        supervisor.check();
+        assert!(!supervisor.is_running());
+        supervisor.check_at(Instant::now() + INITIAL_BACKOFF);
+        assert!(supervisor.is_running());
+        assert_eq!(supervisor.status().restart_count, 1);
+
+        wait_for_exit(&supervisor);
+        supervisor.check_at(Instant::now() + INITIAL_BACKOFF);
+        assert!(!supervisor.is_running());
+        supervisor.check_at(Instant::now() + INITIAL_BACKOFF * 2);
+        assert_eq!(supervisor.status().restart_count, 2);
+
+        supervisor.stop().unwrap();
+        assert_eq!(supervisor.status().state, SuricataState::Stopped);
+        supervisor.check_at(Instant::now() + MAX_BACKOFF);
+        assert!(!supervisor.is_running());
+    }
+
+    #[test]
+    fn stop_ends_the_process_and_reports_when_nothing_runs() {
+        let supervisor = SuricataSupervisor::with_command(long_running);
+        assert_eq!(supervisor.stop().unwrap_err().kind, ErrorKind::SuricataNotRunning);
+
+        let status = supervisor.start().unwrap();
+        assert_eq!(status.state, SuricataState::Running);
+        assert!(status.pid.is_some() && status.uptime_seconds.is_some());
+
+        let started = Instant::now();
+        supervisor.stop().unwrap();
+        assert!(started.elapsed() < STOP_TIMEOUT);
+        let status = supervisor.status();
+        assert_eq!((status.state, status.pid, status.restart_count), (SuricataState::Stopped, None, 0));
+    }
+}
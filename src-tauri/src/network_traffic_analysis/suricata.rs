@@ -1,23 +1,22 @@
 use std::env;
-use std::fs::{OpenOptions, File};
-use std::io::{BufRead, BufReader, Write};
-use std::process::{Command, Stdio, Child};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use pnet::datalink;
 use socket2::{Socket, Domain, Type, Protocol};
 use std::net::SocketAddr;
 use sysinfo::System;
+use tauri::{AppHandle, State};
+use crate::app_data::app_data_dir;
+use crate::audit::record;
+use crate::firewall::snapshots::ChangeSource;
+use crate::error::{AppError, ErrorKind};
+use crate::network_traffic_analysis::store::EventStoreState;
+use crate::network_traffic_analysis::supervisor::{SuricataStatus, SuricataSupervisor};
+use crate::network_traffic_analysis::tailer::EveTailerState;
 
-fn start_suricata(interface: &str, config_path: &str, log_dir: &str) -> std::io::Result<Child> {
-    Command::new("suricata")
-        .arg("-c").arg(config_path)
-        .arg("-i").arg(interface)
-        .arg("-l").arg(log_dir)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()
-}
+pub const RECENT_ALERT_LIMIT: usize = 500;
 
 fn pick_internet_interface() -> Option<String> {
     for iface in datalink::interfaces() {
@@ -36,52 +35,97 @@ fn pick_internet_interface() -> Option<String> {
     None
 }
 
+// Whether the Suricata this app started is running; see get_suricata_status for others.
 #[tauri::command]
-pub fn is_suricata_active() -> bool {
-    let mut sys = System::new_all();
-    sys.refresh_processes();
-    for _process in sys.processes_by_name("suricata") {
-        return true;
-    }
-    false
+#[tracing::instrument(skip_all)]
+pub fn is_suricata_active(supervisor: State<'_, SuricataSupervisor>) -> bool {
+    supervisor.is_running()
 }
 
 #[tauri::command]
-pub fn run_suricata() -> Result<(), String> {
-    let interface = match pick_internet_interface() {
-        Some(i) => i,
-        None => return Ok(()),
-    };
+#[tracing::instrument(skip_all)]
+pub fn get_suricata_status(supervisor: State<'_, SuricataSupervisor>) -> SuricataStatus {
+    supervisor.status()
+}
 
-    if is_suricata_active() {
-        return Ok(());
-    }
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub fn run_suricata(app: AppHandle, supervisor: State<'_, SuricataSupervisor>) -> Result<SuricataStatus, AppError> {
+    let result = supervisor.start();
+    record(&app_data_dir(&app), ChangeSource::Desktop, "run_suricata", Value::Null, &result);
+    result
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub fn kill_suricata(app: AppHandle, supervisor: State<'_, SuricataSupervisor>) -> Result<(), AppError> {
+    let result = supervisor.stop();
+    record(&app_data_dir(&app), ChangeSource::Desktop, "kill_suricata", Value::Null, &result);
+    result
+}
+
+// Where Suricata writes eve.json, and where the files derived from it are kept.
+pub fn suricata_log_dir() -> PathBuf {
+    env::temp_dir().join("suricata_logs")
+}
+
+// Suricata on the interface that reaches the internet, with the bundled config, logging to
+// suricata_log_dir().
+pub fn suricata_command() -> Result<Command, AppError> {
+    let interface = pick_internet_interface()
+        .ok_or_else(|| AppError::not_found("No network interface with internet access was found"))?;
 
-    let mut log_dir = env::temp_dir();
-    log_dir.push("suricata_logs");
-    let log_dir_str = log_dir.to_str().unwrap();
+    let log_dir = suricata_log_dir();
+    std::fs::create_dir_all(&log_dir)
+        .map_err(|e| AppError::from(e).context("Failed to create log dir"))?;
 
-    let base_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+    let config_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
         .join("src")
-        .join("network_traffic_analysis");
-    let config_path = base_dir.join("suricata.yaml");
-    let config_path_str = config_path.to_str().unwrap();
+        .join("network_traffic_analysis")
+        .join("suricata.yaml");
+
+    let mut command = Command::new("suricata");
+    command.arg("-c").arg(config_path).arg("-i").arg(interface).arg("-l").arg(log_dir);
+    Ok(command)
+}
 
-    std::fs::create_dir_all(log_dir_str)
-        .map_err(|e| format!("Failed to create log dir: {}", e))?;    let _suricata_child = start_suricata(&interface, config_path_str, log_dir_str)
-        .map_err(|e| format!("Failed to start Suricata: {}", e))?;
+pub fn spawn_error(e: std::io::Error) -> AppError {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => {
+            AppError::new(ErrorKind::BackendMissing, "Suricata is not installed or not on PATH")
+        },
+        _ => AppError::from(e).context("Failed to start Suricata"),
+    }
+}
 
+// Every Suricata process on the machine, whoever started it.
+pub fn suricata_pids() -> Vec<u32> {
+    let mut sys = System::new();
+    sys.refresh_processes();
+    sys.processes_by_name("suricata").map(|p| p.pid().as_u32()).collect()
+}
+
+// Starts Suricata without supervision, for the CLI when the background service isn't running.
+// It keeps running after the CLI exits, so its output isn't captured.
+pub fn launch_suricata() -> Result<(), AppError> {
+    if !suricata_pids().is_empty() {
+        return Ok(());
+    }
+    suricata_command()?
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(spawn_error)?;
     Ok(())
 }
 
-#[tauri::command]
-pub fn kill_suricata() {
-    let mut sys = System::new_all();
+pub fn terminate_suricata() {
+    let mut sys = System::new();
     sys.refresh_processes();
     for process in sys.processes_by_name("suricata") {
         #[cfg(unix)]
         {
-            process.kill_with(Signal::Term);
+            process.kill_with(sysinfo::Signal::Term);
         }
         #[cfg(windows)]
         {
@@ -92,6 +136,9 @@ pub fn kill_suricata() {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AlertEvent {
+    // Set on alerts read back from the event store.
+    #[serde(default)]
+    pub id: Option<i64>,
     pub timestamp: String,
     pub src_ip: Option<String>,
     pub dest_ip: Option<String>,
@@ -100,149 +147,33 @@ pub struct AlertEvent {
     pub signature: Option<String>,
     pub category: Option<String>,
     pub severity: Option<u8>,
+    // Links the alert to the flow record of the same connection.
+    #[serde(default)]
+    pub flow_id: Option<u64>,
+    #[serde(default)]
+    pub community_id: Option<String>,
 }
 
+// The latest alerts from the event store, oldest first.
 #[tauri::command]
-pub fn read_alert_events() -> Result<Vec<AlertEvent>, String> {
-    let mut log_dir = env::temp_dir();
-    log_dir.push("suricata_logs");    let mut alert_path = log_dir.clone();
-    alert_path.push("alert.json");
-    let alert_path_str = alert_path.to_str().unwrap();
-
-    if !std::path::Path::new(alert_path_str).exists() {
-        File::create(alert_path_str)
-            .map_err(|e| format!("Failed to create alert.json: {}", e))?;
-    }
-
-    let file = File::open(alert_path_str)
-        .map_err(|e| format!("Failed to open alert.json: {}", e))?;
-    let reader = BufReader::new(file);
-    let mut alerts = Vec::new();
-
-    for line in reader.lines() {
-        let line = line.map_err(|e| format!("Failed to read line: {}", e))?;
-        if let Ok(json) = serde_json::from_str::<Value>(&line) {
-            if json.get("event_type").and_then(|v| v.as_str()) == Some("alert") {
-                let alert = AlertEvent {
-                    timestamp: json.get("timestamp").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                    src_ip: json.get("src_ip").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                    dest_ip: json.get("dest_ip").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                    src_port: json.get("src_port").and_then(|v| v.as_u64()).map(|n| n as u16),
-                    dest_port: json.get("dest_port").and_then(|v| v.as_u64()).map(|n| n as u16),
-                    signature: json.get("alert").and_then(|a| a.get("signature")).and_then(|v| v.as_str()).map(|s| s.to_string()),
-                    category: json.get("alert").and_then(|a| a.get("category")).and_then(|v| v.as_str()).map(|s| s.to_string()),
-                    severity: json.get("alert").and_then(|a| a.get("severity")).and_then(|v| v.as_u64()).map(|n| n as u8),
-                };
-                alerts.push(alert);
-            }
-        }
-    }
-    Ok(alerts)
+#[tracing::instrument(skip_all, err)]
+pub fn read_alert_events(events: State<'_, EventStoreState>) -> Result<Vec<AlertEvent>, AppError> {
+    events.store.recent_alerts(RECENT_ALERT_LIMIT)
 }
 
+// Stores whatever Suricata has written to eve.json since the last call.
 #[tauri::command]
-pub fn extract_and_handle_events() -> Result<(), String> {
-    let mut log_dir = std::env::temp_dir();
-    log_dir.push("suricata_logs");
-    let mut eve_path = log_dir.clone();
-    eve_path.push("eve.json");
-    let eve_path_str = eve_path.to_str().unwrap();
-
-    let mut alert_path = log_dir.clone();
-    alert_path.push("alert.json");
-    let alert_path_str = alert_path.to_str().unwrap();
-
-    let mut flow_path = log_dir.clone();
-    flow_path.push("flow.json");
-    let flow_path_str = flow_path.to_str().unwrap();
-
-    if !std::path::Path::new(alert_path_str).exists() {
-        File::create(alert_path_str)
-            .map_err(|e| format!("Failed to create alert.json: {}", e))?;
-    }
-    if !std::path::Path::new(flow_path_str).exists() {
-        File::create(flow_path_str)
-            .map_err(|e| format!("Failed to create flow.json: {}", e))?;
-    }
-
-    let file = File::open(eve_path_str)
-        .map_err(|e| format!("Failed to open eve.json: {}", e))?;
-    let reader = BufReader::new(file);
-
-    let mut alert_file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(alert_path_str)
-        .map_err(|e| format!("Failed to open alert.json: {}", e))?;
-    let mut flow_file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(flow_path_str)
-        .map_err(|e| format!("Failed to open flow.json: {}", e))?;
-
-    for line in reader.lines() {
-        let line = line.map_err(|e| format!("Failed to read line: {}", e))?;
-        if let Ok(json) = serde_json::from_str::<Value>(&line) {
-            match json.get("event_type").and_then(|v| v.as_str()) {
-                Some("alert") => {
-                    writeln!(alert_file, "{}", line).map_err(|e| format!("Failed to write: {}", e))?;
-                }
-                Some("flow") => {
-                    writeln!(flow_file, "{}", line).map_err(|e| format!("Failed to write: {}", e))?;
-                }
-                _ => {}
-            }        }
-    }
-
-    OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(eve_path_str)
-        .map_err(|e| format!("Failed to truncate eve.json: {}", e))?;
-
+#[tracing::instrument(skip_all, err)]
+pub fn extract_and_handle_events(state: State<'_, EveTailerState>) -> Result<(), AppError> {
+    state.tailer.lock().unwrap().poll()?;
     Ok(())
 }
 
-pub fn read_flow_events() -> Result<Vec<FlowEvent>, String> {
-    let mut log_dir = std::env::temp_dir();
-    log_dir.push("suricata_logs");
-    let mut flow_path = log_dir.clone();
-    flow_path.push("flow.json");
-    let flow_path_str = flow_path.to_str().unwrap();
-
-    let file = File::open(flow_path_str)
-        .map_err(|e| format!("Failed to open {}: {}", flow_path_str, e))?;
-    let reader = BufReader::new(file);
-    let mut flow_events = Vec::new();
-
-    for line in reader.lines() {
-        let line = line.map_err(|e| format!("Failed to read line: {}", e))?;
-        if let Ok(json) = serde_json::from_str::<Value>(&line) {
-            if json.get("event_type").and_then(|v| v.as_str()) == Some("flow") {
-                let flow = json.get("flow").unwrap_or(&Value::Null);
-                let flow_event = FlowEvent {
-                    sourceip: json.get("src_ip").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                    destinationip: json.get("dest_ip").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                    sourceport: json.get("src_port").and_then(|v| v.as_u64()).unwrap_or(0) as u16,
-                    destinationport: json.get("dest_port").and_then(|v| v.as_u64()).unwrap_or(0) as u16,
-                    protocol: json.get("proto").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                    bytes_in: flow.get("bytes_toserver").and_then(|v| v.as_u64()).unwrap_or(0),
-                    bytes_out: flow.get("bytes_toclient").and_then(|v| v.as_u64()).unwrap_or(0),
-                    packets_in: flow.get("pkts_toserver").and_then(|v| v.as_u64()).unwrap_or(0) as u32,
-                    packets_out: flow.get("pkts_toclient").and_then(|v| v.as_u64()).unwrap_or(0) as u32,
-                    start_time: flow.get("start").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                    end_time: flow.get("end").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                };
-                flow_events.push(flow_event);
-            }
-        }
-    }
-
-    Ok(flow_events)
-}
-
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FlowEvent {
+    // Set on flows read back from the event store.
+    #[serde(default)]
+    pub id: Option<i64>,
     pub sourceip: String,
     pub destinationip: String,
     pub sourceport: u16,
@@ -253,4 +184,8 @@ pub struct FlowEvent {
     pub packets_in: u32,
     pub packets_out: u32,    pub start_time: String,
     pub end_time: String,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub flow_id: Option<u64>,
+    #[serde(default)]
+    pub community_id: Option<String>,
+}
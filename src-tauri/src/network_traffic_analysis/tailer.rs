@@ -0,0 +1,375 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use crate::app_data::{create_shared_dir, share_with_group, FileLock};
+use crate::error::{AppError, ErrorKind};
+use crate::network_traffic_analysis::eve::{parse_eve_line, EveEvent};
+use crate::network_traffic_analysis::store::EventStore;
+use crate::network_traffic_analysis::suricata::suricata_log_dir;
+
+const EVE_FILE: &str = "eve.json";
+const CURSOR_FILE: &str = "eve_cursor.json";
+// Held from loading the cursor to saving it, so two processes never read the same lines.
+const CURSOR_LOCK_FILE: &str = "eve_cursor.lock";
+// Subscribers get records in batches of at most this many.
+const DISPATCH_BATCH: usize = 1000;
+
+// How far into eve.json events have been handed out. `file_id` is the inode on Unix, so a
+// rotated eve.json is read from its start; elsewhere only truncation can be detected.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TailCursor {
+    pub file_id: Option<u64>,
+    pub offset: u64,
+}
+
+pub type EveSubscriber = Box<dyn FnMut(&[EveEvent]) + Send>;
+
+// Follows eve.json by offset while Suricata keeps writing to it. Complete lines are parsed and
+// passed to every subscriber in order; a trailing partial line is left for the next poll.
+pub struct EveTailer {
+    eve_path: PathBuf,
+    cursor_path: PathBuf,
+    lock_path: PathBuf,
+    cursor: TailCursor,
+    reader: Option<BufReader<File>>,
+    subscribers: Vec<EveSubscriber>,
+}
+
+impl EveTailer {
+    // Reads eve.json from `log_dir` and keeps the read position in `state_dir`.
+    pub fn new(log_dir: &Path, state_dir: &Path) -> Self {
+        let cursor_path = state_dir.join(CURSOR_FILE);
+        EveTailer {
+            eve_path: log_dir.join(EVE_FILE),
+            cursor: load_cursor(&cursor_path),
+            cursor_path,
+            lock_path: state_dir.join(CURSOR_LOCK_FILE),
+            reader: None,
+            subscribers: Vec::new(),
+        }
+    }
+
+    // A tailer that stores alerts and flows in `store`.
+    pub fn with_event_store(log_dir: &Path, state_dir: &Path, store: Arc<EventStore>) -> Self {
+        let mut tailer = EveTailer::new(log_dir, state_dir);
+        tailer.subscribe(move |events| {
+            if let Err(e) = store.insert(events) {
+                warn!("Failed to store Suricata events: {}", e);
+            }
+        });
+        tailer
+    }
+
+    pub fn subscribe(&mut self, subscriber: impl FnMut(&[EveEvent]) + Send + 'static) {
+        self.subscribers.push(Box::new(subscriber));
+    }
+
+    // Hands out everything written since the last poll and returns how many records that was.
+    pub fn poll(&mut self) -> Result<usize, AppError> {
+        // The desktop app, CLI and daemon share the saved cursor.
+        if let Some(dir) = self.lock_path.parent() {
+            create_shared_dir(dir)
+                .map_err(|e| AppError::from(e).context("Failed to create the eve.json cursor directory"))?;
+        }
+        let _lock = FileLock::acquire(&self.lock_path)
+            .map_err(|e| AppError::from(e).context("Failed to lock the eve.json cursor"))?;
+
+        let metadata = fs::metadata(&self.eve_path);
+        let current_id = metadata.as_ref().ok().and_then(file_id);
+        self.catch_up_with_saved_cursor(current_id);
+
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                // Rotated away and not recreated yet: finish what was written to the old file.
+                if self.reader.is_some() {
+                    let count = self.read_available()?;
+                    self.save_cursor();
+                    return Ok(count);
+                }
+                return Err(AppError::new(
+                    ErrorKind::SuricataNotRunning,
+                    "Suricata hasn't written eve.json yet; start it first"
+                ));
+            },
+            Err(e) => return Err(AppError::from(e).context("Failed to read eve.json")),
+        };
+
+        let mut count = 0;
+        let id = file_id(&metadata);
+        if id != self.cursor.file_id {
+            if self.reader.is_some() {
+                count += self.read_available()?;
+            }
+            self.reader = None;
+            self.cursor = TailCursor { file_id: id, offset: 0 };
+        } else if metadata.len() < self.cursor.offset {
+            debug!("eve.json was truncated, reading it from the start");
+            self.cursor.offset = 0;
+        }
+
+        if self.reader.is_none() {
+            let file = File::open(&self.eve_path)
+                .map_err(|e| AppError::from(e).context("Failed to open eve.json"))?;
+            self.reader = Some(BufReader::new(file));
+        }
+        count += self.read_available()?;
+        self.save_cursor();
+        Ok(count)
+    }
+
+    fn read_available(&mut self) -> Result<usize, AppError> {
+        let Some(reader) = self.reader.as_mut() else {
+            return Ok(0);
+        };
+        reader.seek(SeekFrom::Start(self.cursor.offset))
+            .map_err(|e| AppError::from(e).context("Failed to seek in eve.json"))?;
+
+        let mut count = 0;
+        let mut batch = Vec::new();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)
+                .map_err(|e| AppError::from(e).context("Failed to read eve.json"))?;
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            self.cursor.offset += read as u64;
+
+            match parse_eve_line(line.trim_end()) {
+                Some(event) => batch.push(event),
+                None => debug!("Skipping unparseable eve.json line at offset {}", self.cursor.offset),
+            }
+            if batch.len() >= DISPATCH_BATCH {
+                count += batch.len();
+                dispatch(&mut self.subscribers, &mut batch);
+            }
+        }
+        count += batch.len();
+        dispatch(&mut self.subscribers, &mut batch);
+        Ok(count)
+    }
+
+    // Skips what another process has handed out since this one last polled. If it has already
+    // moved on to a rotated eve.json, it also drained the old one.
+    fn catch_up_with_saved_cursor(&mut self, current_id: Option<u64>) {
+        let saved = load_cursor(&self.cursor_path);
+        if saved.file_id == self.cursor.file_id {
+            if saved.offset > self.cursor.offset {
+                self.cursor = saved;
+            }
+        } else if saved.file_id.is_some() && saved.file_id == current_id {
+            self.reader = None;
+            self.cursor = saved;
+        }
+    }
+
+    fn save_cursor(&self) {
+        let result = serde_json::to_string(&self.cursor)
+            .map_err(|e| e.to_string())
+            .and_then(|json| {
+                if let Some(dir) = self.cursor_path.parent() {
+                    create_shared_dir(dir).map_err(|e| e.to_string())?;
+                }
+                fs::write(&self.cursor_path, json).map_err(|e| e.to_string())
+            });
+        match result {
+            Ok(()) => share_with_group(&self.cursor_path, 0o660),
+            Err(e) => warn!("Failed to save eve.json cursor: {}", e),
+        }
+    }
+}
+
+pub struct EveTailerState {
+    pub tailer: Mutex<EveTailer>,
+}
+
+impl EveTailerState {
+    // The read position is kept in `state_dir`, next to the event database.
+    pub fn new(state_dir: &Path, store: Arc<EventStore>) -> Self {
+        EveTailerState {
+            tailer: Mutex::new(EveTailer::with_event_store(&suricata_log_dir(), state_dir, store)),
+        }
+    }
+}
+
+fn dispatch(subscribers: &mut [EveSubscriber], batch: &mut Vec<EveEvent>) {
+    if batch.is_empty() {
+        return;
+    }
+    for subscriber in subscribers.iter_mut() {
+        subscriber(batch);
+    }
+    batch.clear();
+}
+
+fn load_cursor(path: &Path) -> TailCursor {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use crate::network_traffic_analysis::eve::EvePayload;
+
+    const ALERT: &str = r#"{"timestamp":"2025-01-31T10:00:00.000000+0000","event_type":"alert","src_ip":"10.0.0.5","dest_ip":"93.184.216.34","src_port":51000,"dest_port":443,"alert":{"signature":"Test signature","category":"Misc activity","severity":2}}"#;
+    const FLOW: &str = r#"{"timestamp":"2025-01-31T10:00:01.000000+0000","event_type":"flow","src_ip":"10.0.0.5","dest_ip":"93.184.216.34","proto":"TCP","flow":{"bytes_toserver":120,"bytes_toclient":300}}"#;
+    const DNS: &str = r#"{"timestamp":"2025-01-31T10:00:02.000000+0000","event_type":"dns","dns":{"rrname":"example.com"}}"#;
+
+    fn log_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("security-smile-tailer-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn append(path: &Path, text: &str) {
+        let mut file = OpenOptions::new().create(true).append(true).open(path).unwrap();
+        write!(file, "{}", text).unwrap();
+    }
+
+    fn collecting_tailer(dir: &Path) -> (EveTailer, Arc<Mutex<Vec<String>>>) {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut tailer = EveTailer::new(dir, dir);
+        let sink = seen.clone();
+        tailer.subscribe(move |events| {
+            for event in events {
+                let kind = match &event.payload {
+                    EvePayload::Alert(alert) => format!("alert {}", alert.signature.clone().unwrap_or_default()),
+                    EvePayload::Flow(_) => format!("flow {}", event.proto.clone().unwrap_or_default()),
+                    _ => event.event_type.clone(),
+                };
+                sink.lock().unwrap().push(kind);
+            }
+        });
+        (tailer, seen)
+    }
+
+    #[test]
+    fn follows_appends_and_waits_for_partial_lines() {
+        let dir = log_dir("append");
+        let eve = dir.join(EVE_FILE);
+        append(&eve, &format!("{}\n{}\nnot json\n", ALERT, FLOW));
+        let (mut tailer, seen) = collecting_tailer(&dir);
+
+        assert_eq!(tailer.poll().unwrap(), 2);
+        let (half, rest) = DNS.split_at(20);
+        append(&eve, half);
+        assert_eq!(tailer.poll().unwrap(), 0);
+        append(&eve, &format!("{}\n", rest));
+        assert_eq!(tailer.poll().unwrap(), 1);
+
+        assert_eq!(*seen.lock().unwrap(), vec!["alert Test signature", "flow TCP", "dns"]);
+        assert_eq!(load_cursor(&dir.join(CURSOR_FILE)).offset, fs::metadata(&eve).unwrap().len());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn resumes_from_the_saved_cursor_and_restarts_after_truncation() {
+        let dir = log_dir("resume");
+        let eve = dir.join(EVE_FILE);
+        append(&eve, &format!("{}\n", ALERT));
+        EveTailer::new(&dir, &dir).poll().unwrap();
+
+        append(&eve, &format!("{}\n", FLOW));
+        let (mut tailer, seen) = collecting_tailer(&dir);
+        assert_eq!(tailer.poll().unwrap(), 1);
+
+        fs::write(&eve, format!("{}\n", DNS)).unwrap();
+        assert_eq!(tailer.poll().unwrap(), 1);
+        assert_eq!(*seen.lock().unwrap(), vec!["flow TCP", "dns"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn drains_the_old_file_when_eve_json_is_rotated() {
+        let dir = log_dir("rotate");
+        let eve = dir.join(EVE_FILE);
+        append(&eve, &format!("{}\n", ALERT));
+        let (mut tailer, seen) = collecting_tailer(&dir);
+        tailer.poll().unwrap();
+
+        append(&eve, &format!("{}\n", FLOW));
+        fs::rename(&eve, dir.join("eve.json.1")).unwrap();
+        append(&eve, &format!("{}\n", DNS));
+        assert_eq!(tailer.poll().unwrap(), 2);
+        assert_eq!(*seen.lock().unwrap(), vec!["alert Test signature", "flow TCP", "dns"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn tailers_sharing_a_cursor_hand_out_each_line_once() {
+        let dir = log_dir("shared");
+        let eve = dir.join(EVE_FILE);
+        append(&eve, &format!("{}\n", DNS).repeat(200));
+
+        let pollers: Vec<_> = (0..4).map(|_| {
+            let dir = dir.clone();
+            std::thread::spawn(move || {
+                let mut tailer = EveTailer::new(&dir, &dir);
+                (0..20).map(|_| tailer.poll().unwrap()).sum::<usize>()
+            })
+        }).collect();
+        let total: usize = pollers.into_iter().map(|p| p.join().unwrap()).sum();
+
+        assert_eq!(total, 200);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_another_tailer_onto_a_rotated_file() {
+        let dir = log_dir("handoff");
+        let eve = dir.join(EVE_FILE);
+        append(&eve, &format!("{}\n", ALERT));
+        let (mut first, seen) = collecting_tailer(&dir);
+        first.poll().unwrap();
+
+        fs::rename(&eve, dir.join("eve.json.1")).unwrap();
+        append(&eve, &format!("{}\n", DNS));
+        let (mut second, _) = collecting_tailer(&dir);
+        assert_eq!(second.poll().unwrap(), 1);
+
+        assert_eq!(first.poll().unwrap(), 0);
+        assert_eq!(*seen.lock().unwrap(), vec!["alert Test signature"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn feeds_the_event_store() {
+        let dir = log_dir("store");
+        // Recent, so the store doesn't prune them straight away.
+        let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.6f+0000").to_string();
+        let recent = |line: &str| line.replace("2025-01-31T10:00:0", &now[..18]);
+        append(&dir.join(EVE_FILE), &format!("{}\n{}\n{}\n", recent(ALERT), recent(FLOW), DNS));
+        let store = Arc::new(EventStore::in_dir(&dir));
+        EveTailer::with_event_store(&dir, &dir, store.clone()).poll().unwrap();
+
+        let alerts = store.recent_alerts(10).unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].signature.as_deref(), Some("Test signature"));
+        assert_eq!(store.flows().unwrap().len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
